@@ -0,0 +1,384 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{AppError, ErrorKind, WithKind};
+use crate::events::AudioSampleInfo;
+use crate::mix::{
+    check_duration, render, to_frame, ClipPolicy, RenderSettings, RenderStats, SampleCache, LOUD_MOMENTS,
+};
+use crate::random::random_seed;
+use crate::report::format_time;
+
+// Length of the segments a mix with --checkpoint is rendered in, in ms.
+const SEGMENT: f64 = 60000.0;
+
+// How far a mix with --checkpoint has come, written again after every segment. The mixed audio is appended to a file
+// next to it as 32 bit floats, so only this small part is rewritten.
+#[derive(Deserialize, Serialize)]
+struct Checkpoint {
+    // SHA-256 of the events and the settings that move them, a checkpoint of a changed CSV file is not resumed.
+    key: String,
+    // Humanizing has to draw the same deviations for every segment.
+    seed: Option<u64>,
+    // Start of the next segment in ms.
+    position: f64,
+    // Frames of the audio file that belong to finished segments. More of them were cut off by the interruption.
+    frames: usize,
+    // All segments are mixed, only the encoding was interrupted.
+    finished: bool,
+    // None before the first segment.
+    stats: Option<RenderStats>,
+}
+
+// Renders the mix SEGMENT ms at a time with --from and --to, like preview --stream, and writes the checkpoint after
+// every segment. With a checkpoint of the same events it starts after the last segment in it instead of at the start.
+// The checkpoint is left in place until remove is called, after the mix has been written.
+pub fn render_resumable(
+    infos: Vec<AudioSampleInfo>,
+    settings: &RenderSettings,
+    cache: &mut SampleCache,
+    path: &Path,
+) -> Result<(Vec<f32>, RenderStats), Box<dyn Error>> {
+    if settings.on_clip == ClipPolicy::Normalize {
+        let message = "--auto-gain and --on-clip normalize need the whole mix and cannot be used with --checkpoint";
+        return Err(AppError::new(ErrorKind::Arguments, message).into());
+    }
+    if !settings.plugins.is_empty() {
+        let message = "plugins process the whole mix at once and cannot be used with --checkpoint";
+        return Err(AppError::new(ErrorKind::Arguments, message).into());
+    }
+    // The segments only see their own events, the length is checked for all of them.
+    if settings.max_duration > 0.0 {
+        check_duration(&infos, settings.from, settings)?;
+    }
+
+    let key = key(&infos, settings);
+    let audio_path = audio_path(path);
+    let (mut checkpoint, mut data) = match resume(path, &audio_path, &key) {
+        Some(resumed) => {
+            info!("resuming {} at {}", path.display(), format_time(resumed.0.position));
+            resumed
+        }
+        None => {
+            let humanized = settings.humanize_time > 0.0 || settings.humanize_volume > 0.0;
+            let checkpoint = Checkpoint {
+                key,
+                seed: settings.seed.or_else(|| humanized.then(random_seed)),
+                position: settings.from,
+                frames: 0,
+                finished: false,
+                stats: None,
+            };
+            (checkpoint, Vec::new())
+        }
+    };
+
+    let mut audio = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&audio_path)
+        .with_kind(ErrorKind::Output)?;
+    audio
+        .set_len(checkpoint.frames as u64 * 8)
+        .with_kind(ErrorKind::Output)?;
+
+    let sample_rate = settings.sample_rate;
+    while !checkpoint.finished {
+        let from = checkpoint.position;
+        let to = match settings.to {
+            Some(to) if to <= from + SEGMENT => to,
+            _ => from + SEGMENT,
+        };
+        let first = checkpoint.stats.is_none();
+        let part_settings = RenderSettings {
+            from,
+            to: Some(to),
+            max_duration: 0.0,
+            extend_before_zero: first && settings.extend_before_zero,
+            seed: checkpoint.seed,
+            ..settings.clone()
+        };
+        let (mut part, stats) = render(infos.clone(), &part_settings, cache)?;
+
+        // Segments end with the last sample in them, the ones before the end are filled up with silence.
+        let frames = (to_frame(to, sample_rate) - to_frame(stats.start, sample_rate)) as usize;
+        let later = |time: f64| time + settings.humanize_time as f64 >= to;
+        let last = settings.to == Some(to)
+            || (part.len() < frames * 2
+                && !infos.iter().any(|info| later(info.time))
+                && !settings.backing.as_ref().is_some_and(|backing| later(backing.offset)));
+        if !last {
+            part.resize(frames * 2, 0.0);
+        }
+
+        let mut writer = BufWriter::new(&mut audio);
+        for value in &part {
+            writer.write_all(&value.to_le_bytes()).with_kind(ErrorKind::Output)?;
+        }
+        writer.flush().with_kind(ErrorKind::Output)?;
+        drop(writer);
+        audio.sync_data().with_kind(ErrorKind::Output)?;
+
+        checkpoint.stats = Some(append(checkpoint.stats.take(), stats, from, to, last, sample_rate));
+        checkpoint.position = to;
+        checkpoint.frames += part.len() / 2;
+        checkpoint.finished = last;
+        data.extend(part);
+        save(path, &checkpoint).with_kind(ErrorKind::Output)?;
+        info!("mixed up to {}", format_time(to));
+    }
+
+    Ok((data, checkpoint.stats.unwrap()))
+}
+
+// Deletes the checkpoint and its audio once the mix is written.
+pub fn remove(path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::remove_file(audio_path(path)).with_kind(ErrorKind::Output)?;
+    fs::remove_file(path).with_kind(ErrorKind::Output)?;
+    Ok(())
+}
+
+// The audio of mix.checkpoint is in mix.checkpoint.pcm.
+fn audio_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".pcm");
+    PathBuf::from(name)
+}
+
+fn key(infos: &[AudioSampleInfo], settings: &RenderSettings) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{:?}", infos));
+    hasher.update(format!("{:?}", settings));
+    format!("{:x}", hasher.finalize())
+}
+
+// None if there is no checkpoint to resume, it is of other events or its audio is shorter than it says.
+fn resume(path: &Path, audio_path: &Path, key: &str) -> Option<(Checkpoint, Vec<f32>)> {
+    let file = File::open(path).ok()?;
+    let checkpoint: Checkpoint = match serde_json::from_reader(file) {
+        Ok(checkpoint) => checkpoint,
+        Err(err) => {
+            warn!("starting over, {} cannot be read: {}", path.display(), err);
+            return None;
+        }
+    };
+    if checkpoint.key != key {
+        warn!("starting over, {} is of other events or settings", path.display());
+        return None;
+    }
+
+    let mut bytes = vec![0; checkpoint.frames * 8];
+    let read = File::open(audio_path).and_then(|mut file| file.read_exact(&mut bytes));
+    if let Err(err) = read {
+        warn!("starting over, {} cannot be read: {}", audio_path.display(), err);
+        return None;
+    }
+    let data = bytes
+        .chunks_exact(4)
+        .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+        .collect();
+    Some((checkpoint, data))
+}
+
+// Written to a temporary file first, so an interruption while writing leaves the previous checkpoint.
+fn save(path: &Path, checkpoint: &Checkpoint) -> Result<(), Box<dyn Error>> {
+    let mut temporary = OsString::from(path.as_os_str());
+    temporary.push(".tmp");
+    serde_json::to_writer(File::create(&temporary)?, checkpoint)?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+// Adds the stats of a segment to the ones before it. Segments also mix the events from before them that still play
+// and skip everything outside of them, only what starts within a segment belongs to it.
+fn append(
+    total: Option<RenderStats>,
+    part: RenderStats,
+    from: f64,
+    to: f64,
+    last: bool,
+    sample_rate: u32,
+) -> RenderStats {
+    let first = total.is_none();
+    let mut total = total.unwrap_or_else(|| RenderStats {
+        missing: Vec::new(),
+        undecodable: Vec::new(),
+        mixed: 0,
+        skipped: Vec::new(),
+        loudest: Vec::new(),
+        clipping: Vec::new(),
+        events: Vec::new(),
+        ..part
+    });
+    let offset = to_frame(part.start, sample_rate) - to_frame(total.start, sample_rate);
+    if !first {
+        total.peak = total.peak.max(part.peak);
+        total.clipped += part.clipped;
+        total.decode_time += part.decode_time;
+        total.mix_time += part.mix_time;
+    }
+    for name in part.missing {
+        if !total.missing.contains(&name) {
+            total.missing.push(name);
+        }
+    }
+    for name in part.undecodable {
+        if !total.undecodable.contains(&name) {
+            total.undecodable.push(name);
+        }
+    }
+
+    let events: Vec<_> = part
+        .events
+        .into_iter()
+        .filter(|event| first || event.frame >= 0)
+        .collect();
+    total.mixed += events.len();
+    total.events.extend(events.into_iter().map(|mut event| {
+        event.frame += offset;
+        event.time = event.frame as f64 / sample_rate as f64;
+        event
+    }));
+    total.skipped.extend(
+        part.skipped
+            .into_iter()
+            .filter(|event| (first || event.time >= from) && (last || event.time < to)),
+    );
+
+    total.loudest.extend(part.loudest);
+    total
+        .loudest
+        .sort_by(|a, b| b.peak.total_cmp(&a.peak).then(a.time.total_cmp(&b.time)));
+    total.loudest.truncate(LOUD_MOMENTS);
+    total.clipping.extend(part.clipping);
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::cli;
+    use crate::commands;
+    use crate::config::FileConfig;
+    use crate::summary::{MixedEvent, SkippedEvent};
+
+    fn stats(start: f64, peak: f32, events: &[(i64, &str)], skipped: &[f64], missing: &[&str]) -> RenderStats {
+        RenderStats {
+            peak,
+            clipped: 0,
+            decode_time: Duration::ZERO,
+            mix_time: Duration::ZERO,
+            missing: missing.iter().map(|name| name.to_string()).collect(),
+            undecodable: Vec::new(),
+            mixed: events.len(),
+            skipped: skipped
+                .iter()
+                .map(|&time| SkippedEvent {
+                    time,
+                    name: "skipped.wav".to_string(),
+                    reason: "missing sample".into(),
+                })
+                .collect(),
+            loudest: Vec::new(),
+            clipping: Vec::new(),
+            start,
+            gain: 1.0,
+            events: events
+                .iter()
+                .map(|&(frame, name)| MixedEvent {
+                    frame,
+                    time: 0.0,
+                    name: name.to_string(),
+                    file: name.to_string(),
+                    volume: 1.0,
+                    pan: 0.0,
+                    gain_left: 1.0,
+                    gain_right: 1.0,
+                    gain_left_to_right: 0.0,
+                    gain_right_to_left: 0.0,
+                    frames: 100,
+                    duration: 0.1,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn appended_stats() {
+        let first = stats(0.0, 0.5, &[(0, "a.wav"), (900, "b.wav")], &[200.0], &["m.wav"]);
+        let total = append(None, first, 0.0, 1000.0, false, 1000);
+        // The second segment mixes b.wav again because it still plays, and skips the event of the first one again.
+        let second = stats(
+            1000.0,
+            0.8,
+            &[(-100, "b.wav"), (50, "c.wav")],
+            &[200.0, 1500.0],
+            &["m.wav", "n.wav"],
+        );
+        let total = append(Some(total), second, 1000.0, 2000.0, true, 1000);
+
+        assert_eq!(total.peak, 0.8);
+        assert_eq!(total.mixed, 3);
+        assert_eq!(total.missing, ["m.wav", "n.wav"]);
+        assert_eq!(
+            total
+                .events
+                .iter()
+                .map(|event| (event.frame, event.time, event.name.as_str()))
+                .collect::<Vec<_>>(),
+            [(0, 0.0, "a.wav"), (900, 0.9, "b.wav"), (1050, 1.05, "c.wav")]
+        );
+        assert_eq!(
+            total.skipped.iter().map(|event| event.time).collect::<Vec<_>>(),
+            [200.0, 1500.0]
+        );
+    }
+
+    #[test]
+    fn resumed_renders() {
+        let matches = commands::mix::command()
+            .try_get_matches_from(["mix", "-i", "test.csv", "-o", "test.ogg"])
+            .unwrap();
+        let settings = cli::render_settings(&matches, &FileConfig::default()).unwrap();
+        // The second event plays on into the second segment.
+        let infos: Vec<AudioSampleInfo> = [0.0, 59900.0, 61000.0]
+            .into_iter()
+            .map(|time| AudioSampleInfo {
+                time,
+                volume: 1.0,
+                pan: 0.0,
+                name: "sine:440:200ms".to_string(),
+                line: 1,
+                lane: None,
+                hold: None,
+                pitch: None,
+                pan_mode: None,
+                tags: Vec::new(),
+            })
+            .collect();
+        let (whole, _) = render(infos.clone(), &settings, &mut SampleCache::default()).unwrap();
+
+        let path = env::temp_dir().join(format!("audio-sample-mixer-test-{}.checkpoint", process::id()));
+        let (segments, stats) = render_resumable(infos.clone(), &settings, &mut SampleCache::default(), &path).unwrap();
+        assert_eq!(segments.len(), whole.len());
+        assert!(segments.iter().zip(&whole).all(|(a, b)| (a - b).abs() < 1e-6));
+        assert_eq!(stats.mixed, 3);
+
+        // The checkpoint of a finished mix is resumed from its audio.
+        let (resumed, stats) = render_resumable(infos, &settings, &mut SampleCache::default(), &path).unwrap();
+        remove(&path).unwrap();
+        assert_eq!(resumed, segments);
+        assert_eq!(stats.mixed, 3);
+    }
+}
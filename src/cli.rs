@@ -0,0 +1,780 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
+
+use crate::commands;
+use crate::config::{
+    parse_clip_policy, parse_decode_error_policy, parse_missing_policy, parse_pan_mode, parse_precision,
+    parse_range_policy, parse_resampler, parse_sample_normalization, parse_sample_rate, parse_upmix, FileConfig,
+};
+use crate::decode::UpmixLaw;
+use crate::error::{AppError, ErrorKind, WithKind};
+use crate::events::{parse_note, CsvOptions, Lane, RangePolicy};
+use crate::lv2::{parse_plugin, Plugin};
+use crate::manifest::Manifest;
+use crate::mix::{
+    Backing, ClipPolicy, DecodeErrorPolicy, MissingPolicy, PanMode, Precision, RenderSettings, SampleNormalization,
+};
+use crate::resample::ConverterType;
+use crate::resolve::SampleResolver;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+// One hour in ms.
+const DEFAULT_MAX_DURATION: f64 = 3_600_000.0;
+
+pub fn build() -> Command {
+    command!()
+        .about("Mixes audio samples with a given start time, volume and pan into a combined .ogg file.")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("CONFIG_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Read defaults from this file instead of ./audio-sample-mixer.toml or ~/.config/audio-sample-mixer.toml")
+                .global(true),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Print more details, repeat for even more (-vv)")
+                .action(ArgAction::Count)
+                .global(true),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .help("Only print warnings and errors")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose")
+                .global(true),
+        )
+        .arg(
+            Arg::new("log_format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .help("Print log messages as plain text or as one JSON object per line")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true),
+        )
+        .arg(
+            Arg::new("errors")
+                .long("errors")
+                .value_name("FORMAT")
+                .help("Print errors as plain text or as one JSON object with the exit code, kind and message")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true),
+        )
+        .subcommand(commands::mix::command())
+        .subcommand(commands::batch::command())
+        .subcommand(commands::validate::command())
+        .subcommand(commands::inspect::command())
+        .subcommands(playback_commands())
+        .subcommand(commands::serve::command())
+        .subcommand(commands::scan::command())
+        .subcommand(commands::pack::command())
+        .subcommand(commands::diff::command())
+        .subcommand(commands::stats::command())
+}
+
+// The commands that need an audio device, builds without the playback feature leave them out.
+#[cfg(feature = "playback")]
+fn playback_commands() -> Vec<Command> {
+    vec![
+        commands::preview::command(),
+        commands::audition::command(),
+        commands::live::command(),
+    ]
+}
+
+#[cfg(not(feature = "playback"))]
+fn playback_commands() -> Vec<Command> {
+    Vec::new()
+}
+
+pub fn input_arg() -> Arg {
+    Arg::new("input")
+        .short('i')
+        .long("input")
+        .value_name("INPUT_CSV_FILE")
+        .help("CSV file with one `time,volume,pan,file` row per sample event")
+        .value_parser(value_parser!(PathBuf))
+        .required(true)
+}
+
+#[cfg(feature = "playback")]
+pub fn host_arg() -> Arg {
+    Arg::new("host")
+        .long("host")
+        .value_name("HOST")
+        .help("Play through the default device of this audio host, like jack or alsa, instead of the default host")
+}
+
+pub fn resolve_args() -> Vec<Arg> {
+    vec![
+        Arg::new("samples_dir")
+            .short('d')
+            .long("samples-dir")
+            .value_name("DIRECTORY")
+            .help("Look up samples in this directory, can be repeated to search several directories in order")
+            .value_parser(value_parser!(PathBuf))
+            .action(ArgAction::Append),
+        Arg::new("ignore_case")
+            .long("ignore-case")
+            .help("Match sample file names case-insensitively if there is no exact match")
+            .action(ArgAction::SetTrue),
+        Arg::new("any_extension")
+            .long("any-extension")
+            .help("Try .wav, .ogg, .mp3 and .flac if a sample does not exist with the given extension")
+            .action(ArgAction::SetTrue),
+        Arg::new("recursive")
+            .short('R')
+            .long("recursive")
+            .help("Search the subdirectories of the samples directories for samples that were not found")
+            .action(ArgAction::SetTrue),
+        Arg::new("cache_dir")
+            .long("cache-dir")
+            .value_name("DIRECTORY")
+            .help("Keep samples downloaded from http:// and https:// URLs here [default: ~/.cache/audio-sample-mixer]")
+            .value_parser(value_parser!(PathBuf)),
+    ]
+}
+
+pub fn csv_args() -> Vec<Arg> {
+    vec![
+        Arg::new("decimal_comma")
+            .long("decimal-comma")
+            .help("Accept numbers like 0,75 in quoted columns, files separated by ';' or tabs always accept them")
+            .action(ArgAction::SetTrue),
+        Arg::new("volume_db")
+            .long("volume-db")
+            .help("Read the volumes of CSV files in dB, 0 plays a sample at its own level and -6 at half of it")
+            .action(ArgAction::SetTrue),
+        Arg::new("skip_bad_rows")
+            .long("skip-bad-rows")
+            .help("Log CSV rows that cannot be read and leave them out instead of stopping")
+            .action(ArgAction::SetTrue),
+        Arg::new("range_policy")
+            .long("range-policy")
+            .value_name("POLICY")
+            .help("What to do with volumes outside of 0.0 to 1.0 and pans outside of -1.0 to 1.0: error, clamp them or wrap them around [default: error]")
+            .value_parser(parse_range_policy),
+        Arg::new("difficulty")
+            .long("difficulty")
+            .value_name("NAME")
+            .help("Difficulty to mix from an .osz archive with several of them"),
+        Arg::new("script")
+            .long("script")
+            .value_name("RHAI_FILE")
+            .help("Change, add or remove events with the transform function of this Rhai script before mixing")
+            .value_parser(value_parser!(PathBuf)),
+        Arg::new("lane_column")
+            .long("lane-column")
+            .value_name("COLUMN")
+            .help("Column of the CSV file with the lane of every event, counting from 1, for the lanes of the config file")
+            .value_parser(parse_column),
+        Arg::new("hold_column")
+            .long("hold-column")
+            .value_name("COLUMN")
+            .help("Column of the CSV file with a duration that every event loops or cuts its sample to, counting from 1")
+            .value_parser(parse_column),
+        Arg::new("pitch_column")
+            .long("pitch-column")
+            .value_name("COLUMN")
+            .help("Column of the CSV file with the pitch of every event in semitones or as a note name like F#3, counting from 1")
+            .value_parser(parse_column),
+        Arg::new("rate")
+            .long("rate")
+            .value_name("FACTOR")
+            .help("Play the whole chart this many times as fast, like a rate mod, with the samples faster and higher [default: 1.0]")
+            .value_parser(parse_rate),
+        Arg::new("keep_pitch")
+            .long("keep-pitch")
+            .help("Leave the samples as they are with --rate and only move the events")
+            .action(ArgAction::SetTrue)
+            .requires("rate"),
+        Arg::new("offset")
+            .long("offset")
+            .value_name("TIME")
+            .help("Move every event this much later, or earlier with a minus sign, in ms, with s or as mm:ss, to sync with a backing track or video")
+            .value_parser(parse_offset)
+            .allow_hyphen_values(true),
+        Arg::new("tag_column")
+            .long("tag-column")
+            .value_name("COLUMN")
+            .help("Column of the CSV file with tags of every event separated by spaces, counting from 1, for --only-tag and --mute-tag")
+            .value_parser(parse_column),
+        Arg::new("only_tag")
+            .long("only-tag")
+            .value_name("TAG")
+            .num_args(1..)
+            .action(ArgAction::Append)
+            .help("Only read the events with one of these tags"),
+        Arg::new("mute_tag")
+            .long("mute-tag")
+            .value_name("TAG")
+            .num_args(1..)
+            .action(ArgAction::Append)
+            .help("Leave out the events with one of these tags"),
+        Arg::new("solo")
+            .long("solo")
+            .value_name("NAMES")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .help("Only read the events of these samples, separated by commas, * and ? match any text and any character"),
+        Arg::new("mute")
+            .long("mute")
+            .value_name("NAMES")
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .help("Leave out the events of these samples, separated by commas, * and ? match any text and any character"),
+    ]
+}
+
+pub fn render_args() -> Vec<Arg> {
+    let mut args = resolve_args();
+    args.extend(csv_args());
+    args.extend([
+        Arg::new("compact")
+            .short('c')
+            .long("compact")
+            .help("Store decoded samples as 16 bit integers to reduce memory usage")
+            .action(ArgAction::SetTrue),
+        Arg::new("sample_rate")
+            .short('r')
+            .long("sample-rate")
+            .value_name("HZ")
+            .help("Sample rate of the mix from 8000 to 192000 Hz, samples are resampled to it [default: 44100]")
+            .value_parser(parse_sample_rate),
+        Arg::new("threads")
+            .short('j')
+            .long("threads")
+            .value_name("COUNT")
+            .help("Number of threads used to decode samples [default: number of CPUs]")
+            .value_parser(value_parser!(usize)),
+        Arg::new("resampler")
+            .long("resampler")
+            .value_name("RESAMPLER")
+            .help("sinc-best, sinc-medium, sinc-fastest, zero-order-hold or linear [default: sinc-best]")
+            .value_parser(parse_resampler),
+        Arg::new("upmix")
+            .long("upmix")
+            .value_name("LAW")
+            .help("How mono samples are spread to both channels: duplicate at full level or center at -3 dB [default: duplicate]")
+            .value_parser(parse_upmix),
+        Arg::new("pan_mode")
+            .long("pan-mode")
+            .value_name("MODE")
+            .help("How the pan moves stereo samples: balance turns the other channel down, true also moves it over, a pan like 0.5t or 0.5b chooses it for one event [default: balance]")
+            .value_parser(parse_pan_mode),
+        Arg::new("track")
+            .long("track")
+            .value_name("NUMBER")
+            .help("Audio track of files with several of them, counting from 1, a sample name like song.mkv#2 chooses it for one file [default: 1]")
+            .value_parser(parse_track),
+        Arg::new("from")
+            .long("from")
+            .value_name("TIME")
+            .help("Only render from this time on, in ms, with s or as mm:ss, samples triggered earlier still ring into it")
+            .value_parser(parse_time),
+        Arg::new("to")
+            .long("to")
+            .value_name("TIME")
+            .help("Only render until this time, in ms, with s or as mm:ss")
+            .value_parser(parse_time),
+        Arg::new("max_duration")
+            .long("max-duration")
+            .value_name("TIME")
+            .help("Longest mix that is rendered, events after it are reported as errors, 0 allows any length [default: 60:00]")
+            .value_parser(parse_time),
+        Arg::new("dedup_events")
+            .long("dedup-events")
+            .help("Leave out events with the same time, volume, pan and sample as an earlier one")
+            .action(ArgAction::SetTrue),
+        Arg::new("align_transients")
+            .long("align-transients")
+            .help("Start every event early enough that the first transient of its sample instead of the start of the file is on its time")
+            .action(ArgAction::SetTrue),
+        Arg::new("retrigger_fade")
+            .long("retrigger-fade")
+            .value_name("TIME")
+            .help("Crossfade a sample that is triggered again while it still plays over this time instead of summing both, 0 cuts it off")
+            .value_parser(parse_time),
+        Arg::new("auto_gain")
+            .long("auto-gain")
+            .help("Turn the whole mix down just enough that nothing clips instead of clamping the loud parts")
+            .action(ArgAction::SetTrue),
+        Arg::new("allow_ffmpeg")
+            .long("allow-ffmpeg")
+            .help("Decode samples in formats that are not supported, like WMA, with the ffmpeg binary on the PATH")
+            .action(ArgAction::SetTrue),
+        Arg::new("plugin")
+            .short('p')
+            .long("plugin")
+            .value_name("URI[,SYMBOL=VALUE...]")
+            .help("Run the mix through this LV2 plugin with lv2apply before it is clamped, with values for its control ports, can be repeated")
+            .value_parser(parse_plugin)
+            .action(ArgAction::Append),
+        Arg::new("backing")
+            .short('b')
+            .long("backing")
+            .value_name("AUDIO_FILE")
+            .help("Mix the events on top of this song, which is decoded like a sample and plays from 0"),
+        Arg::new("backing_gain")
+            .long("backing-gain")
+            .value_name("DB")
+            .help("Level of the backing track in dB [default: 0]")
+            .value_parser(value_parser!(f32))
+            .allow_negative_numbers(true)
+            .requires("backing"),
+        Arg::new("backing_offset")
+            .long("backing-offset")
+            .value_name("TIME")
+            .help("Start the backing track this much later, or earlier with a minus sign, in ms, with s or as mm:ss")
+            .value_parser(parse_offset)
+            .allow_hyphen_values(true)
+            .requires("backing"),
+        Arg::new("manifest")
+            .short('m')
+            .long("manifest")
+            .value_name("FILE")
+            .help("Check every sample against the SHA-256 checksums in this file, written by sha256sum, before mixing")
+            .value_parser(value_parser!(PathBuf)),
+        Arg::new("strict")
+            .long("strict")
+            .help("Fail instead of converting channels, resampling, skipping broken parts of samples or clipping")
+            .action(ArgAction::SetTrue),
+        Arg::new("extend_before_zero")
+            .long("extend-before-zero")
+            .help("Start the mix at the earliest event when it has a negative time instead of cutting off what plays before 0")
+            .action(ArgAction::SetTrue),
+        Arg::new("humanize")
+            .long("humanize")
+            .value_name("MS")
+            .help("Move every event by a random time of up to this many ms earlier or later")
+            .value_parser(value_parser!(f32)),
+        Arg::new("humanize_volume")
+            .long("humanize-volume")
+            .value_name("AMOUNT")
+            .help("Change the volume of every event randomly by up to this fraction, 0.1 is up to 10% louder or quieter")
+            .value_parser(value_parser!(f32)),
+        Arg::new("seed")
+            .long("seed")
+            .value_name("SEED")
+            .help("Seed for all random decisions, the same seed and inputs produce byte-identical output")
+            .value_parser(value_parser!(u64)),
+        Arg::new("on_missing")
+            .long("on-missing")
+            .value_name("POLICY")
+            .help("What to do with samples that do not exist: error, skip their events or treat them as silence [default: error]")
+            .value_parser(parse_missing_policy),
+        Arg::new("on_decode_error")
+            .long("on-decode-error")
+            .value_name("POLICY")
+            .help("What to do with samples that cannot be decoded: fail, skip their events or treat them as silence, skip and silence keep what was decoded before an error [default: fail]")
+            .value_parser(parse_decode_error_policy),
+        Arg::new("on_clip")
+            .long("on-clip")
+            .value_name("POLICY")
+            .help("What to do with a mix that goes over full scale: clamp it, soft clip it, normalize it down like --auto-gain or fail with the places [default: clamp]")
+            .value_parser(parse_clip_policy)
+            .conflicts_with("auto_gain"),
+        Arg::new("precision")
+            .long("precision")
+            .value_name("PRECISION")
+            .help("Sum the events up in f32 or in f64, which keeps quiet tails under thousands of events exact at twice the memory [default: f32]")
+            .value_parser(parse_precision),
+        Arg::new("normalize_samples")
+            .long("normalize-samples")
+            .value_name("LEVEL")
+            .allow_hyphen_values(true)
+            .help("Turn every sample up or down to a peak like -1dB or a loudness like -23LUFS before it is mixed")
+            .value_parser(parse_sample_normalization),
+    ]);
+    args
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse() {
+        Ok(rate) if (0.25..=4.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("invalid rate '{}', expected a factor from 0.25 to 4.0", value)),
+    }
+}
+
+// Extra columns of a CSV file, the first four hold the event itself.
+fn parse_column(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(column) if column > 4 => Ok(column),
+        _ => Err(format!(
+            "invalid column '{}', the first four columns are time, volume, pan and file",
+            value
+        )),
+    }
+}
+
+// A time like in parse_time that can be negative.
+pub fn parse_offset(value: &str) -> Result<f64, String> {
+    match value.strip_prefix('-') {
+        Some(time) => parse_time(time).map(|time| -time),
+        None => parse_time(value),
+    }
+}
+
+// Accepts milliseconds like 272000 or 272000ms, seconds like 272s or minutes and seconds like 4:32 or 4:32.5 and
+// returns milliseconds.
+pub fn parse_time(value: &str) -> Result<f64, String> {
+    let invalid = || {
+        format!(
+            "invalid time '{}', expected milliseconds, seconds with s or mm:ss",
+            value
+        )
+    };
+
+    let ms = match value.split_once(':') {
+        Some((minutes, seconds)) => {
+            let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+            let seconds: f64 = seconds.parse().map_err(|_| invalid())?;
+            if !(0.0..60.0).contains(&seconds) {
+                return Err(invalid());
+            }
+            (minutes as f64 * 60.0 + seconds) * 1000.0
+        }
+        None => match value.strip_suffix("ms") {
+            Some(ms) => ms.parse().map_err(|_| invalid())?,
+            None => match value.strip_suffix('s') {
+                Some(seconds) => seconds.parse::<f64>().map_err(|_| invalid())? * 1000.0,
+                None => value.parse().map_err(|_| invalid())?,
+            },
+        },
+    };
+
+    if !ms.is_finite() || ms < 0.0 {
+        return Err(invalid());
+    }
+    Ok(ms)
+}
+
+// A file size in bytes, with B, kB, MB or GB for powers of 1000 and KiB, MiB or GiB for powers of 1024.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "invalid size '{}', expected a number of bytes or one with kB, MB or GB",
+            value
+        )
+    };
+    let units = [
+        ("KiB", 1 << 10),
+        ("MiB", 1 << 20),
+        ("GiB", 1 << 30),
+        ("kB", 1000),
+        ("KB", 1000),
+        ("MB", 1000 * 1000),
+        ("GB", 1000 * 1000 * 1000),
+        ("B", 1),
+    ];
+    let (number, unit) = units
+        .iter()
+        .find_map(|(suffix, unit)| value.strip_suffix(suffix).map(|number| (number, *unit)))
+        .unwrap_or((value, 1));
+    let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+    if !number.is_finite() || number <= 0.0 {
+        return Err(invalid());
+    }
+    Ok((number * unit as f64) as u64)
+}
+
+pub fn parse_track(value: &str) -> Result<usize, String> {
+    match value.trim().parse() {
+        Ok(0) => Err("audio tracks are counted from 1".to_string()),
+        Ok(number) => Ok(number),
+        Err(_) => Err(format!("'{}' is not a track number", value)),
+    }
+}
+
+pub fn load_config(matches: &ArgMatches) -> Result<FileConfig, Box<dyn Error>> {
+    FileConfig::load(matches.get_one::<PathBuf>("config").map(PathBuf::as_path)).with_kind(ErrorKind::Arguments)
+}
+
+// Directories given on the command line are searched before the ones from the config file.
+pub fn sample_resolver(matches: &ArgMatches, config: &FileConfig) -> SampleResolver {
+    let mut samples_dirs: Vec<PathBuf> = matches
+        .get_many::<PathBuf>("samples_dir")
+        .map(|dirs| dirs.cloned().collect())
+        .unwrap_or_default();
+    samples_dirs.extend(config.samples_dirs.iter().cloned());
+
+    let mut resolver = SampleResolver::new(samples_dirs);
+    resolver.ignore_case = matches.get_flag("ignore_case") || config.ignore_case.unwrap_or(false);
+    resolver.any_extension = matches.get_flag("any_extension") || config.any_extension.unwrap_or(false);
+    resolver.recursive = matches.get_flag("recursive") || config.recursive.unwrap_or(false);
+    resolver.cache_dir = matches
+        .get_one::<PathBuf>("cache_dir")
+        .or(config.cache_dir.as_ref())
+        .cloned();
+    resolver
+}
+
+pub fn csv_options(matches: &ArgMatches, config: &FileConfig, sample_rate: u32) -> Result<CsvOptions, Box<dyn Error>> {
+    let range_policy = match matches.get_one::<RangePolicy>("range_policy") {
+        Some(policy) => *policy,
+        None => match &config.range_policy {
+            Some(name) => parse_range_policy(name).with_kind(ErrorKind::Arguments)?,
+            None => RangePolicy::Error,
+        },
+    };
+
+    let mut lanes = BTreeMap::new();
+    for (name, lane) in &config.lanes {
+        let offset = match &lane.offset {
+            Some(offset) => parse_offset(offset).map_err(|err| format!("lane {}: {}", name, err)),
+            None => Ok(0.0),
+        }
+        .with_kind(ErrorKind::Arguments)?;
+        let lane = Lane {
+            gain: lane.gain.unwrap_or(0.0),
+            offset,
+            mute: lane.mute.unwrap_or(false),
+        };
+        lanes.insert(name.clone(), lane);
+    }
+    let lane_column = matches.get_one::<usize>("lane_column").copied().or(config.lane_column);
+    let hold_column = matches.get_one::<usize>("hold_column").copied().or(config.hold_column);
+    let pitch_column = matches
+        .get_one::<usize>("pitch_column")
+        .copied()
+        .or(config.pitch_column);
+    let tag_column = matches.get_one::<usize>("tag_column").copied().or(config.tag_column);
+    for column in [lane_column, hold_column, pitch_column, tag_column].iter().flatten() {
+        parse_column(&column.to_string()).with_kind(ErrorKind::Arguments)?;
+    }
+    let values = |name: &str| {
+        matches
+            .get_many::<String>(name)
+            .map_or_else(Vec::new, |tags| tags.cloned().collect())
+    };
+    let mut root_notes = BTreeMap::new();
+    for (name, root) in &config.roots {
+        let root = parse_note(root)
+            .map_err(|err| format!("root note of {}: {}", name, err))
+            .with_kind(ErrorKind::Arguments)?;
+        root_notes.insert(name.clone(), root);
+    }
+    let offset = match matches.get_one::<f64>("offset") {
+        Some(offset) => *offset,
+        None => match &config.offset {
+            Some(offset) => parse_offset(offset).with_kind(ErrorKind::Arguments)?,
+            None => 0.0,
+        },
+    };
+    let mut sample_offsets = BTreeMap::new();
+    for (name, offset) in &config.offsets {
+        let offset = parse_offset(offset)
+            .map_err(|err| format!("offset of {}: {}", name, err))
+            .with_kind(ErrorKind::Arguments)?;
+        sample_offsets.insert(name.clone(), offset);
+    }
+
+    Ok(CsvOptions {
+        decimal_comma: matches.get_flag("decimal_comma") || config.decimal_comma.unwrap_or(false),
+        sample_rate,
+        volume_db: matches.get_flag("volume_db") || config.volume_db.unwrap_or(false),
+        skip_bad_rows: matches.get_flag("skip_bad_rows") || config.skip_bad_rows.unwrap_or(false),
+        range_policy,
+        difficulty: matches.get_one::<String>("difficulty").cloned(),
+        script: matches.get_one::<PathBuf>("script").or(config.script.as_ref()).cloned(),
+        lane_column,
+        hold_column,
+        pitch_column,
+        tag_column,
+        only_tags: values("only_tag"),
+        mute_tags: values("mute_tag"),
+        solo: values("solo"),
+        mute: values("mute"),
+        root_notes,
+        lanes,
+        sample_offsets,
+        rate: matches.get_one::<f64>("rate").copied().unwrap_or(1.0),
+        keep_pitch: matches.get_flag("keep_pitch"),
+        offset,
+    })
+}
+
+pub fn render_settings(matches: &ArgMatches, config: &FileConfig) -> Result<RenderSettings, Box<dyn Error>> {
+    let resampler = match matches.get_one::<ConverterType>("resampler") {
+        Some(resampler) => *resampler,
+        None => match &config.resampler {
+            Some(name) => parse_resampler(name).with_kind(ErrorKind::Arguments)?,
+            None => ConverterType::SincBestQuality,
+        },
+    };
+
+    let upmix = match matches.get_one::<UpmixLaw>("upmix") {
+        Some(upmix) => *upmix,
+        None => match &config.upmix {
+            Some(name) => parse_upmix(name).with_kind(ErrorKind::Arguments)?,
+            None => UpmixLaw::Duplicate,
+        },
+    };
+    let pan_mode = match matches.get_one::<PanMode>("pan_mode") {
+        Some(pan_mode) => *pan_mode,
+        None => match &config.pan_mode {
+            Some(name) => parse_pan_mode(name).with_kind(ErrorKind::Arguments)?,
+            None => PanMode::Balance,
+        },
+    };
+
+    // Nothing is time-stretched, the backing track would play at its own speed and drift apart from the events.
+    let rate = matches.get_one::<f64>("rate").copied().unwrap_or(1.0);
+    if matches.get_flag("keep_pitch") && rate != 1.0 && matches.get_one::<String>("backing").is_some() {
+        let message = "--keep-pitch cannot be used with --backing, the backing track is not stretched to the --rate";
+        return Err(AppError::new(ErrorKind::Arguments, message).into());
+    }
+
+    let on_missing = match matches.get_one::<MissingPolicy>("on_missing") {
+        Some(policy) => *policy,
+        None => match &config.on_missing {
+            Some(name) => parse_missing_policy(name).with_kind(ErrorKind::Arguments)?,
+            None => MissingPolicy::Error,
+        },
+    };
+
+    let on_clip = match matches.get_one::<ClipPolicy>("on_clip") {
+        Some(policy) => *policy,
+        None if matches.get_flag("auto_gain") => ClipPolicy::Normalize,
+        None => match &config.on_clip {
+            Some(name) => parse_clip_policy(name).with_kind(ErrorKind::Arguments)?,
+            None if config.auto_gain.unwrap_or(false) => ClipPolicy::Normalize,
+            None => ClipPolicy::Clamp,
+        },
+    };
+
+    let precision = match matches.get_one::<Precision>("precision") {
+        Some(precision) => *precision,
+        None => match &config.precision {
+            Some(name) => parse_precision(name).with_kind(ErrorKind::Arguments)?,
+            None => Precision::Single,
+        },
+    };
+
+    let normalize_samples = match matches.get_one::<SampleNormalization>("normalize_samples") {
+        Some(level) => Some(*level),
+        None => match &config.normalize_samples {
+            Some(level) => Some(parse_sample_normalization(level).with_kind(ErrorKind::Arguments)?),
+            None => None,
+        },
+    };
+
+    let on_decode_error = match matches.get_one::<DecodeErrorPolicy>("on_decode_error") {
+        Some(policy) => *policy,
+        None => match &config.on_decode_error {
+            Some(name) => parse_decode_error_policy(name).with_kind(ErrorKind::Arguments)?,
+            None => DecodeErrorPolicy::Fail,
+        },
+    };
+
+    // Zero can still come from the config file or the environment.
+    let track = matches.get_one::<usize>("track").copied().or(config.track);
+    if track == Some(0) {
+        return Err(AppError::new(ErrorKind::Arguments, "audio tracks are counted from 1").into());
+    }
+
+    let from = matches.get_one::<f64>("from").copied().unwrap_or(0.0);
+    let to = matches.get_one::<f64>("to").copied();
+    if to.is_some_and(|to| to <= from) {
+        return Err(AppError::new(ErrorKind::Arguments, "--to has to be after --from").into());
+    }
+
+    let max_duration = match matches.get_one::<f64>("max_duration") {
+        Some(max_duration) => *max_duration,
+        None => match &config.max_duration {
+            Some(time) => parse_time(time).with_kind(ErrorKind::Arguments)?,
+            None => DEFAULT_MAX_DURATION,
+        },
+    };
+
+    let retrigger_fade = match matches.get_one::<f64>("retrigger_fade") {
+        Some(fade) => Some(*fade),
+        None => match &config.retrigger_fade {
+            Some(time) => Some(parse_time(time).with_kind(ErrorKind::Arguments)?),
+            None => None,
+        },
+    };
+
+    let manifest = match matches.get_one::<PathBuf>("manifest").or(config.manifest.as_ref()) {
+        Some(path) => Some(Arc::new(Manifest::load(path)?)),
+        None => None,
+    };
+
+    // Plugins on the command line replace the ones of the config file.
+    let plugins = match matches.get_many::<Plugin>("plugin") {
+        Some(plugins) => plugins.cloned().collect(),
+        None => config
+            .plugins
+            .iter()
+            .map(|plugin| parse_plugin(plugin))
+            .collect::<Result<Vec<Plugin>, String>>()
+            .with_kind(ErrorKind::Arguments)?,
+    };
+
+    let threads = matches
+        .get_one::<usize>("threads")
+        .copied()
+        .or(config.threads)
+        .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
+
+    let sample_rate = matches
+        .get_one::<u32>("sample_rate")
+        .copied()
+        .or(config.sample_rate)
+        .unwrap_or(DEFAULT_SAMPLE_RATE);
+
+    Ok(RenderSettings {
+        compact: matches.get_flag("compact"),
+        sample_rate,
+        resampler,
+        upmix,
+        pan_mode,
+        track,
+        on_missing,
+        on_decode_error,
+        from,
+        to,
+        max_duration,
+        dedup_events: matches.get_flag("dedup_events") || config.dedup_events.unwrap_or(false),
+        align_transients: matches.get_flag("align_transients") || config.align_transients.unwrap_or(false),
+        retrigger_fade,
+        on_clip,
+        precision,
+        normalize_samples,
+        strict: matches.get_flag("strict") || config.strict.unwrap_or(false),
+        allow_ffmpeg: matches.get_flag("allow_ffmpeg") || config.allow_ffmpeg.unwrap_or(false),
+        plugins,
+        backing: matches.get_one::<String>("backing").map(|name| Backing {
+            name: name.clone(),
+            gain: 10f32.powf(matches.get_one::<f32>("backing_gain").copied().unwrap_or(0.0) / 20.0),
+            offset: matches.get_one::<f64>("backing_offset").copied().unwrap_or(0.0),
+        }),
+        click: None,
+        manifest,
+        extend_before_zero: matches.get_flag("extend_before_zero") || config.extend_before_zero.unwrap_or(false),
+        humanize_time: matches.get_one::<f32>("humanize").copied().unwrap_or(0.0).max(0.0),
+        humanize_volume: matches
+            .get_one::<f32>("humanize_volume")
+            .copied()
+            .unwrap_or(0.0)
+            .max(0.0),
+        seed: matches.get_one::<u64>("seed").copied(),
+        threads,
+        resolver: sample_resolver(matches, config),
+        csv: csv_options(matches, config, sample_rate)?,
+        progress: None,
+    })
+}
@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use log::info;
+
+use crate::cli;
+use crate::commands::preview::play;
+use crate::config::parse_quality;
+use crate::error::{AppError, ErrorKind, WithKind};
+use crate::events::{check_pan, check_pitch, check_volume, AudioSampleInfo};
+use crate::export::{export, Encoding};
+use crate::mix::{render, SampleCache};
+use crate::resample::convert;
+
+pub fn command() -> Command {
+    Command::new("audition")
+        .about("Decode and mix a single sample like in a CSV file and play it or write it to an .ogg file")
+        .arg(
+            Arg::new("file")
+                .value_name("SAMPLE_FILE")
+                .help("Sample to play, looked up like the file column of a CSV file")
+                .required(true),
+        )
+        .arg(
+            Arg::new("volume")
+                .long("volume")
+                .value_name("VOLUME")
+                .help("Volume factor from 0.0 to 1.0 [default: 1.0]")
+                .value_parser(parse_volume),
+        )
+        .arg(
+            Arg::new("pan")
+                .long("pan")
+                .value_name("PAN")
+                .help("Pan from -1.0 to 1.0 [default: 0.0]")
+                .value_parser(parse_pan)
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::new("pitch")
+                .long("pitch")
+                .value_name("SEMITONES")
+                .help("Play higher or lower by this many semitones, which also changes the length [default: 0]")
+                .value_parser(parse_pitch)
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("OUTPUT_OGG_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write the sample to an .ogg file instead of playing it"),
+        )
+        .arg(
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .help("Overwrite the output file if it already exists")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("quality")
+                .short('q')
+                .long("quality")
+                .value_name("OUTPUT_OGG_QUALITY")
+                .help("Vorbis encoding quality [default: 0.7]")
+                .value_parser(parse_quality),
+        )
+        .arg(cli::host_arg().conflicts_with("output"))
+        .args(cli::render_args())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let file_config = cli::load_config(matches)?;
+    let settings = cli::render_settings(matches, &file_config)?;
+    let output = matches.get_one::<PathBuf>("output");
+
+    if let Some(output) = output {
+        if !matches.get_flag("force") && output.exists() {
+            let message = format!("{} already exists, use --force to overwrite it", output.display());
+            return Err(AppError::new(ErrorKind::Output, message).into());
+        }
+    }
+
+    // A one row CSV file, so the sample goes through the same decoding, resampling and mixing as in a chart.
+    let event = AudioSampleInfo {
+        time: 0.0,
+        volume: matches.get_one::<f32>("volume").copied().unwrap_or(1.0),
+        pan: matches.get_one::<f32>("pan").copied().unwrap_or(0.0),
+        name: matches.get_one::<String>("file").unwrap().clone(),
+        line: 1,
+        lane: None,
+        hold: None,
+        pitch: None,
+        pan_mode: None,
+        tags: Vec::new(),
+    };
+    let (mut data, stats) = render(vec![event], &settings, &mut SampleCache::default())?;
+    info!(
+        "peak {:.3} ({:+.1} dBFS), {} clipped samples",
+        stats.peak,
+        20.0 * stats.peak.log10(),
+        stats.clipped
+    );
+
+    // Pitching plays the sample at another speed: it is resampled as if it had a higher or lower sample rate.
+    let pitch = matches.get_one::<f32>("pitch").copied().unwrap_or(0.0);
+    if pitch != 0.0 && !data.is_empty() {
+        let rate = (settings.sample_rate as f64 * 2f64.powf(pitch as f64 / 12.0)).round() as u32;
+        data = convert(rate.max(1), settings.sample_rate, 2, settings.resampler, &data).with_kind(ErrorKind::Decode)?;
+    }
+
+    match output {
+        Some(output) => {
+            let quality = matches
+                .get_one::<f32>("quality")
+                .copied()
+                .or(file_config.quality)
+                .unwrap_or(0.7);
+            export(
+                &data,
+                output,
+                Encoding::Quality(quality),
+                settings.sample_rate,
+                settings.seed.map(|seed| seed as u32),
+            )
+        }
+        None => play(
+            data,
+            settings.sample_rate,
+            matches.get_one::<String>("host").map(String::as_str),
+        ),
+    }
+}
+
+fn parse_volume(value: &str) -> Result<f32, String> {
+    parse_number(value).and_then(check_volume)
+}
+
+fn parse_pan(value: &str) -> Result<f32, String> {
+    parse_number(value).and_then(check_pan)
+}
+
+fn parse_pitch(value: &str) -> Result<f32, String> {
+    parse_number(value).and_then(check_pitch)
+}
+
+fn parse_number(value: &str) -> Result<f32, String> {
+    value.trim().parse().map_err(|_| format!("'{}' is not a number", value))
+}
@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::cli;
+use crate::commands::mix::{mix_file, ExtraOutputs};
+use crate::config::{check_quality, check_sample_rate, parse_missing_policy, parse_quality};
+use crate::error::{kind_of, AppError, ErrorKind, WithKind};
+use crate::export::Encoding;
+use crate::mix::{RenderSettings, SampleCache};
+use crate::summary::{write_summary, BatchSummary, JobFailure, Summary};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobsFile {
+    jobs: Vec<Job>,
+}
+
+// One entry of the jobs file. Unset values fall back to the command line, the config file and the defaults.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Job {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    quality: Option<f32>,
+    sample_rate: Option<u32>,
+    on_missing: Option<String>,
+    #[serde(default)]
+    samples_dirs: Vec<PathBuf>,
+}
+
+pub fn command() -> Command {
+    Command::new("batch")
+        .about("Mix every job of a jobs file, decoding samples shared between the jobs only once")
+        .arg(
+            Arg::new("jobs")
+                .long("jobs")
+                .value_name("JOBS_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("TOML file with one [[jobs]] table per CSV file to mix")
+                .required(true),
+        )
+        .arg(
+            Arg::new("quality")
+                .short('q')
+                .long("quality")
+                .value_name("OUTPUT_OGG_QUALITY")
+                .help("Vorbis encoding quality of jobs without their own [default: 0.7]")
+                .value_parser(parse_quality),
+        )
+        .arg(
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .help("Overwrite output files that already exist")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("json_summary")
+                .long("json-summary")
+                .value_name("JSON_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write the summaries of all jobs and the failed jobs to a JSON file"),
+        )
+        .args(cli::render_args())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+
+    let jobs_file = matches.get_one::<PathBuf>("jobs").unwrap();
+    let file_config = cli::load_config(matches)?;
+    let settings = cli::render_settings(matches, &file_config)?;
+    let quality = matches
+        .get_one::<f32>("quality")
+        .copied()
+        .or(file_config.quality)
+        .unwrap_or(0.7);
+    let force = matches.get_flag("force");
+
+    let jobs = read_jobs(Path::new(jobs_file))?;
+
+    let mut cache = SampleCache::default();
+    let mut summaries = Vec::new();
+    let mut failures = Vec::new();
+    let mut first_failure = None;
+
+    for (i, job) in jobs.iter().enumerate() {
+        info!("job {} of {}: {}", i + 1, jobs.len(), job.input.display());
+
+        match run_job(job, quality, force, &settings, &mut cache) {
+            Ok(summary) => summaries.push(summary),
+            Err(err) => {
+                error!("{}: {}", job.input.display(), err);
+                let kind = kind_of(err.as_ref());
+                first_failure.get_or_insert(kind);
+                failures.push(JobFailure {
+                    input: job.input.display().to_string(),
+                    code: kind.map(ErrorKind::exit_code).unwrap_or(1),
+                    message: err.to_string(),
+                });
+            }
+        }
+    }
+
+    info!(
+        "{} of {} jobs mixed in {:.1} s, {} samples decoded, {} decodes saved",
+        summaries.len(),
+        jobs.len(),
+        started.elapsed().as_secs_f32(),
+        cache.len(),
+        cache.hits
+    );
+
+    if let Some(path) = matches.get_one::<PathBuf>("json_summary") {
+        let summary = BatchSummary {
+            jobs: summaries,
+            failures,
+            decoded_samples: cache.len(),
+            reused_samples: cache.hits,
+            total: started.elapsed().as_secs_f32(),
+        };
+        write_summary(path, &summary).with_kind(ErrorKind::Output)?;
+    }
+
+    // The exit code is the one of the first failed job.
+    let message = "not all jobs could be mixed";
+    match first_failure {
+        Some(Some(kind)) => Err(AppError::new(kind, message).into()),
+        Some(None) => Err(message.into()),
+        None => Ok(()),
+    }
+}
+
+// Paths in the jobs file are relative to the jobs file.
+fn read_jobs(path: &Path) -> Result<Vec<Job>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| AppError::new(ErrorKind::Input, format!("could not read {}: {}", path.display(), err)))?;
+    let jobs_file: JobsFile = toml::from_str(&text)
+        .map_err(|err| AppError::new(ErrorKind::Input, format!("could not parse {}: {}", path.display(), err)))?;
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut jobs = jobs_file.jobs;
+    for job in jobs.iter_mut() {
+        // Checked before the first job runs, so a typo does not fail a long batch halfway through.
+        if let Some(quality) = job.quality {
+            check_quality(quality).map_err(|err| {
+                AppError::new(
+                    ErrorKind::Input,
+                    format!("{}: job {}: {}", path.display(), job.input.display(), err),
+                )
+            })?;
+        }
+        if let Some(sample_rate) = job.sample_rate {
+            check_sample_rate(sample_rate).map_err(|err| {
+                AppError::new(
+                    ErrorKind::Input,
+                    format!("{}: job {}: {}", path.display(), job.input.display(), err),
+                )
+            })?;
+        }
+        job.input = base.join(&job.input);
+        if let Some(output) = job.output.as_mut() {
+            *output = base.join(&output);
+        }
+        for dir in job.samples_dirs.iter_mut() {
+            *dir = base.join(&dir);
+        }
+    }
+
+    Ok(jobs)
+}
+
+fn run_job(
+    job: &Job,
+    quality: f32,
+    force: bool,
+    settings: &RenderSettings,
+    cache: &mut SampleCache,
+) -> Result<Summary, Box<dyn Error>> {
+    let output = job.output.clone().unwrap_or_else(|| job.input.with_extension("ogg"));
+    if !force && output.exists() {
+        let message = format!("{} already exists, use --force to overwrite it", output.display());
+        return Err(AppError::new(ErrorKind::Output, message).into());
+    }
+
+    let mut settings = settings.clone();
+    if let Some(sample_rate) = job.sample_rate {
+        settings.sample_rate = sample_rate;
+        settings.csv.sample_rate = sample_rate;
+    }
+    if let Some(name) = &job.on_missing {
+        settings.on_missing = parse_missing_policy(name).with_kind(ErrorKind::Input)?;
+    }
+    if !job.samples_dirs.is_empty() {
+        let mut samples_dirs = job.samples_dirs.clone();
+        samples_dirs.extend(settings.resolver.samples_dirs.iter().cloned());
+        settings.resolver = settings.resolver.with_samples_dirs(samples_dirs);
+    }
+
+    mix_file(
+        &job.input,
+        &output,
+        Encoding::Quality(job.quality.unwrap_or(quality)),
+        &settings,
+        cache,
+        &ExtraOutputs::default(),
+        None,
+    )
+}
@@ -0,0 +1,234 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use log::{info, warn};
+
+use crate::cli;
+use crate::decode::read_audio;
+use crate::error::{AppError, ErrorKind};
+use crate::events::read_events;
+use crate::mix::{render, RenderSettings, SampleCache};
+use crate::report::format_time;
+
+// Only the start of the files is compared to find the offset between them, in s.
+const ALIGN_WINDOW: usize = 30;
+// Frames averaged for the coarse search of the offset.
+const COARSE_STEP: usize = 16;
+// Levels below this are printed as silence.
+const FLOOR_DB: f32 = -200.0;
+
+pub fn command() -> Command {
+    Command::new("diff")
+        .about("Subtract two mixes from each other and report what is left per second")
+        .arg(
+            Arg::new("a")
+                .value_name("FILE_A")
+                .help("Audio file, or CSV file or beatmap that is mixed first")
+                .value_parser(value_parser!(PathBuf))
+                .required(true),
+        )
+        .arg(
+            Arg::new("b")
+                .value_name("FILE_B")
+                .help("Audio file, or CSV file or beatmap that is mixed first, to subtract from the first one")
+                .value_parser(value_parser!(PathBuf))
+                .required(true),
+        )
+        .arg(
+            Arg::new("threshold")
+                .long("threshold")
+                .value_name("DBFS")
+                .help("Report seconds where the peak of the difference is above this level [default: -60]")
+                .value_parser(value_parser!(f32))
+                .allow_negative_numbers(true),
+        )
+        .arg(
+            Arg::new("max_offset")
+                .long("max-offset")
+                .value_name("TIME")
+                .help("Largest offset between the files that is searched for, in ms or with s [default: 500]")
+                .value_parser(cli::parse_time),
+        )
+        .arg(
+            Arg::new("no_align")
+                .long("no-align")
+                .help("Compare the files as they are instead of searching for the offset between them")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("max_offset"),
+        )
+        .args(cli::render_args())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let settings = cli::render_settings(matches, &cli::load_config(matches)?)?;
+    let threshold = matches.get_one::<f32>("threshold").copied().unwrap_or(-60.0);
+    let max_offset = matches.get_one::<f64>("max_offset").copied().unwrap_or(500.0);
+    let rate = settings.sample_rate;
+
+    let a = load(matches.get_one::<PathBuf>("a").unwrap(), &settings)?;
+    let b = load(matches.get_one::<PathBuf>("b").unwrap(), &settings)?;
+    if a.len() != b.len() {
+        warn!(
+            "the files are {:.3} s and {:.3} s long, the rest of the shorter one counts as silence",
+            a.len() as f32 / 2.0 / rate as f32,
+            b.len() as f32 / 2.0 / rate as f32
+        );
+    }
+
+    // Positive offsets mean b is late: frame i of a lines up with frame i + offset of b.
+    let offset = if matches.get_flag("no_align") {
+        0
+    } else {
+        let offset = find_offset(&a, &b, (max_offset / 1000.0 * rate as f64).round() as usize, rate);
+        info!("b is {:+.3} ms later than a", offset as f64 * 1000.0 / rate as f64);
+        offset
+    };
+
+    let frame_of = |data: &[f32], frame: i64, channel: usize| {
+        usize::try_from(frame)
+            .ok()
+            .and_then(|frame| data.get(frame * 2 + channel))
+            .copied()
+            .unwrap_or(0.0)
+    };
+    let frames = (a.len() / 2).max((b.len() / 2).saturating_add_signed(-offset as isize));
+
+    let mut total_square = 0.0_f64;
+    let mut total_peak = 0.0_f32;
+    let mut loud_seconds = Vec::new();
+    for second in 0..frames.div_ceil(rate as usize) {
+        let start = second * rate as usize;
+        let end = (start + rate as usize).min(frames);
+        let mut square = 0.0_f64;
+        let mut peak = 0.0_f32;
+        for frame in start..end {
+            for channel in 0..2 {
+                let difference = frame_of(&a, frame as i64, channel) - frame_of(&b, frame as i64 + offset, channel);
+                square += (difference as f64).powi(2);
+                peak = peak.max(difference.abs());
+            }
+        }
+        total_square += square;
+        total_peak = total_peak.max(peak);
+
+        let rms = (square / ((end - start) * 2) as f64).sqrt() as f32;
+        if to_db(peak) > threshold {
+            loud_seconds.push((second, rms, peak));
+        }
+    }
+
+    let rms = (total_square / (frames.max(1) * 2) as f64).sqrt() as f32;
+    info!(
+        "difference: RMS {:.1} dBFS, peak {:.1} dBFS over {}",
+        to_db(rms),
+        to_db(total_peak),
+        format_time(frames as f64 * 1000.0 / rate as f64)
+    );
+
+    if loud_seconds.is_empty() {
+        info!("the difference stays below {} dBFS", threshold);
+        return Ok(());
+    }
+    println!("    second  rms dBFS  peak dBFS");
+    for (second, rms, peak) in &loud_seconds {
+        println!(
+            "{:>10}  {:>8.1}  {:>9.1}",
+            format_time(*second as f64 * 1000.0),
+            to_db(*rms),
+            to_db(*peak)
+        );
+    }
+    let message = format!(
+        "the difference is above {} dBFS in {} of {} seconds",
+        threshold,
+        loud_seconds.len(),
+        frames.div_ceil(rate as usize)
+    );
+    Err(AppError::new(ErrorKind::Validation, message).into())
+}
+
+// Event files are mixed with the render options, audio files are decoded at the sample rate of the mix.
+fn load(path: &Path, settings: &RenderSettings) -> Result<Vec<f32>, Box<dyn Error>> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    if ["csv", "osu", "osz", "mod", "mmp", "xrns"]
+        .iter()
+        .any(|events| extension.eq_ignore_ascii_case(events))
+    {
+        let infos = read_events(path, &settings.csv)?;
+        return Ok(render(infos, settings, &mut SampleCache::default())?.0);
+    }
+
+    read_audio(
+        path,
+        settings.sample_rate,
+        settings.resampler,
+        settings.upmix,
+        settings.track,
+        false,
+        settings.strict,
+    )
+    .map_err(|err| {
+        AppError::new(
+            ErrorKind::Decode,
+            format!("could not decode {}: {}", path.display(), err),
+        )
+        .into()
+    })
+}
+
+// The offset in frames up to `max` either way where the start of both files matches best. It is searched on
+// averages of COARSE_STEP frames first and then refined around the best one.
+fn find_offset(a: &[f32], b: &[f32], max: usize, rate: u32) -> i64 {
+    let window = ALIGN_WINDOW * rate as usize;
+    let mono = |data: &[f32]| -> Vec<f32> {
+        data.chunks_exact(2)
+            .take(window + max)
+            .map(|frame| frame[0] + frame[1])
+            .collect()
+    };
+    let (a, b) = (mono(a), mono(b));
+
+    let average = |data: &[f32]| -> Vec<f32> {
+        data.chunks(COARSE_STEP)
+            .map(|values| values.iter().sum::<f32>() / values.len() as f32)
+            .collect()
+    };
+    let (coarse_a, coarse_b) = (average(&a), average(&b));
+    let coarse_max = (max / COARSE_STEP) as i64;
+    let coarse = best_lag(&coarse_a, &coarse_b, -coarse_max..=coarse_max, window / COARSE_STEP);
+
+    let around = coarse * COARSE_STEP as i64;
+    let step = COARSE_STEP as i64;
+    let range = (around - step).max(-(max as i64))..=(around + step).min(max as i64);
+    best_lag(&a, &b, range, window)
+}
+
+// The lag with the largest correlation over the first `window` values of a.
+fn best_lag(a: &[f32], b: &[f32], lags: std::ops::RangeInclusive<i64>, window: usize) -> i64 {
+    let mut best: (i64, f64) = (0, f64::MIN);
+    for lag in lags {
+        let correlation: f64 = a
+            .iter()
+            .take(window)
+            .enumerate()
+            .filter_map(|(i, value)| {
+                let j = usize::try_from(i as i64 + lag).ok()?;
+                Some(*value as f64 * *b.get(j)? as f64)
+            })
+            .sum();
+        // Ties go to the smaller offset, so identical silence does not move anything.
+        if correlation > best.1 || (correlation == best.1 && lag.abs() < best.0.abs()) {
+            best = (lag, correlation);
+        }
+    }
+    best.0
+}
+
+fn to_db(value: f32) -> f32 {
+    if value > 0.0 {
+        20.0 * value.log10()
+    } else {
+        FLOOR_DB
+    }
+}
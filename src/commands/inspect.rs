@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use log::warn;
+
+use crate::cli;
+use crate::decode::probe_audio;
+use crate::download::is_url;
+use crate::error::{AppError, ErrorKind};
+use crate::events::{read_events, trigger_counts, AudioSampleInfo};
+use crate::report::format_time;
+use crate::resolve::{list_files, sample_path, split_track, SampleResolver};
+use crate::synth::parse_synth;
+use crate::timeline::{bars, density};
+
+const NAME_WIDTH: usize = 24;
+
+pub fn command() -> Command {
+    Command::new("inspect")
+        .about("Summarize the events and samples of a CSV file")
+        .arg(cli::input_arg())
+        .args(cli::csv_args())
+        .arg(
+            Arg::new("timeline")
+                .long("timeline")
+                .help("Show how many events every sample has over time and where most events start at once")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("width")
+                .long("width")
+                .value_name("COLUMNS")
+                .help("Number of columns of the timeline [default: 64]")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("at")
+                .long("at")
+                .value_name("TIME")
+                .help("Only list the events that are sounding at this time, in ms, with s or as mm:ss")
+                .value_parser(cli::parse_time),
+        )
+        .arg(
+            Arg::new("unused")
+                .long("unused")
+                .value_name("SAMPLES_DIR")
+                .help("List the audio files below this directory that no event plays and the samples that have no file")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["at", "timeline"]),
+        )
+        .args(cli::resolve_args())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+
+    let config = cli::load_config(matches)?;
+
+    let infos = read_events(
+        input,
+        &cli::csv_options(matches, &config, config.sample_rate.unwrap_or(cli::DEFAULT_SAMPLE_RATE))?,
+    )?;
+
+    if let Some(at) = matches.get_one::<f64>("at") {
+        print_sounding(&infos, *at, &cli::sample_resolver(matches, &config));
+        return Ok(());
+    }
+
+    if let Some(dir) = matches.get_one::<PathBuf>("unused") {
+        return print_unused(&infos, dir, &cli::sample_resolver(matches, &config));
+    }
+
+    let counts = trigger_counts(&infos);
+
+    let first = infos.iter().map(|info| info.time).fold(f64::INFINITY, f64::min);
+    let last = infos.iter().map(|info| info.time).fold(f64::NEG_INFINITY, f64::max);
+
+    println!("Events: {}", infos.len());
+    println!("Samples: {}", counts.len());
+    if !infos.is_empty() {
+        println!("First event: {} ms", first);
+        println!("Last event: {} ms", last);
+    }
+
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    for (name, count) in counts {
+        println!("{:>8}  {}", count, name);
+    }
+
+    if matches.get_flag("timeline") && !infos.is_empty() {
+        let width = matches.get_one::<usize>("width").copied().unwrap_or(64).max(1);
+        print_timeline(&infos, first, last, width);
+    }
+
+    Ok(())
+}
+
+// Lists the events whose sample is still playing at `at`, which needs the length of every sample.
+fn print_sounding(infos: &[AudioSampleInfo], at: f64, resolver: &SampleResolver) {
+    let mut durations: HashMap<&str, Option<f64>> = HashMap::new();
+    let mut unknown = Vec::new();
+
+    println!("Events sounding at {}:", format_time(at));
+    println!(
+        "{:>8}  {:>10}  {:>6}  {:>5}  {:>9}  sample",
+        "line", "time", "volume", "pan", "remaining"
+    );
+    for info in infos.iter().filter(|info| info.time <= at) {
+        let duration = *durations.entry(&info.name).or_insert_with(|| {
+            let duration = match parse_synth(&info.name) {
+                Some(synth) => synth.ok().map(|synth| synth.duration()),
+                None => probe_audio(&resolver.resolve(&info.name), split_track(&info.name).1)
+                    .ok()
+                    .map(|audio| audio.duration()),
+            };
+            if duration.is_none() {
+                unknown.push(info.name.as_str());
+            }
+            duration.map(|duration| duration as f64 * 1000.0)
+        });
+
+        let Some(duration) = duration else {
+            continue;
+        };
+        let remaining = info.time + info.hold.unwrap_or(duration) - at;
+        if remaining > 0.0 {
+            println!(
+                "{:>8}  {:>10}  {:>6}  {:>5}  {:>7.3} s  {}",
+                info.line,
+                format_time(info.time),
+                info.volume,
+                info.pan,
+                remaining / 1000.0,
+                info.name
+            );
+        }
+    }
+
+    if !unknown.is_empty() {
+        warn!(
+            "the length of {} samples is unknown, they are missing or cannot be decoded: {}",
+            unknown.len(),
+            unknown.join(", ")
+        );
+    }
+}
+
+// Compares the files below `dir` with the samples of the events, which are looked up in it before anywhere else, to
+// find what a pack can do without and what it lacks.
+fn print_unused(infos: &[AudioSampleInfo], dir: &Path, resolver: &SampleResolver) -> Result<(), Box<dyn Error>> {
+    if !dir.is_dir() {
+        let message = format!("{} is not a directory", dir.display());
+        return Err(AppError::new(ErrorKind::Input, message).into());
+    }
+    let mut dirs = vec![dir.to_path_buf()];
+    dirs.extend(resolver.samples_dirs.iter().cloned());
+    let resolver = resolver.with_samples_dirs(dirs);
+
+    let mut used = HashSet::new();
+    let mut missing = Vec::new();
+    for (name, count) in trigger_counts(infos) {
+        // Generated samples have no file, downloaded ones are not in the pack.
+        if parse_synth(&name).is_some() || is_url(split_track(&name).0) {
+            continue;
+        }
+        let in_dir = dir.join(sample_path(split_track(&name).0));
+        let path = if in_dir.is_file() {
+            in_dir
+        } else {
+            resolver.resolve(&name)
+        };
+        match fs::canonicalize(path) {
+            Ok(path) if path.is_file() => {
+                used.insert(path);
+            }
+            _ => missing.push((name, count)),
+        }
+    }
+
+    // Only audio files count, a pack can also have a readme or a license.
+    let unused: Vec<PathBuf> = list_files(&[dir.to_path_buf()])
+        .into_iter()
+        .filter(|file| fs::canonicalize(file).map_or(true, |path| !used.contains(&path)))
+        .filter(|file| probe_audio(file, None).is_ok())
+        .collect();
+
+    println!("Unused files: {}", unused.len());
+    for file in &unused {
+        println!("  {}", file.strip_prefix(dir).unwrap_or(file).display());
+    }
+    println!("Samples without a file: {}", missing.len());
+    for (name, count) in &missing {
+        println!("{:>8}  {}", count, name);
+    }
+    Ok(())
+}
+
+// One row per sample, all scaled to the busiest column of any sample, below the events of all samples together.
+fn print_timeline(infos: &[AudioSampleInfo], first: f64, last: f64, width: usize) {
+    let start = first.min(0.0);
+    let end = last.max(start + 1.0);
+
+    let mut times: BTreeMap<&str, Vec<f64>> = BTreeMap::new();
+    for info in infos {
+        times.entry(&info.name).or_default().push(info.time);
+    }
+
+    let all: Vec<f64> = infos.iter().map(|info| info.time).collect();
+    let total = density(&all, start, end, width);
+    let rows: Vec<(&str, Vec<usize>)> = times
+        .iter()
+        .map(|(name, times)| (*name, density(times, start, end, width)))
+        .collect();
+    let max = rows
+        .iter()
+        .flat_map(|(_, counts)| counts.iter().copied())
+        .max()
+        .unwrap_or(0);
+
+    println!();
+    println!(
+        "Timeline from {} to {}, {:.0} ms per column",
+        format_time(start),
+        format_time(end),
+        (end - start) / width as f64
+    );
+    println!(
+        "{:<NAME_WIDTH$} |{}| up to {}",
+        "all samples",
+        bars(&total, total.iter().copied().max().unwrap_or(0)),
+        total.iter().max().unwrap_or(&0)
+    );
+    for (name, counts) in rows {
+        println!("{:<NAME_WIDTH$} |{}|", shorten(name, NAME_WIDTH), bars(&counts, max));
+    }
+    println!("{:<NAME_WIDTH$}  full bar: {} events per column", "", max);
+
+    // Events at exactly the same time are the ones most likely to clip.
+    let mut stacks: BTreeMap<u64, usize> = BTreeMap::new();
+    for info in infos {
+        *stacks.entry(info.time.to_bits()).or_insert(0) += 1;
+    }
+    if let Some((time, count)) = stacks.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))) {
+        println!("Most events at once: {} at {} ms", count, f64::from_bits(*time));
+    }
+}
+
+// Keeps the end of long names, which usually tells samples apart.
+fn shorten(name: &str, width: usize) -> String {
+    let count = name.chars().count();
+    if count <= width {
+        return name.to_string();
+    }
+    let end: String = name.chars().skip(count - width + 1).collect();
+    format!("…{}", end)
+}
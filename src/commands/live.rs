@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufRead};
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{BufferSize, SampleRate, StreamConfig};
+use log::{debug, error, info, warn};
+
+use crate::cli;
+use crate::commands::preview::output_device;
+use crate::error::{AppError, ErrorKind, WithKind};
+use crate::events::{check_pan, check_volume, read_events, read_events_from, AudioSampleInfo};
+use crate::mix::{gains, render, ClipPolicy, Gains, PanMode, RenderSettings, SampleCache};
+
+// Largest OSC packet that is read, more than any /play message needs.
+const MAX_PACKET: usize = 8192;
+
+// How often the end of the voices is checked for once stdin is closed.
+const END_POLL: Duration = Duration::from_millis(10);
+
+// A sample that is playing, with the frame it has reached. It starts at the frame `start` of the audio stream, or
+// right away once that has passed.
+struct Voice {
+    data: Arc<Vec<f32>>,
+    start: u64,
+    frame: usize,
+    gains: Gains,
+}
+
+// When an event row from stdin is played: at a time since live started, some time after the row arrived or as
+// soon as it did.
+#[derive(Clone, Copy)]
+enum Start {
+    At(f64),
+    After(f64),
+    Now,
+}
+
+// An argument of an OSC message, only the types a /play message uses.
+#[derive(Debug)]
+enum OscArgument {
+    Number(f32),
+    Text(String),
+}
+
+pub fn command() -> Command {
+    Command::new("live")
+        .about("Play samples through the audio device as /play messages arrive over OSC, or event rows on stdin")
+        .arg(
+            Arg::new("input")
+                .short('i')
+                .long("input")
+                .value_name("INPUT_CSV_FILE")
+                .help("Decode the samples of this CSV file before listening, so their first /play has no delay")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("listen")
+                .short('l')
+                .long("listen")
+                .value_name("ADDRESS")
+                .help("Address and UDP port to receive OSC messages on")
+                .default_value("127.0.0.1:9000"),
+        )
+        .arg(
+            Arg::new("stdin")
+                .long("stdin")
+                .help("Read events from stdin instead of OSC, CSV rows whose time is ms since the start, +ms from now or now")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("buffer")
+                .long("buffer")
+                .value_name("FRAMES")
+                .help("Frames the audio device asks for at once, fewer have less latency [default: that of the device]")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(cli::host_arg())
+        .args(cli::render_args())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let settings = cli::render_settings(matches, &cli::load_config(matches)?)?;
+    if !settings.plugins.is_empty() || settings.backing.is_some() {
+        let message = "plugins and backing tracks belong to a whole mix and cannot be used with live";
+        return Err(AppError::new(ErrorKind::Arguments, message).into());
+    }
+    // Every sample is decoded on its own, it has to start right away and be left as it is.
+    let settings = RenderSettings {
+        from: 0.0,
+        to: None,
+        on_clip: ClipPolicy::Clamp,
+        backing: None,
+        humanize_time: 0.0,
+        humanize_volume: 0.0,
+        ..settings
+    };
+    let listen = matches.get_one::<String>("listen").unwrap();
+    let host = matches.get_one::<String>("host").map(String::as_str);
+
+    let mut cache = SampleCache::default();
+    let mut samples: HashMap<String, Arc<Vec<f32>>> = HashMap::new();
+    if let Some(input) = matches.get_one::<PathBuf>("input") {
+        let mut names: Vec<String> = read_events(input, &settings.csv)?
+            .into_iter()
+            .map(|info| info.name)
+            .collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            let data = load(&name, &settings, &mut cache)?;
+            samples.insert(name, data);
+        }
+        info!("decoded {} samples of {}", samples.len(), input.display());
+    }
+
+    let socket = if matches.get_flag("stdin") {
+        None
+    } else {
+        Some(UdpSocket::bind(listen).with_kind(ErrorKind::Arguments)?)
+    };
+    let buffer_size = match matches.get_one::<u32>("buffer") {
+        Some(0) => return Err(AppError::new(ErrorKind::Arguments, "--buffer needs at least one frame").into()),
+        Some(frames) => BufferSize::Fixed(*frames),
+        None => BufferSize::Default,
+    };
+    let (sender, receiver) = channel();
+    // Frames the audio stream has played, the clock of the events from stdin.
+    let clock = Arc::new(AtomicU64::new(0));
+    let stream = output_device(host)?.build_output_stream(
+        &StreamConfig {
+            channels: 2,
+            sample_rate: SampleRate(settings.sample_rate),
+            buffer_size,
+        },
+        voice_callback(receiver, clock.clone()),
+        |err| error!("audio stream error: {}", err),
+        None,
+    )?;
+    stream.play()?;
+
+    match socket {
+        Some(socket) => listen_osc(&socket, &sender, &mut samples, &settings, &mut cache),
+        None => read_stdin(&sender, &clock, &mut samples, &settings, &mut cache),
+    }
+}
+
+fn listen_osc(
+    socket: &UdpSocket,
+    sender: &Sender<Voice>,
+    samples: &mut HashMap<String, Arc<Vec<f32>>>,
+    settings: &RenderSettings,
+    cache: &mut SampleCache,
+) -> Result<(), Box<dyn Error>> {
+    info!(
+        "listening for /play <sample> [volume] [pan] on udp://{}",
+        socket.local_addr()?
+    );
+
+    let mut buffer = [0; MAX_PACKET];
+    loop {
+        let (size, from) = socket.recv_from(&mut buffer)?;
+        let mut messages = Vec::new();
+        if let Err(err) = parse_packet(&buffer[..size], &mut messages) {
+            warn!("ignoring a broken OSC packet from {}: {}", from, err);
+            continue;
+        }
+
+        for (address, arguments) in messages {
+            if address != "/play" {
+                debug!("ignoring {} from {}", address, from);
+                continue;
+            }
+            let voice = match play_message(&arguments) {
+                Ok(event) => voice(event, 0, samples, settings, cache),
+                Err(err) => Err(err.into()),
+            };
+            match voice {
+                // Only fails once the stream is gone, which the error callback reports.
+                Ok(voice) => sender.send(voice)?,
+                Err(err) => warn!("/play {:?}: {}", arguments, err),
+            }
+        }
+    }
+}
+
+// Plays the rows of stdin until it is closed and then until the last voice has ended. Their times are on the clock
+// of the audio stream, so rows that arrive together keep their distance however long decoding takes.
+fn read_stdin(
+    sender: &Sender<Voice>,
+    clock: &AtomicU64,
+    samples: &mut HashMap<String, Arc<Vec<f32>>>,
+    settings: &RenderSettings,
+    cache: &mut SampleCache,
+) -> Result<(), Box<dyn Error>> {
+    info!("playing the event rows of stdin, with times in ms since the start, +ms from now or now");
+    let frames = |ms: f64| (ms.max(0.0) * settings.sample_rate as f64 / 1000.0).round() as u64;
+    let mut end = 0;
+    for line in io::stdin().lock().lines() {
+        let line = line.with_kind(ErrorKind::Input)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let events = match parse_row(&line, settings) {
+            Ok(events) => events,
+            Err(err) => {
+                warn!("{}: {}", line.trim(), err);
+                continue;
+            }
+        };
+        for (start, event) in events {
+            let now = clock.load(Ordering::Relaxed);
+            let start = match start {
+                Start::At(ms) => {
+                    let start = frames(ms);
+                    if start < now {
+                        let late = (now - start) as f64 * 1000.0 / settings.sample_rate as f64;
+                        warn!("{} arrived {:.0} ms after its time and plays now", event.name, late);
+                    }
+                    start.max(now)
+                }
+                Start::After(ms) => now + frames(ms),
+                Start::Now => now,
+            };
+            let name = event.name.clone();
+            match voice(event, start, samples, settings, cache) {
+                Ok(voice) => {
+                    end = end.max(start + (voice.data.len() / 2) as u64);
+                    sender.send(voice)?;
+                }
+                Err(err) => warn!("{}: {}", name, err),
+            }
+        }
+    }
+    while clock.load(Ordering::Relaxed) < end {
+        thread::sleep(END_POLL);
+    }
+    Ok(())
+}
+
+// The events of a CSV row with a time like in a CSV file, +time for a time after now or now.
+fn parse_row(line: &str, settings: &RenderSettings) -> Result<Vec<(Start, AudioSampleInfo)>, Box<dyn Error>> {
+    let (time, rest) = match line.find([',', ';', '\t']) {
+        Some(position) => line.split_at(position),
+        None => (line, ""),
+    };
+    let (start, time): (fn(f64) -> Start, &str) = match time.trim() {
+        "now" => (|_| Start::Now, "0"),
+        time => match time.strip_prefix('+') {
+            Some(after) => (Start::After, after),
+            None => (Start::At, time),
+        },
+    };
+    let events = read_events_from(format!("{}{}", time, rest).as_bytes(), &settings.csv)?;
+    Ok(events.into_iter().map(|event| (start(event.time), event)).collect())
+}
+
+// The samples that are playing get new voices from the receiver and are summed up in the audio callback, so a
+// message never waits for the device and the device never waits for a lock. The callback also moves the clock on by
+// the frames it has played.
+fn voice_callback(
+    receiver: Receiver<Voice>,
+    clock: Arc<AtomicU64>,
+) -> impl FnMut(&mut [f32], &cpal::OutputCallbackInfo) + Send + 'static {
+    let mut voices: Vec<Voice> = Vec::new();
+    move |output: &mut [f32], _: &cpal::OutputCallbackInfo| {
+        voices.extend(receiver.try_iter());
+        output.fill(0.0);
+        let now = clock.load(Ordering::Relaxed);
+        let frames = output.len() / 2;
+        for voice in voices.iter_mut() {
+            // Voices that are due later start in the middle of this buffer or wait for another one.
+            let offset = voice.start.saturating_sub(now);
+            if offset >= frames as u64 {
+                continue;
+            }
+            for frame in output.chunks_exact_mut(2).skip(offset as usize) {
+                let Some(values) = voice.data.get(voice.frame * 2..voice.frame * 2 + 2) else {
+                    break;
+                };
+                let (left, right) = voice.gains.apply((values[0], values[1]));
+                frame[0] += left;
+                frame[1] += right;
+                voice.frame += 1;
+            }
+        }
+        voices.retain(|voice| voice.frame * 2 < voice.data.len());
+        clock.store(now + frames as u64, Ordering::Relaxed);
+        for value in output.iter_mut() {
+            *value = value.clamp(-1.0, 1.0);
+        }
+    }
+}
+
+fn voice(
+    event: AudioSampleInfo,
+    start: u64,
+    samples: &mut HashMap<String, Arc<Vec<f32>>>,
+    settings: &RenderSettings,
+    cache: &mut SampleCache,
+) -> Result<Voice, Box<dyn Error>> {
+    // A pitch or a hold changes the sample itself, so the event is mixed on its own like in a mix.
+    if event.pitch.is_some() || event.hold.is_some() {
+        let name = event.name.clone();
+        let (data, stats) = render(vec![AudioSampleInfo { time: 0.0, ..event }], settings, cache)?;
+        if stats.mixed == 0 {
+            return Err(AppError::new(ErrorKind::MissingSample, format!("{} could not be loaded", name)).into());
+        }
+        return Ok(Voice {
+            data: Arc::new(data),
+            start,
+            frame: 0,
+            gains: gains(1.0, 0.0, PanMode::Balance),
+        });
+    }
+    let data = match samples.get(&event.name) {
+        Some(data) => data.clone(),
+        None => {
+            let data = load(&event.name, settings, cache)?;
+            samples.insert(event.name.clone(), data.clone());
+            data
+        }
+    };
+    Ok(Voice {
+        data,
+        start,
+        frame: 0,
+        gains: gains(event.volume, event.pan, event.pan_mode.unwrap_or(settings.pan_mode)),
+    })
+}
+
+// Samples go through the same decoding and resampling as in a mix, as a mix of one event at full volume.
+fn load(name: &str, settings: &RenderSettings, cache: &mut SampleCache) -> Result<Arc<Vec<f32>>, Box<dyn Error>> {
+    let event = AudioSampleInfo {
+        time: 0.0,
+        volume: 1.0,
+        pan: 0.0,
+        name: name.to_string(),
+        line: 0,
+        lane: None,
+        hold: None,
+        pitch: None,
+        pan_mode: None,
+        tags: Vec::new(),
+    };
+    let (data, stats) = render(vec![event], settings, cache)?;
+    if stats.mixed == 0 {
+        return Err(AppError::new(ErrorKind::MissingSample, format!("{} could not be loaded", name)).into());
+    }
+    Ok(Arc::new(data))
+}
+
+// `/play <sample> [volume] [pan]`, numbers can be floats or integers.
+fn play_message(arguments: &[OscArgument]) -> Result<AudioSampleInfo, String> {
+    let name = match arguments.first() {
+        Some(OscArgument::Text(name)) if !name.is_empty() => name.clone(),
+        _ => return Err("the first argument has to be the sample".to_string()),
+    };
+    let number = |index: usize, default: f32| match arguments.get(index) {
+        None => Ok(default),
+        Some(OscArgument::Number(number)) => Ok(*number),
+        Some(OscArgument::Text(text)) => Err(format!("{} is not a number", text)),
+    };
+    Ok(AudioSampleInfo {
+        time: 0.0,
+        volume: check_volume(number(1, 1.0)?)?,
+        pan: check_pan(number(2, 0.0)?)?,
+        name,
+        line: 0,
+        lane: None,
+        hold: None,
+        pitch: None,
+        pan_mode: None,
+        tags: Vec::new(),
+    })
+}
+
+// The messages of an OSC packet, bundles are unpacked and played at once, whatever their time tag is.
+fn parse_packet(packet: &[u8], messages: &mut Vec<(String, Vec<OscArgument>)>) -> Result<(), String> {
+    if packet.starts_with(b"#bundle\0") {
+        // The time tag follows the name.
+        let mut rest = packet.get(16..).ok_or("the bundle is too short")?;
+        while !rest.is_empty() {
+            let size = rest.get(..4).ok_or("an element of the bundle has no size")?;
+            let size = i32::from_be_bytes([size[0], size[1], size[2], size[3]]).max(0) as usize;
+            let element = rest.get(4..4 + size).ok_or("an element of the bundle is cut off")?;
+            parse_packet(element, messages)?;
+            rest = &rest[4 + size..];
+        }
+        return Ok(());
+    }
+
+    let mut position = 0;
+    let address = read_string(packet, &mut position)?;
+    if !address.starts_with('/') {
+        return Err(format!("{} is not an OSC address", address));
+    }
+    // Messages without type tags are allowed by OSC 1.0 and have no arguments for us.
+    let tags = if position < packet.len() {
+        read_string(packet, &mut position)?
+    } else {
+        ",".to_string()
+    };
+
+    let mut arguments = Vec::new();
+    for tag in tags.chars().skip(1) {
+        let mut bytes = |count: usize| {
+            let bytes = packet
+                .get(position..position + count)
+                .ok_or(format!("the argument {} is cut off", tag))?;
+            position += count;
+            Ok::<&[u8], String>(bytes)
+        };
+        let argument = match tag {
+            'f' => OscArgument::Number(f32::from_be_bytes(bytes(4)?.try_into().unwrap())),
+            'i' => OscArgument::Number(i32::from_be_bytes(bytes(4)?.try_into().unwrap()) as f32),
+            'd' => OscArgument::Number(f64::from_be_bytes(bytes(8)?.try_into().unwrap()) as f32),
+            'h' => OscArgument::Number(i64::from_be_bytes(bytes(8)?.try_into().unwrap()) as f32),
+            's' | 'S' => OscArgument::Text(read_string(packet, &mut position)?),
+            _ => return Err(format!("arguments of type {} are not supported", tag)),
+        };
+        arguments.push(argument);
+    }
+    messages.push((address, arguments));
+    Ok(())
+}
+
+// A string padded with zeros to a multiple of 4 bytes.
+fn read_string(packet: &[u8], position: &mut usize) -> Result<String, String> {
+    let rest = packet.get(*position..).unwrap_or(&[]);
+    let length = rest
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or("a string is not terminated")?;
+    let text = String::from_utf8_lossy(&rest[..length]).into_owned();
+    *position += (length + 4) & !3;
+    Ok(text)
+}
@@ -0,0 +1,1087 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use log::{debug, error, info, warn};
+
+use crate::checkpoint::{self, render_resumable};
+use crate::cli;
+use crate::commands::validate;
+use crate::config::{parse_quality, FileConfig};
+use crate::error::{AppError, ErrorKind, WithKind};
+use crate::events::{check_volume, read_events, read_events_and_markers, AudioSampleInfo, Marker};
+use crate::export::{export, export_with_comments, fingerprint, Encoding};
+use crate::loudness::loudest_window;
+use crate::metronome::{click_track, read_tempo_map, Click};
+use crate::midi::write_midi;
+use crate::mix::{album, concat, render, to_frame, RenderSettings, RenderStats, SampleCache};
+use crate::reaper::write_project;
+use crate::report::{format_time, write_cue, write_labels, write_report};
+use crate::spectrogram::write_spectrogram;
+use crate::summary::{write_summary, EventExport, PreviewClip, Summary, Timings};
+use crate::watch::{wait_for_change, watched_files};
+
+// Length of the fades at both ends of the clip of --preview in ms, shorter for short clips.
+const PREVIEW_FADE: f64 = 1000.0;
+// Length of the fade out before the cut of --trim-end in ms.
+const TRIM_FADE: f64 = 10.0;
+
+struct Config {
+    input: PathBuf,
+    // Files to append with --concat instead of mixing the events of the input.
+    concat: Vec<String>,
+    crossfade: f64,
+    // CSV files to mix one after the other with --album, with the gaps between them and their chapter titles.
+    album: Vec<PathBuf>,
+    gaps: Vec<f64>,
+    titles: Vec<String>,
+    output: Option<PathBuf>,
+    export_rpp: Option<PathBuf>,
+    export_midi: Option<PathBuf>,
+    note_map: Option<PathBuf>,
+    dry_run: bool,
+    json_summary: Option<PathBuf>,
+    report: Option<PathBuf>,
+    extras: ExtraOutputs,
+    watch: bool,
+    checkpoint: Option<PathBuf>,
+    encoding: Encoding,
+    render: RenderSettings,
+}
+
+// Files written next to the .ogg file from the same mix.
+#[derive(Default)]
+pub struct ExtraOutputs {
+    pub labels: Option<PathBuf>,
+    pub spectrogram: Option<PathBuf>,
+    pub events: Option<PathBuf>,
+    pub click: Option<Click>,
+    // Split the .ogg file at the markers into numbered files with a cue sheet.
+    pub split: bool,
+    pub preview: Option<Preview>,
+    // ms the output is filled up to with silence, and cut to with trim_end.
+    pub pad_to: Option<f64>,
+    pub trim_end: bool,
+}
+
+// A short clip of the mix written next to it, `length` ms from `start` on or from the loudest part without one.
+pub struct Preview {
+    pub length: f64,
+    pub start: Option<f64>,
+}
+
+pub fn command() -> Command {
+    Command::new("mix")
+        .about("Mix the events of a CSV file into an .ogg file")
+        .arg(
+            cli::input_arg()
+                .required(false)
+                .required_unless_present_any(["concat", "album"])
+                .conflicts_with_all(["concat", "album"]),
+        )
+        .arg(
+            Arg::new("concat")
+                .long("concat")
+                .value_name("AUDIO_FILE")
+                .num_args(1..)
+                .help("Append these audio files back to back instead of mixing the events of a CSV file")
+                .conflicts_with_all(["export_rpp", "export_midi", "dry_run", "watch", "backing"]),
+        )
+        .arg(
+            Arg::new("crossfade")
+                .long("crossfade")
+                .value_name("TIME")
+                .help("Let every file of --concat start this long before the previous one ends and fade into it")
+                .value_parser(cli::parse_time)
+                .requires("concat"),
+        )
+        .arg(
+            Arg::new("album")
+                .long("album")
+                .value_name("CSV_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .num_args(1..)
+                .help("Mix these CSV files one after the other into one .ogg file without gaps, with a chapter for every track")
+                .conflicts_with_all(["concat", "export_rpp", "export_midi", "dry_run", "watch", "backing", "from", "to"]),
+        )
+        .arg(
+            Arg::new("gap")
+                .long("gap")
+                .value_name("TIME")
+                .num_args(1..)
+                .help("Silence between the tracks of --album, one time for all of them or one for every two tracks")
+                .value_parser(cli::parse_time)
+                .requires("album"),
+        )
+        .arg(
+            Arg::new("titles")
+                .long("titles")
+                .value_name("TITLE")
+                .num_args(1..)
+                .help("Chapter titles of the tracks of --album, one for every track [default: the names of the CSV files]")
+                .requires("album"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("OUTPUT_OGG_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Path of the .ogg file to write")
+                .required_unless_present_any(["dry_run", "output_dir", "export_rpp", "export_midi"])
+                .conflicts_with("output_dir"),
+        )
+        .arg(
+            Arg::new("output_dir")
+                .short('O')
+                .long("output-dir")
+                .value_name("DIRECTORY")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write the .ogg file into this directory, named after the CSV file"),
+        )
+        .arg(
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .help("Overwrite the output file if it already exists")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("quality")
+                .short('q')
+                .long("quality")
+                .value_name("OUTPUT_OGG_QUALITY")
+                .help("Vorbis encoding quality [default: 0.7]")
+                .value_parser(parse_quality),
+        )
+        .arg(
+            Arg::new("bitrate")
+                .long("bitrate")
+                .value_name("KBPS")
+                .help("Encode with an average bitrate in kbit/s instead of a quality")
+                .value_parser(value_parser!(u32))
+                .conflicts_with("quality"),
+        )
+        .arg(
+            Arg::new("min_bitrate")
+                .long("min-bitrate")
+                .value_name("KBPS")
+                .help("Never go below this bitrate in kbit/s")
+                .value_parser(value_parser!(u32))
+                .conflicts_with("quality"),
+        )
+        .arg(
+            Arg::new("max_bitrate")
+                .long("max-bitrate")
+                .value_name("KBPS")
+                .help("Never go above this bitrate in kbit/s, for streaming targets with a limit")
+                .value_parser(value_parser!(u32))
+                .conflicts_with("quality"),
+        )
+        .arg(
+            Arg::new("target_size")
+                .long("target-size")
+                .value_name("SIZE")
+                .help("Pick the bitrate so the .ogg file is at most this large, like 8MB or 500kB")
+                .value_parser(cli::parse_size)
+                .conflicts_with_all(["quality", "bitrate", "min_bitrate", "max_bitrate"]),
+        )
+        .arg(
+            Arg::new("cbr")
+                .long("cbr")
+                .help("Keep the bitrate of --bitrate constant")
+                .action(ArgAction::SetTrue)
+                .requires("bitrate"),
+        )
+        .arg(
+            Arg::new("export_rpp")
+                .long("export-rpp")
+                .value_name("RPP_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write a Reaper project with every event as a media item instead of mixing")
+                .conflicts_with_all(["output", "output_dir", "dry_run"]),
+        )
+        .arg(
+            Arg::new("export_midi")
+                .long("export-midi")
+                .value_name("MIDI_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write the events as notes of a MIDI file instead of mixing, with the volume as velocity")
+                .conflicts_with_all(["output", "output_dir", "dry_run", "export_rpp"]),
+        )
+        .arg(
+            Arg::new("note_map")
+                .long("note-map")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Notes of the samples for --export-midi, one `file,note` per line")
+                .requires("export_midi"),
+        )
+        .arg(
+            Arg::new("dry_run")
+                .short('n')
+                .long("dry-run")
+                .help("Parse the CSV file and probe every sample, then exit without mixing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("json_summary")
+                .long("json-summary")
+                .value_name("JSON_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write duration, peak level, clipping, trigger counts and timings of the run to a JSON file"),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("TEXT_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write a readable report with skipped events, the peak level, clipping and the loudest moments to a file"),
+        )
+        .arg(
+            Arg::new("labels")
+                .long("labels")
+                .value_name("TEXT_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write an Audacity label track with a label at every event of the mix"),
+        )
+        .arg(
+            Arg::new("spectrogram")
+                .long("spectrogram")
+                .value_name("PNG_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write a spectrogram of the mix with a log frequency scale as a PNG image"),
+        )
+        .arg(
+            Arg::new("export_events")
+                .long("export-events")
+                .value_name("JSON_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write every event as it was mixed to a JSON file: frame, time, gains after the pan law, duration and file"),
+        )
+        .arg(
+            Arg::new("click")
+                .long("click")
+                .value_name("BPM|TEMPO_FILE")
+                .help("Overlay a click on every beat, higher on the first beat of a bar, as BPM[/BEATS_PER_BAR][@TIME] or a file with one `time,bpm[,beats_per_bar]` line per tempo change"),
+        )
+        .arg(
+            Arg::new("click_volume")
+                .long("click-volume")
+                .value_name("VOLUME")
+                .help("Volume of the click from 0.0 to 1.0 [default: 0.5]")
+                .value_parser(value_parser!(f32))
+                .requires("click"),
+        )
+        .arg(
+            Arg::new("click_stem")
+                .long("click-stem")
+                .value_name("OGG_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write the click to its own .ogg file as long as the mix instead of overlaying it")
+                .requires("click"),
+        )
+        .arg(
+            Arg::new("split")
+                .long("split")
+                .help("Split the .ogg file at the marker rows into numbered files, one per track, and write a cue sheet of them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("preview")
+                .long("preview")
+                .value_name("LENGTH")
+                .help("Also write a clip of the mix this long with a fade in and out, to <OUTPUT>-preview.ogg")
+                .value_parser(cli::parse_time),
+        )
+        .arg(
+            Arg::new("preview_start")
+                .long("preview-start")
+                .value_name("TIME|auto")
+                .help("Where the clip of --preview starts, auto picks the loudest part [default: auto]")
+                .requires("preview"),
+        )
+        .arg(
+            Arg::new("pad_to")
+                .long("pad-to")
+                .value_name("DURATION")
+                .help("Fill the output up with silence to this length, in ms, with s or as mm:ss")
+                .value_parser(cli::parse_time),
+        )
+        .arg(
+            Arg::new("trim_end")
+                .long("trim-end")
+                .help("Also cut the output off at the length of --pad-to if it is longer, with a short fade out")
+                .action(ArgAction::SetTrue)
+                .requires("pad_to"),
+        )
+        .arg(
+            Arg::new("watch")
+                .short('w')
+                .long("watch")
+                .help("Keep running and mix again whenever the CSV file or one of its samples changes")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("checkpoint")
+                .long("checkpoint")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Mix a minute at a time and keep what is done in this file, so a run that is interrupted resumes where it stopped, mixes over an hour need --max-duration 0")
+                .conflicts_with_all(["concat", "album", "watch", "dry_run", "export_rpp", "export_midi"]),
+        )
+        .args(cli::render_args())
+}
+
+fn parse_arguments(matches: &ArgMatches) -> Result<Config, Box<dyn Error>> {
+    let file_config = cli::load_config(matches)?;
+
+    let concat: Vec<String> = matches
+        .get_many::<String>("concat")
+        .map(|files| files.cloned().collect())
+        .unwrap_or_default();
+    let album: Vec<PathBuf> = matches
+        .get_many::<PathBuf>("album")
+        .map(|files| files.cloned().collect())
+        .unwrap_or_default();
+    // A concatenation or album is named after its first file.
+    let input = match matches.get_one::<PathBuf>("input") {
+        Some(input) => input.to_owned(),
+        None if !album.is_empty() => album[0].clone(),
+        None => PathBuf::from(&concat[0]),
+    };
+    let (gaps, titles) = album_tracks(matches, &album).with_kind(ErrorKind::Arguments)?;
+    let dry_run = matches.get_flag("dry_run");
+
+    let output = match matches.get_one::<PathBuf>("output_dir") {
+        Some(dir) => Some(output_in_dir(&input, dir).with_kind(ErrorKind::Output)?),
+        None => matches.get_one::<PathBuf>("output").cloned(),
+    };
+
+    let export_rpp = matches.get_one::<PathBuf>("export_rpp").cloned();
+    let export_midi = matches.get_one::<PathBuf>("export_midi").cloned();
+
+    let click = match matches.get_one::<String>("click") {
+        Some(value) => Some(Click {
+            tempo_map: read_tempo_map(value).with_kind(ErrorKind::Arguments)?,
+            volume: check_volume(matches.get_one::<f32>("click_volume").copied().unwrap_or(0.5))
+                .with_kind(ErrorKind::Arguments)?,
+            stem: matches.get_one::<PathBuf>("click_stem").cloned(),
+        }),
+        None => None,
+    };
+    // A click that is overlaid is mixed in with the events, only one with a stem is written on its own.
+    let (click, overlay) = match click {
+        Some(click) if click.stem.is_none() => (None, Some(click)),
+        click => (click, None),
+    };
+
+    let preview = match matches.get_one::<f64>("preview") {
+        Some(&length) => {
+            let start = match matches.get_one::<String>("preview_start").map(String::as_str) {
+                None | Some("auto") => None,
+                Some(value) => Some(
+                    cli::parse_time(value)
+                        .map_err(|err| format!("{} or auto", err))
+                        .with_kind(ErrorKind::Arguments)?,
+                ),
+            };
+            Some(Preview { length, start })
+        }
+        None => None,
+    };
+
+    let config = Config {
+        input,
+        concat,
+        crossfade: matches.get_one::<f64>("crossfade").copied().unwrap_or(0.0),
+        album,
+        gaps,
+        titles,
+        output,
+        export_rpp,
+        export_midi,
+        note_map: matches.get_one::<PathBuf>("note_map").cloned(),
+        dry_run,
+        json_summary: matches.get_one::<PathBuf>("json_summary").cloned(),
+        report: matches.get_one::<PathBuf>("report").cloned(),
+        extras: ExtraOutputs {
+            labels: matches.get_one::<PathBuf>("labels").cloned(),
+            spectrogram: matches.get_one::<PathBuf>("spectrogram").cloned(),
+            events: matches.get_one::<PathBuf>("export_events").cloned(),
+            click,
+            split: matches.get_flag("split"),
+            preview,
+            pad_to: matches.get_one::<f64>("pad_to").copied(),
+            trim_end: matches.get_flag("trim_end"),
+        },
+        watch: matches.get_flag("watch"),
+        checkpoint: matches.get_one::<PathBuf>("checkpoint").cloned(),
+        encoding: encoding(matches, &file_config)?,
+        render: RenderSettings {
+            click: overlay,
+            ..cli::render_settings(matches, &file_config)?
+        },
+    };
+
+    if !dry_run && !matches.get_flag("force") {
+        if let Some(path) = existing_output(&config) {
+            let message = format!("{} already exists, use --force to overwrite it", path.display());
+            return Err(AppError::new(ErrorKind::Output, message).into());
+        }
+    }
+
+    debug!("Input Path: {}", config.input.display());
+    if let Some(output) = &config.output {
+        debug!("Output Path: {}", output.display());
+    }
+    debug!("Output Encoding: {:?}", config.encoding);
+    debug!("Sample Rate: {}", config.render.sample_rate);
+    debug!("Compact Samples: {}", config.render.compact);
+
+    Ok(config)
+}
+
+// The first of the files the mix would write that already exists. How many tracks --split writes is only known after
+// mixing, so any numbered track of the output counts.
+fn existing_output(config: &Config) -> Option<PathBuf> {
+    let extras = &config.extras;
+    let mut paths: Vec<PathBuf> = [
+        &config.export_rpp,
+        &config.export_midi,
+        &config.json_summary,
+        &config.report,
+        &extras.labels,
+        &extras.spectrogram,
+        &extras.events,
+    ]
+    .into_iter()
+    .flatten()
+    .cloned()
+    .collect();
+    paths.extend(extras.click.as_ref().and_then(|click| click.stem.clone()));
+    if let Some(output) = &config.output {
+        paths.push(output.clone());
+        if extras.preview.is_some() {
+            paths.push(preview_path(output));
+        }
+        if extras.split {
+            paths.push(output.with_extension("cue"));
+            paths.extend(existing_track(output));
+        }
+    }
+    paths.into_iter().find(|path| path.exists())
+}
+
+fn existing_track(output: &Path) -> Option<PathBuf> {
+    let prefix = format!("{}-", output.file_stem()?.to_string_lossy());
+    let dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.strip_prefix(&prefix)
+                .and_then(|name| name.strip_suffix(".ogg"))
+                .is_some_and(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+        })
+}
+
+// The output file for --output-dir is named after the input file, songs/chart.csv becomes <dir>/chart.ogg.
+fn output_in_dir(input: &Path, dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let stem = input.file_stem().ok_or("the input file has no name")?;
+    fs::create_dir_all(dir)?;
+
+    let mut output = dir.join(stem);
+    output.set_extension("ogg");
+    Ok(output)
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let config = parse_arguments(matches)?;
+
+    if !config.watch {
+        if !mix(&config)? {
+            return Err(AppError::new(ErrorKind::Validation, "the dry run found problems").into());
+        }
+        return Ok(());
+    }
+
+    // Errors only end the current run, the next change to the files gets another try.
+    loop {
+        if let Err(err) = mix(&config) {
+            error!("{}", err);
+        }
+        info!("watching {} for changes", config.input.display());
+        wait_for_change(&watched_files(
+            &config.input,
+            &config.render.resolver,
+            &config.render.csv,
+        ));
+    }
+}
+
+// Returns false if a dry run found problems.
+fn mix(config: &Config) -> Result<bool, Box<dyn Error>> {
+    if config.dry_run {
+        let infos = read_events(&config.input, &config.render.csv)?;
+        return Ok(validate::check(&infos, &config.render.resolver));
+    }
+    if let Some(path) = &config.export_rpp {
+        let infos = read_events(&config.input, &config.render.csv)?;
+        write_project(path, &infos, &config.render).with_kind(ErrorKind::Output)?;
+        return Ok(true);
+    }
+    if let Some(path) = &config.export_midi {
+        let infos = read_events(&config.input, &config.render.csv)?;
+        write_midi(path, &infos, &config.render, config.note_map.as_deref()).with_kind(ErrorKind::Output)?;
+        return Ok(true);
+    }
+
+    let output = config.output.as_ref().unwrap();
+    let summary = if !config.album.is_empty() {
+        album_files(config, output)?
+    } else if config.concat.is_empty() {
+        mix_file(
+            &config.input,
+            output,
+            config.encoding,
+            &config.render,
+            &mut SampleCache::default(),
+            &config.extras,
+            config.checkpoint.as_deref(),
+        )?
+    } else {
+        concat_files(config, output)?
+    };
+
+    if let Some(path) = &config.json_summary {
+        write_summary(path, &summary).with_kind(ErrorKind::Output)?;
+    }
+    if let Some(path) = &config.report {
+        write_report(path, &summary).with_kind(ErrorKind::Output)?;
+    }
+
+    Ok(true)
+}
+
+// Mixes one CSV file into one .ogg file, in segments that are kept in the checkpoint file if there is one.
+pub fn mix_file(
+    input: &Path,
+    output: &Path,
+    encoding: Encoding,
+    settings: &RenderSettings,
+    cache: &mut SampleCache,
+    extras: &ExtraOutputs,
+    checkpoint: Option<&Path>,
+) -> Result<Summary, Box<dyn Error>> {
+    let started = Instant::now();
+
+    let (infos, markers) = read_events_and_markers(input, &settings.csv)?;
+    let events = infos.len();
+    let labelled = extras.labels.as_ref().map(|_| infos.clone());
+
+    let (data, stats) = match checkpoint {
+        Some(path) => render_resumable(infos, settings, cache, path)?,
+        None => render(infos, settings, cache)?,
+    };
+    let mix = Mixed {
+        input: input.display().to_string(),
+        events,
+        labelled,
+        markers,
+        started,
+    };
+    let summary = write_mix(mix, data, stats, output, encoding, settings, extras)?;
+    if let Some(path) = checkpoint {
+        checkpoint::remove(path)?;
+    }
+    Ok(summary)
+}
+
+// Appends the files of --concat into one .ogg file.
+fn concat_files(config: &Config, output: &Path) -> Result<Summary, Box<dyn Error>> {
+    let started = Instant::now();
+
+    let mut infos = Vec::with_capacity(config.concat.len());
+    let (data, stats) = concat(
+        &config.concat,
+        config.crossfade,
+        &config.render,
+        &mut SampleCache::default(),
+        &mut infos,
+    )?;
+    let mix = Mixed {
+        input: config.concat.join(", "),
+        events: config.concat.len(),
+        labelled: Some(infos),
+        markers: Vec::new(),
+        started,
+    };
+    write_mix(
+        mix,
+        data,
+        stats,
+        output,
+        config.encoding,
+        &config.render,
+        &config.extras,
+    )
+}
+
+// Mixes the CSV files of --album into one .ogg file, with the tracks as chapters.
+fn album_files(config: &Config, output: &Path) -> Result<Summary, Box<dyn Error>> {
+    let started = Instant::now();
+
+    let mut tracks = Vec::with_capacity(config.album.len());
+    for path in &config.album {
+        tracks.push(read_events(path, &config.render.csv)?);
+    }
+    let all: Vec<AudioSampleInfo> = tracks.iter().flatten().cloned().collect();
+    let events = all.len();
+
+    let lengths: Vec<usize> = tracks.iter().map(Vec::len).collect();
+    let mut starts = Vec::with_capacity(tracks.len());
+    let (data, stats) = album(
+        tracks,
+        &config.gaps,
+        &config.render,
+        &mut SampleCache::default(),
+        &mut starts,
+    )?;
+    // The labels are at the times of the events in the album.
+    let labelled = config.extras.labels.as_ref().map(|_| {
+        let mut events = all.into_iter();
+        let mut labelled = Vec::with_capacity(events.len());
+        for (start, length) in starts.iter().zip(lengths) {
+            labelled.extend(events.by_ref().take(length).map(|info| AudioSampleInfo {
+                time: start + info.time,
+                ..info
+            }));
+        }
+        labelled
+    });
+    let markers = starts
+        .iter()
+        .zip(&config.titles)
+        .map(|(&time, title)| Marker {
+            time,
+            label: title.clone(),
+        })
+        .collect();
+
+    let mix = Mixed {
+        input: config
+            .album
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        events,
+        labelled,
+        markers,
+        started,
+    };
+    write_mix(
+        mix,
+        data,
+        stats,
+        output,
+        config.encoding,
+        &config.render,
+        &config.extras,
+    )
+}
+
+// The gaps between the tracks of --album, one for every two tracks, and their titles.
+fn album_tracks(matches: &ArgMatches, album: &[PathBuf]) -> Result<(Vec<f64>, Vec<String>), Box<dyn Error>> {
+    let gaps: Vec<f64> = matches
+        .get_many::<f64>("gap")
+        .map(|gaps| gaps.copied().collect())
+        .unwrap_or_default();
+    let gaps = match gaps.len() {
+        0 => Vec::new(),
+        1 => vec![gaps[0]; album.len().saturating_sub(1)],
+        count if count == album.len() - 1 => gaps,
+        count => {
+            return Err(format!(
+                "--gap has {} times for {} tracks, expected one for all of them or {}, one for every two tracks",
+                count,
+                album.len(),
+                album.len() - 1
+            )
+            .into())
+        }
+    };
+
+    let titles: Vec<String> = match matches.get_many::<String>("titles") {
+        Some(titles) => titles.cloned().collect(),
+        None => album
+            .iter()
+            .map(|path| path.file_stem().unwrap_or_default().to_string_lossy().into_owned())
+            .collect(),
+    };
+    if titles.len() != album.len() {
+        let message = format!("--titles has {} titles for {} tracks", titles.len(), album.len());
+        return Err(message.into());
+    }
+    Ok((gaps, titles))
+}
+
+// What went into a mix, for its extra outputs and summary.
+struct Mixed {
+    input: String,
+    events: usize,
+    labelled: Option<Vec<AudioSampleInfo>>,
+    markers: Vec<Marker>,
+    started: Instant,
+}
+
+fn write_mix(
+    mix: Mixed,
+    mut data: Vec<f32>,
+    stats: RenderStats,
+    output: &Path,
+    encoding: Encoding,
+    settings: &RenderSettings,
+    extras: &ExtraOutputs,
+) -> Result<Summary, Box<dyn Error>> {
+    let Mixed {
+        input,
+        events,
+        labelled,
+        markers,
+        started,
+    } = mix;
+    let serial = settings.seed.map(|seed| seed as u32);
+
+    let rendered = data.len();
+    if let Some(length) = extras.pad_to {
+        fit_length(&mut data, length, extras.trim_end, settings.sample_rate);
+    }
+    // What --pad-to added after the mix is silent, so the click goes on over it without clipping.
+    if let Some(click) = settings.click.as_ref().filter(|_| data.len() > rendered) {
+        let track = click_track(
+            &click.tempo_map,
+            stats.start,
+            data.len() / 2,
+            settings.sample_rate,
+            click.volume,
+        );
+        for (value, click) in data.iter_mut().zip(track).skip(rendered) {
+            *value += click;
+        }
+    }
+    if let Some(Click {
+        tempo_map,
+        volume,
+        stem: Some(path),
+    }) = &extras.click
+    {
+        let track = click_track(tempo_map, stats.start, data.len() / 2, settings.sample_rate, *volume);
+        export(&track, path, encoding, settings.sample_rate, serial)?;
+    }
+    let duration = data.len() as f32 / 2.0 / settings.sample_rate as f32;
+
+    if let (Some(path), Some(events)) = (&extras.labels, &labelled) {
+        write_labels(path, events, stats.start, duration as f64).with_kind(ErrorKind::Output)?;
+    }
+    if let Some(path) = &extras.spectrogram {
+        write_spectrogram(path, &data, settings.sample_rate).with_kind(ErrorKind::Output)?;
+    }
+    if let Some(path) = &extras.events {
+        let export = EventExport {
+            input: &input,
+            output: &output.display().to_string(),
+            sample_rate: settings.sample_rate,
+            start: stats.start / 1000.0,
+            duration,
+            events: &stats.events,
+        };
+        write_summary(path, &export).with_kind(ErrorKind::Output)?;
+    }
+
+    let fingerprint = fingerprint(&data);
+    info!("fingerprint {}", fingerprint);
+
+    // Counted after dedup, the range and the policies, so events that were dropped are not in it.
+    let mut triggers = BTreeMap::new();
+    for event in &stats.events {
+        *triggers.entry(event.name.clone()).or_insert(0) += 1;
+    }
+
+    // Markers become chapters of the .ogg file, in the comment format of the Vorbis chapter extension.
+    let end = stats.start + duration as f64 * 1000.0;
+    let markers: Vec<Marker> = markers
+        .into_iter()
+        .filter(|marker| marker.time >= stats.start && marker.time <= end)
+        .collect();
+    let mut comments = Vec::with_capacity(markers.len() * 2);
+    for (i, marker) in markers.iter().enumerate() {
+        comments.push(format!(
+            "CHAPTER{:03}={}",
+            i + 1,
+            chapter_time(marker.time - stats.start)
+        ));
+        comments.push(format!("CHAPTER{:03}NAME={}", i + 1, marker.label));
+    }
+
+    let encode_started = Instant::now();
+    let mut tracks = Vec::new();
+    if extras.split && !markers.is_empty() {
+        tracks = write_tracks(
+            &data,
+            &markers,
+            stats.start,
+            output,
+            encoding,
+            settings.sample_rate,
+            serial,
+        )?;
+    } else {
+        if extras.split {
+            warn!("there are no markers to split the mix at, writing it to one file");
+        }
+        export_with_comments(&data, output, encoding, settings.sample_rate, serial, &comments)?;
+    }
+    let preview = match &extras.preview {
+        Some(preview) => Some(write_preview(
+            &data,
+            preview,
+            stats.start,
+            output,
+            encoding,
+            settings.sample_rate,
+            serial,
+        )?),
+        None => None,
+    };
+    let encode_time = encode_started.elapsed();
+
+    Ok(Summary {
+        input,
+        output: output.display().to_string(),
+        sample_rate: settings.sample_rate,
+        duration,
+        events,
+        peak: stats.peak,
+        peak_dbfs: 20.0 * stats.peak.log10(),
+        clipped_samples: stats.clipped,
+        gain_db: 20.0 * stats.gain.log10(),
+        fingerprint,
+        triggers,
+        missing_samples: stats.missing,
+        undecodable_samples: stats.undecodable,
+        mixed_events: stats.mixed,
+        skipped_events: stats.skipped,
+        loudest: stats.loudest,
+        clipping: stats.clipping,
+        markers,
+        tracks,
+        preview,
+        timings: Timings {
+            decode: stats.decode_time.as_secs_f32(),
+            mix: stats.mix_time.as_secs_f32(),
+            encode: encode_time.as_secs_f32(),
+            total: started.elapsed().as_secs_f32(),
+        },
+    })
+}
+
+// Writes the parts of the mix from every marker to the next one to numbered files, song.ogg becomes song-01.ogg,
+// song-02.ogg and so on, and lists them in the cue sheet song.cue. The parts are split on the frame of the marker, a
+// part before the first marker is a track without a title. Returns the files.
+fn write_tracks(
+    data: &[f32],
+    markers: &[Marker],
+    start: f64,
+    output: &Path,
+    encoding: Encoding,
+    sample_rate: u32,
+    serial: Option<u32>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let frames = data.len() / 2;
+    let frame_of =
+        |time: f64| (to_frame(time, sample_rate) - to_frame(start, sample_rate)).clamp(0, frames as i64) as usize;
+    let mut bounds: Vec<(usize, Option<&str>)> = vec![(0, None)];
+    for marker in markers {
+        bounds.push((frame_of(marker.time), Some(&marker.label)));
+    }
+    bounds.push((frames, None));
+    // Markers at the same frame would leave empty tracks, the last one of them starts the track.
+    let parts: Vec<(Range<usize>, Option<&str>)> = bounds
+        .windows(2)
+        .filter(|bounds| bounds[1].0 > bounds[0].0)
+        .map(|bounds| (bounds[0].0..bounds[1].0, bounds[0].1))
+        .collect();
+
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let width = parts.len().to_string().len().max(2);
+    let mut cue = Vec::with_capacity(parts.len());
+    for (i, (range, title)) in parts.iter().enumerate() {
+        let path = output.with_file_name(format!("{}-{:0width$}.ogg", stem, i + 1, width = width));
+        let mut comments = vec![format!("TRACKNUMBER={}", i + 1), format!("TRACKTOTAL={}", parts.len())];
+        if let Some(title) = title {
+            comments.push(format!("TITLE={}", title));
+        }
+        export_with_comments(
+            &data[range.start * 2..range.end * 2],
+            &path,
+            encoding,
+            sample_rate,
+            serial,
+            &comments,
+        )?;
+        cue.push((path, title.map(str::to_string)));
+    }
+
+    let names: Vec<(String, Option<String>)> = cue
+        .iter()
+        .map(|(path, title)| {
+            (
+                path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                title.clone(),
+            )
+        })
+        .collect();
+    write_cue(&output.with_extension("cue"), &stem, &names).with_kind(ErrorKind::Output)?;
+    Ok(cue.into_iter().map(|(path, _)| path.display().to_string()).collect())
+}
+
+// Fills the mix up with silence to `length` ms from its start, or cuts it off there with trim after a fade out.
+fn fit_length(data: &mut Vec<f32>, length: f64, trim: bool, sample_rate: u32) {
+    let frames = to_frame(length, sample_rate).max(0) as usize;
+    let over = (data.len() / 2).saturating_sub(frames);
+    if over == 0 {
+        data.resize(frames * 2, 0.0);
+    } else if trim {
+        // No longer than what is kept, so nothing is faded when the mix is cut down to nothing.
+        let fade = (to_frame(TRIM_FADE, sample_rate) as usize).min(frames);
+        for (i, frame) in data[(frames - fade) * 2..frames * 2].chunks_exact_mut(2).enumerate() {
+            let gain = 1.0 - (i + 1) as f32 / fade as f32;
+            frame[0] *= gain;
+            frame[1] *= gain;
+        }
+        data.truncate(frames * 2);
+        info!(
+            "cut off the last {:.3} s to end at {}",
+            over as f64 / sample_rate as f64,
+            format_time(length)
+        );
+    } else {
+        warn!(
+            "the mix is {:.3} s longer than --pad-to {}, --trim-end cuts it off",
+            over as f64 / sample_rate as f64,
+            format_time(length)
+        );
+    }
+}
+
+// Writes the clip of --preview to song-preview.ogg next to song.ogg. A start too close to the end is moved back so the
+// clip is as long as asked for, mixes that are shorter are written whole.
+fn write_preview(
+    data: &[f32],
+    preview: &Preview,
+    start: f64,
+    output: &Path,
+    encoding: Encoding,
+    sample_rate: u32,
+    serial: Option<u32>,
+) -> Result<PreviewClip, Box<dyn Error>> {
+    let frames = data.len() / 2;
+    let length = (to_frame(preview.length, sample_rate).max(0) as usize).min(frames);
+    let first = match preview.start {
+        Some(time) => (to_frame(time, sample_rate) - to_frame(start, sample_rate)).max(0) as usize,
+        None => loudest_window(data, sample_rate, preview.length / 1000.0),
+    }
+    .min(frames - length);
+    let time = start + first as f64 * 1000.0 / sample_rate as f64;
+    info!("preview from {}", format_time(time));
+
+    let mut clip = data[first * 2..(first + length) * 2].to_vec();
+    let fade = to_frame(PREVIEW_FADE, sample_rate).max(0) as usize;
+    let fade = fade.min(length / 4).max(1);
+    for frame in 0..fade.min(length) {
+        let gain = frame as f32 / fade as f32;
+        for channel in 0..2 {
+            clip[frame * 2 + channel] *= gain;
+            clip[(length - 1 - frame) * 2 + channel] *= gain;
+        }
+    }
+
+    let path = preview_path(output);
+    export(&clip, &path, encoding, sample_rate, serial)?;
+    Ok(PreviewClip {
+        file: path.display().to_string(),
+        start: time,
+        duration: length as f32 / sample_rate as f32,
+    })
+}
+
+// song.ogg has its preview in song-preview.ogg.
+fn preview_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    output.with_file_name(format!("{}-preview.ogg", stem))
+}
+
+// HH:MM:SS.mmm, as chapter times are written.
+fn chapter_time(ms: f64) -> String {
+    let ms = ms.max(0.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+// --target-size wins over everything else. Bitrate options on the command line or in the config file switch from a
+// quality to bitrate management, a quality on the command line wins over bitrates of the config file.
+fn encoding(matches: &ArgMatches, config: &FileConfig) -> Result<Encoding, Box<dyn Error>> {
+    if let Some(size) = matches.get_one::<u64>("target_size") {
+        return Ok(Encoding::Size(*size));
+    }
+    let quality = matches.get_one::<f32>("quality").copied();
+    let from_config = |value: Option<u32>| if quality.is_some() { None } else { value };
+    let average = matches
+        .get_one::<u32>("bitrate")
+        .copied()
+        .or(from_config(config.bitrate));
+    let mut min = matches
+        .get_one::<u32>("min_bitrate")
+        .copied()
+        .or(from_config(config.min_bitrate));
+    let mut max = matches
+        .get_one::<u32>("max_bitrate")
+        .copied()
+        .or(from_config(config.max_bitrate));
+    if average.is_none() && min.is_none() && max.is_none() {
+        return Ok(Encoding::Quality(quality.or(config.quality).unwrap_or(0.7)));
+    }
+
+    if matches.get_flag("cbr") || (quality.is_none() && config.cbr.unwrap_or(false)) {
+        let Some(average) = average else {
+            return Err(AppError::new(ErrorKind::Arguments, "cbr needs a bitrate to keep constant").into());
+        };
+        (min, max) = (Some(average), Some(average));
+    }
+    if let (Some(min), Some(max)) = (min, max) {
+        if min > max {
+            let message = format!(
+                "the minimum bitrate {} kbit/s is above the maximum of {} kbit/s",
+                min, max
+            );
+            return Err(AppError::new(ErrorKind::Arguments, message).into());
+        }
+    }
+    Ok(Encoding::Bitrate { average, min, max })
+}
@@ -0,0 +1,15 @@
+#[cfg(feature = "playback")]
+pub mod audition;
+pub mod batch;
+pub mod diff;
+pub mod inspect;
+#[cfg(feature = "playback")]
+pub mod live;
+pub mod mix;
+pub mod pack;
+#[cfg(feature = "playback")]
+pub mod preview;
+pub mod scan;
+pub mod serve;
+pub mod stats;
+pub mod validate;
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use log::{debug, info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::cli;
+use crate::config::parse_quality;
+use crate::decode::{probe_audio, read_audio, UpmixLaw};
+use crate::error::{AppError, ErrorKind, WithKind};
+use crate::events::{read_events, AudioSampleInfo, CsvOptions};
+use crate::export::{encode, write_wav, Encoding};
+use crate::mix::PanMode;
+use crate::resample::ConverterType;
+use crate::resolve::{sample_path, split_track};
+use crate::synth::parse_synth;
+
+// A column of the new CSV file after the first four, counting from 1, with the option that reads it.
+struct Column {
+    option: &'static str,
+    number: usize,
+    value: fn(&AudioSampleInfo) -> Option<String>,
+}
+
+// A sample as it is written to the pack.
+struct Packed {
+    file_name: String,
+    // Silence cut off at the start in ms, events of the sample are moved later by this much.
+    lead: f64,
+}
+
+pub fn command() -> Command {
+    Command::new("pack")
+        .about("Copy the samples a CSV file uses into a minimal keysound pack with a CSV file that points to them")
+        .arg(cli::input_arg())
+        .arg(
+            Arg::new("output_dir")
+                .short('o')
+                .long("output-dir")
+                .value_name("DIRECTORY")
+                .value_parser(value_parser!(PathBuf))
+                .help("Directory to write the samples and the new CSV file to")
+                .required(true),
+        )
+        .arg(
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .help("Write into the output directory even if it is not empty")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Write the samples as 16 bit .wav files or as .ogg files")
+                .value_parser(["wav", "ogg"])
+                .default_value("wav"),
+        )
+        .arg(
+            Arg::new("quality")
+                .short('q')
+                .long("quality")
+                .value_name("OUTPUT_OGG_QUALITY")
+                .help("Vorbis encoding quality with --format ogg [default: 0.7]")
+                .value_parser(parse_quality),
+        )
+        .arg(
+            Arg::new("threshold")
+                .long("threshold")
+                .value_name("DBFS")
+                .help("Cut off the start and end of samples while they are quieter than this [default: -60]")
+                .value_parser(value_parser!(f32))
+                .allow_negative_numbers(true),
+        )
+        .args(cli::resolve_args())
+        .args(cli::csv_args())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+    let output_dir = matches.get_one::<PathBuf>("output_dir").unwrap();
+    let format = matches.get_one::<String>("format").unwrap().as_str();
+    let threshold = 10f32.powf(matches.get_one::<f32>("threshold").copied().unwrap_or(-60.0) / 20.0);
+    let config = cli::load_config(matches)?;
+    let quality = matches
+        .get_one::<f32>("quality")
+        .copied()
+        .or(config.quality)
+        .unwrap_or(0.7);
+    let resolver = cli::sample_resolver(matches, &config);
+
+    let not_empty = fs::read_dir(output_dir).is_ok_and(|mut entries| entries.next().is_some());
+    if not_empty && !matches.get_flag("force") {
+        let message = format!("{} is not empty, use --force to write into it", output_dir.display());
+        return Err(AppError::new(ErrorKind::Output, message).into());
+    }
+
+    let options = cli::csv_options(matches, &config, config.sample_rate.unwrap_or(cli::DEFAULT_SAMPLE_RATE))?;
+    let infos = read_events(input, &options)?;
+
+    let mut names: Vec<&str> = infos.iter().map(|info| info.name.as_str()).collect();
+    names.sort();
+    names.dedup();
+
+    let paths: Vec<(&str, PathBuf)> = names.iter().map(|name| (*name, resolver.resolve(name))).collect();
+    let missing: Vec<&str> = paths
+        .iter()
+        .filter(|(name, path)| !path.is_file() && parse_synth(name).is_none())
+        .map(|(name, _)| *name)
+        .collect();
+    if !missing.is_empty() {
+        let message = format!("missing samples: {}", missing.join(", "));
+        return Err(AppError::new(ErrorKind::MissingSample, message).into());
+    }
+
+    fs::create_dir_all(output_dir).with_kind(ErrorKind::Output)?;
+
+    let mut packed: HashMap<&str, Option<Packed>> = HashMap::new();
+    // File names by checksum of the trimmed audio, identical samples are only written once.
+    let mut written: HashMap<String, String> = HashMap::new();
+    let mut used_names: Vec<String> = Vec::new();
+    let mut original_size = 0;
+    let mut packed_size = 0;
+    let mut originals: Vec<&Path> = Vec::new();
+
+    for (name, path) in &paths {
+        // Generated samples need no file, their events keep the name.
+        if let Some(synth) = parse_synth(name) {
+            synth.with_kind(ErrorKind::Input)?;
+            let file_name = name.to_string();
+            packed.insert(name, Some(Packed { file_name, lead: 0.0 }));
+            continue;
+        }
+        if !originals.contains(&path.as_path()) {
+            original_size += fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+            originals.push(path);
+        }
+
+        let track = split_track(name).1;
+        let decode_error =
+            |err: Box<dyn Error>| AppError::new(ErrorKind::Decode, format!("could not decode {}: {}", name, err));
+        // Samples keep their own sample rate, mono files decode to two equal channels and are written as mono again.
+        let sample_rate = probe_audio(path, track).map_err(decode_error)?.sample_rate;
+        let data = read_audio(
+            path,
+            sample_rate,
+            ConverterType::SincBestQuality,
+            UpmixLaw::Duplicate,
+            track,
+            false,
+            false,
+        )
+        .map_err(decode_error)?;
+
+        let loud = |frame: &[f32]| frame.iter().any(|value| value.abs() > threshold);
+        let Some(first) = data.chunks_exact(2).position(loud) else {
+            warn!("{} is silent, its events are left out", name);
+            packed.insert(name, None);
+            continue;
+        };
+        let last = data.chunks_exact(2).rposition(loud).unwrap();
+        let data = &data[first * 2..(last + 1) * 2];
+        let lead = first as f64 * 1000.0 / sample_rate as f64;
+        let mono = data.chunks_exact(2).all(|frame| frame[0] == frame[1]);
+
+        let mut hasher = Sha256::new();
+        hasher.update(sample_rate.to_le_bytes());
+        for value in data {
+            hasher.update(value.to_le_bytes());
+        }
+        let checksum = format!("{:x}", hasher.finalize());
+
+        if let Some(file_name) = written.get(&checksum) {
+            debug!("{} is the same as {}", name, file_name);
+            packed.insert(
+                name,
+                Some(Packed {
+                    file_name: file_name.clone(),
+                    lead,
+                }),
+            );
+            continue;
+        }
+
+        let file_name = unique_name(name, format, &used_names);
+        let output = output_dir.join(&file_name);
+        match format {
+            "ogg" => {
+                let buffer = encode(data, Encoding::Quality(quality), sample_rate, None)?;
+                fs::write(&output, buffer).with_kind(ErrorKind::Output)?;
+            }
+            _ if mono => {
+                let left: Vec<f32> = data.iter().step_by(2).copied().collect();
+                write_wav(&left, 1, &output, sample_rate).with_kind(ErrorKind::Output)?;
+            }
+            _ => write_wav(data, 2, &output, sample_rate).with_kind(ErrorKind::Output)?,
+        }
+        packed_size += fs::metadata(&output).map(|metadata| metadata.len()).unwrap_or(0);
+
+        debug!(
+            "{} is packed as {}, {:.1} ms of silence cut off at the start",
+            name, file_name, lead
+        );
+        written.insert(checksum, file_name.clone());
+        used_names.push(file_name.clone());
+        packed.insert(name, Some(Packed { file_name, lead }));
+    }
+
+    // The same events with the packed file names, moved by the silence cut off at the start of their sample.
+    let extras = extra_columns(&infos, &options);
+    let width = extras.iter().map(|column| column.number).fold(4, usize::max);
+    let mut csv = String::new();
+    for info in &infos {
+        if let Some(sample) = &packed[info.name.as_str()] {
+            let mut row = vec![String::new(); width];
+            // Rounded to µs, which is still well below a frame.
+            row[0] = (((info.time + sample.lead) * 1000.0).round() / 1000.0).to_string();
+            row[1] = info.volume.to_string();
+            row[2] = format!(
+                "{}{}",
+                info.pan,
+                match info.pan_mode {
+                    Some(PanMode::Balance) => "b",
+                    Some(PanMode::TruePan) => "t",
+                    None => "",
+                }
+            );
+            row[3] = format!("\"{}\"", sample.file_name);
+            for column in &extras {
+                if let Some(value) = (column.value)(info) {
+                    row[column.number - 1] = if value.contains([',', '"', ';', '\t']) {
+                        format!("\"{}\"", value.replace('"', "\"\""))
+                    } else {
+                        value
+                    };
+                }
+            }
+            csv += &row.join(",");
+            csv += "\n";
+        }
+    }
+    let stem = input.file_stem().ok_or("the input file has no name")?;
+    let csv_path = output_dir.join(stem).with_extension("csv");
+    fs::write(&csv_path, csv).with_kind(ErrorKind::Output)?;
+
+    info!(
+        "packed {} samples of {} into {} files, {} KB instead of {} KB, the events are in {}",
+        names.len(),
+        input.display(),
+        written.len(),
+        packed_size / 1024,
+        original_size / 1024,
+        csv_path.display()
+    );
+    if !extras.is_empty() {
+        let options: Vec<String> = extras
+            .iter()
+            .map(|column| format!("{} {}", column.option, column.number))
+            .collect();
+        info!("mix {} with {}", csv_path.display(), options.join(" "));
+    }
+    Ok(())
+}
+
+// The columns of the pitch, hold, lane and tags the events use: the ones they were read from, or the next free ones
+// for events that are not from a CSV file.
+fn extra_columns(infos: &[AudioSampleInfo], options: &CsvOptions) -> Vec<Column> {
+    let columns = [
+        Column {
+            option: "--lane-column",
+            number: options.lane_column.unwrap_or(0),
+            value: |info| info.lane.clone(),
+        },
+        Column {
+            option: "--hold-column",
+            number: options.hold_column.unwrap_or(0),
+            value: |info| info.hold.map(|hold| ((hold * 1000.0).round() / 1000.0).to_string()),
+        },
+        Column {
+            option: "--pitch-column",
+            number: options.pitch_column.unwrap_or(0),
+            value: |info| info.pitch.map(|pitch| pitch.to_string()),
+        },
+        Column {
+            option: "--tag-column",
+            number: options.tag_column.unwrap_or(0),
+            value: |info| (!info.tags.is_empty()).then(|| info.tags.join(" ")),
+        },
+    ];
+    let mut taken: Vec<usize> = columns.iter().map(|column| column.number).collect();
+    let mut used = Vec::new();
+    for mut column in columns {
+        if !infos.iter().any(|info| (column.value)(info).is_some()) {
+            continue;
+        }
+        if column.number == 0 {
+            column.number = (5..).find(|number| !taken.contains(number)).unwrap();
+            taken.push(column.number);
+        }
+        used.push(column);
+    }
+    used
+}
+
+// The file name of the sample with the extension of the format, numbered if another sample already has it.
+fn unique_name(name: &str, format: &str, used_names: &[String]) -> String {
+    let path = sample_path(split_track(name).0);
+    let stem = match path.file_stem() {
+        Some(stem) => stem.to_string_lossy().replace(['"', '\\', '/'], "_"),
+        None => "sample".to_string(),
+    };
+
+    let mut file_name = format!("{}.{}", stem, format);
+    let mut number = 2;
+    while used_names.iter().any(|used| used.eq_ignore_ascii_case(&file_name)) {
+        file_name = format!("{}_{}.{}", stem, number, format);
+        number += 1;
+    }
+    file_name
+}
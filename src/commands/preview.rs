@@ -0,0 +1,302 @@
+use std::error::Error;
+use std::io::{stdin, BufRead};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, Device, SampleRate, Stream, StreamConfig};
+use log::{error, info, warn};
+
+use crate::cli;
+use crate::error::{AppError, ErrorKind};
+use crate::events::{read_events, AudioSampleInfo};
+use crate::mix::{render, to_frame, ClipPolicy, RenderSettings, SampleCache};
+use crate::tui;
+
+// Length of the parts --stream mixes one after the other, in ms.
+const STREAM_CHUNK: f64 = 5000.0;
+
+pub fn command() -> Command {
+    Command::new("preview")
+        .about("Mix the events of a CSV file and play the result through the default audio device")
+        .arg(cli::input_arg())
+        .arg(
+            Arg::new("tui")
+                .short('t')
+                .long("tui")
+                .help("Show the timeline, trigger counts and levels in the terminal, with commands to mix again, mute samples and play parts")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stream")
+                .short('s')
+                .long("stream")
+                .help("Start playing as soon as the first seconds are mixed and mix the rest while playing")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("tui"),
+        )
+        .arg(cli::host_arg())
+        .args(cli::render_args())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+    let settings = cli::render_settings(matches, &cli::load_config(matches)?)?;
+    let host = matches.get_one::<String>("host").map(String::as_str);
+
+    if matches.get_flag("tui") {
+        return tui::run(input, settings, host);
+    }
+
+    let infos = read_events(input, &settings.csv)?;
+    if matches.get_flag("stream") {
+        return stream(infos, &settings, host);
+    }
+    let (data, _) = render(infos, &settings, &mut SampleCache::default())?;
+
+    play(data, settings.sample_rate, host)
+}
+
+// Mixes STREAM_CHUNK ms at a time with --from and --to and hands every part to the player as soon as it is mixed.
+// Samples are only decoded for the first part, if mixing falls behind playback waits for it.
+fn stream(infos: Vec<AudioSampleInfo>, settings: &RenderSettings, host: Option<&str>) -> Result<(), Box<dyn Error>> {
+    if settings.on_clip == ClipPolicy::Normalize {
+        let message = "--auto-gain and --on-clip normalize need the whole mix and cannot be used with --stream";
+        return Err(AppError::new(ErrorKind::Arguments, message).into());
+    }
+    if !settings.plugins.is_empty() {
+        let message = "plugins process the whole mix at once and cannot be used with --stream";
+        return Err(AppError::new(ErrorKind::Arguments, message).into());
+    }
+
+    let mut cache = SampleCache::default();
+    let mut player: Option<Player> = None;
+    let mut from = settings.from;
+    loop {
+        let to = match settings.to {
+            Some(to) if to <= from + STREAM_CHUNK => to,
+            _ => from + STREAM_CHUNK,
+        };
+        let part_settings = RenderSettings {
+            from,
+            to: Some(to),
+            extend_before_zero: false,
+            ..settings.clone()
+        };
+        let (mut data, _) = render(infos.clone(), &part_settings, &mut cache)?;
+
+        // Parts end with the last sample in them, the ones before the end are filled up with silence.
+        let frames = (to_frame(to, settings.sample_rate) - to_frame(from, settings.sample_rate)) as usize;
+        let last = settings.to == Some(to) || (data.len() < frames * 2 && infos.iter().all(|info| info.time < to));
+        if !last {
+            data.resize(frames * 2, 0.0);
+        }
+
+        match &player {
+            Some(player) => player.append(&data),
+            None => {
+                let started = Player::new(data, settings.sample_rate, false, host)?;
+                started.transport.growing.store(true, Ordering::Relaxed);
+                let command_transport = started.transport.clone();
+                let sample_rate = settings.sample_rate;
+                thread::spawn(move || read_commands(&command_transport, usize::MAX, sample_rate));
+                info!("playing while mixing");
+                info!("enter or p: pause/resume, f/b [seconds]: seek forward/back, s <seconds>: seek to, q: quit");
+                player = Some(started);
+            }
+        }
+
+        let player = player.as_ref().unwrap();
+        if last || player.transport.stopped.load(Ordering::Relaxed) {
+            break;
+        }
+        from = to;
+    }
+
+    let player = player.unwrap();
+    player.transport.growing.store(false, Ordering::Relaxed);
+    info!("mixed {:.1} s", player.len() as f32 / 2.0 / settings.sample_rate as f32);
+    while !player.transport.stopped.load(Ordering::Relaxed) && player.position() < player.len() {
+        sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+// Playback state shared between the audio callback, the command reader and the main thread.
+struct Transport {
+    position: AtomicUsize,
+    paused: AtomicBool,
+    stopped: AtomicBool,
+    // More of the mix is still coming, so playback waits at the end instead of playing on in silence.
+    growing: AtomicBool,
+}
+
+// Plays a mix through the default audio device or the one of another host. The mix can be replaced or extended
+// while the stream is running.
+pub struct Player {
+    _stream: Stream,
+    transport: Arc<Transport>,
+    data: Arc<Mutex<Arc<Vec<f32>>>>,
+}
+
+impl Player {
+    pub fn new(data: Vec<f32>, sample_rate: u32, paused: bool, host: Option<&str>) -> Result<Player, Box<dyn Error>> {
+        let device = output_device(host)?;
+
+        let config = StreamConfig {
+            channels: 2,
+            sample_rate: SampleRate(sample_rate),
+            buffer_size: BufferSize::Default,
+        };
+
+        let transport = Arc::new(Transport {
+            position: AtomicUsize::new(0),
+            paused: AtomicBool::new(paused),
+            stopped: AtomicBool::new(false),
+            growing: AtomicBool::new(false),
+        });
+        let data = Arc::new(Mutex::new(Arc::new(data)));
+        let stream_transport = transport.clone();
+        let stream_data = data.clone();
+
+        let stream = device.build_output_stream(
+            &config,
+            move |output: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                // The lock is only held for replacing the mix, play silence instead of waiting for it.
+                let data = match stream_data.try_lock() {
+                    Ok(data) if !stream_transport.paused.load(Ordering::Relaxed) => data.clone(),
+                    _ => {
+                        output.fill(0.0);
+                        return;
+                    }
+                };
+                let growing = stream_transport.growing.load(Ordering::Relaxed);
+                if growing && stream_transport.position.load(Ordering::Relaxed) + output.len() > data.len() {
+                    output.fill(0.0);
+                    return;
+                }
+                let start = stream_transport.position.fetch_add(output.len(), Ordering::Relaxed);
+                for (i, sample) in output.iter_mut().enumerate() {
+                    *sample = *data.get(start + i).unwrap_or(&0.0);
+                }
+            },
+            |err| error!("audio stream error: {}", err),
+            None,
+        )?;
+        stream.play()?;
+
+        Ok(Player {
+            _stream: stream,
+            transport,
+            data,
+        })
+    }
+
+    pub fn set_data(&self, data: Vec<f32>) {
+        *self.data.lock().unwrap() = Arc::new(data);
+    }
+
+    pub fn append(&self, data: &[f32]) {
+        let mut current = self.data.lock().unwrap();
+        Arc::make_mut(&mut current).extend_from_slice(data);
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.lock().unwrap().len()
+    }
+
+    pub fn position(&self) -> usize {
+        self.transport.position.load(Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.transport.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.transport.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn seek(&self, position: usize) {
+        seek(&self.transport, position);
+    }
+}
+
+// The default output device of the named cpal host, or of the default host without one.
+pub fn output_device(host: Option<&str>) -> Result<Device, Box<dyn Error>> {
+    let host = match host {
+        None => cpal::default_host(),
+        Some(name) => {
+            let hosts = cpal::available_hosts();
+            let Some(id) = hosts.iter().find(|id| id.name().eq_ignore_ascii_case(name)) else {
+                let names: Vec<&str> = hosts.iter().map(|id| id.name()).collect();
+                let message = format!("audio host {} is not available, there is {}", name, names.join(", "));
+                return Err(AppError::new(ErrorKind::Arguments, message).into());
+            };
+            cpal::host_from_id(*id)?
+        }
+    };
+    Ok(host.default_output_device().ok_or("no audio output device available")?)
+}
+
+pub fn play(data: Vec<f32>, sample_rate: u32, host: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let length = data.len();
+    let player = Player::new(data, sample_rate, false, host)?;
+
+    info!("playing {:.1} s", length as f32 / 2.0 / sample_rate as f32);
+    info!("enter or p: pause/resume, f/b [seconds]: seek forward/back, s <seconds>: seek to, q: quit");
+
+    let command_transport = player.transport.clone();
+    thread::spawn(move || read_commands(&command_transport, length, sample_rate));
+
+    while !player.transport.stopped.load(Ordering::Relaxed) && player.position() < length {
+        sleep(Duration::from_millis(100));
+    }
+
+    Ok(())
+}
+
+// Reads transport commands from stdin line by line until it is closed.
+fn read_commands(transport: &Transport, length: usize, sample_rate: u32) {
+    let to_seconds = |position: usize| position as f32 / 2.0 / sample_rate as f32;
+    // Positions count interleaved samples, so they have to stay on a frame boundary.
+    let to_position = |seconds: f32| ((seconds.max(0.0) * sample_rate as f32) as usize * 2).min(length);
+
+    for line in stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let argument = words.next().and_then(|word| word.parse::<f32>().ok());
+
+        let current = to_seconds(transport.position.load(Ordering::Relaxed));
+        match (command, argument) {
+            ("", _) | ("p", _) => {
+                let paused = !transport.paused.fetch_xor(true, Ordering::Relaxed);
+                info!("{} at {:.1} s", if paused { "paused" } else { "playing" }, current);
+                continue;
+            }
+            ("f", seconds) => seek(transport, to_position(current + seconds.unwrap_or(5.0))),
+            ("b", seconds) => seek(transport, to_position(current - seconds.unwrap_or(5.0))),
+            ("s", Some(seconds)) => seek(transport, to_position(seconds)),
+            ("q", _) => {
+                transport.stopped.store(true, Ordering::Relaxed);
+                break;
+            }
+            _ => {
+                warn!("unknown command '{}'", line.trim());
+                continue;
+            }
+        }
+        info!("at {:.1} s", to_seconds(transport.position.load(Ordering::Relaxed)));
+    }
+}
+
+fn seek(transport: &Transport, position: usize) {
+    transport.position.store(position, Ordering::Relaxed);
+}
@@ -0,0 +1,129 @@
+use std::error::Error;
+use std::fs;
+use std::io::{stdout, Write};
+use std::path::PathBuf;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use log::{debug, info, warn};
+
+use crate::cli;
+use crate::decode::probe_audio;
+use crate::error::{AppError, ErrorKind, WithKind};
+use crate::resolve::list_files;
+
+pub fn command() -> Command {
+    Command::new("scan")
+        .about("Probe every audio file of a directory and write a starter CSV file with one event per file")
+        .arg(
+            Arg::new("dir")
+                .value_name("DIRECTORY")
+                .help("Directory with the samples")
+                .value_parser(value_parser!(PathBuf))
+                .required(true),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("OUTPUT_CSV_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write the CSV file here instead of printing it"),
+        )
+        .arg(
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .help("Overwrite the output file if it already exists")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("recursive")
+                .long("recursive")
+                .help("Also scan the subdirectories")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("gap")
+                .long("gap")
+                .value_name("TIME")
+                .help("Silence between the end of one sample and the next event, in ms or with s [default: 500]")
+                .value_parser(cli::parse_time),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let dir = matches.get_one::<PathBuf>("dir").unwrap();
+    let gap = matches.get_one::<f64>("gap").copied().unwrap_or(500.0);
+    let output = matches.get_one::<PathBuf>("output");
+
+    if let Some(output) = output {
+        if !matches.get_flag("force") && output.exists() {
+            let message = format!("{} already exists, use --force to overwrite it", output.display());
+            return Err(AppError::new(ErrorKind::Output, message).into());
+        }
+    }
+    if !dir.is_dir() {
+        let message = format!("{} is not a directory", dir.display());
+        return Err(AppError::new(ErrorKind::Input, message).into());
+    }
+
+    let files = if matches.get_flag("recursive") {
+        list_files(std::slice::from_ref(dir))
+    } else {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)
+            .with_kind(ErrorKind::Input)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        files.sort();
+        files
+    };
+
+    // Events follow each other, so every sample can be heard on its own when the starter file is mixed.
+    let mut csv = String::new();
+    let mut time = 0.0_f64;
+    let mut count = 0;
+    for file in &files {
+        let audio = match probe_audio(file, None) {
+            Ok(audio) => audio,
+            Err(err) => {
+                debug!("skipping {}: {}", file.display(), err);
+                continue;
+            }
+        };
+
+        let name = file
+            .strip_prefix(dir)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .replace('\\', "/");
+        if name.contains('"') {
+            warn!("skipping {}: quotes in file names are not supported", file.display());
+            continue;
+        }
+
+        // The columns after the file are ignored when mixing and only tell what was found.
+        csv += &format!(
+            "{},1.0,0.0,\"{}\",{:.3},{}\n",
+            time.round(),
+            name,
+            audio.duration(),
+            audio.sample_rate
+        );
+        time += audio.duration() as f64 * 1000.0 + gap;
+        count += 1;
+    }
+
+    match output {
+        Some(output) => fs::write(output, &csv).with_kind(ErrorKind::Output)?,
+        None => stdout().write_all(csv.as_bytes()).with_kind(ErrorKind::Output)?,
+    }
+
+    info!(
+        "{} of {} files are audio files, mix them with --samples-dir {}",
+        count,
+        files.len(),
+        dir.display()
+    );
+    Ok(())
+}
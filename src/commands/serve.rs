@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use log::{info, warn};
+use zip::ZipArchive;
+
+use crate::cli;
+use crate::config::{parse_quality, parse_sample_rate};
+use crate::download::is_url;
+use crate::error::{kind_of, to_json, AppError, ErrorKind};
+use crate::events::{read_events_from, AudioSampleInfo};
+use crate::export::{encode, Encoding};
+use crate::mix::{render, RenderSettings, SampleCache};
+use crate::resolve::sample_path;
+
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(30);
+// Of the files in a posted archive, so that a small archive cannot fill the disk.
+const MAX_UNPACKED: u64 = 1 << 30;
+
+// Posted archives are unpacked into directories numbered by this.
+static ARCHIVES: AtomicUsize = AtomicUsize::new(0);
+
+struct Job {
+    infos: Vec<AudioSampleInfo>,
+    quality: f32,
+    settings: RenderSettings,
+    // The unpacked samples of an archive, removed with the job.
+    _archive: Option<Unpacked>,
+    reply: Sender<Response>,
+}
+
+struct Unpacked(PathBuf);
+
+impl Drop for Unpacked {
+    fn drop(&mut self) {
+        if !self.0.exists() {
+            return;
+        }
+        if let Err(err) = fs::remove_dir_all(&self.0) {
+            warn!("could not remove {}: {}", self.0.display(), err);
+        }
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: u16, body: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain",
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    // Errors are sent as the same JSON objects as --errors json prints.
+    fn error(err: &(dyn Error + 'static)) -> Response {
+        let status = match kind_of(err) {
+            Some(ErrorKind::Arguments) | Some(ErrorKind::Input) => 400,
+            // The CSV file is fine, but the samples or the mix are not, like with --on-clip error or --strict.
+            Some(ErrorKind::MissingSample) | Some(ErrorKind::Decode) | Some(ErrorKind::Validation) => 422,
+            _ => 500,
+        };
+        Response {
+            status,
+            content_type: "application/json",
+            body: to_json(err).into_bytes(),
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+pub fn command() -> Command {
+    Command::new("serve")
+        .about("Mix CSV files posted to a small HTTP API and send back the .ogg file")
+        .arg(
+            Arg::new("listen")
+                .short('l')
+                .long("listen")
+                .value_name("ADDRESS")
+                .help("Address and port to listen on")
+                .default_value("127.0.0.1:8080"),
+        )
+        .arg(
+            Arg::new("workers")
+                .short('w')
+                .long("workers")
+                .value_name("COUNT")
+                .help("Number of mixes that run at the same time")
+                .value_parser(value_parser!(usize))
+                .default_value("2"),
+        )
+        .arg(
+            Arg::new("queue")
+                .long("queue")
+                .value_name("COUNT")
+                .help("Number of requests that wait for a worker before new ones are rejected")
+                .value_parser(value_parser!(usize))
+                .default_value("16"),
+        )
+        .arg(
+            Arg::new("max_body")
+                .long("max-body")
+                .value_name("BYTES")
+                .help("Largest accepted CSV file")
+                .value_parser(value_parser!(usize))
+                .default_value("16777216"),
+        )
+        .arg(
+            Arg::new("allow_urls")
+                .long("allow-urls")
+                .help("Download samples named by http:// and https:// URLs, which requests cannot use otherwise")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("quality")
+                .short('q')
+                .long("quality")
+                .value_name("OUTPUT_OGG_QUALITY")
+                .help("Vorbis encoding quality of requests without a quality parameter [default: 0.7]")
+                .value_parser(parse_quality),
+        )
+        .args(cli::render_args())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let file_config = cli::load_config(matches)?;
+    let mut settings = cli::render_settings(matches, &file_config)?;
+    // Requests may only use samples from the samples directories, not any file below the current directory.
+    settings.resolver.in_current_dir = false;
+    // Otherwise any request could make the server fetch from other hosts and fill the cache.
+    settings.resolver.downloads = matches.get_flag("allow_urls");
+    if settings.resolver.samples_dirs.is_empty() {
+        warn!("there are no samples directories, only the samples of posted archives can be used");
+    }
+    let quality = matches
+        .get_one::<f32>("quality")
+        .copied()
+        .or(file_config.quality)
+        .unwrap_or(0.7);
+    let workers = *matches.get_one::<usize>("workers").unwrap();
+    let queue = *matches.get_one::<usize>("queue").unwrap();
+    let max_body = *matches.get_one::<usize>("max_body").unwrap();
+    let address = matches.get_one::<String>("listen").unwrap();
+
+    let listener = TcpListener::bind(address)?;
+    info!(
+        "listening on http://{}, POST a CSV file or zip archive to /render",
+        address
+    );
+
+    let (sender, receiver) = sync_channel::<Job>(queue);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..workers.max(1) {
+        let receiver = receiver.clone();
+        thread::spawn(move || work(&receiver));
+    }
+
+    let settings = Arc::new(settings);
+    let waiting = Arc::new(AtomicUsize::new(0));
+
+    // A connection thread for every request that can be mixed or wait, and as many accepted connections waiting for
+    // one of them. Connections beyond that are answered with 503 right away.
+    let (connections, accepted) = sync_channel::<TcpStream>(queue);
+    let accepted = Arc::new(Mutex::new(accepted));
+    for _ in 0..workers.max(1) + queue {
+        let accepted = accepted.clone();
+        let sender = sender.clone();
+        let settings = settings.clone();
+        let waiting = waiting.clone();
+        thread::spawn(move || {
+            connect(&accepted, &sender, &settings, quality, max_body, &waiting);
+        });
+    }
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("could not accept connection: {}", err);
+                continue;
+            }
+        };
+
+        if let Err(TrySendError::Full(mut stream)) = connections.try_send(stream) {
+            let response = Response::text(503, "too many connections, try again later\n");
+            let _ = stream.set_write_timeout(Some(TIMEOUT));
+            if let Err(err) = write_response(&mut stream, &response) {
+                warn!("connection failed: {}", err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn connect(
+    accepted: &Mutex<Receiver<TcpStream>>,
+    sender: &SyncSender<Job>,
+    settings: &RenderSettings,
+    quality: f32,
+    max_body: usize,
+    waiting: &AtomicUsize,
+) {
+    loop {
+        let stream = match accepted.lock().unwrap().recv() {
+            Ok(stream) => stream,
+            Err(_) => break,
+        };
+        if let Err(err) = handle(stream, sender, settings, quality, max_body, waiting) {
+            warn!("connection failed: {}", err);
+        }
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => break,
+        };
+
+        let response = render(job.infos, &job.settings, &mut SampleCache::default())
+            .and_then(|(data, _)| {
+                encode(
+                    &data,
+                    Encoding::Quality(job.quality),
+                    job.settings.sample_rate,
+                    job.settings.seed.map(|seed| seed as u32),
+                )
+            })
+            .map(|ogg| Response {
+                status: 200,
+                content_type: "audio/ogg",
+                body: ogg,
+            })
+            .unwrap_or_else(|err| Response::error(err.as_ref()));
+
+        // The client may have hung up in the meantime.
+        let _ = job.reply.send(response);
+    }
+}
+
+fn handle(
+    mut stream: TcpStream,
+    sender: &SyncSender<Job>,
+    settings: &RenderSettings,
+    quality: f32,
+    max_body: usize,
+    waiting: &AtomicUsize,
+) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let response = match read_request(&mut stream, max_body) {
+        Ok(request) => respond(request, sender, settings, quality, waiting),
+        Err(response) => response,
+    };
+
+    write_response(&mut stream, &response)
+}
+
+fn respond(
+    request: Request,
+    sender: &SyncSender<Job>,
+    settings: &RenderSettings,
+    quality: f32,
+    waiting: &AtomicUsize,
+) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/health") => Response::text(200, "ok\n"),
+        ("GET", "/status") => Response {
+            status: 200,
+            content_type: "application/json",
+            body: format!("{{\"waiting\":{}}}", waiting.load(Ordering::Relaxed)).into_bytes(),
+        },
+        ("POST", "/render") => match job_from_request(&request, settings, quality) {
+            Ok((job, reply)) => {
+                info!("rendering {} events", job.infos.len());
+                let response = submit(sender, job, &reply, waiting);
+                info!("request finished with status {}", response.status);
+                response
+            }
+            Err(err) => Response::error(err.as_ref()),
+        },
+        (_, "/health") | (_, "/status") | (_, "/render") => Response::text(405, "method not allowed\n"),
+        _ => Response::text(404, "not found\n"),
+    }
+}
+
+fn submit(sender: &SyncSender<Job>, job: Job, reply: &Receiver<Response>, waiting: &AtomicUsize) -> Response {
+    match sender.try_send(job) {
+        Ok(()) => {
+            waiting.fetch_add(1, Ordering::Relaxed);
+            let response = reply
+                .recv()
+                .unwrap_or_else(|_| Response::text(500, "the render failed\n"));
+            waiting.fetch_sub(1, Ordering::Relaxed);
+            response
+        }
+        Err(TrySendError::Full(_)) => Response::text(503, "too many requests are waiting, try again later\n"),
+        Err(TrySendError::Disconnected(_)) => Response::text(500, "no workers are running\n"),
+    }
+}
+
+// The query can override the quality and sample rate: POST /render?quality=0.5&sample_rate=48000. The body is a CSV
+// file or a zip archive with one CSV file and the samples it uses, which are looked up in the archive first.
+fn job_from_request(
+    request: &Request,
+    settings: &RenderSettings,
+    quality: f32,
+) -> Result<(Job, Receiver<Response>), Box<dyn Error>> {
+    let quality = match request.query.get("quality") {
+        Some(value) => parse_quality(value).map_err(|err| AppError::new(ErrorKind::Arguments, err))?,
+        None => quality,
+    };
+    let mut settings = settings.clone();
+    if let Some(value) = request.query.get("sample_rate") {
+        settings.sample_rate = parse_sample_rate(value)
+            .map_err(|err| AppError::new(ErrorKind::Arguments, format!("invalid sample_rate parameter: {}", err)))?;
+        settings.csv.sample_rate = settings.sample_rate;
+    }
+
+    let (infos, archive) = if request.body.starts_with(b"PK\x03\x04") {
+        let (csv, archive) = unpack(&request.body)?;
+        let mut samples_dirs = vec![archive.0.clone()];
+        samples_dirs.extend(settings.resolver.samples_dirs.iter().cloned());
+        settings.resolver = settings.resolver.with_samples_dirs(samples_dirs);
+        (read_events_from(csv.as_slice(), &settings.csv)?, Some(archive))
+    } else {
+        (read_events_from(request.body.as_slice(), &settings.csv)?, None)
+    };
+
+    // Requests may only use samples from the samples directories, not any file the server can read.
+    for info in &infos {
+        if is_url(&info.name) && !settings.resolver.downloads {
+            let message = format!(
+                "sample {} is a URL, which the server only downloads with --allow-urls",
+                info.name
+            );
+            return Err(AppError::new(ErrorKind::Input, message).into());
+        }
+        let path = sample_path(&info.name);
+        let outside = |component| matches!(component, Component::ParentDir | Component::Prefix(_));
+        if path.has_root() || path.components().any(outside) {
+            let message = format!("sample {} is outside of the samples directories", info.name);
+            return Err(AppError::new(ErrorKind::Input, message).into());
+        }
+    }
+
+    let (reply, receiver) = channel();
+    let job = Job {
+        infos,
+        quality,
+        settings,
+        _archive: archive,
+        reply,
+    };
+    Ok((job, receiver))
+}
+
+// Returns the CSV file of an archive and the directory its other files are unpacked to.
+fn unpack(body: &[u8]) -> Result<(Vec<u8>, Unpacked), Box<dyn Error>> {
+    let invalid = |message: String| AppError::new(ErrorKind::Input, message);
+    let mut archive =
+        ZipArchive::new(Cursor::new(body)).map_err(|err| invalid(format!("the archive cannot be read: {}", err)))?;
+
+    let csv_files: Vec<usize> = (0..archive.len())
+        .filter(|&index| {
+            archive
+                .by_index(index)
+                .is_ok_and(|entry| entry.is_file() && entry.name().to_lowercase().ends_with(".csv"))
+        })
+        .collect();
+    if csv_files.len() != 1 {
+        return Err(invalid(format!("the archive has {} CSV files instead of one", csv_files.len())).into());
+    }
+    let too_large = || {
+        invalid(format!(
+            "the files of the archive are larger than {} bytes",
+            MAX_UNPACKED
+        ))
+    };
+    // The sizes the archive claims only reject it early, what is unpacked is counted as well.
+    let declared: u64 = (0..archive.len())
+        .filter_map(|index| archive.by_index(index).ok().map(|entry| entry.size()))
+        .sum();
+    if declared > MAX_UNPACKED {
+        return Err(too_large().into());
+    }
+
+    let dir = Unpacked(env::temp_dir().join(format!(
+        "audio-sample-mixer-serve-{}-{}",
+        process::id(),
+        ARCHIVES.fetch_add(1, Ordering::Relaxed)
+    )));
+    let mut csv = Vec::new();
+    let mut remaining = MAX_UNPACKED;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        // Names that would leave the directory are not unpacked, like with the samples of .osz files.
+        let Some(name) = entry.enclosed_name().map(|name| name.to_path_buf()) else {
+            continue;
+        };
+        if !entry.is_file() {
+            continue;
+        }
+        // One byte more than is left shows that the entry does not fit.
+        let mut limited = entry.by_ref().take(remaining + 1);
+        let written = if index == csv_files[0] {
+            limited.read_to_end(&mut csv)? as u64
+        } else {
+            let target = dir.0.join(&name);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut limited, &mut File::create(&target)?)?
+        };
+        remaining = remaining.checked_sub(written).ok_or_else(too_large)?;
+    }
+    Ok((csv, dir))
+}
+
+fn read_request(stream: &mut TcpStream, max_body: usize) -> Result<Request, Response> {
+    let bad_request = || Response::text(400, "bad request\n");
+
+    let mut reader = BufReader::new(stream);
+    let mut head = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        let read = reader.read_until(b'\n', &mut line).map_err(|_| bad_request())?;
+        if read == 0 {
+            return Err(bad_request());
+        }
+        head.extend_from_slice(&line);
+        if head.len() > MAX_HEADER_SIZE {
+            return Err(Response::text(431, "request header fields too large\n"));
+        }
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        }
+    }
+
+    let head = String::from_utf8(head).map_err(|_| bad_request())?;
+    let mut lines = head.lines();
+
+    let mut request_line = lines.next().unwrap_or("").split_whitespace();
+    let method = request_line.next().ok_or_else(bad_request)?.to_string();
+    let target = request_line.next().ok_or_else(bad_request)?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut content_length = 0;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().map_err(|_| bad_request())?;
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            return Err(Response::text(411, "a content-length header is required\n"));
+        }
+    }
+
+    if content_length > max_body {
+        return Err(Response::text(413, "the CSV file is too large\n"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(|_| bad_request())?;
+
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query: parse_query(query),
+        body,
+    })
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> Result<(), Box<dyn Error>> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use log::warn;
+
+use crate::decode::{probe_audio, read_audio, UpmixLaw};
+use crate::error::{AppError, ErrorKind};
+use crate::loudness::integrated_loudness;
+use crate::report::format_time;
+use crate::resample::ConverterType;
+
+pub fn command() -> Command {
+    Command::new("stats")
+        .about("Print the duration, sample rate, channels, peak, RMS and loudness of audio files")
+        .arg(
+            Arg::new("files")
+                .value_name("FILE")
+                .help("Samples or mixes to measure")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Append)
+                .required(true),
+        )
+}
+
+// Levels are measured on the file as it is mixed: mono files play on both sides at full level, which makes them
+// 3 LU louder than the same file measured as mono.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let files: Vec<&PathBuf> = matches.get_many::<PathBuf>("files").unwrap().collect();
+
+    println!("  duration      rate  channels  peak dBFS  RMS dBFS     LUFS  file");
+    let mut failed = 0;
+    for file in &files {
+        let measured = probe_audio(file, None).and_then(|audio| {
+            let data = read_audio(
+                file,
+                audio.sample_rate,
+                ConverterType::SincBestQuality,
+                UpmixLaw::Duplicate,
+                None,
+                false,
+                false,
+            )?;
+            Ok((audio, data))
+        });
+        let (audio, data) = match measured {
+            Ok(measured) => measured,
+            Err(err) => {
+                warn!("could not decode {}: {}", file.display(), err);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let peak = data.iter().fold(0.0_f32, |peak, value| peak.max(value.abs()));
+        let square: f64 = data.iter().map(|value| (*value as f64).powi(2)).sum();
+        let rms = (square / data.len().max(1) as f64).sqrt() as f32;
+        let lufs = match integrated_loudness(&data, audio.sample_rate) {
+            Some(lufs) => format!("{:.1}", lufs),
+            None => "-".to_string(),
+        };
+
+        println!(
+            "{:>10}  {:>8}  {:>8}  {:>9}  {:>8}  {:>7}  {}",
+            format_time(audio.duration() as f64 * 1000.0),
+            audio.sample_rate,
+            audio.channels,
+            level(peak),
+            level(rms),
+            lufs,
+            file.display()
+        );
+    }
+
+    if failed > 0 {
+        let message = format!("{} of {} files could not be decoded", failed, files.len());
+        return Err(AppError::new(ErrorKind::Decode, message).into());
+    }
+    Ok(())
+}
+
+fn level(value: f32) -> String {
+    if value > 0.0 {
+        format!("{:.1}", 20.0 * value.log10())
+    } else {
+        "-".to_string()
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::{ArgMatches, Command};
+use log::{info, warn};
+
+use crate::cli;
+use crate::decode::probe_audio;
+use crate::error::{AppError, ErrorKind};
+use crate::events::{read_events, AudioSampleInfo};
+use crate::resolve::{split_track, SampleResolver};
+use crate::synth::parse_synth;
+
+pub fn command() -> Command {
+    Command::new("validate")
+        .about("Parse the CSV file and probe every referenced sample without mixing")
+        .arg(cli::input_arg())
+        .args(cli::resolve_args())
+        .args(cli::csv_args())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+    let config = cli::load_config(matches)?;
+
+    let infos = read_events(
+        input,
+        &cli::csv_options(matches, &config, config.sample_rate.unwrap_or(cli::DEFAULT_SAMPLE_RATE))?,
+    )?;
+
+    if !check(&infos, &cli::sample_resolver(matches, &config)) {
+        return Err(AppError::new(ErrorKind::Validation, "validation found problems").into());
+    }
+
+    Ok(())
+}
+
+// Prints every problem found in the events and their samples. Returns false if there was any.
+pub fn check(infos: &[AudioSampleInfo], resolver: &SampleResolver) -> bool {
+    let mut problems = 0;
+    let mut durations: HashMap<&str, Option<f32>> = HashMap::new();
+
+    for info in infos {
+        let line = info.line;
+
+        // Times, volumes and pans that are not numbers or outside of their range are already rejected when reading
+        // the CSV file.
+        if info.time < 0.0 {
+            info!(
+                "line {}: time {} is negative, what plays before 0 is cut off without --extend-before-zero",
+                line, info.time
+            );
+        }
+
+        if durations.contains_key(info.name.as_str()) {
+            continue;
+        }
+
+        let path = resolver.resolve(&info.name);
+        let duration = if let Some(synth) = parse_synth(&info.name) {
+            match synth {
+                Ok(synth) => Some(synth.duration()),
+                Err(err) => {
+                    warn!("line {}: {}", line, err);
+                    problems += 1;
+                    None
+                }
+            }
+        } else if !path.is_file() {
+            warn!("line {}: missing sample {}", line, info.name);
+            problems += 1;
+            None
+        } else {
+            match probe_audio(&path, split_track(&info.name).1) {
+                Ok(audio) => Some(audio.duration()),
+                Err(err) => {
+                    warn!("line {}: could not decode {}: {}", line, path.display(), err);
+                    problems += 1;
+                    None
+                }
+            }
+        };
+        durations.insert(&info.name, duration);
+    }
+
+    let total = infos
+        .iter()
+        .map(|info| {
+            let duration = durations[info.name.as_str()].unwrap_or(0.0) as f64;
+            info.time / 1000.0 + info.hold.map_or(duration, |hold| hold / 1000.0)
+        })
+        .fold(0.0, f64::max);
+
+    info!("Total duration: {:.3} s", total);
+    info!(
+        "{} events, {} samples, {} problems",
+        infos.len(),
+        durations.len(),
+        problems
+    );
+
+    problems == 0
+}
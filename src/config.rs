@@ -0,0 +1,487 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::cli::{parse_offset, parse_time};
+use crate::decode::UpmixLaw;
+use crate::events::RangePolicy;
+use crate::lv2::parse_plugin;
+use crate::mix::{ClipPolicy, DecodeErrorPolicy, MissingPolicy, PanMode, Precision, SampleNormalization};
+use crate::resample::ConverterType;
+use serde::Deserialize;
+
+pub const CONFIG_FILE_NAME: &str = "audio-sample-mixer.toml";
+const ENV_PREFIX: &str = "ASM_";
+// Sample rates a mix can have.
+pub const SAMPLE_RATES: RangeInclusive<u32> = 8000..=192_000;
+
+// Defaults read from audio-sample-mixer.toml. Every value can be overridden on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub quality: Option<f32>,
+    // Bitrate management in kbit/s instead of the quality.
+    pub bitrate: Option<u32>,
+    pub min_bitrate: Option<u32>,
+    pub max_bitrate: Option<u32>,
+    pub cbr: Option<bool>,
+    pub sample_rate: Option<u32>,
+    pub samples_dirs: Vec<PathBuf>,
+    pub ignore_case: Option<bool>,
+    pub any_extension: Option<bool>,
+    pub recursive: Option<bool>,
+    pub cache_dir: Option<PathBuf>,
+    pub threads: Option<usize>,
+    pub resampler: Option<String>,
+    pub upmix: Option<String>,
+    pub pan_mode: Option<String>,
+    pub track: Option<usize>,
+    pub on_missing: Option<String>,
+    pub on_decode_error: Option<String>,
+    pub on_clip: Option<String>,
+    pub precision: Option<String>,
+    pub normalize_samples: Option<String>,
+    pub decimal_comma: Option<bool>,
+    pub volume_db: Option<bool>,
+    pub skip_bad_rows: Option<bool>,
+    pub range_policy: Option<String>,
+    pub extend_before_zero: Option<bool>,
+    pub max_duration: Option<String>,
+    pub offset: Option<String>,
+    pub dedup_events: Option<bool>,
+    pub align_transients: Option<bool>,
+    pub retrigger_fade: Option<String>,
+    pub auto_gain: Option<bool>,
+    pub strict: Option<bool>,
+    pub allow_ffmpeg: Option<bool>,
+    pub plugins: Vec<String>,
+    pub script: Option<PathBuf>,
+    pub manifest: Option<PathBuf>,
+    pub lane_column: Option<usize>,
+    pub hold_column: Option<usize>,
+    pub pitch_column: Option<usize>,
+    pub tag_column: Option<usize>,
+    // Root notes of samples by name, as note names like "F#3".
+    pub roots: BTreeMap<String, String>,
+    // Offsets of samples by name, as times like "-12ms".
+    pub offsets: BTreeMap<String, String>,
+    // Settings of lanes by name, as [lanes.7] tables.
+    pub lanes: BTreeMap<String, LaneConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LaneConfig {
+    // In dB.
+    pub gain: Option<f32>,
+    pub offset: Option<String>,
+    pub mute: Option<bool>,
+}
+
+impl FileConfig {
+    // Environment variables take precedence over the config file, command line options over both.
+    pub fn load(path: Option<&Path>) -> Result<FileConfig, Box<dyn Error>> {
+        let mut config = FileConfig::load_file(path)?;
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn load_file(path: Option<&Path>) -> Result<FileConfig, Box<dyn Error>> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match env_var("CONFIG").map(PathBuf::from).or_else(find_config_file) {
+                Some(path) => path,
+                None => return Ok(FileConfig::default()),
+            },
+        };
+
+        let text = fs::read_to_string(&path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+        let mut config: FileConfig =
+            toml::from_str(&text).map_err(|err| format!("could not parse {}: {}", path.display(), err))?;
+
+        if let Some(quality) = config.quality {
+            check_quality(quality).map_err(|err| format!("{}: {}", path.display(), err))?;
+        }
+        if let Some(sample_rate) = config.sample_rate {
+            check_sample_rate(sample_rate).map_err(|err| format!("{}: {}", path.display(), err))?;
+        }
+
+        // Relative sample directories are relative to the config file, so a shared config works from anywhere.
+        let base = path.parent().unwrap_or(Path::new(""));
+        for dir in config.samples_dirs.iter_mut() {
+            if dir.is_relative() {
+                *dir = base.join(&dir);
+            }
+        }
+        if let Some(manifest) = config.manifest.as_mut().filter(|manifest| manifest.is_relative()) {
+            *manifest = base.join(&manifest);
+        }
+        if let Some(cache_dir) = config.cache_dir.as_mut().filter(|cache_dir| cache_dir.is_relative()) {
+            *cache_dir = base.join(&cache_dir);
+        }
+        if let Some(script) = config.script.as_mut().filter(|script| script.is_relative()) {
+            *script = base.join(&script);
+        }
+
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(quality) = parse_env("QUALITY")? {
+            check_quality(quality).map_err(|err| format!("{}QUALITY: {}", ENV_PREFIX, err))?;
+            self.quality = Some(quality);
+        }
+        if let Some(bitrate) = parse_env("BITRATE")? {
+            self.bitrate = Some(bitrate);
+        }
+        if let Some(min_bitrate) = parse_env("MIN_BITRATE")? {
+            self.min_bitrate = Some(min_bitrate);
+        }
+        if let Some(max_bitrate) = parse_env("MAX_BITRATE")? {
+            self.max_bitrate = Some(max_bitrate);
+        }
+        if let Some(cbr) = parse_env_flag("CBR")? {
+            self.cbr = Some(cbr);
+        }
+        if let Some(sample_rate) = parse_env("SAMPLE_RATE")? {
+            check_sample_rate(sample_rate).map_err(|err| format!("{}SAMPLE_RATE: {}", ENV_PREFIX, err))?;
+            self.sample_rate = Some(sample_rate);
+        }
+        if let Some(lane_column) = parse_env("LANE_COLUMN")? {
+            self.lane_column = Some(lane_column);
+        }
+        if let Some(hold_column) = parse_env("HOLD_COLUMN")? {
+            self.hold_column = Some(hold_column);
+        }
+        if let Some(pitch_column) = parse_env("PITCH_COLUMN")? {
+            self.pitch_column = Some(pitch_column);
+        }
+        if let Some(tag_column) = parse_env("TAG_COLUMN")? {
+            self.tag_column = Some(tag_column);
+        }
+        if let Some(threads) = parse_env("THREADS")? {
+            self.threads = Some(threads);
+        }
+        if let Some(track) = parse_env("TRACK")? {
+            self.track = Some(track);
+        }
+        if let Some(ignore_case) = parse_env_flag("IGNORE_CASE")? {
+            self.ignore_case = Some(ignore_case);
+        }
+        if let Some(any_extension) = parse_env_flag("ANY_EXTENSION")? {
+            self.any_extension = Some(any_extension);
+        }
+        if let Some(recursive) = parse_env_flag("RECURSIVE")? {
+            self.recursive = Some(recursive);
+        }
+        if let Some(decimal_comma) = parse_env_flag("DECIMAL_COMMA")? {
+            self.decimal_comma = Some(decimal_comma);
+        }
+        if let Some(volume_db) = parse_env_flag("VOLUME_DB")? {
+            self.volume_db = Some(volume_db);
+        }
+        if let Some(skip_bad_rows) = parse_env_flag("SKIP_BAD_ROWS")? {
+            self.skip_bad_rows = Some(skip_bad_rows);
+        }
+        if let Some(extend_before_zero) = parse_env_flag("EXTEND_BEFORE_ZERO")? {
+            self.extend_before_zero = Some(extend_before_zero);
+        }
+        if let Some(dedup_events) = parse_env_flag("DEDUP_EVENTS")? {
+            self.dedup_events = Some(dedup_events);
+        }
+        if let Some(align_transients) = parse_env_flag("ALIGN_TRANSIENTS")? {
+            self.align_transients = Some(align_transients);
+        }
+        if let Some(auto_gain) = parse_env_flag("AUTO_GAIN")? {
+            self.auto_gain = Some(auto_gain);
+        }
+        if let Some(strict) = parse_env_flag("STRICT")? {
+            self.strict = Some(strict);
+        }
+        if let Some(allow_ffmpeg) = parse_env_flag("ALLOW_FFMPEG")? {
+            self.allow_ffmpeg = Some(allow_ffmpeg);
+        }
+        // Several plugins are separated by spaces, neither URIs nor their control values contain any.
+        if let Some(plugins) = env_var("PLUGINS") {
+            self.plugins = plugins.split_whitespace().map(str::to_string).collect();
+            for plugin in &self.plugins {
+                parse_plugin(plugin).map_err(|err| format!("{}PLUGINS: {}", ENV_PREFIX, err))?;
+            }
+        }
+        if let Some(script) = env_var("SCRIPT") {
+            self.script = Some(PathBuf::from(script));
+        }
+        if let Some(cache_dir) = env_var("CACHE_DIR") {
+            self.cache_dir = Some(PathBuf::from(cache_dir));
+        }
+        if let Some(manifest) = env_var("MANIFEST") {
+            self.manifest = Some(PathBuf::from(manifest));
+        }
+        if let Some(max_duration) = env_var("MAX_DURATION") {
+            parse_time(&max_duration).map_err(|err| format!("{}MAX_DURATION: {}", ENV_PREFIX, err))?;
+            self.max_duration = Some(max_duration);
+        }
+        if let Some(offset) = env_var("OFFSET") {
+            parse_offset(&offset).map_err(|err| format!("{}OFFSET: {}", ENV_PREFIX, err))?;
+            self.offset = Some(offset);
+        }
+        if let Some(retrigger_fade) = env_var("RETRIGGER_FADE") {
+            parse_time(&retrigger_fade).map_err(|err| format!("{}RETRIGGER_FADE: {}", ENV_PREFIX, err))?;
+            self.retrigger_fade = Some(retrigger_fade);
+        }
+        if let Some(resampler) = env_var("RESAMPLER") {
+            parse_resampler(&resampler).map_err(|err| format!("{}RESAMPLER: {}", ENV_PREFIX, err))?;
+            self.resampler = Some(resampler);
+        }
+        if let Some(upmix) = env_var("UPMIX") {
+            parse_upmix(&upmix).map_err(|err| format!("{}UPMIX: {}", ENV_PREFIX, err))?;
+            self.upmix = Some(upmix);
+        }
+        if let Some(pan_mode) = env_var("PAN_MODE") {
+            parse_pan_mode(&pan_mode).map_err(|err| format!("{}PAN_MODE: {}", ENV_PREFIX, err))?;
+            self.pan_mode = Some(pan_mode);
+        }
+        if let Some(range_policy) = env_var("RANGE_POLICY") {
+            parse_range_policy(&range_policy).map_err(|err| format!("{}RANGE_POLICY: {}", ENV_PREFIX, err))?;
+            self.range_policy = Some(range_policy);
+        }
+        if let Some(on_missing) = env_var("ON_MISSING") {
+            parse_missing_policy(&on_missing).map_err(|err| format!("{}ON_MISSING: {}", ENV_PREFIX, err))?;
+            self.on_missing = Some(on_missing);
+        }
+        if let Some(on_decode_error) = env_var("ON_DECODE_ERROR") {
+            parse_decode_error_policy(&on_decode_error)
+                .map_err(|err| format!("{}ON_DECODE_ERROR: {}", ENV_PREFIX, err))?;
+            self.on_decode_error = Some(on_decode_error);
+        }
+        if let Some(on_clip) = env_var("ON_CLIP") {
+            parse_clip_policy(&on_clip).map_err(|err| format!("{}ON_CLIP: {}", ENV_PREFIX, err))?;
+            self.on_clip = Some(on_clip);
+        }
+        if let Some(precision) = env_var("PRECISION") {
+            parse_precision(&precision).map_err(|err| format!("{}PRECISION: {}", ENV_PREFIX, err))?;
+            self.precision = Some(precision);
+        }
+        if let Some(level) = env_var("NORMALIZE_SAMPLES") {
+            parse_sample_normalization(&level).map_err(|err| format!("{}NORMALIZE_SAMPLES: {}", ENV_PREFIX, err))?;
+            self.normalize_samples = Some(level);
+        }
+
+        // Like PATH, several directories are separated by ':' (';' on Windows). They are searched before the
+        // directories of the config file.
+        if let Some(dirs) = env::var_os(format!("{}SAMPLES_DIR", ENV_PREFIX)) {
+            let mut samples_dirs: Vec<PathBuf> = env::split_paths(&dirs)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .collect();
+            samples_dirs.append(&mut self.samples_dirs);
+            self.samples_dirs = samples_dirs;
+        }
+
+        Ok(())
+    }
+}
+
+// Empty variables count as unset, so `ASM_QUALITY= audio-sample-mixer ...` disables an exported value.
+fn env_var(name: &str) -> Option<String> {
+    env::var(format!("{}{}", ENV_PREFIX, name))
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+fn parse_env<T: FromStr>(name: &str) -> Result<Option<T>, String> {
+    match env_var(name) {
+        Some(value) => match value.trim().parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(format!("invalid value '{}' of {}{}", value, ENV_PREFIX, name)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn parse_env_flag(name: &str) -> Result<Option<bool>, String> {
+    match env_var(name).map(|value| value.trim().to_ascii_lowercase()) {
+        Some(value) => match value.as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Some(true)),
+            "0" | "false" | "no" | "off" => Ok(Some(false)),
+            _ => Err(format!(
+                "invalid value '{}' of {}{}, expected true or false",
+                value, ENV_PREFIX, name
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+fn find_config_file() -> Option<PathBuf> {
+    let local = PathBuf::from(CONFIG_FILE_NAME);
+    if local.is_file() {
+        return Some(local);
+    }
+
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    let global = config_dir.join(CONFIG_FILE_NAME);
+    if global.is_file() {
+        Some(global)
+    } else {
+        None
+    }
+}
+
+// The range libvorbis accepts, it fails deep inside the encoder otherwise.
+pub fn check_quality(quality: f32) -> Result<f32, String> {
+    if (-0.1..=1.0).contains(&quality) {
+        Ok(quality)
+    } else {
+        Err(format!(
+            "quality {} is outside of -0.1 to 1.0, use 1.0 for the best quality or 0.7 for the default",
+            quality
+        ))
+    }
+}
+
+pub fn parse_quality(value: &str) -> Result<f32, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", value))
+        .and_then(check_quality)
+}
+
+// Lower rates cannot hold much of the audio and a typo like 441000 would take ten times the memory and time.
+pub fn check_sample_rate(sample_rate: u32) -> Result<u32, String> {
+    if SAMPLE_RATES.contains(&sample_rate) {
+        Ok(sample_rate)
+    } else {
+        Err(format!(
+            "sample rate {} Hz is outside of {} to {} Hz",
+            sample_rate,
+            SAMPLE_RATES.start(),
+            SAMPLE_RATES.end()
+        ))
+    }
+}
+
+pub fn parse_sample_rate(value: &str) -> Result<u32, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not a sample rate in Hz", value))
+        .and_then(check_sample_rate)
+}
+
+pub fn parse_resampler(name: &str) -> Result<ConverterType, String> {
+    match name {
+        "sinc-best" => Ok(ConverterType::SincBestQuality),
+        "sinc-medium" => Ok(ConverterType::SincMediumQuality),
+        "sinc-fastest" => Ok(ConverterType::SincFastest),
+        "zero-order-hold" => Ok(ConverterType::ZeroOrderHold),
+        "linear" => Ok(ConverterType::Linear),
+        _ => Err(format!(
+            "unknown resampler '{}', expected one of sinc-best, sinc-medium, sinc-fastest, zero-order-hold, linear",
+            name
+        )),
+    }
+}
+
+pub fn parse_upmix(name: &str) -> Result<UpmixLaw, String> {
+    match name {
+        "duplicate" => Ok(UpmixLaw::Duplicate),
+        "center" => Ok(UpmixLaw::Center),
+        _ => Err(format!(
+            "unknown upmix law '{}', expected one of duplicate, center",
+            name
+        )),
+    }
+}
+
+pub fn parse_pan_mode(name: &str) -> Result<PanMode, String> {
+    match name {
+        "balance" => Ok(PanMode::Balance),
+        "true" => Ok(PanMode::TruePan),
+        _ => Err(format!("unknown pan mode '{}', expected one of balance, true", name)),
+    }
+}
+
+pub fn parse_missing_policy(name: &str) -> Result<MissingPolicy, String> {
+    match name {
+        "error" => Ok(MissingPolicy::Error),
+        "skip" => Ok(MissingPolicy::Skip),
+        "silence" => Ok(MissingPolicy::Silence),
+        _ => Err(format!(
+            "unknown missing sample policy '{}', expected one of error, skip, silence",
+            name
+        )),
+    }
+}
+
+pub fn parse_range_policy(name: &str) -> Result<RangePolicy, String> {
+    match name {
+        "error" => Ok(RangePolicy::Error),
+        "clamp" => Ok(RangePolicy::Clamp),
+        "wrap" => Ok(RangePolicy::Wrap),
+        _ => Err(format!(
+            "unknown range policy '{}', expected one of error, clamp, wrap",
+            name
+        )),
+    }
+}
+
+pub fn parse_clip_policy(name: &str) -> Result<ClipPolicy, String> {
+    match name {
+        "clamp" => Ok(ClipPolicy::Clamp),
+        "soft" => Ok(ClipPolicy::Soft),
+        "normalize" => Ok(ClipPolicy::Normalize),
+        "error" => Ok(ClipPolicy::Error),
+        _ => Err(format!(
+            "unknown clip policy '{}', expected one of clamp, soft, normalize, error",
+            name
+        )),
+    }
+}
+
+pub fn parse_precision(name: &str) -> Result<Precision, String> {
+    match name {
+        "f32" => Ok(Precision::Single),
+        "f64" => Ok(Precision::Double),
+        _ => Err(format!("unknown precision '{}', expected one of f32, f64", name)),
+    }
+}
+
+// A peak like -1dB or a loudness like -23LUFS, both at most 0.
+pub fn parse_sample_normalization(value: &str) -> Result<SampleNormalization, String> {
+    let lower = value.trim().to_ascii_lowercase();
+    let (level, normalization): (&str, fn(f32) -> SampleNormalization) = match lower.strip_suffix("lufs") {
+        Some(level) => (level, SampleNormalization::Loudness),
+        None => match lower.strip_suffix("db") {
+            Some(level) => (level, SampleNormalization::Peak),
+            None => ("", SampleNormalization::Peak),
+        },
+    };
+    match level.trim().parse::<f32>() {
+        Ok(level) if level.is_finite() && level <= 0.0 => Ok(normalization(level)),
+        _ => Err(format!(
+            "invalid sample level '{}', expected a peak like -1dB or a loudness like -23LUFS",
+            value
+        )),
+    }
+}
+
+pub fn parse_decode_error_policy(name: &str) -> Result<DecodeErrorPolicy, String> {
+    match name {
+        "fail" => Ok(DecodeErrorPolicy::Fail),
+        "skip" => Ok(DecodeErrorPolicy::Skip),
+        "silence" => Ok(DecodeErrorPolicy::Silence),
+        _ => Err(format!(
+            "unknown decode error policy '{}', expected one of fail, skip, silence",
+            name
+        )),
+    }
+}
@@ -0,0 +1,565 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use symphonia::core::audio::{Channels, SampleBuffer};
+use symphonia::core::codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, Track};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use log::{debug, info, warn};
+
+use crate::resample::{convert, ConverterType};
+
+// How mono samples are spread to both channels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpmixLaw {
+    // The same signal at full level on both sides, so a mono sample sounds about 3 dB louder than a stereo one.
+    Duplicate,
+    // -3 dB on both sides, the same loudness as a centred stereo sample.
+    Center,
+}
+
+pub fn read_audio(
+    path: &Path,
+    output_rate: u32,
+    resampler: ConverterType,
+    upmix: UpmixLaw,
+    track_number: Option<usize>,
+    salvage: bool,
+    strict: bool,
+) -> Result<Vec<f32>, Box<dyn Error>> {
+    let mut format = open_format(path)?;
+
+    let track = find_track(&*format, track_number)?;
+
+    let params = &track.codec_params;
+    if params.delay.is_some() || params.padding.is_some() {
+        debug!(
+            "{}: trimming {} frames of encoder delay and {} frames of padding",
+            path.display(),
+            params.delay.unwrap_or(0),
+            params.padding.unwrap_or(0)
+        );
+    }
+
+    // Use the default options for the decoder.
+    let dec_opts: DecoderOptions = Default::default();
+
+    // Create a decoder for the track.
+    let mut decoder = make_decoder(&track.codec_params, &dec_opts)?;
+
+    // Store the track identifier, it will be used to filter packets.
+    let mut track_id = track.id;
+
+    let mut data = Vec::new();
+
+    // Finished parts of the file with their sample rate, when it changes in the middle of the stream.
+    let mut segments: Vec<(u32, Vec<f32>)> = Vec::new();
+
+    let mut channels = None;
+
+    let mut sample_rate = 0;
+
+    // Seconds decoded before the current packet, to tell where damaged samples are.
+    let mut position = 0.0;
+    let mut damaged = 0;
+    let mut first_damaged = None;
+
+    // The decode loop.
+    loop {
+        // Get the next packet from the media format.
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(symphonia::core::errors::Error::ResetRequired) => {
+                // The track list has been changed, which chained OGG files do at the start of every further
+                // logical stream. Pick the audio track again and continue with a new decoder for it.
+                let (id, new_decoder) = reset_decoder(&*format, track_number, &dec_opts)?;
+                track_id = id;
+                decoder = new_decoder;
+                continue;
+            }
+            Err(err) => {
+                // A unrecoverable error occured, halt decoding.\
+                // This is totally how you do it
+                if err.to_string() == "end of stream" {
+                    break;
+                }
+                if salvage && !(data.is_empty() && segments.is_empty()) {
+                    warn!("{}: {}, keeping the audio decoded before it", path.display(), err);
+                    break;
+                }
+                return Err(err.into());
+            }
+        };
+
+        // Consume any new metadata that has been read since the last packet.
+        while !format.metadata().is_latest() {
+            // Pop the old head of the metadata queue.
+            format.metadata().pop();
+
+            // Consume the new metadata at the head of the metadata queue.
+        }
+
+        // If the packet does not belong to the selected track, skip over it.
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        // Decode the packet into audio samples.
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                if spec.rate != sample_rate && !data.is_empty() {
+                    let message = format!(
+                        "the sample rate changes from {} Hz to {} Hz, resampling both parts separately",
+                        sample_rate, spec.rate
+                    );
+                    let message = fix_up(path, strict, message)?;
+                    info!("{}", message);
+                    segments.push((sample_rate, std::mem::take(&mut data)));
+                }
+                sample_rate = spec.rate;
+                match channels {
+                    None => channels = Some(spec.channels),
+                    Some(previous) if previous != spec.channels => {
+                        debug!(
+                            "{}: the channels change from {} to {}",
+                            path.display(),
+                            previous.count(),
+                            spec.channels.count()
+                        );
+                        channels = Some(spec.channels);
+                    }
+                    Some(_) => {}
+                }
+
+                // Copy the decoded audio into a buffer with the file's own channel layout, converting the
+                // samples to f32, and mix that down to stereo.
+                let mut sample_buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                sample_buf.copy_interleaved_ref(decoded);
+
+                // NaN and infinity from damaged files would spread through the sum to everything mixed after them,
+                // so they are silenced here.
+                let gains = downmix_gains(spec.channels, upmix);
+                for (index, frame) in sample_buf.samples().chunks_exact(gains.len()).enumerate() {
+                    let (mut left, mut right) = (0.0, 0.0);
+                    for (value, (left_gain, right_gain)) in frame.iter().zip(&gains) {
+                        let value = if value.is_finite() {
+                            *value
+                        } else {
+                            damaged += 1;
+                            first_damaged.get_or_insert(position + index as f64 / spec.rate as f64);
+                            0.0
+                        };
+                        left += value * left_gain;
+                        right += value * right_gain;
+                    }
+                    data.push(left);
+                    data.push(right);
+                }
+                position += sample_buf.samples().len() as f64 / gains.len() as f64 / spec.rate as f64;
+            }
+
+            Err(symphonia::core::errors::Error::IoError(err)) => {
+                // The packet failed to decode due to an IO error, skip the packet.
+                let message = fix_up(path, strict, format!("skipping packet: {}", err))?;
+                warn!("{}", message);
+                continue;
+            }
+            Err(symphonia::core::errors::Error::DecodeError(err)) => {
+                // The packet failed to decode due to invalid data, skip the packet.
+                let message = fix_up(path, strict, format!("skipping packet: {}", err))?;
+                warn!("{}", message);
+                continue;
+            }
+            Err(err) => {
+                // An unrecoverable error occured, halt decoding.
+                if salvage && !(data.is_empty() && segments.is_empty()) {
+                    warn!("{}: {}, keeping the audio decoded before it", path.display(), err);
+                    break;
+                }
+                return Err(err.into());
+            }
+        }
+    }
+
+    if let Some(first) = first_damaged {
+        let message = format!(
+            "replaced {} damaged samples (NaN or infinite) with silence, the first at {:.3} s",
+            damaged, first
+        );
+        let message = fix_up(path, strict, message)?;
+        warn!("{}", message);
+    }
+
+    if let Some(channels) = channels.filter(|channels| channels.count() != 2) {
+        let message = format!("{} channels, converting to stereo", channels.count());
+        let message = fix_up(path, strict, message)?;
+        info!("{}", message);
+    }
+
+    if !segments.is_empty() {
+        // Every part is resampled on its own, so each one keeps its pitch and length.
+        segments.push((sample_rate, data));
+        let mut resampled = Vec::new();
+        for (rate, segment) in segments {
+            if rate == output_rate || segment.is_empty() {
+                resampled.extend(segment);
+            } else {
+                resampled.extend(convert(rate, output_rate, 2, resampler, &segment)?);
+            }
+        }
+        return Ok(resampled);
+    }
+
+    if sample_rate != output_rate {
+        let message = format!("resampling {} Hz to {} Hz", sample_rate, output_rate);
+        let message = fix_up(path, strict, message)?;
+        info!("{}", message);
+        data = convert(sample_rate, output_rate, 2, resampler, &data)?;
+    }
+
+    Ok(data)
+}
+
+// Decodes a file with the ffmpeg binary on the PATH, for formats symphonia does not support. ffmpeg also converts
+// it to stereo and resamples it, with its own downmix and resampler.
+pub fn read_with_ffmpeg(
+    path: &Path,
+    output_rate: u32,
+    track_number: Option<usize>,
+) -> Result<Vec<f32>, Box<dyn Error>> {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-v", "error", "-nostdin", "-i"]).arg(path);
+    if let Some(number) = track_number {
+        command.args(["-map".to_string(), format!("0:a:{}", number - 1)]);
+    }
+    command.args(["-f", "f32le", "-ac", "2", "-ar", &output_rate.to_string(), "-"]);
+
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("could not run ffmpeg: {}", err))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or("").trim();
+        return Err(format!("ffmpeg failed: {}", reason).into());
+    }
+
+    Ok(output
+        .stdout
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .map(|value| if value.is_finite() { value } else { 0.0 })
+        .collect())
+}
+
+// Something read_audio changed or left out to make a file usable. With --strict that is an error, otherwise the
+// message to log.
+fn fix_up(path: &Path, strict: bool, message: String) -> Result<String, Box<dyn Error>> {
+    if strict {
+        Err(format!("{}, which --strict does not allow", message).into())
+    } else {
+        Ok(format!("{}: {}", path.display(), message))
+    }
+}
+
+// Left and right gain of every channel in the order they are interleaved. Mono is played on both sides as the
+// upmix law says, the others follow the usual downmix: centre channels at -3 dB on both sides, surrounds at -3 dB on their side and
+// the LFE channels are left out.
+fn downmix_gains(channels: Channels, upmix: UpmixLaw) -> Vec<(f32, f32)> {
+    const HALF: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    if channels.count() <= 2 {
+        return match (channels.count(), upmix) {
+            (1, UpmixLaw::Duplicate) => vec![(1.0, 1.0)],
+            (1, UpmixLaw::Center) => vec![(HALF, HALF)],
+            _ => vec![(1.0, 0.0), (0.0, 1.0)],
+        };
+    }
+
+    let left = Channels::FRONT_LEFT | Channels::FRONT_LEFT_WIDE | Channels::FRONT_LEFT_HIGH;
+    let right = Channels::FRONT_RIGHT | Channels::FRONT_RIGHT_WIDE | Channels::FRONT_RIGHT_HIGH;
+    let left_surround = Channels::REAR_LEFT
+        | Channels::SIDE_LEFT
+        | Channels::FRONT_LEFT_CENTRE
+        | Channels::REAR_LEFT_CENTRE
+        | Channels::TOP_FRONT_LEFT
+        | Channels::TOP_REAR_LEFT;
+    let right_surround = Channels::REAR_RIGHT
+        | Channels::SIDE_RIGHT
+        | Channels::FRONT_RIGHT_CENTRE
+        | Channels::REAR_RIGHT_CENTRE
+        | Channels::TOP_FRONT_RIGHT
+        | Channels::TOP_REAR_RIGHT;
+    let lfe = Channels::LFE1 | Channels::LFE2;
+
+    channels
+        .iter()
+        .map(|channel| {
+            if left.contains(channel) {
+                (1.0, 0.0)
+            } else if right.contains(channel) {
+                (0.0, 1.0)
+            } else if left_surround.contains(channel) {
+                (HALF, 0.0)
+            } else if right_surround.contains(channel) {
+                (0.0, HALF)
+            } else if lfe.contains(channel) {
+                (0.0, 0.0)
+            } else {
+                (HALF, HALF)
+            }
+        })
+        .collect()
+}
+
+// The format is recognized by the content of the file, the extension is only a hint. Files without an extension or
+// with the wrong one, like an Ogg Vorbis file named .wav, are read as what they are.
+fn open_format(path: &Path) -> Result<Box<dyn FormatReader>, Box<dyn Error>> {
+    let src = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(src), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    // Lossy encoders put some silence in front of the audio and pad the end to a whole frame. With gapless playback
+    // the decoders cut both off as far as the file tells how long they are (LAME tags of MP3 files, the granule
+    // positions of Ogg files), so these samples start exactly at their time like WAV files.
+    let meta_opts: MetadataOptions = Default::default();
+    let fmt_opts = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+    match symphonia::default::get_probe().format(&hint, mss, &fmt_opts, &meta_opts) {
+        Ok(probed) => Ok(probed.format),
+        Err(SymphoniaError::Unsupported(_)) => Err("the content is not in any audio format this build can read".into()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// The codecs are features of their own, a container like MP4 can be read without the codec of its audio.
+fn make_decoder(params: &CodecParameters, options: &DecoderOptions) -> Result<Box<dyn Decoder>, Box<dyn Error>> {
+    match symphonia::default::get_codecs().make(params, options) {
+        Ok(decoder) => Ok(decoder),
+        Err(SymphoniaError::Unsupported(_)) => Err("the codec of the audio is not in this build".into()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// The audio track with this number, counting from 1 and only the tracks with a known codec, or the first one.
+fn find_track(format: &dyn FormatReader, number: Option<usize>) -> Result<&Track, String> {
+    let mut tracks = format
+        .tracks()
+        .iter()
+        .filter(|t| t.codec_params.codec != CODEC_TYPE_NULL);
+    match number {
+        None => tracks.next().ok_or_else(|| "no supported audio tracks".to_string()),
+        Some(number) => {
+            let count = tracks.clone().count();
+            tracks.nth(number.saturating_sub(1)).ok_or_else(|| match count {
+                0 => "no supported audio tracks".to_string(),
+                1 => format!("there is no audio track {}, the file only has one", number),
+                _ => format!("there is no audio track {}, the file has {}", number, count),
+            })
+        }
+    }
+}
+
+fn reset_decoder(
+    format: &dyn FormatReader,
+    number: Option<usize>,
+    options: &DecoderOptions,
+) -> Result<(u32, Box<dyn Decoder>), Box<dyn Error>> {
+    let track = find_track(format, number).map_err(|err| format!("{} after a stream reset", err))?;
+    let decoder = make_decoder(&track.codec_params, options)?;
+    Ok((track.id, decoder))
+}
+
+pub struct AudioInfo {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub frames: u64,
+}
+
+impl AudioInfo {
+    pub fn duration(&self) -> f32 {
+        self.frames as f32 / self.sample_rate as f32
+    }
+}
+
+// Checks that a file can be opened and decoded without decoding more of it than necessary.
+pub fn probe_audio(path: &Path, track_number: Option<usize>) -> Result<AudioInfo, Box<dyn Error>> {
+    let mut format = open_format(path)?;
+
+    let track = find_track(&*format, track_number)?;
+    let params = track.codec_params.clone();
+    let mut track_id = track.id;
+
+    let mut decoder = make_decoder(&params, &Default::default())?;
+
+    if let (Some(sample_rate), Some(channels), Some(frames)) = (params.sample_rate, params.channels, params.n_frames) {
+        return Ok(AudioInfo {
+            sample_rate,
+            channels: channels.count(),
+            frames,
+        });
+    }
+
+    // The container does not know its length, so count the decoded frames instead.
+    let mut info = AudioInfo {
+        sample_rate: params.sample_rate.unwrap_or(0),
+        channels: params.channels.map(|channels| channels.count()).unwrap_or(0),
+        frames: 0,
+    };
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(symphonia::core::errors::Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(symphonia::core::errors::Error::ResetRequired) => {
+                (track_id, decoder) = reset_decoder(&*format, track_number, &Default::default())?;
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = decoder.decode(&packet)?;
+        info.sample_rate = decoded.spec().rate;
+        info.channels = decoded.spec().channels.count();
+        info.frames += decoded.frames() as u64;
+    }
+
+    if info.sample_rate == 0 {
+        return Err("could not determine the sample rate".into());
+    }
+
+    Ok(info)
+}
+
+// The first loop of the `smpl` chunk of a WAV file, in frames at the file's own sample rate. The end is the frame after
+// the last one of the loop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleLoop {
+    pub sample_rate: u32,
+    pub start: u64,
+    pub end: u64,
+}
+
+// Bytes of a `smpl` chunk up to the end of its first loop, which is more than a `fmt ` chunk needs.
+const LOOP_CHUNK_SIZE: usize = 36 + 24;
+
+// Looks for loop points in a WAV file. Other files, files without a `smpl` chunk and empty loops have none.
+pub fn read_sample_loop(path: &Path) -> Option<SampleLoop> {
+    let mut file = File::open(path).ok()?;
+    let mut header = [0; 12];
+    file.read_exact(&mut header).ok()?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut sample_rate = None;
+    let mut sample_loop = None;
+    let mut chunk = [0; 8];
+    while file.read_exact(&mut chunk).is_ok() {
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+        match &chunk[0..4] {
+            b"fmt " | b"smpl" => {
+                // The sample rate and the first loop are all that is needed, whatever size the chunk claims.
+                let read = size.min(LOOP_CHUNK_SIZE);
+                let mut body = vec![0; read];
+                file.read_exact(&mut body).ok()?;
+                let word = |at: usize| {
+                    body.get(at..at + 4)
+                        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                };
+                if &chunk[0..4] == b"fmt " {
+                    sample_rate = word(4);
+                } else if word(28).unwrap_or(0) > 0 {
+                    // After the 36 byte header come loops of 24 bytes: id, type, start, inclusive end, ...
+                    sample_loop = word(44).zip(word(48));
+                }
+                file.seek(SeekFrom::Current((size - read + size % 2) as i64)).ok()?;
+            }
+            // Chunks are padded to an even length.
+            _ => {
+                file.seek(SeekFrom::Current((size + size % 2) as i64)).ok()?;
+            }
+        }
+        if let (Some(sample_rate), Some((start, end))) = (sample_rate, sample_loop) {
+            return (end >= start && sample_rate > 0).then_some(SampleLoop {
+                sample_rate,
+                start: start as u64,
+                end: end as u64 + 1,
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+
+    // Loop of a WAV file made of these chunks after the RIFF header, all of them claiming `claimed` bytes if given.
+    fn sample_loop(name: &str, chunks: &[(&[u8; 4], Vec<u8>)], claimed: Option<u32>) -> Option<SampleLoop> {
+        let mut body = b"WAVE".to_vec();
+        for (id, data) in chunks {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&claimed.unwrap_or(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        file.extend(body);
+        let path = env::temp_dir().join(format!("audio-sample-mixer-test-{}-{}.wav", process::id(), name));
+        fs::write(&path, file).unwrap();
+        let sample_loop = read_sample_loop(&path);
+        fs::remove_file(&path).unwrap();
+        sample_loop
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn sample_loops() {
+        let fmt = words(&[0x0002_0001, 44100, 44100 * 4, 0x0010_0004]);
+        let smpl = words(&[0, 0, 22675, 60, 0, 0, 0, 1, 0, 0, 0, 1000, 1999, 0, 0]);
+        assert_eq!(
+            sample_loop(
+                "loop",
+                &[(b"fmt ", fmt.clone()), (b"LIST", vec![0; 3]), (b"smpl", smpl)],
+                None
+            ),
+            Some(SampleLoop {
+                sample_rate: 44100,
+                start: 1000,
+                end: 2000
+            })
+        );
+        assert_eq!(sample_loop("no-loop", &[(b"fmt ", fmt)], None), None);
+        // A chunk that claims 4 GB ends with the file, which is all that is read of it.
+        assert_eq!(sample_loop("huge", &[(b"fmt ", vec![0; 16])], Some(u32::MAX)), None);
+    }
+}
@@ -0,0 +1,92 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+
+// Samples named by an http:// or https:// URL are downloaded with curl into a cache shared by all projects. Files are
+// stored under the SHA-256 checksum of their content, so a sample behind several URLs is only kept once, and every
+// URL remembers the checksum it was downloaded as, so it is only downloaded once.
+pub fn is_url(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+// ~/.cache/audio-sample-mixer or the same below $XDG_CACHE_HOME.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let cache_dir = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_dir.join("audio-sample-mixer"))
+}
+
+pub fn fetch(url: &str, cache_dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let files = cache_dir.join("files");
+    let urls = cache_dir.join("urls");
+    let entry = urls.join(checksum(url.as_bytes()));
+
+    if let Ok(checksum) = fs::read_to_string(&entry) {
+        let path = files.join(file_name(checksum.trim(), url));
+        if path.is_file() {
+            debug!("{} is cached as {}", url, path.display());
+            return Ok(path);
+        }
+    }
+
+    fs::create_dir_all(&files).map_err(|err| format!("could not create {}: {}", files.display(), err))?;
+    fs::create_dir_all(&urls).map_err(|err| format!("could not create {}: {}", urls.display(), err))?;
+
+    // Downloaded next to the cached files and only moved in place once complete, an interrupted download is not
+    // mistaken for the sample the next time.
+    let part = files.join(format!(".{}-{}.part", checksum(url.as_bytes()), process::id()));
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location"])
+        .args(["--proto", "=http,https", "--output"])
+        .arg(&part)
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("could not run curl: {}", err))?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&part);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or("").trim();
+        return Err(format!("curl failed: {}", reason).into());
+    }
+
+    let data = fs::read(&part).map_err(|err| format!("could not read {}: {}", part.display(), err))?;
+    let content = checksum(&data);
+    let path = files.join(file_name(&content, url));
+    fs::rename(&part, &path)
+        .map_err(|err| format!("could not move {} to {}: {}", part.display(), path.display(), err))?;
+    fs::write(&entry, &content).map_err(|err| format!("could not write {}: {}", entry.display(), err))?;
+
+    info!("downloaded {} ({} bytes) to {}", url, data.len(), path.display());
+    Ok(path)
+}
+
+fn checksum(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+// The extension of the URL is kept as a hint for the decoder.
+fn file_name(checksum: &str, url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let extension = path
+        .rsplit_once('/')
+        .and_then(|(_, last)| last.rsplit_once('.'))
+        .map(|(_, extension)| extension)
+        .filter(|extension| {
+            !extension.is_empty() && extension.len() <= 5 && extension.bytes().all(|byte| byte.is_ascii_alphanumeric())
+        });
+    match extension {
+        Some(extension) => format!("{}.{}", checksum, extension.to_ascii_lowercase()),
+        None => checksum.to_string(),
+    }
+}
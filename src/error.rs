@@ -0,0 +1,117 @@
+use std::error::Error;
+use std::fmt;
+
+use log::error;
+use serde::Serialize;
+
+// The exit codes are part of the interface for scripts, existing values must not change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    Arguments,
+    Input,
+    MissingSample,
+    Encode,
+    Decode,
+    Output,
+    Validation,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Arguments => 2,
+            ErrorKind::Input => 3,
+            ErrorKind::MissingSample => 4,
+            ErrorKind::Encode => 5,
+            ErrorKind::Decode => 6,
+            ErrorKind::Output => 7,
+            ErrorKind::Validation => 8,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Arguments => "arguments",
+            ErrorKind::Input => "input",
+            ErrorKind::MissingSample => "missing-sample",
+            ErrorKind::Encode => "encode",
+            ErrorKind::Decode => "decode",
+            ErrorKind::Output => "output",
+            ErrorKind::Validation => "validation",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AppError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl AppError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> AppError {
+        AppError {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for AppError {}
+
+pub trait WithKind<T> {
+    // Turns any error into an AppError of `kind`, unless it already is one.
+    fn with_kind(self, kind: ErrorKind) -> Result<T, Box<dyn Error>>;
+}
+
+impl<T, E: Into<Box<dyn Error>>> WithKind<T> for Result<T, E> {
+    fn with_kind(self, kind: ErrorKind) -> Result<T, Box<dyn Error>> {
+        self.map_err(|err| {
+            let err = err.into();
+            if err.is::<AppError>() {
+                err
+            } else {
+                AppError::new(kind, err.to_string()).into()
+            }
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError<'a> {
+    code: i32,
+    kind: &'a str,
+    message: String,
+}
+
+pub fn kind_of(err: &(dyn Error + 'static)) -> Option<ErrorKind> {
+    err.downcast_ref::<AppError>().map(|err| err.kind)
+}
+
+pub fn to_json(err: &(dyn Error + 'static)) -> String {
+    let kind = kind_of(err);
+    let json = JsonError {
+        code: kind.map(ErrorKind::exit_code).unwrap_or(1),
+        kind: kind.map(ErrorKind::name).unwrap_or("other"),
+        message: err.to_string(),
+    };
+    serde_json::to_string(&json).expect("could not serialize error")
+}
+
+// Prints the error as a log message or as one JSON object on stderr and returns the exit code for it.
+// Errors that were not classified get exit code 1.
+pub fn report(err: &(dyn Error + 'static), json: bool) -> i32 {
+    if json {
+        eprintln!("{}", to_json(err));
+    } else {
+        error!("{}", err);
+    }
+
+    kind_of(err).map(ErrorKind::exit_code).unwrap_or(1)
+}
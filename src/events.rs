@@ -0,0 +1,900 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::num::ParseFloatError;
+use std::path::{Path, PathBuf};
+
+use csv::{ReaderBuilder, StringRecord};
+use encoding_rs::{Encoding, SHIFT_JIS, UTF_8};
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::error::{AppError, ErrorKind};
+use crate::lmms::read_project;
+use crate::mix::PanMode;
+use crate::osu::read_beatmap;
+use crate::renoise::read_song;
+use crate::script;
+use crate::tracker::read_module;
+
+#[derive(Clone, Debug)]
+pub struct AudioSampleInfo {
+    // ms, as f64 so positions given in samples stay exact
+    pub time: f64,
+    pub volume: f32,
+    pub pan: f32,
+    pub name: String,
+    // Line of the CSV file, for messages
+    pub line: u64,
+    // Lane or note column of a rhythm game, from the lane column of a CSV file.
+    pub lane: Option<String>,
+    // ms the sample is looped or cut to, from the hold column of a CSV file. None plays it once.
+    pub hold: Option<f64>,
+    // Semitones the sample is played higher or lower, from the pitch column of a CSV file.
+    pub pitch: Option<f32>,
+    // From a b or t suffix of the pan in a CSV file, None uses --pan-mode.
+    pub pan_mode: Option<PanMode>,
+    // From the tag column of a CSV file, for --only-tag and --mute-tag.
+    pub tags: Vec<String>,
+}
+
+// A point on the timeline of a chart, from a CSV row with only a time in ms and a label.
+#[derive(Clone, Debug, Serialize)]
+pub struct Marker {
+    pub time: f64,
+    pub label: String,
+}
+
+// Settings of the events of one lane: a gain in dB, a time offset in ms and whether it is left out.
+#[derive(Clone, Debug, Default)]
+pub struct Lane {
+    pub gain: f32,
+    pub offset: f64,
+    pub mute: bool,
+}
+
+const COLUMNS: [&str; 4] = ["time", "volume", "pan", "file"];
+// MIDI note number of C4, the root note of samples without one.
+const MIDDLE_C: i32 = 60;
+// Semitones an event can be played higher or lower, 4 octaves down already plays a sample 16 times as long.
+const MAX_PITCH: f32 = 48.0;
+// Extensions of tracker modules.
+const MODULE_EXTENSIONS: [&str; 4] = ["mod", "xm", "it", "s3m"];
+const MAX_PROBLEMS: usize = 20;
+const SNIPPET_LENGTH: usize = 80;
+
+// How the numbers of a CSV file are written.
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    // Accept 0,75 as 0.75. Files separated by ';' or tabs always accept it.
+    pub decimal_comma: bool,
+    // Times with the smp suffix are counted at this sample rate.
+    pub sample_rate: u32,
+    // Volumes are in dB. A header row can also say so for one file, and volumes with a dB suffix always are.
+    pub volume_db: bool,
+    // Log rows that cannot be read and leave them out instead of failing.
+    pub skip_bad_rows: bool,
+    pub range_policy: RangePolicy,
+    // Difficulty of an .osz archive with several of them.
+    pub difficulty: Option<String>,
+    // Rhai script whose transform function changes the events after they are read.
+    pub script: Option<PathBuf>,
+    // Columns of a CSV file with the lane and the hold duration of every event, counting from 1.
+    pub lane_column: Option<usize>,
+    pub hold_column: Option<usize>,
+    // Column with the pitch of every event, in semitones or as a note name that is compared to the root note of the
+    // sample, a MIDI note number. Samples without one are at C4.
+    pub pitch_column: Option<usize>,
+    // Column with tags of every event separated by spaces, only events with one of only_tags and none of mute_tags
+    // are read when they are given.
+    pub tag_column: Option<usize>,
+    pub only_tags: Vec<String>,
+    pub mute_tags: Vec<String>,
+    // Sample names or patterns with * and ?, only events of the solo samples and none of the muted ones are read.
+    pub solo: Vec<String>,
+    pub mute: Vec<String>,
+    pub root_notes: BTreeMap<String, i32>,
+    pub lanes: BTreeMap<String, Lane>,
+    // ms every event of a sample is moved by, for samples with silence or a slow attack before their hit.
+    pub sample_offsets: BTreeMap<String, f64>,
+    // Speed of the whole chart like a rate mod of a rhythm game, 1.5 plays it in two thirds of the time. The samples
+    // are played faster and higher with it, or as they are with keep_pitch.
+    pub rate: f64,
+    pub keep_pitch: bool,
+    // ms every event and marker is moved by after the rate, to sync a render with a backing track or video.
+    pub offset: f64,
+}
+
+// What happens to volumes outside of 0.0 to 1.0 and pans outside of -1.0 to 1.0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RangePolicy {
+    Error,
+    // Use the nearest value in the range.
+    Clamp,
+    // Continue from the other end of the range, a pan of 1.5 becomes -0.5.
+    Wrap,
+}
+
+// osu! beatmaps are read instead of CSV files when they have the extension .osu or .osz, tracker modules with .mod,
+// .xm, .it or .s3m, LMMS projects with .mmp or .mmpz and Renoise songs with .xrns.
+pub fn read_events(path: &Path, options: &CsvOptions) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    read_events_and_markers(path, options).map(|(infos, _)| infos)
+}
+
+// Like read_events, but also returns the marker rows of CSV files. Other formats have none.
+pub fn read_events_and_markers(
+    path: &Path,
+    options: &CsvOptions,
+) -> Result<(Vec<AudioSampleInfo>, Vec<Marker>), Box<dyn Error>> {
+    let source = path.display().to_string();
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let without_markers = |infos: Vec<AudioSampleInfo>| (infos, Vec::new());
+    let parsed = if extension == "osu" || extension == "osz" {
+        read_beatmap(path, options).map(without_markers)
+    } else if MODULE_EXTENSIONS.contains(&extension.as_str()) {
+        read_module(path).map(without_markers)
+    } else if extension == "mmp" || extension == "mmpz" {
+        read_project(path).map(without_markers)
+    } else if extension == "xrns" {
+        read_song(path).map(without_markers)
+    } else {
+        File::open(path)
+            .map_err(|err| err.into())
+            .and_then(|file| parse_events(file, &source, options))
+    };
+    parsed
+        .and_then(|(infos, mut markers)| {
+            let mut infos = apply_filters(apply_lanes(run_script(infos, options)?, options), options);
+            move_events(&mut infos, &mut markers, options);
+            Ok((infos, markers))
+        })
+        .map_err(|err| AppError::new(ErrorKind::Input, format!("{}: {}", source, err)).into())
+}
+
+// Reads events from CSV data that does not come from a file.
+pub fn read_events_from<R: Read>(reader: R, options: &CsvOptions) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    parse_events(reader, "input", options)
+        .and_then(|(infos, _)| run_script(infos, options))
+        .map(|infos| {
+            let mut infos = apply_filters(apply_lanes(infos, options), options);
+            move_events(&mut infos, &mut [], options);
+            infos
+        })
+        .map_err(|err| AppError::new(ErrorKind::Input, err.to_string()).into())
+}
+
+// Leaves out the events of muted lanes and moves and turns up or down the others, and moves the events of samples with
+// an offset. After the script so it sees every event as it is in the file.
+fn apply_lanes(infos: Vec<AudioSampleInfo>, options: &CsvOptions) -> Vec<AudioSampleInfo> {
+    if options.lanes.is_empty() && options.sample_offsets.is_empty() {
+        return infos;
+    }
+    let count = infos.len();
+    let infos: Vec<AudioSampleInfo> = infos
+        .into_iter()
+        .filter_map(|mut info| {
+            if let Some(offset) = options.sample_offsets.get(&info.name) {
+                info.time += offset;
+            }
+            if let Some(lane) = info.lane.as_ref().and_then(|lane| options.lanes.get(lane)) {
+                if lane.mute {
+                    return None;
+                }
+                info.time += lane.offset;
+                info.volume *= 10f32.powf(lane.gain / 20.0);
+            }
+            Some(info)
+        })
+        .collect();
+    if infos.len() < count {
+        info!("left out {} events of muted lanes", count - infos.len());
+    }
+    infos
+}
+
+// Leaves out the events without one of --only-tag and those with one of --mute-tag, and by their sample name those
+// not matching --solo and those matching --mute, also after the script.
+fn apply_filters(infos: Vec<AudioSampleInfo>, options: &CsvOptions) -> Vec<AudioSampleInfo> {
+    if options.only_tags.is_empty()
+        && options.mute_tags.is_empty()
+        && options.solo.is_empty()
+        && options.mute.is_empty()
+    {
+        return infos;
+    }
+    for pattern in options.solo.iter().chain(&options.mute) {
+        if !infos.iter().any(|info| matches_name(pattern, &info.name)) {
+            warn!("no sample name matches '{}'", pattern);
+        }
+    }
+    let count = infos.len();
+    let infos: Vec<AudioSampleInfo> = infos
+        .into_iter()
+        .filter(|info| {
+            (options.only_tags.is_empty() || info.tags.iter().any(|tag| options.only_tags.contains(tag)))
+                && !info.tags.iter().any(|tag| options.mute_tags.contains(tag))
+                && (options.solo.is_empty() || options.solo.iter().any(|pattern| matches_name(pattern, &info.name)))
+                && !options.mute.iter().any(|pattern| matches_name(pattern, &info.name))
+        })
+        .collect();
+    if infos.len() < count {
+        info!("left out {} events by their tags or sample names", count - infos.len());
+    }
+    infos
+}
+
+// Whether a pattern with * for any text and ? for any character matches the whole sample name or its file name, so
+// kick* matches drums/kick1.wav.
+fn matches_name(pattern: &str, name: &str) -> bool {
+    let file_name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let pattern: Vec<char> = pattern.chars().collect();
+    glob(&pattern, &name.chars().collect::<Vec<_>>()) || glob(&pattern, &file_name.chars().collect::<Vec<_>>())
+}
+
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, _) => text.is_empty(),
+        (Some('*'), _) => glob(&pattern[1..], text) || (!text.is_empty() && glob(pattern, &text[1..])),
+        (Some('?'), Some(_)) => glob(&pattern[1..], &text[1..]),
+        (Some(expected), Some(found)) if expected == found => glob(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+// Moves the events and markers to where they are at --rate and plays the samples as many semitones higher as that
+// speeds them up, unless --keep-pitch leaves them as they are. Held events are held for a shorter time. Then
+// everything is moved by --offset.
+fn move_events(infos: &mut [AudioSampleInfo], markers: &mut [Marker], options: &CsvOptions) {
+    if options.rate == 1.0 && options.offset == 0.0 {
+        return;
+    }
+    for info in infos.iter_mut() {
+        if options.rate != 1.0 {
+            info.time /= options.rate;
+            info.hold = info.hold.map(|hold| hold / options.rate);
+            if !options.keep_pitch {
+                info.pitch = Some(info.pitch.unwrap_or(0.0) + rate_semitones(options.rate));
+            }
+        }
+        info.time += options.offset;
+    }
+    for marker in markers {
+        marker.time = marker.time / options.rate + options.offset;
+    }
+}
+
+// Semitones a sample is played higher to play it `rate` times as fast.
+pub fn rate_semitones(rate: f64) -> f32 {
+    (12.0 * rate.log2()) as f32
+}
+
+fn run_script(infos: Vec<AudioSampleInfo>, options: &CsvOptions) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    match &options.script {
+        Some(path) => script::transform(path, infos),
+        None => Ok(infos),
+    }
+}
+
+fn parse_events<R: Read>(
+    mut reader: R,
+    source: &str,
+    options: &CsvOptions,
+) -> Result<(Vec<AudioSampleInfo>, Vec<Marker>), Box<dyn Error>> {
+    let mut text = Vec::new();
+    reader.read_to_end(&mut text)?;
+    let text = to_utf8(text, source);
+
+    // Spreadsheets in locales with a decimal comma separate the columns with ';' instead.
+    let delimiter = detect_delimiter(&text);
+    let decimal_comma = options.decimal_comma || delimiter != b',';
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(text.as_slice());
+
+    let mut infos = Vec::new();
+    let mut markers = Vec::new();
+    // Every broken row is reported at once, so a file can be fixed in one go.
+    let mut problems = Vec::new();
+    let mut bad_rows = 0;
+    let mut out_of_range = 0;
+    let mut volume_db = options.volume_db;
+    let mut first = true;
+    let mut variables = BTreeMap::new();
+
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(err) => {
+                bad_rows += 1;
+                let line = err.position().map_or(0, |position| position.line());
+                let snippet = err
+                    .position()
+                    .map_or(String::new(), |position| snippet(&text, position.byte()));
+                problems.push(format!("line {}: {}{}", line, err, snippet));
+                continue;
+            }
+        };
+        let position = record
+            .position()
+            .expect("records read from a reader have a position")
+            .clone();
+        let line = position.line();
+        let row = snippet(&text, position.byte());
+
+        // A row like `$kick_gain = 0.8` defines a variable for the rows after it, which fill in `$kick_gain` in any of
+        // their fields. Defining it again changes it from there on.
+        if let Some(definition) = record.get(0).and_then(|field| field.trim().strip_prefix('$')) {
+            let definition = record
+                .iter()
+                .skip(1)
+                .fold(definition.to_string(), |row, field| row + "," + field);
+            match definition
+                .split_once('=')
+                .map(|(name, value)| (name.trim(), value.trim()))
+            {
+                Some((name, value)) if is_variable_name(name) => {
+                    let value = substitute(value, &variables);
+                    variables.insert(name.to_string(), value);
+                }
+                _ => {
+                    bad_rows += 1;
+                    problems.push(format!(
+                        "line {}: expected a variable like $name = value, names are letters, digits and _{}",
+                        line, row
+                    ));
+                }
+            }
+            continue;
+        }
+        let record = if record.iter().any(|field| field.contains('$')) {
+            record
+                .iter()
+                .map(|field| substitute(field, &variables))
+                .collect::<StringRecord>()
+        } else {
+            record
+        };
+
+        // A header row before the first event names the columns. Only the volume column changes anything: volume_db
+        // or db reads its volumes in dB.
+        if std::mem::take(&mut first)
+            && record
+                .get(0)
+                .is_some_and(|field| field.trim().eq_ignore_ascii_case("time"))
+        {
+            let volume = record.get(1).unwrap_or("").trim().to_lowercase();
+            volume_db |= matches!(volume.as_str(), "volume_db" | "db");
+            continue;
+        }
+
+        if record.len() == 2 {
+            match parse_time(&record[0], decimal_comma, options.sample_rate) {
+                Ok(time) => markers.push(Marker {
+                    time,
+                    label: record[1].trim().to_string(),
+                }),
+                Err(err) => {
+                    bad_rows += 1;
+                    problems.push(format!("line {}, marker time: {}{}", line, err, row));
+                }
+            }
+            continue;
+        }
+
+        // Further columns are ignored, they can hold notes like the ones written by scan.
+        if record.len() < COLUMNS.len() {
+            bad_rows += 1;
+            problems.push(format!(
+                "line {}: expected at least {} columns ({}), found {}{}",
+                line,
+                COLUMNS.len(),
+                COLUMNS.join(", "),
+                record.len(),
+                row
+            ));
+            continue;
+        }
+
+        let mut column_problem = |column: usize, message: String| {
+            problems.push(format!("line {}, column {}: {}{}", line, COLUMNS[column], message, row));
+        };
+        let time = parse_time(&record[0], decimal_comma, options.sample_rate).map_err(|err| column_problem(0, err));
+        let volume = parse_volume(&record[1], volume_db, decimal_comma)
+            .and_then(|(volume, db)| match options.range_policy {
+                RangePolicy::Error if db && volume > 1.0 => Err(format!(
+                    "'{}' is above 0 dB, which plays the sample at its own level",
+                    record[1].trim()
+                )),
+                RangePolicy::Error if volume < 0.0 => Err(format!(
+                    "volume {} is below 0.0, volumes in dB need --volume-db or a dB suffix",
+                    volume
+                )),
+                RangePolicy::Error => check_volume(volume),
+                policy => {
+                    fit_range(volume, 0.0, 1.0, policy, &mut out_of_range).map_or_else(|| check_volume(volume), Ok)
+                }
+            })
+            .map_err(|err| column_problem(1, err));
+        let (pan_field, pan_mode) = split_pan_mode(&record[2]);
+        let pan = parse_number(pan_field, decimal_comma)
+            .map_err(|_| format!("'{}' is not a number", &record[2]))
+            .and_then(|pan| match options.range_policy {
+                RangePolicy::Error => check_pan(pan as f32),
+                policy => fit_range(pan as f32, -1.0, 1.0, policy, &mut out_of_range)
+                    .map_or_else(|| check_pan(pan as f32), Ok),
+            })
+            .map_err(|err| column_problem(2, err));
+
+        let hold = match options
+            .hold_column
+            .and_then(|column| record.get(column - 1))
+            .map(str::trim)
+        {
+            Some(hold) if !hold.is_empty() => parse_time(hold, decimal_comma, options.sample_rate)
+                .and_then(|hold| {
+                    if hold > 0.0 {
+                        Ok(Some(hold))
+                    } else {
+                        Err(format!("hold duration {} is not positive", hold))
+                    }
+                })
+                .map_err(|err| problems.push(format!("line {}, hold column: {}{}", line, err, row))),
+            _ => Ok(None),
+        };
+
+        let pitch = match options
+            .pitch_column
+            .and_then(|column| record.get(column - 1))
+            .map(str::trim)
+        {
+            Some(pitch) if !pitch.is_empty() => parse_pitch(pitch, &record[3], decimal_comma, options)
+                .map(Some)
+                .map_err(|err| problems.push(format!("line {}, pitch column: {}{}", line, err, row))),
+            _ => Ok(None),
+        };
+
+        match (time, volume, pan, hold, pitch) {
+            (Ok(time), Ok(volume), Ok(pan), Ok(hold), Ok(pitch)) => infos.push(AudioSampleInfo {
+                time,
+                volume,
+                pan,
+                name: record[3].to_string(),
+                line,
+                lane: options
+                    .lane_column
+                    .and_then(|column| record.get(column - 1))
+                    .map(str::trim)
+                    .filter(|lane| !lane.is_empty())
+                    .map(str::to_string),
+                hold,
+                pitch,
+                pan_mode,
+                tags: options
+                    .tag_column
+                    .and_then(|column| record.get(column - 1))
+                    .map_or_else(Vec::new, |tags| tags.split_whitespace().map(str::to_string).collect()),
+            }),
+            _ => bad_rows += 1,
+        }
+    }
+
+    if out_of_range > 0 {
+        let action = match options.range_policy {
+            RangePolicy::Wrap => "wrapped",
+            _ => "clamped",
+        };
+        warn!(
+            "{}: {} {} volumes or pans that were out of range, check if the file uses another scale",
+            source, action, out_of_range
+        );
+    }
+
+    if options.skip_bad_rows {
+        for problem in &problems {
+            warn!("{}: {}", source, problem);
+        }
+        if bad_rows > 0 {
+            warn!(
+                "{}: skipped {} of {} rows that could not be read",
+                source,
+                bad_rows,
+                bad_rows + infos.len()
+            );
+        }
+    } else if !problems.is_empty() {
+        let count = problems.len();
+        problems.truncate(MAX_PROBLEMS);
+        let mut message = format!("{} problems in the CSV file:\n  {}", count, problems.join("\n  "));
+        if count > MAX_PROBLEMS {
+            message += &format!("\n  and {} more", count - MAX_PROBLEMS);
+        }
+        return Err(message.into());
+    }
+
+    Ok((infos, markers))
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Fills in the variables of a field. A $ that is not followed by the name of a defined variable is left as it is, so
+// file names with a $ in them still work.
+fn substitute(field: &str, variables: &BTreeMap<String, String>) -> String {
+    let mut result = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(start) = rest.find('$') {
+        result += &rest[..start];
+        let after = &rest[start + 1..];
+        let length = after
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(after.len());
+        match variables.get(&after[..length]) {
+            Some(value) => result += value,
+            None => result += &rest[start..start + 1 + length],
+        }
+        rest = &after[length..];
+    }
+    result + rest
+}
+
+// Semitones like -2 or +3.5, or a note name like F#3, which is as many semitones away from the root note of the sample.
+fn parse_pitch(value: &str, name: &str, decimal_comma: bool, options: &CsvOptions) -> Result<f32, String> {
+    if let Ok(semitones) = parse_number(value.trim_start_matches('+'), decimal_comma) {
+        return check_pitch(semitones as f32);
+    }
+    let note = parse_note(value)?;
+    let root = options.root_notes.get(name).copied().unwrap_or(MIDDLE_C);
+    check_pitch((note - root) as f32)
+}
+
+// MIDI note number of a note name: a letter from A to G, any number of # or b and an octave, C4 is 60.
+pub fn parse_note(value: &str) -> Result<i32, String> {
+    let invalid = || {
+        format!(
+            "'{}' is neither a number of semitones nor a note name like C4 or F#3",
+            value
+        )
+    };
+    let mut chars = value.chars();
+    let pitch_class = match chars.next().map(|letter| letter.to_ascii_uppercase()) {
+        Some('C') => 0,
+        Some('D') => 2,
+        Some('E') => 4,
+        Some('F') => 5,
+        Some('G') => 7,
+        Some('A') => 9,
+        Some('B') => 11,
+        _ => return Err(invalid()),
+    };
+    let rest = chars.as_str();
+    let octave = rest.trim_start_matches(['#', 'b']);
+    let accidentals = &rest[..rest.len() - octave.len()];
+    let shift = accidentals.matches('#').count() as i32 - accidentals.matches('b').count() as i32;
+    let octave: i32 = octave.parse().map_err(|_| invalid())?;
+    Ok((octave + 1) * 12 + pitch_class + shift)
+}
+
+// Files with a byte order mark are read in its encoding without the mark, which spreadsheets like to write. Other files
+// are UTF-8, unless they are not valid UTF-8: charts of Japanese games are often saved as Shift_JIS.
+fn to_utf8(text: Vec<u8>, source: &str) -> Vec<u8> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(&text) {
+        if encoding == UTF_8 {
+            return text[bom_length..].to_vec();
+        }
+        let (decoded, _, _) = encoding.decode(&text);
+        return decoded.into_owned().into_bytes();
+    }
+
+    if std::str::from_utf8(&text).is_ok() {
+        return text;
+    }
+
+    info!("{}: not UTF-8, reading it as Shift_JIS", source);
+    let (decoded, _, _) = SHIFT_JIS.decode(&text);
+    decoded.into_owned().into_bytes()
+}
+
+// The row starting at `byte`, shortened to fit on one line, for error messages.
+fn snippet(text: &[u8], byte: u64) -> String {
+    let rest = text.get(byte as usize..).unwrap_or_default();
+    let line = rest.split(|&byte| byte == b'\n').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim_end();
+
+    let mut snippet: String = line.chars().take(SNIPPET_LENGTH).collect();
+    if line.chars().count() > SNIPPET_LENGTH {
+        snippet += "...";
+    }
+    format!("\n    {}", snippet)
+}
+
+// Files that separate the first row into at least four columns with ';' or tabs outside of quotes use them, all
+// others ','. Commas are not counted because they can be decimal commas in such files.
+fn detect_delimiter(text: &[u8]) -> u8 {
+    let line = text.split(|&byte| byte == b'\n').next().unwrap_or_default();
+
+    let mut quoted = false;
+    let (mut semicolons, mut tabs) = (0, 0);
+    for &byte in line {
+        match byte {
+            b'"' => quoted = !quoted,
+            b';' if !quoted => semicolons += 1,
+            b'\t' if !quoted => tabs += 1,
+            _ => {}
+        }
+    }
+
+    if semicolons >= 3 {
+        b';'
+    } else if tabs >= 3 {
+        b'\t'
+    } else {
+        b','
+    }
+}
+
+// Times are in ms unless they end in ms, s or smp (samples at the sample rate of the mix): 1500, 1500ms, 1.5s and
+// 66150smp at 44100 Hz are the same time.
+// A time, or a sum of them like 1200+35ms for times with a variable like 1200+$offset.
+fn parse_time(field: &str, decimal_comma: bool, sample_rate: u32) -> Result<f64, String> {
+    match parse_single_time(field, decimal_comma, sample_rate) {
+        Err(err) if field.contains('+') => field
+            .split('+')
+            .map(|time| parse_single_time(time, decimal_comma, sample_rate))
+            .sum::<Result<f64, String>>()
+            .map_err(|_| err),
+        time => time,
+    }
+}
+
+fn parse_single_time(field: &str, decimal_comma: bool, sample_rate: u32) -> Result<f64, String> {
+    let field = field.trim();
+    // Samples are multiplied before they are divided, 57smp at 44100 Hz is a few ulps off the other way round.
+    let (number, factor, divisor) = if let Some(samples) = field.strip_suffix("smp") {
+        (samples, 1000.0, sample_rate as f64)
+    } else if let Some(ms) = field.strip_suffix("ms") {
+        (ms, 1.0, 1.0)
+    } else if let Some(seconds) = field.strip_suffix('s') {
+        (seconds, 1000.0, 1.0)
+    } else {
+        (field, 1.0, 1.0)
+    };
+
+    match parse_number(number, decimal_comma) {
+        Ok(value) if value.is_finite() => Ok(value * factor / divisor),
+        _ => Err(format!(
+            "'{}' is not a time, expected ms or a number with ms, s or smp",
+            field
+        )),
+    }
+}
+
+// A volume factor, or one in dB with volume_db or the dB suffix: -6dB is about 0.5 and -inf is silent. Also returns
+// whether it was in dB. A product like 0.5*0.8, for volumes with a variable like 0.5*$kick_gain, multiplies the
+// factors, which adds volumes in dB.
+fn parse_volume(field: &str, volume_db: bool, decimal_comma: bool) -> Result<(f32, bool), String> {
+    match parse_single_volume(field, volume_db, decimal_comma) {
+        Err(err) if field.contains('*') => field
+            .split('*')
+            .map(|volume| parse_single_volume(volume, volume_db, decimal_comma))
+            .try_fold((1.0, false), |(product, any_db), volume| {
+                volume.map(|(volume, db)| (product * volume, any_db || db))
+            })
+            .map_err(|_| err),
+        volume => volume,
+    }
+}
+
+fn parse_single_volume(field: &str, volume_db: bool, decimal_comma: bool) -> Result<(f32, bool), String> {
+    let field = field.trim();
+    let (number, db) = match field.strip_suffix("dB").or_else(|| field.strip_suffix("db")) {
+        Some(number) => (number, true),
+        None => (field, volume_db),
+    };
+    let volume = parse_number(number, decimal_comma).map_err(|_| format!("'{}' is not a number", field))?;
+    if db {
+        Ok((10f64.powf(volume / 20.0) as f32, true))
+    } else {
+        Ok((volume as f32, false))
+    }
+}
+
+pub fn check_volume(volume: f32) -> Result<f32, String> {
+    if (0.0..=1.0).contains(&volume) {
+        Ok(volume)
+    } else if volume > 1.0 && volume <= 100.0 {
+        Err(format!(
+            "volume {} is outside of 0.0 to 1.0, volumes from 0 to 100 have to be divided by 100",
+            volume
+        ))
+    } else {
+        Err(format!(
+            "volume {} is outside of 0.0 to 1.0, 1.0 plays the sample at its own level",
+            volume
+        ))
+    }
+}
+
+// Brings a value into min..=max as the policy says and counts the ones it changed. NaN and infinity cannot be
+// brought into any range.
+fn fit_range(value: f32, min: f32, max: f32, policy: RangePolicy, changed: &mut usize) -> Option<f32> {
+    if !value.is_finite() {
+        return None;
+    }
+    if (min..=max).contains(&value) {
+        return Some(value);
+    }
+    *changed += 1;
+    match policy {
+        RangePolicy::Wrap => Some(min + (value - min).rem_euclid(max - min)),
+        _ => Some(value.clamp(min, max)),
+    }
+}
+
+pub fn check_pan(pan: f32) -> Result<f32, String> {
+    if (-1.0..=1.0).contains(&pan) {
+        Ok(pan)
+    } else {
+        Err(format!(
+            "pan {} is outside of -1.0 (left) to 1.0 (right), 0.0 is the center",
+            pan
+        ))
+    }
+}
+
+pub fn check_pitch(pitch: f32) -> Result<f32, String> {
+    if (-MAX_PITCH..=MAX_PITCH).contains(&pitch) {
+        Ok(pitch)
+    } else {
+        Err(format!(
+            "pitch {} is outside of -{} to {} semitones from the sample",
+            pitch, MAX_PITCH, MAX_PITCH
+        ))
+    }
+}
+
+// A pan like 0.5t is truly panned and 0.5b balanced, whatever --pan-mode is.
+fn split_pan_mode(field: &str) -> (&str, Option<PanMode>) {
+    let field = field.trim();
+    if let Some(pan) = field.strip_suffix(['t', 'T']) {
+        (pan, Some(PanMode::TruePan))
+    } else if let Some(pan) = field.strip_suffix(['b', 'B']) {
+        (pan, Some(PanMode::Balance))
+    } else {
+        (field, None)
+    }
+}
+
+// With a decimal comma, 1.250,5 is read as 1250.5. Numbers without a comma are read as usual.
+fn parse_number(field: &str, decimal_comma: bool) -> Result<f64, ParseFloatError> {
+    let field = field.trim();
+    if decimal_comma && field.contains(',') {
+        return field.replace('.', "").replace(',', ".").parse();
+    }
+    field.parse()
+}
+
+// Number of events per sample name.
+pub fn trigger_counts(infos: &[AudioSampleInfo]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for info in infos {
+        *counts.entry(info.name.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli;
+    use crate::commands;
+    use crate::config::FileConfig;
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number(" 0.75 ", false), Ok(0.75));
+        assert_eq!(parse_number("-2", false), Ok(-2.0));
+        assert!(parse_number("0,75", false).is_err());
+        assert!(parse_number("", false).is_err());
+        assert_eq!(parse_number("0,75", true), Ok(0.75));
+        assert_eq!(parse_number("-0,5", true), Ok(-0.5));
+        // The dots are thousands separators once there is a comma.
+        assert_eq!(parse_number("1.250,5", true), Ok(1250.5));
+        assert_eq!(parse_number("1.234.567,25", true), Ok(1_234_567.25));
+        // Without a comma a dot is still the decimal point.
+        assert_eq!(parse_number("1.5", true), Ok(1.5));
+        assert!(parse_number("1,2,3", true).is_err());
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("1500", false, 44100), Ok(1500.0));
+        assert_eq!(parse_time(" 1500ms ", false, 44100), Ok(1500.0));
+        assert_eq!(parse_time("1.5s", false, 44100), Ok(1500.0));
+        assert_eq!(parse_time("66150smp", false, 44100), Ok(1500.0));
+        assert_eq!(parse_time("-250ms", false, 44100), Ok(-250.0));
+        assert_eq!(parse_time("1,5s", true, 44100), Ok(1500.0));
+        // Sample positions come out as the same frame again.
+        for samples in [1, 57, 155, 274, 3_000_017] {
+            let time = parse_time(&format!("{}smp", samples), false, 44100).unwrap();
+            assert_eq!(time, samples as f64 * 1000.0 / 44100.0);
+            assert_eq!((time * 44100.0 / 1000.0).round() as i64, samples);
+        }
+        assert_eq!(parse_time("1200+35ms", false, 44100), Ok(1235.0));
+        assert_eq!(
+            parse_time("1s+100smp", false, 48000),
+            Ok(1000.0 + 100.0 * 1000.0 / 48000.0)
+        );
+        for field in ["", "ms", "1.5x", "1500 smp s", "inf", "1+", "1e400s"] {
+            assert!(parse_time(field, false, 44100).is_err(), "{}", field);
+        }
+    }
+
+    fn options() -> CsvOptions {
+        let matches = commands::mix::command()
+            .try_get_matches_from(["mix", "-i", "test.csv", "-o", "test.ogg"])
+            .unwrap();
+        cli::render_settings(&matches, &FileConfig::default()).unwrap().csv
+    }
+
+    #[test]
+    fn variables() {
+        let text = "$kick_gain = 0.8\n\
+                    $offset = 35ms\n\
+                    0,$kick_gain,0,kick.wav\n\
+                    1000+$offset,1,0,kick.wav\n\
+                    $kick_gain = 0.5\n\
+                    $half = $kick_gain*0.5\n\
+                    2000,$half,0,kick.wav\n\
+                    3000,1,0,$5 $undefined.wav\n";
+        let infos = read_events_from(text.as_bytes(), &options()).unwrap();
+        assert_eq!(
+            infos.iter().map(|info| info.time).collect::<Vec<_>>(),
+            [0.0, 1035.0, 2000.0, 3000.0]
+        );
+        assert_eq!(
+            infos.iter().map(|info| info.volume).collect::<Vec<_>>(),
+            [0.8, 1.0, 0.25, 1.0]
+        );
+        // A $ that is not a defined variable is left in the field.
+        assert_eq!(infos[3].name, "$5 $undefined.wav");
+
+        for row in ["$ = 1", "$kick-gain = 1", "$kick_gain"] {
+            let text = format!("{}\n0,1,0,kick.wav\n", row);
+            assert!(read_events_from(text.as_bytes(), &options()).is_err(), "{}", row);
+        }
+    }
+
+    #[test]
+    fn pan_modes() {
+        assert_eq!(split_pan_mode("0.5"), ("0.5", None));
+        assert_eq!(split_pan_mode(" -0.5t "), ("-0.5", Some(PanMode::TruePan)));
+        assert_eq!(split_pan_mode("1T"), ("1", Some(PanMode::TruePan)));
+        assert_eq!(split_pan_mode("-1b"), ("-1", Some(PanMode::Balance)));
+        assert_eq!(split_pan_mode("0,25B"), ("0,25", Some(PanMode::Balance)));
+        // Only the suffix is split off, what is left still has to be a number.
+        assert_eq!(split_pan_mode("t"), ("", Some(PanMode::TruePan)));
+        assert_eq!(split_pan_mode("0.5 b"), ("0.5 ", Some(PanMode::Balance)));
+        assert!(parse_number(split_pan_mode("0.5 b").0, false).is_ok());
+        assert!(parse_number(split_pan_mode("bt").0, false).is_err());
+        assert_eq!(split_pan_mode(""), ("", None));
+    }
+
+    #[test]
+    fn pitches() {
+        assert_eq!(check_pitch(-48.0), Ok(-48.0));
+        assert_eq!(check_pitch(7.5), Ok(7.5));
+        assert!(check_pitch(-200.0).is_err());
+        assert!(check_pitch(48.5).is_err());
+        assert!(check_pitch(f32::NAN).is_err());
+    }
+}
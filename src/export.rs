@@ -0,0 +1,356 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::os::raw::c_long;
+use std::path::Path;
+
+use hound::SampleFormat;
+use hound::WavWriter;
+use log::info;
+use sha2::{Digest, Sha256};
+
+use crate::error::{AppError, ErrorKind, WithKind};
+#[cfg(not(feature = "libvorbis"))]
+use crate::pure_vorbis::encode_managed;
+#[cfg(feature = "libvorbis")]
+use crate::vorbis::encode_managed;
+
+// How the Vorbis encoder spends bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    // From -0.1 to 1.0, the bitrate follows what the audio needs.
+    Quality(f32),
+    // Bitrate management in kbit/s: an average to aim for and hard limits, the same for all three is a constant
+    // bitrate.
+    Bitrate {
+        average: Option<u32>,
+        min: Option<u32>,
+        max: Option<u32>,
+    },
+    // At most this many bytes, with the average bitrate that fits.
+    Size(u64),
+}
+
+// Files over the size of --target-size are encoded again at a lower bitrate this many times before giving up.
+const SIZE_ATTEMPTS: usize = 5;
+
+pub fn export(
+    data: &[f32],
+    output_file: &Path,
+    encoding: Encoding,
+    sample_rate: u32,
+    serial: Option<u32>,
+) -> Result<(), Box<dyn Error>> {
+    export_with_comments(data, output_file, encoding, sample_rate, serial, &[])
+}
+
+// Like export, with `comments` like "TITLE=Set 1" in the Vorbis comment header.
+pub fn export_with_comments(
+    data: &[f32],
+    output_file: &Path,
+    encoding: Encoding,
+    sample_rate: u32,
+    serial: Option<u32>,
+    comments: &[String],
+) -> Result<(), Box<dyn Error>> {
+    info!("exporting to {}", output_file.display());
+    let buffer = encode_with_comments(data, encoding, sample_rate, serial, comments)?;
+
+    let mut ogg_file = File::create(output_file).with_kind(ErrorKind::Output)?;
+    ogg_file.write_all(&buffer).with_kind(ErrorKind::Output)?;
+    Ok(())
+}
+
+// The encoder picks a random Ogg stream serial number. Without one passed in, it is taken from the fingerprint of
+// the audio, so the same mix always gives the same file.
+pub fn encode(
+    data: &[f32],
+    encoding: Encoding,
+    sample_rate: u32,
+    serial: Option<u32>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    encode_with_comments(data, encoding, sample_rate, serial, &[])
+}
+
+fn encode_with_comments(
+    data: &[f32],
+    encoding: Encoding,
+    sample_rate: u32,
+    serial: Option<u32>,
+    comments: &[String],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let pcm_data = to_pcm(data);
+
+    let mut buffer = match encoding {
+        Encoding::Quality(quality) => encode_quality(&pcm_data, quality, sample_rate)?,
+        Encoding::Size(size) => {
+            // The comments are added afterwards and have to fit as well.
+            let comment_bytes: usize = comments.iter().map(|comment| comment.len() + 4).sum();
+            encode_to_size(&pcm_data, size.saturating_sub(comment_bytes as u64), sample_rate)?
+        }
+        Encoding::Bitrate { average, min, max } => {
+            if cfg!(not(feature = "libvorbis")) && (min.is_some() || max.is_some()) {
+                let message = "--min-bitrate, --max-bitrate and --cbr need a build with the libvorbis feature";
+                return Err(AppError::new(ErrorKind::Encode, message).into());
+            }
+            let bits = |kbps: Option<u32>| kbps.map_or(-1, |kbps| kbps as c_long * 1000);
+            // libvorbis only has setups for some bitrates at every sample rate.
+            encode_managed(&pcm_data, sample_rate, bits(average), bits(min), bits(max)).map_err(|code| {
+                let message = format!(
+                    "vorbis encoder failed with error {}, it may not support these bitrates at {} Hz",
+                    code, sample_rate
+                );
+                AppError::new(ErrorKind::Encode, message)
+            })?
+        }
+    };
+    if !comments.is_empty() {
+        buffer = set_comments(&buffer, comments).ok_or_else(|| {
+            AppError::new(
+                ErrorKind::Encode,
+                "could not find the Vorbis comment header to write to",
+            )
+        })?;
+    }
+
+    let serial = serial.unwrap_or_else(|| {
+        let fingerprint = pcm_fingerprint(&pcm_data);
+        u32::from_str_radix(&fingerprint[..8], 16).unwrap_or(0)
+    });
+    set_stream_serial(&mut buffer, serial);
+
+    Ok(buffer)
+}
+
+// Samples of both channels handed to vorbis-encoder at once, a second at 48 kHz.
+#[cfg(feature = "libvorbis")]
+const ENCODE_PART: usize = 96_000;
+
+#[cfg(feature = "libvorbis")]
+fn encode_quality(pcm_data: &[i16], quality: f32, sample_rate: u32) -> Result<Vec<u8>, AppError> {
+    let encode_error = |code| AppError::new(ErrorKind::Encode, format!("vorbis encoder failed with error {}", code));
+    let mut encoder = vorbis_encoder::Encoder::new(2, sample_rate as u64, quality).map_err(encode_error)?;
+    // vorbis-encoder wants a Vec, so the samples are copied over a part at a time.
+    let mut buffer = Vec::new();
+    for part in pcm_data.chunks(ENCODE_PART) {
+        buffer.extend(encoder.encode(&part.to_vec()).map_err(encode_error)?);
+    }
+    // Without flushing the last pages of the stream are never written.
+    buffer.extend(encoder.flush().map_err(encode_error)?);
+    Ok(buffer)
+}
+
+#[cfg(not(feature = "libvorbis"))]
+fn encode_quality(pcm_data: &[i16], quality: f32, sample_rate: u32) -> Result<Vec<u8>, AppError> {
+    Ok(crate::pure_vorbis::encode(pcm_data, sample_rate, quality))
+}
+
+// Sizes that allow more than this many bits per second are first tried at the best quality, which is usually below.
+const MAX_SIZE_BITRATE: f64 = 320_000.0;
+
+// The headers and the framing of the pages take some bytes of their own, so the first attempt aims somewhat below
+// the size. It is an average bitrate, which the encoder can miss, so a file that is too large is encoded again with
+// the bitrate lowered by as much as it was over.
+fn encode_to_size(pcm_data: &[i16], size: u64, sample_rate: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+    let seconds = (pcm_data.len() / 2) as f64 / sample_rate as f64;
+    let mut bitrate = size as f64 * 8.0 / seconds.max(0.001) * 0.95;
+    if bitrate > MAX_SIZE_BITRATE {
+        let buffer = encode_quality(pcm_data, 1.0, sample_rate)?;
+        if buffer.len() as u64 <= size {
+            info!("encoded at the best quality, which fits into {} bytes", size);
+            return Ok(buffer);
+        }
+        bitrate = MAX_SIZE_BITRATE;
+    }
+    for _ in 0..SIZE_ATTEMPTS {
+        let buffer = encode_managed(pcm_data, sample_rate, bitrate as c_long, -1, -1).map_err(|code| {
+            let message = format!(
+                "{} bytes would need {:.0} kbit/s, which the vorbis encoder does not support at {} Hz (error {})",
+                size,
+                bitrate / 1000.0,
+                sample_rate,
+                code
+            );
+            AppError::new(ErrorKind::Encode, message)
+        })?;
+        if buffer.len() as u64 <= size {
+            info!("encoded at {:.0} kbit/s to fit into {} bytes", bitrate / 1000.0, size);
+            return Ok(buffer);
+        }
+        bitrate *= size as f64 / buffer.len() as f64 * 0.98;
+    }
+    let message = format!("could not encode the mix into {} bytes", size);
+    Err(AppError::new(ErrorKind::Encode, message).into())
+}
+
+// SHA-256 of the 16 bit samples handed to the encoder. It only depends on the mix, not on the encoder version, so
+// renders can be compared by it.
+pub fn fingerprint(data: &[f32]) -> String {
+    pcm_fingerprint(&to_pcm(data))
+}
+
+fn pcm_fingerprint(pcm_data: &[i16]) -> String {
+    let mut hasher = Sha256::new();
+    for value in pcm_data {
+        hasher.update(value.to_le_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn to_pcm(data: &[f32]) -> Vec<i16> {
+    data.iter().map(|&x| (x * i16::MAX as f32) as i16).collect()
+}
+
+// Replaces the comments of the Vorbis comment header, the second packet of the stream, and keeps the vendor string of
+// the encoder. The comment and setup headers are paged again and the pages after them renumbered, their checksums are
+// left to set_stream_serial. None if the stream does not start with the three Vorbis headers on pages of their own.
+fn set_comments(data: &[u8], comments: &[String]) -> Option<Vec<u8>> {
+    let mut pages = Vec::new();
+    let mut pos = 0;
+    while pos + 27 <= data.len() && &data[pos..pos + 4] == b"OggS" {
+        let header_len = 27 + data[pos + 26] as usize;
+        let body_len: usize = data
+            .get(pos + 27..pos + header_len)?
+            .iter()
+            .map(|&len| len as usize)
+            .sum();
+        pages.push(data.get(pos..pos + header_len + body_len)?);
+        pos += header_len + body_len;
+    }
+
+    // The identification header fills the first page, the comment and setup headers end on a later one.
+    let mut packets = vec![Vec::new()];
+    let mut header_pages = 0;
+    for page in pages.iter().skip(1) {
+        let lacing = &page[27..27 + page[26] as usize];
+        let mut offset = 27 + lacing.len();
+        for &len in lacing {
+            packets
+                .last_mut()?
+                .extend_from_slice(&page[offset..offset + len as usize]);
+            offset += len as usize;
+            if len < 255 {
+                packets.push(Vec::new());
+            }
+        }
+        header_pages += 1;
+        if packets.len() > 2 {
+            break;
+        }
+    }
+    let (comment, setup) = match &packets[..] {
+        [comment, setup, rest] if rest.is_empty() => (comment, setup),
+        _ => return None,
+    };
+    if comment.get(..7)? != b"\x03vorbis" {
+        return None;
+    }
+    let vendor_len = u32::from_le_bytes(comment.get(7..11)?.try_into().ok()?) as usize;
+
+    let mut packet = comment.get(..11 + vendor_len)?.to_vec();
+    packet.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        packet.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        packet.extend_from_slice(comment.as_bytes());
+    }
+    packet.push(1);
+
+    // Pages hold up to 255 lacing values, a packet of n bytes takes n / 255 + 1 of them.
+    let mut lacing = Vec::new();
+    let mut ends = Vec::new();
+    for packet in [&packet, setup] {
+        lacing.resize(lacing.len() + packet.len() / 255, 255);
+        lacing.push((packet.len() % 255) as u8);
+        ends.push(lacing.len());
+    }
+    let body = [packet.as_slice(), setup.as_slice()].concat();
+    let mut output = pages[0].to_vec();
+    let mut sequence = 1_u32;
+    let (mut segment, mut offset) = (0, 0);
+    while segment < lacing.len() {
+        let count = (lacing.len() - segment).min(255);
+        let page_lacing = &lacing[segment..segment + count];
+        let length: usize = page_lacing.iter().map(|&len| len as usize).sum();
+        // Pages that start in the middle of a packet are marked as continued, pages on which no packet ends have no
+        // granule position.
+        let continued = segment > 0 && !ends.contains(&segment);
+        let finishes = ends.iter().any(|&end| end > segment && end <= segment + count);
+        let granule = if finishes { 0 } else { u64::MAX };
+        output.extend_from_slice(&pages[0][..5]);
+        output.push(continued as u8);
+        output.extend_from_slice(&granule.to_le_bytes());
+        output.extend_from_slice(&pages[0][14..18]);
+        output.extend_from_slice(&sequence.to_le_bytes());
+        output.extend_from_slice(&[0; 4]);
+        output.push(count as u8);
+        output.extend_from_slice(page_lacing);
+        output.extend_from_slice(&body[offset..offset + length]);
+        sequence += 1;
+        segment += count;
+        offset += length;
+    }
+
+    for page in &pages[1 + header_pages..] {
+        let mut page = page.to_vec();
+        page[18..22].copy_from_slice(&sequence.to_le_bytes());
+        sequence += 1;
+        output.extend_from_slice(&page);
+    }
+    output.extend_from_slice(&data[pos..]);
+    Some(output)
+}
+
+// Rewrites the serial number of every Ogg page and updates the page checksums.
+fn set_stream_serial(data: &mut [u8], serial: u32) {
+    let mut pos = 0;
+
+    while pos + 27 <= data.len() && &data[pos..pos + 4] == b"OggS" {
+        let header_len = 27 + data[pos + 26] as usize;
+        if pos + header_len > data.len() {
+            break;
+        }
+        let body_len: usize = data[pos + 27..pos + header_len].iter().map(|&len| len as usize).sum();
+        if pos + header_len + body_len > data.len() {
+            break;
+        }
+
+        let page = &mut data[pos..pos + header_len + body_len];
+        page[14..18].copy_from_slice(&serial.to_le_bytes());
+        page[22..26].fill(0);
+        let crc = ogg_crc(page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+
+        pos += header_len + body_len;
+    }
+}
+
+fn ogg_crc(data: &[u8]) -> u32 {
+    let mut crc = 0_u32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// Writes interleaved samples with `channels` channels as a 16 bit .wav file.
+pub fn write_wav(data: &[f32], channels: u16, output_file: &Path, sample_rate: u32) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+
+    let mut writer = WavWriter::create(output_file, spec)?;
+    for value in data {
+        writer.write_sample((value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16)?;
+    }
+    writer.finalize()
+}
@@ -0,0 +1,216 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+
+use crate::events::AudioSampleInfo;
+use crate::xml::{parse_xml, Element};
+
+// LMMS counts 192 ticks per 4/4 bar and 12 ticks per step of a beat/bassline pattern.
+const TICKS_PER_BEAT: f64 = 48.0;
+const TICKS_PER_STEP: f64 = 12.0;
+const DEFAULT_BPM: f64 = 140.0;
+
+const INSTRUMENT_TRACK: &str = "0";
+const BB_TRACK: &str = "1";
+const SAMPLE_TRACK: &str = "2";
+
+// A track that plays one sample: the AudioFileProcessor of an instrument track or a sample track.
+struct Sampler {
+    sample: String,
+    volume: f64,
+    pan: f64,
+}
+
+struct Project<'a> {
+    base: &'a Path,
+    tick: f64,
+    events: Vec<AudioSampleInfo>,
+    // Notes of instruments that are not AudioFileProcessors, which cannot be played.
+    unplayable: usize,
+}
+
+// Reads the sample tracks, AudioFileProcessor tracks and beat/bassline patterns of an LMMS project. Every note plays
+// its sample from the start at its own pitch, keys, envelopes, effects and automation are left out.
+pub fn read_project(path: &Path) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("mmpz"))
+    {
+        return Err("compressed .mmpz projects cannot be read, save the project as .mmp in LMMS".into());
+    }
+
+    let root = parse_xml(&fs::read_to_string(path)?)?;
+    if root.name != "lmms-project" {
+        return Err(format!("<{}> is not an LMMS project", root.name).into());
+    }
+    let bpm = root
+        .child("head")
+        .map_or(DEFAULT_BPM, |head| head.number("bpm", DEFAULT_BPM));
+    let song = root
+        .child("song")
+        .and_then(|song| song.child("trackcontainer"))
+        .ok_or("the project has no song")?;
+
+    let mut project = Project {
+        base: path.parent().unwrap_or(Path::new("")),
+        tick: 60000.0 / bpm.max(1.0) / TICKS_PER_BEAT,
+        events: Vec::new(),
+        unplayable: 0,
+    };
+
+    // Beat/bassline tracks are numbered in the order they appear, their patterns are the pattern with the same
+    // number in every track of the beat/bassline editor.
+    let bb_tracks = song
+        .children("track")
+        .find_map(|track| track.child("bbtrack")?.child("trackcontainer"));
+    let mut bb_index = 0;
+    for track in song.children("track") {
+        match track.attribute("type") {
+            Some(INSTRUMENT_TRACK) if !is_muted(track) => project.instrument_track(track),
+            Some(SAMPLE_TRACK) if !is_muted(track) => project.sample_track(track),
+            Some(BB_TRACK) => {
+                if !is_muted(track) {
+                    let placements: Vec<(f64, f64)> = track
+                        .children("bbtco")
+                        .filter(|clip| !is_muted(clip))
+                        .map(|clip| (clip.number("pos", 0.0), clip.number("len", 0.0)))
+                        .collect();
+                    if let Some(container) = bb_tracks {
+                        project.bb_patterns(container, bb_index, &placements);
+                    }
+                }
+                bb_index += 1;
+            }
+            _ => {}
+        }
+    }
+
+    if project.unplayable > 0 {
+        warn!(
+            "left out {} notes of instruments that are not AudioFileProcessors",
+            project.unplayable
+        );
+    }
+    project.events.sort_by(|a, b| a.time.total_cmp(&b.time));
+    info!("read {} events from {}", project.events.len(), path.display());
+    Ok(project.events)
+}
+
+impl Project<'_> {
+    fn sample_track(&mut self, track: &Element) {
+        let settings = track.child("sampletrack");
+        let volume = settings.map_or(100.0, |settings| settings.number("vol", 100.0)) / 100.0;
+        let pan = settings.map_or(0.0, |settings| settings.number("pan", 0.0)) / 100.0;
+
+        // Clips are called sampleclip since LMMS 1.3.
+        let clips = track.children("sampletco").chain(track.children("sampleclip"));
+        for clip in clips.filter(|clip| !is_muted(clip)) {
+            let Some(sample) = clip.attribute("src").filter(|src| !src.is_empty()) else {
+                continue;
+            };
+            let sampler = Sampler {
+                sample: self.sample_name(sample),
+                volume,
+                pan,
+            };
+            self.push(&sampler, clip.number("pos", 0.0), 1.0, 0.0);
+        }
+    }
+
+    fn instrument_track(&mut self, track: &Element) {
+        for pattern in track.children("pattern").filter(|pattern| !is_muted(pattern)) {
+            self.pattern(track, pattern, &[(pattern.number("pos", 0.0), f64::MAX)], 0.0);
+        }
+    }
+
+    // Plays the notes of a pattern in every (start, length) range in ticks. Beat/bassline patterns are repeated
+    // every `length` ticks for as long as their placement lasts, song patterns play once.
+    fn pattern(&mut self, track: &Element, pattern: &Element, ranges: &[(f64, f64)], length: f64) {
+        let notes: Vec<&Element> = pattern.children("note").collect();
+        if notes.is_empty() {
+            return;
+        }
+        let Some(sampler) = self.sampler(track) else {
+            self.unplayable += notes.len() * ranges.len().max(1);
+            return;
+        };
+
+        for (range_start, range_length) in ranges {
+            let mut offset = 0.0;
+            loop {
+                for note in &notes {
+                    let position = note.number("pos", 0.0) + offset;
+                    if position < *range_length {
+                        let volume = note.number("vol", 100.0) / 100.0;
+                        let pan = note.number("pan", 0.0) / 100.0;
+                        self.push(&sampler, range_start + position, volume, pan);
+                    }
+                }
+                offset += length;
+                if length <= 0.0 || offset >= *range_length {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn bb_patterns(&mut self, container: &Element, index: usize, placements: &[(f64, f64)]) {
+        for track in container.children("track") {
+            if is_muted(track) || track.attribute("type") != Some(INSTRUMENT_TRACK) {
+                continue;
+            }
+            let Some(pattern) = track.children("pattern").nth(index) else {
+                continue;
+            };
+            let length = pattern.number("steps", 16.0) * TICKS_PER_STEP;
+            self.pattern(track, pattern, placements, length);
+        }
+    }
+
+    fn sampler(&self, track: &Element) -> Option<Sampler> {
+        let settings = track.child("instrumenttrack")?;
+        let processor = settings.child("instrument")?.child("audiofileprocessor")?;
+        let sample = processor.attribute("src").filter(|src| !src.is_empty())?;
+        Some(Sampler {
+            sample: self.sample_name(sample),
+            volume: settings.number("vol", 100.0) / 100.0 * processor.number("amp", 100.0) / 100.0,
+            pan: settings.number("pan", 0.0) / 100.0,
+        })
+    }
+
+    // Samples next to the project are used from there, others like the factory samples of LMMS (drums/kick01.ogg)
+    // keep their name for the samples directories.
+    fn sample_name(&self, sample: &str) -> String {
+        let path = self.base.join(sample);
+        if path.is_file() {
+            path.to_string_lossy().into_owned()
+        } else {
+            sample.to_string()
+        }
+    }
+
+    fn push(&mut self, sampler: &Sampler, tick: f64, volume: f64, pan: f64) {
+        let volume = (sampler.volume * volume) as f32;
+        if volume <= 0.0 {
+            return;
+        }
+        self.events.push(AudioSampleInfo {
+            time: tick * self.tick,
+            volume: volume.min(1.0),
+            pan: ((sampler.pan + pan) as f32).clamp(-1.0, 1.0),
+            name: sampler.sample.clone(),
+            line: 0,
+            lane: None,
+            hold: None,
+            pitch: None,
+            pan_mode: None,
+            tags: Vec::new(),
+        });
+    }
+}
+
+fn is_muted(element: &Element) -> bool {
+    element.attribute("muted") == Some("1")
+}
@@ -0,0 +1,87 @@
+use std::io::{stderr, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ArgMatches;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+
+#[derive(Clone, Copy)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+struct Logger {
+    level: LevelFilter,
+    format: LogFormat,
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp_ms: u64,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Messages of the decoding libraries are only interesting with -vv.
+        let ours = metadata.target().starts_with(env!("CARGO_CRATE_NAME"));
+        metadata.level() <= self.level && (ours || self.level == LevelFilter::Trace)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = match self.format {
+            LogFormat::Text => match record.level() {
+                Level::Info => record.args().to_string(),
+                Level::Warn => format!("warning: {}", record.args()),
+                level => format!("{}: {}", level.as_str().to_lowercase(), record.args()),
+            },
+            LogFormat::Json => {
+                let timestamp_ms = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_millis() as u64)
+                    .unwrap_or(0);
+                let json = JsonRecord {
+                    timestamp_ms,
+                    level: &record.level().as_str().to_lowercase(),
+                    target: record.target(),
+                    message: record.args().to_string(),
+                };
+                serde_json::to_string(&json).expect("could not serialize log record")
+            }
+        };
+
+        let _ = writeln!(stderr(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = stderr().flush();
+    }
+}
+
+// Info is the default level, every -v adds one level of detail and --quiet only keeps warnings and errors.
+pub fn init(matches: &ArgMatches) {
+    let level = if matches.get_flag("quiet") {
+        LevelFilter::Warn
+    } else {
+        match matches.get_count("verbose") {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    };
+
+    let format = match matches.get_one::<String>("log_format").map(String::as_str) {
+        Some("json") => LogFormat::Json,
+        _ => LogFormat::Text,
+    };
+
+    log::set_boxed_logger(Box::new(Logger { level, format })).expect("logger already initialized");
+    log::set_max_level(level);
+}
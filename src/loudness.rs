@@ -0,0 +1,126 @@
+use std::f64::consts::PI;
+
+// Blocks of 400 ms that overlap by 75 %, as in ITU-R BS.1770.
+const BLOCK: f64 = 0.4;
+const STEP: f64 = 0.1;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+// Integrated loudness of interleaved stereo data in LUFS after ITU-R BS.1770, None for silence. Audio shorter than
+// one block, like most keysounds, is measured as a single block.
+pub fn integrated_loudness(data: &[f32], sample_rate: u32) -> Option<f32> {
+    let (steps, total) = step_powers(data, sample_rate);
+
+    let per_block = (BLOCK / STEP).round() as usize;
+    let mut blocks: Vec<f64> = steps
+        .windows(per_block)
+        .map(|window| window.iter().sum::<f64>() / per_block as f64)
+        .collect();
+    if blocks.is_empty() && data.len() >= 2 {
+        blocks.push(total / (data.len() / 2) as f64);
+    }
+
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+    let mean_above = |gate: f64| {
+        let gated: Vec<f64> = blocks.iter().copied().filter(|power| loudness(*power) > gate).collect();
+        if gated.is_empty() {
+            None
+        } else {
+            Some(gated.iter().sum::<f64>() / gated.len() as f64)
+        }
+    };
+
+    let relative_gate = loudness(mean_above(ABSOLUTE_GATE)?) + RELATIVE_GATE;
+    Some(loudness(mean_above(relative_gate.max(ABSOLUTE_GATE))?) as f32)
+}
+
+// First frame of the `length` s of the data that sound loudest, with the most K-weighted energy. It is on a STEP and 0
+// for data that is not longer.
+pub fn loudest_window(data: &[f32], sample_rate: u32, length: f64) -> usize {
+    let (steps, _) = step_powers(data, sample_rate);
+    let count = ((length / STEP).round() as usize).max(1);
+    if steps.len() <= count {
+        return 0;
+    }
+
+    let mut sum: f64 = steps[..count].iter().sum();
+    let (mut best, mut best_sum) = (0, sum);
+    for i in count..steps.len() {
+        sum += steps[i] - steps[i - count];
+        if sum > best_sum {
+            (best, best_sum) = (i + 1 - count, sum);
+        }
+    }
+    best * (STEP * sample_rate as f64).round() as usize
+}
+
+// Mean square of the K-weighted signal in every STEP, summed over both channels, and the sum of squares of all of it.
+fn step_powers(data: &[f32], sample_rate: u32) -> (Vec<f64>, f64) {
+    let rate = sample_rate as f64;
+    let mut filters = [k_weighting(rate), k_weighting(rate)];
+
+    let step = (STEP * rate).round() as usize;
+    let mut steps = Vec::new();
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    for (i, frame) in data.chunks_exact(2).enumerate() {
+        for (channel, value) in frame.iter().enumerate() {
+            let weighted = filters[channel]
+                .iter_mut()
+                .fold(*value as f64, |value, filter| filter.process(value));
+            sum += weighted * weighted;
+            total += weighted * weighted;
+        }
+        if (i + 1) % step == 0 {
+            steps.push(sum / step as f64);
+            sum = 0.0;
+        }
+    }
+    (steps, total)
+}
+
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    // Transposed direct form II.
+    fn process(&mut self, value: f64) -> f64 {
+        let output = self.b[0] * value + self.state[0];
+        self.state[0] = self.b[1] * value - self.a[0] * output + self.state[1];
+        self.state[1] = self.b[2] * value - self.a[1] * output;
+        output
+    }
+}
+
+// The high shelf and the high pass of the K-weighting, with the coefficients of the standard recalculated for the
+// sample rate.
+fn k_weighting(rate: f64) -> [Biquad; 2] {
+    let (frequency, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * frequency / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [0.0; 2],
+    };
+
+    let (frequency, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * frequency / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
@@ -0,0 +1,115 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use log::info;
+
+// An LV2 plugin on the master with the values of its control ports, written as URI,SYMBOL=VALUE,...
+#[derive(Clone, Debug)]
+pub struct Plugin {
+    pub uri: String,
+    pub controls: Vec<(String, f32)>,
+}
+
+pub fn parse_plugin(value: &str) -> Result<Plugin, String> {
+    let mut parts = value.split(',');
+    let uri = parts.next().unwrap_or("").trim();
+    if uri.is_empty() || !uri.contains(':') {
+        return Err(format!("{} does not start with a plugin URI", value));
+    }
+
+    let mut controls = Vec::new();
+    for control in parts {
+        let (symbol, number) = control
+            .split_once('=')
+            .ok_or_else(|| format!("{} is not SYMBOL=VALUE", control))?;
+        let number = number
+            .trim()
+            .parse::<f32>()
+            .map_err(|_| format!("the value of {} is not a number", symbol.trim()))?;
+        controls.push((symbol.trim().to_string(), number));
+    }
+    Ok(Plugin {
+        uri: uri.to_string(),
+        controls,
+    })
+}
+
+// Runs the mix through the plugins one after another with the lv2apply binary of lilv on the PATH, so no LV2 host
+// has to be linked in. The mix is handed over as a 32 bit float file and does not clip between the plugins. Stereo
+// plugins process both channels, mono plugins have to be run by lv2apply once per channel and are not supported.
+pub fn apply(plugins: &[Plugin], data: Vec<f32>, sample_rate: u32) -> Result<Vec<f32>, Box<dyn Error>> {
+    let mut data = data;
+    for (i, plugin) in plugins.iter().enumerate() {
+        let base = env::temp_dir().join(format!("audio-sample-mixer-{}-{}", process::id(), i));
+        let (input, output) = (base.with_extension("in.wav"), base.with_extension("out.wav"));
+        let result = run(plugin, &data, sample_rate, &input, &output);
+        let _ = fs::remove_file(&input);
+        let _ = fs::remove_file(&output);
+
+        let processed = result.map_err(|err| format!("{}: {}", plugin.uri, err))?;
+        if processed.len() != data.len() {
+            return Err(format!(
+                "{} returned {} frames instead of {}",
+                plugin.uri,
+                processed.len() / 2,
+                data.len() / 2
+            )
+            .into());
+        }
+        info!("ran the mix through {}", plugin.uri);
+        data = processed;
+    }
+    Ok(data)
+}
+
+fn run(
+    plugin: &Plugin,
+    data: &[f32],
+    sample_rate: u32,
+    input: &Path,
+    output: &Path,
+) -> Result<Vec<f32>, Box<dyn Error>> {
+    // As float, so that peaks above full scale reach the plugin.
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(input, spec)?;
+    for sample in data {
+        writer.write_sample(*sample)?;
+    }
+    writer.finalize()?;
+
+    let mut command = Command::new("lv2apply");
+    for (symbol, value) in &plugin.controls {
+        command.arg("-c").arg(symbol).arg(value.to_string());
+    }
+    command.arg("-i").arg(input).arg("-o").arg(output).arg(&plugin.uri);
+
+    let result = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("could not run lv2apply: {}", err))?;
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let reason = stderr.lines().last().unwrap_or("").trim();
+        return Err(format!("lv2apply failed: {}", reason).into());
+    }
+
+    let mut reader = WavReader::open(output)?;
+    let spec = reader.spec();
+    if spec.channels != 2 || spec.sample_format != SampleFormat::Float {
+        return Err(format!("lv2apply wrote {} channels instead of stereo float", spec.channels).into());
+    }
+    let samples: Result<Vec<f32>, hound::Error> = reader.samples::<f32>().collect();
+    Ok(samples?
+        .into_iter()
+        .map(|value| if value.is_finite() { value } else { 0.0 })
+        .collect())
+}
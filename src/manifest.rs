@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::info;
+use sha2::{Digest, Sha256};
+
+use crate::error::{AppError, ErrorKind};
+use crate::resolve::split_track;
+
+// Problems listed before the rest is only counted.
+const MAX_PROBLEMS: usize = 10;
+
+// SHA-256 checksums of the samples a chart may use, in the format of sha256sum: one "<checksum>  <sample name>" per
+// line. `sha256sum *.wav > samples.sha256` in the samples directory writes one.
+pub struct Manifest {
+    path: PathBuf,
+    checksums: HashMap<String, String>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| AppError::new(ErrorKind::Input, format!("could not read {}: {}", path.display(), err)))?;
+
+        let mut checksums = HashMap::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // sha256sum marks files read in binary mode with a '*' in front of the name.
+            let parsed = line
+                .split_once(' ')
+                .map(|(checksum, name)| (checksum, name.trim_start_matches([' ', '*'])))
+                .filter(|(checksum, name)| {
+                    checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit()) && !name.is_empty()
+                });
+            let Some((checksum, name)) = parsed else {
+                let message = format!(
+                    "{} line {}: expected a SHA-256 checksum and a sample name",
+                    path.display(),
+                    index + 1
+                );
+                return Err(AppError::new(ErrorKind::Input, message).into());
+            };
+            checksums.insert(key(name), checksum.to_ascii_lowercase());
+        }
+
+        Ok(Manifest {
+            path: path.to_path_buf(),
+            checksums,
+        })
+    }
+
+    // Every sample has to be in the manifest with the checksum of its file, anything else fails the render.
+    pub fn verify(&self, samples: &[(String, PathBuf)]) -> Result<(), Box<dyn Error>> {
+        let mut problems = Vec::new();
+        for (name, path) in samples {
+            let Some(expected) = self.checksums.get(&key(name)) else {
+                problems.push(format!("{} is not in the manifest", name));
+                continue;
+            };
+            let data = fs::read(path).map_err(|err| {
+                AppError::new(ErrorKind::Input, format!("could not read {}: {}", path.display(), err))
+            })?;
+            let mut hasher = Sha256::new();
+            hasher.update(&data);
+            let found = format!("{:x}", hasher.finalize());
+            if &found != expected {
+                problems.push(format!(
+                    "{} ({}) has the checksum {}, the manifest expects {}",
+                    name,
+                    path.display(),
+                    found,
+                    expected
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            info!("{} samples match {}", samples.len(), self.path.display());
+            return Ok(());
+        }
+
+        let count = problems.len();
+        problems.truncate(MAX_PROBLEMS);
+        let mut message = format!(
+            "{} samples do not match {}:\n  {}",
+            count,
+            self.path.display(),
+            problems.join("\n  ")
+        );
+        if count > MAX_PROBLEMS {
+            message += &format!("\n  and {} more", count - MAX_PROBLEMS);
+        }
+        Err(AppError::new(ErrorKind::Validation, message).into())
+    }
+}
+
+// Sample names of the CSV file and of the manifest are compared without the track number, with '/' between
+// directories and without a leading "./".
+fn key(name: &str) -> String {
+    let name = split_track(name).0.replace('\\', "/");
+    name.trim_start_matches("./").to_string()
+}
@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::f32::consts::TAU;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::parse_time;
+
+// Clicks are short sine bursts, higher on the first beat of a bar.
+const DOWNBEAT_HZ: f32 = 1760.0;
+const BEAT_HZ: f32 = 880.0;
+const CLICK_MS: f32 = 30.0;
+const DECAY_MS: f32 = 6.0;
+
+// A click track for a mix, overlaid on it or written to its own file.
+#[derive(Clone, Debug)]
+pub struct Click {
+    pub tempo_map: Vec<TempoChange>,
+    pub volume: f32,
+    pub stem: Option<PathBuf>,
+}
+
+// Tempo from `time` on, until the next change. Every change starts a new bar.
+#[derive(Clone, Debug)]
+pub struct TempoChange {
+    pub time: f64,
+    pub bpm: f64,
+    pub beats_per_bar: u32,
+}
+
+// Reads a tempo map from a file with one `time,bpm[,beats_per_bar]` line per tempo change, or takes a single tempo
+// written as `BPM[/BEATS_PER_BAR][@TIME]` like 174/4@250ms.
+pub fn read_tempo_map(value: &str) -> Result<Vec<TempoChange>, Box<dyn Error>> {
+    let path = Path::new(value);
+    if !path.is_file() {
+        let (tempo, time) = match value.split_once('@') {
+            Some((tempo, time)) => (tempo, parse_time(time)?),
+            None => (value, 0.0),
+        };
+        let (bpm, beats_per_bar) = match tempo.split_once('/') {
+            Some((bpm, beats_per_bar)) => (bpm, Some(beats_per_bar)),
+            None => (tempo, None),
+        };
+        return Ok(vec![tempo_change(time, bpm, beats_per_bar)?]);
+    }
+
+    let mut changes = Vec::new();
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let change = match fields[..] {
+            [time, bpm] => parse_time(time).and_then(|time| tempo_change(time, bpm, None)),
+            [time, bpm, beats_per_bar] => {
+                parse_time(time).and_then(|time| tempo_change(time, bpm, Some(beats_per_bar)))
+            }
+            _ => Err("expected time,bpm[,beats_per_bar]".to_string()),
+        };
+        changes.push(change.map_err(|err| format!("{}:{}: {}", path.display(), i + 1, err))?);
+    }
+    if changes.is_empty() {
+        return Err(format!("{} has no tempo changes", path.display()).into());
+    }
+    changes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Ok(changes)
+}
+
+fn tempo_change(time: f64, bpm: &str, beats_per_bar: Option<&str>) -> Result<TempoChange, String> {
+    let bpm: f64 = bpm
+        .parse()
+        .ok()
+        .filter(|bpm: &f64| bpm.is_finite() && *bpm > 0.0)
+        .ok_or_else(|| format!("invalid tempo '{}', expected beats per minute", bpm))?;
+    let beats_per_bar = match beats_per_bar {
+        Some(beats) => beats
+            .parse()
+            .ok()
+            .filter(|beats| *beats > 0)
+            .ok_or_else(|| format!("invalid number of beats per bar '{}'", beats))?,
+        None => 4,
+    };
+    Ok(TempoChange {
+        time,
+        bpm,
+        beats_per_bar,
+    })
+}
+
+// Renders the clicks of the tempo map as a stereo track of `frames` frames that starts at `start` ms.
+pub fn click_track(tempo_map: &[TempoChange], start: f64, frames: usize, sample_rate: u32, volume: f32) -> Vec<f32> {
+    let mut data = vec![0.0; frames * 2];
+    let click_frames = (CLICK_MS / 1000.0 * sample_rate as f32) as usize;
+    let end = start + frames as f64 * 1000.0 / sample_rate as f64;
+
+    for (i, change) in tempo_map.iter().enumerate() {
+        let until = tempo_map.get(i + 1).map_or(end, |next| next.time.min(end));
+        let beat_length = 60000.0 / change.bpm;
+        let mut beat = 0;
+        loop {
+            let time = change.time + beat as f64 * beat_length;
+            if time >= until {
+                break;
+            }
+            let frequency = if beat % change.beats_per_bar == 0 {
+                DOWNBEAT_HZ
+            } else {
+                BEAT_HZ
+            };
+            let first = ((time - start) * sample_rate as f64 / 1000.0).round() as i64;
+            for frame in 0..click_frames {
+                let Some(index) = usize::try_from(first + frame as i64)
+                    .ok()
+                    .filter(|index| *index < frames)
+                else {
+                    continue;
+                };
+                let seconds = frame as f32 / sample_rate as f32;
+                let value = volume * (TAU * frequency * seconds).sin() * (-seconds * 1000.0 / DECAY_MS).exp();
+                data[index * 2] += value;
+                data[index * 2 + 1] += value;
+            }
+            beat += 1;
+        }
+    }
+    data
+}
@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+
+use crate::decode::probe_audio;
+use crate::error::{AppError, ErrorKind};
+use crate::events::AudioSampleInfo;
+use crate::mix::RenderSettings;
+use crate::resolve::split_track;
+use crate::synth::parse_synth;
+
+// Ticks per quarter note at the fixed 120 BPM of the file, one tick is a bit more than a ms.
+const DIVISION: u16 = 480;
+const TEMPO: u32 = 500_000;
+// Notes given to samples without one in the note map, starting at the bass drum of General MIDI.
+const FIRST_FREE_NOTE: u8 = 36;
+// Length of the notes of samples that cannot be probed, in ms.
+const DEFAULT_LENGTH: f64 = 100.0;
+
+// Writes the events as notes of a standard MIDI file instead of mixing: one note per event, with the note of its
+// sample in the note map, the volume as velocity and as long as the sample. Samples missing from the map get the
+// next free notes.
+pub fn write_midi(
+    path: &Path,
+    infos: &[AudioSampleInfo],
+    settings: &RenderSettings,
+    note_map: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let mut notes = match note_map {
+        Some(note_map) => load_note_map(note_map)?,
+        None => HashMap::new(),
+    };
+
+    let mut lengths = BTreeMap::new();
+    for info in infos {
+        if lengths.contains_key(info.name.as_str()) {
+            continue;
+        }
+        let file = settings.resolver.resolve(&info.name);
+        let duration = match parse_synth(&info.name) {
+            Some(synth) => synth.map(|synth| synth.duration()).map_err(Into::into),
+            None => probe_audio(&file, split_track(&info.name).1.or(settings.track)).map(|audio| audio.duration()),
+        };
+        let length = match duration {
+            Ok(duration) => duration as f64 * 1000.0,
+            Err(err) => {
+                warn!("{}: {}, its notes are {} ms long", info.name, err, DEFAULT_LENGTH);
+                DEFAULT_LENGTH
+            }
+        };
+        lengths.insert(info.name.as_str(), length);
+    }
+
+    let mut free = (FIRST_FREE_NOTE..=127)
+        .filter(|note| !notes.values().any(|used| used == note))
+        .collect::<Vec<_>>();
+    free.reverse();
+    for name in lengths.keys() {
+        if !notes.contains_key(*name) {
+            let note = free
+                .pop()
+                .ok_or_else(|| AppError::new(ErrorKind::Input, "there are more samples than free MIDI notes"))?;
+            info!("{} is note {}", name, note);
+            notes.insert(name.to_string(), note);
+        }
+    }
+
+    let mut events: Vec<&AudioSampleInfo> = infos.iter().collect();
+    events.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    // (tick, note on after note off at the same tick, message)
+    let mut messages: Vec<(u64, bool, [u8; 3])> = Vec::new();
+    // Index of the note off of the last note per note number, a note played again ends the one before.
+    let mut playing: HashMap<u8, usize> = HashMap::new();
+    let mut before_zero = 0;
+    for info in events {
+        if info.time < 0.0 {
+            before_zero += 1;
+            continue;
+        }
+        // Velocity 0 is a note off.
+        let velocity = (info.volume * 127.0).round() as u8;
+        if velocity == 0 {
+            continue;
+        }
+        let note = notes[&info.name];
+        let start = to_tick(info.time);
+        let end = to_tick(info.time + lengths[info.name.as_str()]).max(start + 1);
+        if let Some(&index) = playing.get(&note) {
+            let previous = &mut messages[index].0;
+            *previous = (*previous).min(start);
+        }
+        messages.push((start, true, [0x90, note, velocity]));
+        playing.insert(note, messages.len());
+        messages.push((end, false, [0x80, note, 0]));
+    }
+    messages.sort_by_key(|(tick, on, _)| (*tick, *on));
+    if before_zero > 0 {
+        warn!("left out {} events before 0, MIDI files start at 0", before_zero);
+    }
+
+    let mut track = Vec::new();
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    write_meta(&mut track, 0x03, name.as_bytes());
+    write_meta(&mut track, 0x51, &TEMPO.to_be_bytes()[1..]);
+    let mut tick = 0;
+    for (at, _, message) in &messages {
+        write_variable(&mut track, at - tick);
+        track.extend_from_slice(message);
+        tick = *at;
+    }
+    write_meta(&mut track, 0x2f, &[]);
+
+    let mut file = Vec::new();
+    file.extend_from_slice(b"MThd");
+    file.extend_from_slice(&6_u32.to_be_bytes());
+    file.extend_from_slice(&0_u16.to_be_bytes());
+    file.extend_from_slice(&1_u16.to_be_bytes());
+    file.extend_from_slice(&DIVISION.to_be_bytes());
+    file.extend_from_slice(b"MTrk");
+    file.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    file.extend_from_slice(&track);
+
+    fs::write(path, file)?;
+    info!("wrote {} notes to {}", messages.len() / 2, path.display());
+    Ok(())
+}
+
+// One "<sample name>,<note>" per line with notes from 0 to 127, the sample names as in the CSV file. Lines starting
+// with '#' are comments.
+fn load_note_map(path: &Path) -> Result<HashMap<String, u8>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| AppError::new(ErrorKind::Input, format!("could not read {}: {}", path.display(), err)))?;
+
+    let mut notes = HashMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line.rsplit_once(',').and_then(|(name, note)| {
+            let name = name.trim().trim_matches('"');
+            let note = note.trim().parse::<u8>().ok().filter(|note| *note <= 127)?;
+            Some((name, note)).filter(|(name, _)| !name.is_empty())
+        });
+        let Some((name, note)) = parsed else {
+            let message = format!(
+                "{} line {}: expected a sample name and a note from 0 to 127",
+                path.display(),
+                index + 1
+            );
+            return Err(AppError::new(ErrorKind::Input, message).into());
+        };
+        notes.insert(name.to_string(), note);
+    }
+    Ok(notes)
+}
+
+fn to_tick(ms: f64) -> u64 {
+    (ms * 1000.0 * DIVISION as f64 / TEMPO as f64).round() as u64
+}
+
+fn write_meta(track: &mut Vec<u8>, kind: u8, data: &[u8]) {
+    track.extend_from_slice(&[0, 0xff, kind]);
+    write_variable(track, data.len() as u64);
+    track.extend_from_slice(data);
+}
+
+// Delta times and lengths are stored with 7 bits per byte, the high bit set on all but the last.
+fn write_variable(track: &mut Vec<u8>, value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    track.extend_from_slice(&bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    fn note_map(name: &str, text: &str) -> Result<HashMap<String, u8>, Box<dyn Error>> {
+        let path = env::temp_dir().join(format!("audio-sample-mixer-test-{}-{}.csv", process::id(), name));
+        fs::write(&path, text).unwrap();
+        let notes = load_note_map(&path);
+        fs::remove_file(&path).unwrap();
+        notes
+    }
+
+    #[test]
+    fn note_maps() {
+        let notes = note_map(
+            "valid",
+            "# drums\n\nkick.wav,36\n\"snare, dry.wav\" , 38\nhat.wav,127\n",
+        )
+        .unwrap();
+        assert_eq!(notes.len(), 3);
+        assert_eq!(notes["kick.wav"], 36);
+        // Only the last comma separates the note, names can have commas of their own.
+        assert_eq!(notes["snare, dry.wav"], 38);
+        assert_eq!(notes["hat.wav"], 127);
+    }
+
+    #[test]
+    fn broken_note_maps() {
+        for (name, text) in [("high", "kick.wav,128\n"), ("empty", ",36\n"), ("plain", "kick.wav\n")] {
+            let err = note_map(name, text).unwrap_err().to_string();
+            assert!(
+                err.ends_with("line 1: expected a sample name and a note from 0 to 127"),
+                "{}",
+                err
+            );
+        }
+        let err = note_map("second", "kick.wav,36\nsnare.wav,-1\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("line 2:"), "{}", err);
+    }
+
+    #[test]
+    fn ticks() {
+        assert_eq!(to_tick(0.0), 0);
+        assert_eq!(to_tick(500.0), DIVISION as u64);
+        assert_eq!(to_tick(1.0), 1);
+        assert_eq!(to_tick(60_000.0), 57_600);
+    }
+
+    #[test]
+    fn variable_lengths() {
+        for (value, bytes) in [
+            (0, &[0x00][..]),
+            (0x7f, &[0x7f]),
+            (0x80, &[0x81, 0x00]),
+            (0x3fff, &[0xff, 0x7f]),
+            (0x20_0000, &[0x81, 0x80, 0x80, 0x00]),
+            (0x0fff_ffff, &[0xff, 0xff, 0xff, 0x7f]),
+        ] {
+            let mut track = Vec::new();
+            write_variable(&mut track, value);
+            assert_eq!(track, bytes, "{:#x}", value);
+        }
+    }
+}
@@ -0,0 +1,1963 @@
+use std::any::Any;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::ops::{AddAssign, Range};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use num::clamp;
+use serde::{Deserialize, Serialize};
+
+use crate::decode::{probe_audio, read_audio, read_sample_loop, read_with_ffmpeg, SampleLoop, UpmixLaw};
+use crate::error::{AppError, ErrorKind, WithKind};
+use crate::events::{rate_semitones, AudioSampleInfo, CsvOptions};
+use crate::loudness::integrated_loudness;
+use crate::lv2::{self, Plugin};
+use crate::manifest::Manifest;
+use crate::metronome::{click_track, Click};
+use crate::random::{random_seed, Rng};
+use crate::report::format_time;
+use crate::resample::{convert, ConverterType};
+use crate::resolve::{split_track, SampleResolver};
+use crate::summary::{ClipRange, LoudMoment, MixedEvent, SkippedEvent};
+use crate::synth::parse_synth;
+
+// The loudest moments are searched in windows of this many ms, so one loud hit is only reported once.
+const LOUD_WINDOW: f64 = 100.0;
+pub const LOUD_MOMENTS: usize = 10;
+// Clipped samples closer together than this many ms are reported as one range.
+const CLIP_GAP: f64 = 50.0;
+// Level relative to the peak of a sample at which --align-transients takes it for its first transient.
+const ONSET_THRESHOLD_DB: f32 = -20.0;
+// Level above which --on-clip soft rounds off the mix, everything below it stays as it is.
+const SOFT_CLIP_KNEE_DB: f32 = -1.0;
+// Clipped places listed by --on-clip error.
+const CLIP_ERROR_PLACES: usize = 10;
+
+// Decoded samples are either kept as f32 or, with --compact, as i16 to roughly halve memory usage.
+#[derive(Debug)]
+pub enum SampleData {
+    Float(Vec<f32>),
+    Compact(Vec<i16>),
+}
+
+impl SampleData {
+    pub fn new(data: Vec<f32>, compact: bool) -> SampleData {
+        if compact {
+            SampleData::Compact(data.iter().map(|&x| (x * i16::MAX as f32) as i16).collect())
+        } else {
+            SampleData::Float(data)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            SampleData::Float(data) => data.len(),
+            SampleData::Compact(data) => data.len(),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> f32 {
+        match self {
+            SampleData::Float(data) => data[index],
+            SampleData::Compact(data) => data[index] as f32 / i16::MAX as f32,
+        }
+    }
+
+    // Number of stereo frames, a left and a right value each.
+    pub fn frames(&self) -> usize {
+        self.len() / 2
+    }
+
+    pub fn frame(&self, frame: usize) -> (f32, f32) {
+        (self.get(frame * 2), self.get(frame * 2 + 1))
+    }
+}
+
+// How the pan of an event moves a stereo sample. A balance turns the channel on the other side down, a true pan also
+// moves it over, so a wide sample panned fully to the right plays both of its channels on the right.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PanMode {
+    Balance,
+    TruePan,
+}
+
+// What happens to events whose sample file does not exist.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissingPolicy {
+    Error,
+    Skip,
+    Silence,
+}
+
+// What happens to samples that exist but cannot be decoded. With skip and silence the audio decoded before an error in
+// the middle of a file is still used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodeErrorPolicy {
+    Fail,
+    Skip,
+    Silence,
+}
+
+// What happens to a mix that goes over full scale.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipPolicy {
+    // Cut off at full scale.
+    Clamp,
+    // Round off what goes over SOFT_CLIP_KNEE_DB, which distorts less than cutting it off.
+    Soft,
+    // Turn the whole mix down until its peak is at full scale, like --auto-gain.
+    Normalize,
+    // Fail with the places that go over.
+    Error,
+}
+
+// What the events are summed up in. Single precision loses the low bits of quiet tails under thousands of events,
+// double precision keeps them at twice the memory while mixing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Precision {
+    Single,
+    Double,
+}
+
+// The level every sample is brought to before it is mixed, for packs put together from sources with different levels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleNormalization {
+    // Peak in dBFS.
+    Peak(f32),
+    // Integrated loudness in LUFS, as in stats.
+    Loudness(f32),
+}
+
+// The sum of the events while they are mixed, in the precision of the settings.
+enum Bus {
+    Single(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+// A full-length song the events are mixed on top of, played like a sample from `offset` ms on with `gain` as its
+// volume factor.
+#[derive(Clone, Debug)]
+pub struct Backing {
+    pub name: String,
+    pub gain: f32,
+    pub offset: f64,
+}
+
+#[derive(Clone)]
+pub struct RenderSettings {
+    pub compact: bool,
+    pub sample_rate: u32,
+    pub resampler: ConverterType,
+    pub upmix: UpmixLaw,
+    // For events without a pan mode of their own.
+    pub pan_mode: PanMode,
+    // Audio track of files with several of them, counting from 1. None is the first one.
+    pub track: Option<usize>,
+    pub on_missing: MissingPolicy,
+    pub on_decode_error: DecodeErrorPolicy,
+    // Only the part between these times in ms is rendered.
+    pub from: f64,
+    pub to: Option<f64>,
+    // Longest mix in ms that is rendered, later events are taken for typos. 0 allows any length.
+    pub max_duration: f64,
+    // Leave out exact duplicates of earlier events.
+    pub dedup_events: bool,
+    // Start every event as much earlier as its sample takes to reach its first transient, so the hit is on time.
+    pub align_transients: bool,
+    // Fade a sample out over this many ms when it is triggered again while it still plays, and the new one in, instead
+    // of summing them up. None sums them up.
+    pub retrigger_fade: Option<f64>,
+    pub on_clip: ClipPolicy,
+    pub precision: Precision,
+    // Turn every sample except the backing track up or down to this level, on top of the volume of its events.
+    pub normalize_samples: Option<SampleNormalization>,
+    // Fail instead of fixing up samples (converting channels, resampling, skipping broken packets) or clamping.
+    pub strict: bool,
+    // Decode samples symphonia cannot read with an ffmpeg binary.
+    pub allow_ffmpeg: bool,
+    // LV2 plugins the mix is run through before it is turned down or clamped.
+    pub plugins: Vec<Plugin>,
+    pub backing: Option<Backing>,
+    // Overlaid on the mix before it is turned down or clamped, so the clip policy sees it.
+    pub click: Option<Click>,
+    // Checksums every sample has to match before anything is decoded.
+    pub manifest: Option<Arc<Manifest>>,
+    // Start the mix at the earliest event when it is before 0 instead of cutting off what plays before 0.
+    pub extend_before_zero: bool,
+    // Maximum random deviation of event times in ms and of volumes as a fraction of the volume.
+    pub humanize_time: f32,
+    pub humanize_volume: f32,
+    pub seed: Option<u64>,
+    pub threads: usize,
+    pub resolver: SampleResolver,
+    pub csv: CsvOptions,
+    pub progress: Option<Arc<Progress>>,
+}
+
+// Everything that changes the mix, which checkpoints are keyed by. The threads, the progress and the manifest do
+// not change it, the CSV options are already in the events.
+impl fmt::Debug for RenderSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenderSettings")
+            .field("compact", &self.compact)
+            .field("sample_rate", &self.sample_rate)
+            .field("resampler", &self.resampler)
+            .field("upmix", &self.upmix)
+            .field("pan_mode", &self.pan_mode)
+            .field("track", &self.track)
+            .field("on_missing", &self.on_missing)
+            .field("on_decode_error", &self.on_decode_error)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .field("max_duration", &self.max_duration)
+            .field("dedup_events", &self.dedup_events)
+            .field("align_transients", &self.align_transients)
+            .field("retrigger_fade", &self.retrigger_fade)
+            .field("on_clip", &self.on_clip)
+            .field("precision", &self.precision)
+            .field("normalize_samples", &self.normalize_samples)
+            .field("strict", &self.strict)
+            .field("allow_ffmpeg", &self.allow_ffmpeg)
+            .field("plugins", &self.plugins)
+            .field("backing", &self.backing)
+            .field("click", &self.click)
+            .field("extend_before_zero", &self.extend_before_zero)
+            .field("humanize_time", &self.humanize_time)
+            .field("humanize_volume", &self.humanize_volume)
+            .field("seed", &self.seed)
+            .field("resolver", &self.resolver)
+            .finish_non_exhaustive()
+    }
+}
+
+// Counters that show how far a render running on another thread has come.
+#[derive(Default)]
+pub struct Progress {
+    pub decoded: AtomicUsize,
+    pub to_decode: AtomicUsize,
+    pub mixed: AtomicUsize,
+    pub to_mix: AtomicUsize,
+}
+
+impl Progress {
+    fn start(&self, to_decode: usize) {
+        self.decoded.store(0, Ordering::Relaxed);
+        self.to_decode.store(to_decode, Ordering::Relaxed);
+        self.mixed.store(0, Ordering::Relaxed);
+        self.to_mix.store(0, Ordering::Relaxed);
+    }
+}
+
+// Decoded samples by file and sample rate, so the renders of a batch decode every sample only once. The other
+// settings that change decoding (resampler, upmix, compact) are the same for a whole batch.
+#[derive(Default)]
+pub struct SampleCache {
+    samples: HashMap<(PathBuf, u32), Arc<SampleData>>,
+    pub hits: usize,
+}
+
+impl SampleCache {
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RenderStats {
+    pub peak: f32,
+    pub clipped: usize,
+    pub decode_time: Duration,
+    pub mix_time: Duration,
+    pub missing: Vec<String>,
+    pub undecodable: Vec<String>,
+    pub mixed: usize,
+    pub skipped: Vec<SkippedEvent>,
+    pub loudest: Vec<LoudMoment>,
+    pub clipping: Vec<ClipRange>,
+    // Time of the first sample of the mix in ms, --from or the earliest event with extend_before_zero.
+    pub start: f64,
+    // Factor the mix was turned down by with auto_gain, 1.0 without it.
+    pub gain: f32,
+    // Ordered by frame, then by name.
+    pub events: Vec<MixedEvent>,
+}
+
+pub fn render(
+    infos: Vec<AudioSampleInfo>,
+    settings: &RenderSettings,
+    cache: &mut SampleCache,
+) -> Result<(Vec<f32>, RenderStats), Box<dyn Error>> {
+    let (mut data, mut stats) = mix_unfinished(infos, settings, cache)?;
+    let started = Instant::now();
+    finish(&mut data, &mut stats, settings)?;
+    stats.mix_time += started.elapsed();
+    Ok((data, stats))
+}
+
+// Mixes the events, leaving the plugins, auto gain and clamping to finish. The levels of the stats are not filled in.
+fn mix_unfinished(
+    infos: Vec<AudioSampleInfo>,
+    settings: &RenderSettings,
+    cache: &mut SampleCache,
+) -> Result<(Vec<f32>, RenderStats), Box<dyn Error>> {
+    let mut infos = infos;
+    if settings.humanize_time > 0.0 || settings.humanize_volume > 0.0 {
+        humanize(&mut infos, settings);
+    }
+    // The backing track is not humanized, everything else treats it as one more event.
+    if let Some(backing) = &settings.backing {
+        // It keeps up with the events at --rate, unless --keep-pitch leaves every sample as it is.
+        let rate = settings.csv.rate;
+        infos.push(AudioSampleInfo {
+            time: backing.offset / rate,
+            volume: backing.gain,
+            pan: 0.0,
+            name: backing.name.clone(),
+            line: 0,
+            lane: None,
+            hold: None,
+            pitch: (rate != 1.0 && !settings.csv.keep_pitch).then(|| rate_semitones(rate)),
+            pan_mode: None,
+            tags: Vec::new(),
+        });
+    }
+
+    let earliest = infos.iter().map(|info| info.time).fold(0.0, f64::min);
+    let start_ms = if settings.extend_before_zero && settings.from == 0.0 {
+        earliest
+    } else {
+        settings.from
+    };
+
+    let (infos, mut skipped) = if start_ms > 0.0 || earliest < start_ms || settings.to.is_some() {
+        events_in_range(infos, start_ms, settings)
+    } else {
+        (infos, Vec::new())
+    };
+
+    if settings.max_duration > 0.0 {
+        check_duration(&infos, start_ms, settings)?;
+    }
+
+    let infos = if settings.dedup_events {
+        drop_duplicates(infos, settings.sample_rate, &mut skipped)
+    } else {
+        infos
+    };
+
+    let mut names = Vec::new();
+    // Ordered by name, so the samples are always summed up in the same order.
+    let mut timing_map = BTreeMap::new();
+
+    for info in infos.iter() {
+        add_timing(info, settings, &mut timing_map);
+
+        if !names.contains(&info.name) {
+            names.push(info.name.clone());
+        }
+    }
+
+    let mut samples = Vec::with_capacity(names.len());
+    let mut files = HashMap::with_capacity(names.len());
+    let mut missing = Vec::new();
+    let mut synthesized = Vec::new();
+    for name in names {
+        if let Some(synth) = parse_synth(&name) {
+            let line = infos.iter().find(|info| info.name == name).map_or(0, |info| info.line);
+            let synth = synth.map_err(|err| AppError::new(ErrorKind::Input, format!("line {}: {}", line, err)))?;
+            // It is generated as a whole, even when --from and --to only need a part of it.
+            let length = synth.duration() as f64 * 1000.0;
+            if settings.max_duration > 0.0 && length > settings.max_duration {
+                let message = format!(
+                    "line {}: {} is {} long, longer than the maximum duration of {}, raise --max-duration",
+                    line,
+                    name,
+                    format_time(length),
+                    format_time(settings.max_duration)
+                );
+                return Err(AppError::new(ErrorKind::Input, message).into());
+            }
+            synthesized.push((name, synth));
+            continue;
+        }
+        let path = settings.resolver.resolve(&name);
+        if path.is_file() {
+            files.insert(name.clone(), path.display().to_string());
+            samples.push((name, path));
+        } else {
+            missing.push(name);
+        }
+    }
+
+    if let Some(manifest) = &settings.manifest {
+        manifest.verify(&samples)?;
+    }
+
+    if !missing.is_empty() {
+        let events: usize = missing.iter().map(|name| timing_map[name].len()).sum();
+        match settings.on_missing {
+            MissingPolicy::Error => {
+                let message = format!(
+                    "missing samples: {} (use --on-missing skip or silence to render anyway)",
+                    missing.join(", ")
+                );
+                return Err(AppError::new(ErrorKind::MissingSample, message).into());
+            }
+            MissingPolicy::Skip => warn!(
+                "skipping {} events of {} missing samples: {}",
+                events,
+                missing.len(),
+                missing.join(", ")
+            ),
+            MissingPolicy::Silence => warn!(
+                "{} events of {} missing samples are silent: {}",
+                events,
+                missing.len(),
+                missing.join(", ")
+            ),
+        }
+    }
+
+    // Only held events sustain in the loop of a sample.
+    let loops: HashMap<String, SampleLoop> = samples
+        .iter()
+        .filter(|(name, _)| {
+            timing_map
+                .get(name)
+                .is_some_and(|list| list.iter().any(|timing| timing.hold.is_some()))
+        })
+        .filter_map(|(name, path)| read_sample_loop(path).map(|sample_loop| (name.clone(), sample_loop)))
+        .collect();
+
+    let started = Instant::now();
+    let mut sample_map = HashMap::with_capacity(samples.len());
+    let mut uncached = Vec::new();
+    for (name, path) in samples {
+        match cache.samples.get(&(path.clone(), settings.sample_rate)) {
+            Some(sample) => {
+                cache.hits += 1;
+                sample_map.insert(name, sample.clone());
+            }
+            None => uncached.push((name, path)),
+        }
+    }
+    if let Some(progress) = &settings.progress {
+        progress.start(uncached.len());
+    }
+    let mut loaded = load_samples(&uncached, settings)?;
+    let mut undecodable = Vec::new();
+    for (name, path) in uncached {
+        match loaded.remove(&name) {
+            Some(sample) => {
+                let sample = Arc::new(sample);
+                cache.samples.insert((path, settings.sample_rate), sample.clone());
+                sample_map.insert(name, sample);
+            }
+            None => undecodable.push(name),
+        }
+    }
+    for (name, synth) in synthesized {
+        let data = SampleData::new(synth.render(settings.sample_rate), settings.compact);
+        sample_map.insert(name, Arc::new(data));
+    }
+    let decode_time = started.elapsed();
+
+    // Samples that could not be decoded are treated like missing ones with the matching policy.
+    undecodable.sort();
+    for name in &undecodable {
+        match settings.on_decode_error {
+            DecodeErrorPolicy::Silence => {
+                sample_map.insert(name.clone(), Arc::new(SampleData::new(Vec::new(), settings.compact)));
+            }
+            _ => {
+                timing_map.remove(name);
+                for info in infos.iter().filter(|info| &info.name == name) {
+                    skipped.push(SkippedEvent {
+                        time: info.time,
+                        name: info.name.clone(),
+                        reason: "sample could not be decoded".into(),
+                    });
+                }
+            }
+        }
+    }
+
+    // Skipped events do not count towards the length of the mix, silent ones do.
+    for name in &missing {
+        match settings.on_missing {
+            MissingPolicy::Silence => {
+                sample_map.insert(name.clone(), Arc::new(SampleData::new(Vec::new(), settings.compact)));
+            }
+            _ => {
+                timing_map.remove(name);
+            }
+        }
+    }
+    if settings.on_missing == MissingPolicy::Skip {
+        for info in infos.iter().filter(|info| missing.contains(&info.name)) {
+            skipped.push(SkippedEvent {
+                time: info.time,
+                name: info.name.clone(),
+                reason: "missing sample".into(),
+            });
+        }
+    }
+    skipped.sort_by(|a, b| a.time.total_cmp(&b.time));
+    let mixed = timing_map.values().map(|list| list.len()).sum();
+
+    let backing = settings.backing.as_ref().map(|backing| &backing.name);
+    let levels: HashMap<&String, f32> = match settings.normalize_samples {
+        Some(target) => sample_map
+            .iter()
+            .filter(|(name, _)| Some(*name) != backing)
+            .filter_map(|(name, sample)| {
+                let gain = normalization_gain(sample, target, settings.sample_rate)?;
+                debug!("{} is normalized with a gain of {:+.1} dB", name, 20.0 * gain.log10());
+                Some((name, gain))
+            })
+            .collect(),
+        None => HashMap::new(),
+    };
+
+    let started = Instant::now();
+
+    // Everything is positioned in frames, so the left and right values of a sample always stay on their side.
+    let start = to_frame(start_ms, settings.sample_rate);
+    // Pitched events play their sample resampled, once for every pitch it is played at.
+    let mut pitched: HashMap<String, HashMap<i32, Arc<SampleData>>> = HashMap::new();
+    for (name, list) in &timing_map {
+        let Some(sample) = sample_map.get(name) else {
+            continue;
+        };
+        for timing in list.iter().filter(|timing| timing.cents != 0) {
+            if let Entry::Vacant(entry) = pitched.entry(name.clone()).or_default().entry(timing.cents) {
+                entry.insert(Arc::new(pitch_shift(sample, timing.cents, settings)?));
+            }
+        }
+    }
+    let sample_of = |name: &String, timing: &Timing| match timing.cents {
+        0 => sample_map.get(name),
+        cents => pitched.get(name).and_then(|pitches| pitches.get(&cents)),
+    };
+    // The loop of a pitched sample moves with it.
+    let playback_of = |name: &String, timing: &Timing, sample: &SampleData| {
+        let rate = settings.sample_rate as f64 / pitch_ratio(timing.cents);
+        let sustain = loops
+            .get(name)
+            .and_then(|sample_loop| sustain(sample_loop, rate, sample.frames()));
+        timing.playback(sample.frames(), sustain)
+    };
+
+    if settings.align_transients {
+        for (name, list) in timing_map.iter_mut() {
+            let mut onsets = HashMap::new();
+            for timing in list.iter_mut() {
+                let Some(sample) = sample_of(name, timing) else {
+                    continue;
+                };
+                let onset = *onsets.entry(timing.cents).or_insert_with(|| {
+                    let onset = onset(sample);
+                    debug!(
+                        "{} reaches its first transient after {:.1} ms",
+                        name,
+                        onset as f64 * 1000.0 / settings.sample_rate as f64
+                    );
+                    onset
+                });
+                timing.frame -= onset as i64;
+            }
+        }
+    }
+
+    let mut end = calculate_max_length(&timing_map, |name, timing| {
+        sample_of(name, timing).map(|sample| playback_of(name, timing, sample).length())
+    });
+    if let Some(to) = settings.to {
+        end = end.min(to_frame(to, settings.sample_rate));
+    }
+
+    let length = (end - start).max(0) as usize * 2;
+    let mut bus = match settings.precision {
+        Precision::Single => Bus::Single(vec![0.0; length]),
+        Precision::Double => Bus::Double(vec![0.0; length]),
+    };
+    let mut events = Vec::with_capacity(mixed);
+
+    let length_of = |name: &String, timing: &Timing| {
+        sample_of(name, timing).map_or(0, |sample| playback_of(name, timing, sample).length())
+    };
+    let fades: HashMap<&String, Vec<Option<Fade>>> = match settings.retrigger_fade {
+        Some(fade) => timing_map
+            .iter()
+            .map(|(name, list)| {
+                let length = to_frame(fade, settings.sample_rate);
+                (name, retrigger_fades(list, |timing| length_of(name, timing), length))
+            })
+            .collect(),
+        None => HashMap::new(),
+    };
+    // Events are summed in the order of their time and then their name, so the same events give the same bits on
+    // every run and platform, whatever order the rows came in.
+    let mut order: Vec<(&String, usize)> = timing_map
+        .iter()
+        .filter(|(name, _)| sample_map.contains_key(*name))
+        .flat_map(|(name, list)| (0..list.len()).map(move |i| (name, i)))
+        .collect();
+    order.sort_by(|a, b| {
+        let frame = |(name, i): &(&String, usize)| timing_map[*name][*i].frame;
+        frame(a).cmp(&frame(b)).then_with(|| a.0.cmp(b.0))
+    });
+
+    if let Some(progress) = &settings.progress {
+        progress.to_mix.store(order.len(), Ordering::Relaxed);
+    }
+    for (name, i) in order {
+        if let Some(progress) = &settings.progress {
+            progress.mixed.fetch_add(1, Ordering::Relaxed);
+        }
+        let timing = &timing_map[name][i];
+        let Some(sample) = sample_of(name, timing) else {
+            continue;
+        };
+        let frame = timing.frame;
+        let level = levels.get(name).copied().unwrap_or(1.0);
+        let gains = gains(timing.volume * level, timing.pan, timing.pan_mode);
+        let fade = fades.get(name).and_then(|fades| fades[i]);
+        let playback = playback_of(name, timing, sample);
+        let length = playback.length();
+        let frames = fade.map_or(length, |fade| fade.end().min(length));
+        events.push(MixedEvent {
+            frame: frame - start,
+            time: (frame - start) as f64 / settings.sample_rate as f64,
+            name: name.clone(),
+            file: files.get(name).cloned().unwrap_or_else(|| name.clone()),
+            volume: timing.volume,
+            pan: timing.pan,
+            gain_left: gains.left,
+            gain_right: gains.right,
+            gain_left_to_right: gains.left_to_right,
+            gain_right_to_left: gains.right_to_left,
+            frames,
+            duration: frames as f64 / settings.sample_rate as f64,
+        });
+        let (position, skip) = if frame >= start {
+            ((frame - start) as usize, 0)
+        } else {
+            (0, (start - frame) as usize)
+        };
+        let range = skip..frames;
+        match (&mut bus, fade) {
+            (Bus::Single(data), Some(fade)) => mix_faded(data, sample, playback, position, range, gains, fade),
+            (Bus::Single(data), None) => mix(data, sample, playback, position, range, gains),
+            (Bus::Double(data), Some(fade)) => mix_faded(data, sample, playback, position, range, gains, fade),
+            (Bus::Double(data), None) => mix(data, sample, playback, position, range, gains),
+        }
+    }
+    let data = match bus {
+        Bus::Single(data) => data,
+        Bus::Double(data) => data.into_iter().map(|value| value as f32).collect(),
+    };
+
+    let stats = RenderStats {
+        peak: 0.0,
+        clipped: 0,
+        decode_time,
+        mix_time: started.elapsed(),
+        missing,
+        undecodable,
+        mixed,
+        skipped,
+        loudest: Vec::new(),
+        clipping: Vec::new(),
+        start: start_ms,
+        gain: 1.0,
+        events,
+    };
+
+    Ok((data, stats))
+}
+
+// The factor that brings a sample to the target level, None for silence.
+fn normalization_gain(sample: &SampleData, target: SampleNormalization, sample_rate: u32) -> Option<f32> {
+    let (level, target) = match target {
+        SampleNormalization::Peak(target) => {
+            let peak = (0..sample.len()).map(|i| sample.get(i).abs()).fold(0.0, f32::max);
+            ((peak > 0.0).then(|| 20.0 * peak.log10())?, target)
+        }
+        SampleNormalization::Loudness(target) => {
+            let data: Vec<f32> = (0..sample.len()).map(|i| sample.get(i)).collect();
+            (integrated_loudness(&data, sample_rate)?, target)
+        }
+    };
+    Some(10f32.powf((target - level) / 20.0))
+}
+
+// The first frame of a sample that reaches ONSET_THRESHOLD_DB below its peak, 0 for silent samples.
+fn onset(sample: &SampleData) -> usize {
+    let peak = (0..sample.len()).map(|i| sample.get(i).abs()).fold(0.0, f32::max);
+    let threshold = peak * 10f32.powf(ONSET_THRESHOLD_DB / 20.0);
+    (0..sample.frames())
+        .position(|frame| {
+            let (left, right) = sample.frame(frame);
+            left.abs().max(right.abs()) >= threshold
+        })
+        .unwrap_or(0)
+}
+
+// Runs the plugins over the mix, overlays the click, turns it down or clamps it and fills in the levels of the stats.
+fn finish(data: &mut Vec<f32>, stats: &mut RenderStats, settings: &RenderSettings) -> Result<(), Box<dyn Error>> {
+    if !settings.plugins.is_empty() {
+        *data = lv2::apply(&settings.plugins, std::mem::take(data), settings.sample_rate)?;
+    }
+    if let Some(click) = &settings.click {
+        let track = click_track(
+            &click.tempo_map,
+            stats.start,
+            data.len() / 2,
+            settings.sample_rate,
+            click.volume,
+        );
+        for (value, click) in data.iter_mut().zip(track) {
+            *value += click;
+        }
+    }
+
+    stats.gain = if settings.on_clip == ClipPolicy::Normalize {
+        auto_gain(data)
+    } else {
+        1.0
+    };
+    stats
+        .events
+        .sort_by(|a, b| a.frame.cmp(&b.frame).then_with(|| a.name.cmp(&b.name)));
+    for event in stats.events.iter_mut() {
+        event.gain_left *= stats.gain;
+        event.gain_right *= stats.gain;
+        event.gain_left_to_right *= stats.gain;
+        event.gain_right_to_left *= stats.gain;
+    }
+
+    stats.loudest = loudest_moments(data, stats.start, settings);
+    stats.clipping = clipped_ranges(data, stats.start, settings.sample_rate);
+
+    let peak = data.iter().fold(0.0_f32, |peak, value| peak.max(value.abs()));
+    let clipped = data.iter().filter(|value| value.abs() > 1.0).count();
+
+    if clipped > 0 && settings.on_clip == ClipPolicy::Error {
+        let mut places: Vec<String> = stats
+            .clipping
+            .iter()
+            .take(CLIP_ERROR_PLACES)
+            .map(|range| {
+                format!(
+                    "{} to {}, up to {:+.1} dB",
+                    format_time(range.start),
+                    format_time(range.end),
+                    range.overshoot_db
+                )
+            })
+            .collect();
+        if stats.clipping.len() > CLIP_ERROR_PLACES {
+            places.push(format!("and {} more", stats.clipping.len() - CLIP_ERROR_PLACES));
+        }
+        let message = format!(
+            "{} samples go over full scale in {} places (--on-clip normalize avoids it):\n  {}",
+            clipped,
+            stats.clipping.len(),
+            places.join("\n  ")
+        );
+        return Err(AppError::new(ErrorKind::Validation, message).into());
+    }
+    if clipped > 0 && settings.strict {
+        let message = format!(
+            "{} samples were clipped in {} places, the first at {}, which --strict does not allow (--auto-gain avoids it)",
+            clipped,
+            stats.clipping.len(),
+            format_time(stats.clipping[0].start)
+        );
+        return Err(AppError::new(ErrorKind::Validation, message).into());
+    }
+
+    match settings.on_clip {
+        ClipPolicy::Soft => soft_clip(data),
+        _ => {
+            for element in data.iter_mut() {
+                *element = clamp(*element, -1.0, 1.0);
+            }
+        }
+    }
+    if clipped > 0 {
+        warn!(
+            "{} samples were {} in {} places, up to {:+.1} dB over full scale, the first at {}",
+            clipped,
+            if settings.on_clip == ClipPolicy::Soft {
+                "soft clipped"
+            } else {
+                "clipped"
+            },
+            stats.clipping.len(),
+            20.0 * peak.log10(),
+            format_time(stats.clipping[0].start)
+        );
+    }
+
+    stats.peak = peak;
+    stats.clipped = clipped;
+    Ok(())
+}
+
+// Appends the files back to back, each one starting `crossfade` ms before the previous one ends and fading into it
+// with equal power. Every file is decoded like a sample of a mix, with the same policies for missing and broken files.
+// The plugins, click and clip policy are applied to the whole concatenation, and --offset moves it: what it moves
+// before 0 is cut off like events before 0. The files that were appended are added to `events`, at the time they
+// start.
+pub fn concat(
+    names: &[String],
+    crossfade: f64,
+    settings: &RenderSettings,
+    cache: &mut SampleCache,
+    events: &mut Vec<AudioSampleInfo>,
+) -> Result<(Vec<f32>, RenderStats), Box<dyn Error>> {
+    let file_settings = RenderSettings {
+        from: 0.0,
+        to: None,
+        max_duration: 0.0,
+        plugins: Vec::new(),
+        click: None,
+        extend_before_zero: false,
+        humanize_time: 0.0,
+        humanize_volume: 0.0,
+        ..settings.clone()
+    };
+    let overlap = to_frame(crossfade, settings.sample_rate).max(0) as usize;
+    let appended = events.len();
+
+    let started = Instant::now();
+    let mut data: Vec<f32> = Vec::new();
+    let mut stats = RenderStats {
+        peak: 0.0,
+        clipped: 0,
+        decode_time: Duration::ZERO,
+        mix_time: Duration::ZERO,
+        missing: Vec::new(),
+        undecodable: Vec::new(),
+        mixed: 0,
+        skipped: Vec::new(),
+        loudest: Vec::new(),
+        clipping: Vec::new(),
+        start: 0.0,
+        gain: 1.0,
+        events: Vec::new(),
+    };
+
+    for (i, name) in names.iter().enumerate() {
+        let frames = data.len() / 2;
+        let event = AudioSampleInfo {
+            time: 0.0,
+            volume: 1.0,
+            pan: 0.0,
+            name: name.clone(),
+            line: i as u64 + 1,
+            lane: None,
+            hold: None,
+            pitch: None,
+            pan_mode: None,
+            tags: Vec::new(),
+        };
+        let (file, file_stats) = mix_unfinished(vec![event.clone()], &file_settings, cache)?;
+
+        // The crossfade cannot be longer than either file.
+        let fade = overlap.min(frames).min(file.len() / 2);
+        let start = frames - fade;
+        let time = start as f64 * 1000.0 / settings.sample_rate as f64;
+        stats.decode_time += file_stats.decode_time;
+        stats.missing.extend(file_stats.missing);
+        stats.undecodable.extend(file_stats.undecodable);
+        stats.mixed += file_stats.mixed;
+        stats.skipped.extend(
+            file_stats
+                .skipped
+                .into_iter()
+                .map(|skipped| SkippedEvent { time, ..skipped }),
+        );
+        stats
+            .events
+            .extend(file_stats.events.into_iter().map(|mixed| MixedEvent {
+                frame: start as i64,
+                time: time / 1000.0,
+                ..mixed
+            }));
+        if file_stats.mixed == 0 {
+            continue;
+        }
+
+        for frame in 0..fade {
+            let position = (frame as f32 + 0.5) / fade as f32 * std::f32::consts::FRAC_PI_2;
+            let (out, into) = (position.cos(), position.sin());
+            for channel in 0..2 {
+                let index = (start + frame) * 2 + channel;
+                data[index] = data[index] * out + file[frame * 2 + channel] * into;
+            }
+        }
+        data.extend_from_slice(&file[fade * 2..]);
+        events.push(AudioSampleInfo { time, ..event });
+    }
+
+    let shift = to_frame(settings.csv.offset, settings.sample_rate).max(-((data.len() / 2) as i64));
+    if shift > 0 {
+        data.splice(0..0, vec![0.0; shift as usize * 2]);
+    } else {
+        data.drain(..(-shift) as usize * 2);
+    }
+    let time = shift as f64 * 1000.0 / settings.sample_rate as f64;
+    for event in events.iter_mut().skip(appended) {
+        event.time += time;
+    }
+    for skipped in stats.skipped.iter_mut() {
+        skipped.time += time;
+    }
+    for mixed in stats.events.iter_mut() {
+        mixed.frame += shift;
+        mixed.time += time / 1000.0;
+    }
+
+    info!("appended {} of {} files", stats.mixed, names.len());
+    finish(&mut data, &mut stats, settings)?;
+    stats.mix_time = started.elapsed().saturating_sub(stats.decode_time);
+
+    Ok((data, stats))
+}
+
+// Mixes the events of every track and appends the tracks back to back, each one starting on the frame after the last
+// one of the track before it and `gaps[i]` ms later before track i + 1. Tracks start at 0 of their events, events
+// before 0 are cut off like without extend_before_zero. The plugins, auto gain and clamping are
+// applied to the whole album, so it has one level. The times the tracks start at in ms are added to `starts`.
+pub fn album(
+    tracks: Vec<Vec<AudioSampleInfo>>,
+    gaps: &[f64],
+    settings: &RenderSettings,
+    cache: &mut SampleCache,
+    starts: &mut Vec<f64>,
+) -> Result<(Vec<f32>, RenderStats), Box<dyn Error>> {
+    let track_settings = RenderSettings {
+        from: 0.0,
+        to: None,
+        extend_before_zero: false,
+        ..settings.clone()
+    };
+
+    let mut data: Vec<f32> = Vec::new();
+    let mut stats = RenderStats {
+        peak: 0.0,
+        clipped: 0,
+        decode_time: Duration::ZERO,
+        mix_time: Duration::ZERO,
+        missing: Vec::new(),
+        undecodable: Vec::new(),
+        mixed: 0,
+        skipped: Vec::new(),
+        loudest: Vec::new(),
+        clipping: Vec::new(),
+        start: 0.0,
+        gain: 1.0,
+        events: Vec::new(),
+    };
+    for (i, infos) in tracks.into_iter().enumerate() {
+        if i > 0 {
+            let gap = to_frame(gaps.get(i - 1).copied().unwrap_or(0.0), settings.sample_rate).max(0) as usize;
+            data.resize(data.len() + gap * 2, 0.0);
+        }
+        let (track, track_stats) = mix_unfinished(infos, &track_settings, cache)?;
+
+        let frame = data.len() / 2;
+        let time = frame as f64 * 1000.0 / settings.sample_rate as f64;
+        starts.push(time);
+        stats.decode_time += track_stats.decode_time;
+        stats.mix_time += track_stats.mix_time;
+        for name in track_stats.missing {
+            if !stats.missing.contains(&name) {
+                stats.missing.push(name);
+            }
+        }
+        for name in track_stats.undecodable {
+            if !stats.undecodable.contains(&name) {
+                stats.undecodable.push(name);
+            }
+        }
+        stats.mixed += track_stats.mixed;
+        stats
+            .skipped
+            .extend(track_stats.skipped.into_iter().map(|skipped| SkippedEvent {
+                time: time + skipped.time,
+                ..skipped
+            }));
+        stats
+            .events
+            .extend(track_stats.events.into_iter().map(|mixed| MixedEvent {
+                frame: frame as i64 + mixed.frame,
+                time: time / 1000.0 + mixed.time,
+                ..mixed
+            }));
+        data.extend(track);
+    }
+
+    let started = Instant::now();
+    finish(&mut data, &mut stats, settings)?;
+    stats.mix_time += started.elapsed();
+
+    Ok((data, stats))
+}
+
+// Leaves everything up to the knee as it is and bends what is louder smoothly towards full scale, which it never
+// reaches.
+fn soft_clip(data: &mut [f32]) {
+    let knee = 10f32.powf(SOFT_CLIP_KNEE_DB / 20.0);
+    for value in data.iter_mut() {
+        let level = value.abs();
+        if level > knee {
+            let level = knee + (1.0 - knee) * ((level - knee) / (1.0 - knee)).tanh();
+            *value = level.copysign(*value);
+        }
+    }
+}
+
+// Scales the mix so its peak is at full scale if it is louder and returns the factor. The whole mix gets the same
+// gain, so the balance between the samples stays the same.
+fn auto_gain(data: &mut [f32]) -> f32 {
+    let peak = data.iter().fold(0.0_f32, |peak, value| peak.max(value.abs()));
+    if peak <= 1.0 {
+        info!(
+            "the peak is {:+.1} dBFS, auto gain leaves the mix as it is",
+            20.0 * peak.log10()
+        );
+        return 1.0;
+    }
+
+    let gain = 1.0 / peak;
+    for value in data.iter_mut() {
+        *value *= gain;
+    }
+    info!(
+        "turned the mix down by {:.1} dB so that nothing clips",
+        -20.0 * gain.log10()
+    );
+    gain
+}
+
+// Leaves out events that repeat an earlier one exactly: the same sample at the same frame with the same volume, pan,
+// pitch and hold. Some exporters write every event twice, which makes it 6 dB louder.
+fn drop_duplicates(
+    infos: Vec<AudioSampleInfo>,
+    sample_rate: u32,
+    skipped: &mut Vec<SkippedEvent>,
+) -> Vec<AudioSampleInfo> {
+    let mut seen = HashSet::with_capacity(infos.len());
+    let mut kept = Vec::with_capacity(infos.len());
+    let mut duplicates = 0;
+
+    for info in infos {
+        let key = (
+            info.name.clone(),
+            to_frame(info.time, sample_rate),
+            info.volume.to_bits(),
+            info.pan.to_bits(),
+            info.pan_mode,
+            info.pitch.map(f32::to_bits),
+            info.hold.map(f64::to_bits),
+        );
+        if seen.insert(key) {
+            kept.push(info);
+        } else {
+            duplicates += 1;
+            skipped.push(SkippedEvent {
+                time: info.time,
+                name: info.name,
+                reason: "duplicate event".into(),
+            });
+        }
+    }
+
+    if duplicates > 0 {
+        info!("left out {} duplicate events", duplicates);
+    }
+    kept
+}
+
+// Fails on the first event that is still playing after the maximum duration, before anything is decoded or allocated.
+// A pitched down sample plays for longer, so the lengths of the samples are probed.
+pub fn check_duration(infos: &[AudioSampleInfo], start: f64, settings: &RenderSettings) -> Result<(), Box<dyn Error>> {
+    let end = start + settings.max_duration;
+    let mut durations = HashMap::new();
+    let Some((info, until)) = infos
+        .iter()
+        .map(|info| {
+            let duration = event_duration(info, settings, &mut durations).unwrap_or(0.0);
+            (info, info.time + duration)
+        })
+        .filter(|(_, until)| *until > end)
+        .min_by_key(|(info, _)| info.line)
+    else {
+        return Ok(());
+    };
+
+    let message = format!(
+        "line {}: the event of {} at {} plays until {}, after the maximum duration of {}, check its time and pitch or \
+         raise --max-duration, 0 allows any length",
+        info.line,
+        info.name,
+        format_time(info.time),
+        format_time(until),
+        format_time(settings.max_duration)
+    );
+    Err(AppError::new(ErrorKind::Input, message).into())
+}
+
+// How long an event plays in ms: as long as it is held, or as long as its sample at its pitch. The lengths of the
+// samples are probed once for every name, None if the sample cannot be probed.
+fn event_duration(
+    info: &AudioSampleInfo,
+    settings: &RenderSettings,
+    durations: &mut HashMap<String, Option<f32>>,
+) -> Option<f64> {
+    if info.hold.is_some() {
+        return info.hold;
+    }
+    let duration = durations.entry(info.name.clone()).or_insert_with(|| {
+        if let Some(synth) = parse_synth(&info.name) {
+            return synth.ok().map(|synth| synth.duration());
+        }
+        let path = settings.resolver.resolve(&info.name);
+        probe_audio(&path, track_of(&info.name, settings))
+            .ok()
+            .map(|audio| audio.duration())
+    });
+    duration.map(|duration| duration as f64 * 1000.0 / pitch_ratio(cents(info)))
+}
+
+// Moves every event by a random amount of time and changes its volume, in the order of the CSV file.
+fn humanize(infos: &mut [AudioSampleInfo], settings: &RenderSettings) {
+    let seed = settings.seed.unwrap_or_else(random_seed);
+    info!("humanizing with seed {}", seed);
+
+    let mut rng = Rng::new(seed);
+    for info in infos.iter_mut() {
+        let time = info.time + rng.spread(settings.humanize_time) as f64;
+        let volume = info.volume * (1.0 + rng.spread(settings.humanize_volume));
+        // Only events that were at 0 or later are kept there, negative times are allowed in the CSV file.
+        info.time = if info.time >= 0.0 { time.max(0.0) } else { time };
+        // Lane gains can take volumes above 1, humanizing only keeps them from turning negative.
+        if settings.humanize_volume > 0.0 {
+            info.volume = volume.max(0.0);
+        }
+    }
+}
+
+// Drops the events that cannot be heard between the start (--from or 0) and --to. Earlier events are kept while their
+// sample is still playing at the start, which only needs the length of the sample instead of decoding it.
+fn events_in_range(
+    infos: Vec<AudioSampleInfo>,
+    start: f64,
+    settings: &RenderSettings,
+) -> (Vec<AudioSampleInfo>, Vec<SkippedEvent>) {
+    let too_early = if settings.from > 0.0 {
+        "ends before --from"
+    } else {
+        "ends before 0"
+    };
+    let mut durations: HashMap<String, Option<f32>> = HashMap::new();
+    let mut kept = Vec::with_capacity(infos.len());
+    let mut skipped = Vec::new();
+
+    for info in infos {
+        let reason = if settings.to.is_some_and(|to| info.time >= to) {
+            Some("starts after --to")
+        } else if info.time >= start {
+            None
+        } else {
+            match event_duration(&info, settings, &mut durations) {
+                Some(duration) if info.time + duration <= start => Some(too_early),
+                _ => None,
+            }
+        };
+
+        match reason {
+            Some(reason) => skipped.push(SkippedEvent {
+                time: info.time,
+                name: info.name,
+                reason: reason.into(),
+            }),
+            None => kept.push(info),
+        }
+    }
+
+    (kept, skipped)
+}
+
+// The parts of the mix that go over full scale and are clamped, in time order.
+fn clipped_ranges(data: &[f32], start: f64, sample_rate: u32) -> Vec<ClipRange> {
+    let to_ms = |frame: usize| start + frame as f64 / sample_rate as f64 * 1000.0;
+    let gap = to_frame(CLIP_GAP, sample_rate).max(1) as usize;
+
+    // First and last frame, clipped samples and peak of every range.
+    let mut ranges: Vec<(usize, usize, usize, f32)> = Vec::new();
+    for (frame, values) in data.chunks_exact(2).enumerate() {
+        let peak = values[0].abs().max(values[1].abs());
+        if peak <= 1.0 {
+            continue;
+        }
+        let clipped = values.iter().filter(|value| value.abs() > 1.0).count();
+        match ranges.last_mut() {
+            Some(range) if frame - range.1 <= gap => {
+                range.1 = frame;
+                range.2 += clipped;
+                range.3 = range.3.max(peak);
+            }
+            _ => ranges.push((frame, frame, clipped, peak)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(first, last, clipped_samples, peak)| ClipRange {
+            start: to_ms(first),
+            end: to_ms(last + 1),
+            clipped_samples,
+            peak,
+            overshoot_db: 20.0 * peak.log10(),
+        })
+        .collect()
+}
+
+// The highest peaks of the mix before clamping, loudest first. Only windows that are louder than both neighbours
+// count, so the decay of one loud hit is not reported as several moments.
+fn loudest_moments(data: &[f32], start: f64, settings: &RenderSettings) -> Vec<LoudMoment> {
+    let window = (to_frame(LOUD_WINDOW, settings.sample_rate) as usize * 2).max(2);
+
+    let windows: Vec<(usize, f32)> = data
+        .chunks(window)
+        .enumerate()
+        .map(|(chunk, values)| {
+            let (index, peak) = values
+                .iter()
+                .map(|value| value.abs())
+                .enumerate()
+                .fold((0, 0.0_f32), |max, value| if value.1 > max.1 { value } else { max });
+            (chunk * window + index, peak)
+        })
+        .collect();
+
+    let mut peaks: Vec<(usize, f32)> = windows
+        .iter()
+        .enumerate()
+        .filter(|(i, (_, peak))| {
+            let previous = if *i > 0 { windows[i - 1].1 } else { 0.0 };
+            let next = windows.get(i + 1).map_or(0.0, |window| window.1);
+            *peak > 0.0 && *peak >= previous && *peak > next
+        })
+        .map(|(_, window)| *window)
+        .collect();
+    peaks.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    peaks.truncate(LOUD_MOMENTS);
+
+    peaks
+        .into_iter()
+        .map(|(index, peak)| LoudMoment {
+            time: start + (index / 2) as f64 / settings.sample_rate as f64 * 1000.0,
+            peak,
+            peak_dbfs: 20.0 * peak.log10(),
+        })
+        .collect()
+}
+
+// Decodes every sample once, spreading the files over `settings.threads` worker threads.
+fn load_samples(
+    samples: &[(String, PathBuf)],
+    settings: &RenderSettings,
+) -> Result<HashMap<String, SampleData>, Box<dyn Error>> {
+    let next = AtomicUsize::new(0);
+    let sample_map = Mutex::new(HashMap::with_capacity(samples.len()));
+    let failure = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..settings.threads.max(1) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some((name, path)) = samples.get(index) else {
+                    break;
+                };
+                if failure.lock().unwrap().is_some() {
+                    break;
+                }
+
+                info!("loading {}", name);
+                let salvage = settings.on_decode_error != DecodeErrorPolicy::Fail && !settings.strict;
+                // Broken files can make the decoders panic, that only fails this sample.
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let track = track_of(name, settings);
+                    read_audio(
+                        path,
+                        settings.sample_rate,
+                        settings.resampler,
+                        settings.upmix,
+                        track,
+                        salvage,
+                        settings.strict,
+                    )
+                }))
+                .unwrap_or_else(|panic| Err(panic_message(panic).into()));
+
+                // --strict errors are not decoding problems, ffmpeg would only hide them.
+                let result = match result {
+                    Err(err) if settings.allow_ffmpeg && !settings.strict => {
+                        match read_with_ffmpeg(path, settings.sample_rate, track_of(name, settings)) {
+                            Ok(data) => {
+                                info!("{}: {}, decoded it with ffmpeg instead", path.display(), err);
+                                Ok(data)
+                            }
+                            Err(ffmpeg_err) => Err(format!("{}, and {}", err, ffmpeg_err).into()),
+                        }
+                    }
+                    result => result,
+                };
+
+                match result {
+                    Ok(data) => {
+                        let data = SampleData::new(data, settings.compact);
+                        sample_map.lock().unwrap().insert(name.clone(), data);
+                        if let Some(progress) = &settings.progress {
+                            progress.decoded.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    Err(err) => {
+                        let message = format!("could not decode {}: {}", path.display(), err);
+                        match settings.on_decode_error {
+                            _ if settings.strict => {
+                                failure.lock().unwrap().get_or_insert(message);
+                            }
+                            DecodeErrorPolicy::Fail => {
+                                failure.lock().unwrap().get_or_insert(message);
+                            }
+                            DecodeErrorPolicy::Skip => warn!("{}, skipping its events", message),
+                            DecodeErrorPolicy::Silence => warn!("{}, its events are silent", message),
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(message) = failure.into_inner().unwrap() {
+        return Err(AppError::new(ErrorKind::Decode, message).into());
+    }
+
+    Ok(sample_map.into_inner().unwrap())
+}
+
+// The track number in the name of a sample wins over --track.
+fn track_of(name: &str, settings: &RenderSettings) -> Option<usize> {
+    split_track(name).1.or(settings.track)
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    let message = match panic.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+    };
+    format!("the decoder failed: {}", message)
+}
+
+// How much of each channel of a sample an event plays on each channel of the mix.
+#[derive(Clone, Copy, Debug)]
+pub struct Gains {
+    pub left: f32,
+    pub right: f32,
+    // Of the left channel on the right one and the other way round, only for a true pan.
+    pub left_to_right: f32,
+    pub right_to_left: f32,
+}
+
+impl Gains {
+    pub fn apply(&self, (left, right): (f32, f32)) -> (f32, f32) {
+        (
+            left * self.left + right * self.right_to_left,
+            right * self.right + left * self.left_to_right,
+        )
+    }
+}
+
+// Gains of an event. Panning turns the other side down, a true pan adds what it takes away from it to the side it pans
+// to. 0.0 leaves both channels at the volume.
+pub fn gains(volume: f32, pan: f32, mode: PanMode) -> Gains {
+    let left = volume * (1.0 - pan).clamp(0.0, 1.0);
+    let right = volume * (1.0 + pan).clamp(0.0, 1.0);
+    match mode {
+        PanMode::Balance => Gains {
+            left,
+            right,
+            left_to_right: 0.0,
+            right_to_left: 0.0,
+        },
+        PanMode::TruePan => Gains {
+            left,
+            right,
+            left_to_right: volume - left,
+            right_to_left: volume - right,
+        },
+    }
+}
+
+// Adds the frames `range` of an event playing `sample` to the stereo frames of `data` starting at frame `start`.
+fn mix<T: Copy + AddAssign + From<f32>>(
+    data: &mut [T],
+    sample: &SampleData,
+    playback: Playback,
+    start: usize,
+    range: Range<usize>,
+    gains: Gains,
+) {
+    let Some(frames) = data.get_mut(start * 2..) else {
+        return;
+    };
+    for (frame, i) in frames.chunks_exact_mut(2).zip(range) {
+        let (left, right) = gains.apply(sample.frame(playback.frame(i)));
+        frame[0] += T::from(left);
+        frame[1] += T::from(right);
+    }
+}
+
+// How a sample that is retriggered while it plays fades, in frames of the sample: in over `fade_in` frames at its
+// start and out over `length` frames from `cut` on.
+#[derive(Clone, Copy, Debug)]
+struct Fade {
+    fade_in: usize,
+    cut: Option<usize>,
+    length: usize,
+}
+
+impl Fade {
+    // The frame after the last one that is heard.
+    fn end(&self) -> usize {
+        self.cut.map_or(usize::MAX, |cut| cut + self.length)
+    }
+
+    fn gain(&self, frame: usize) -> f32 {
+        let fade_in = if frame < self.fade_in {
+            (frame + 1) as f32 / (self.fade_in + 1) as f32
+        } else {
+            1.0
+        };
+        let fade_out = match self.cut {
+            Some(cut) if frame >= cut => 1.0 - (frame - cut + 1) as f32 / (self.length + 1) as f32,
+            _ => 1.0,
+        };
+        fade_in * fade_out.max(0.0)
+    }
+}
+
+// The fades of the events of one sample, in the order of `list`. Events that do not overlap the one before or after
+// them play as they are. Identical audio is faded linearly, which keeps its level through the crossfade.
+fn retrigger_fades(list: &[Timing], length_of: impl Fn(&Timing) -> usize, length: i64) -> Vec<Option<Fade>> {
+    let length = length.max(0) as usize;
+    let mut order: Vec<usize> = (0..list.len()).collect();
+    order.sort_by_key(|i| list[*i].frame);
+
+    let mut fades = vec![None; list.len()];
+    for (position, i) in order.iter().enumerate() {
+        let frame = list[*i].frame;
+        let overlaps = |earlier: &Timing, later: i64| later - earlier.frame < length_of(earlier) as i64;
+        let fade_in = match position.checked_sub(1).map(|before| &list[order[before]]) {
+            Some(before) if overlaps(before, frame) => length,
+            _ => 0,
+        };
+        let cut = match order.get(position + 1).map(|after| list[*after].frame) {
+            Some(after) if overlaps(&list[*i], after) => Some((after - frame) as usize),
+            _ => None,
+        };
+        if fade_in > 0 || cut.is_some() {
+            fades[*i] = Some(Fade { fade_in, cut, length });
+        }
+    }
+    fades
+}
+
+fn mix_faded<T: Copy + AddAssign + From<f32>>(
+    data: &mut [T],
+    sample: &SampleData,
+    playback: Playback,
+    start: usize,
+    range: Range<usize>,
+    gains: Gains,
+    fade: Fade,
+) {
+    let Some(frames) = data.get_mut(start * 2..) else {
+        return;
+    };
+    for (frame, i) in frames.chunks_exact_mut(2).zip(range) {
+        let (left, right) = gains.apply(sample.frame(playback.frame(i)));
+        let gain = fade.gain(i);
+        frame[0] += T::from(left * gain);
+        frame[1] += T::from(right * gain);
+    }
+}
+
+// When and how loud an event plays its sample, in frames of the mix.
+#[derive(Clone, Copy, Debug)]
+struct Timing {
+    frame: i64,
+    volume: f32,
+    pan: f32,
+    pan_mode: PanMode,
+    // Frames the sample is looped or cut to, None plays it once.
+    hold: Option<usize>,
+    // Pitch in cents, so events at the same pitch share their resampled sample.
+    cents: i32,
+}
+
+impl Timing {
+    // How the event plays a sample that is `frames` long and may have a loop to sustain in.
+    fn playback(&self, frames: usize, sustain: Option<Range<usize>>) -> Playback {
+        match (self.hold, sustain) {
+            (Some(hold), Some(sustain)) if frames > 0 => Playback::Sustain {
+                frames,
+                start: sustain.start,
+                end: sustain.end,
+                loops: hold.saturating_sub(sustain.end).div_ceil(sustain.len()),
+            },
+            (Some(hold), None) if frames > 0 => Playback::Repeat { frames, length: hold },
+            _ => Playback::Repeat { frames, length: frames },
+        }
+    }
+}
+
+// Which frame of its sample an event plays at each of its own frames.
+#[derive(Clone, Copy, Debug)]
+enum Playback {
+    // The sample once, or started over and cut for an event that holds it for `length` frames.
+    Repeat {
+        frames: usize,
+        length: usize,
+    },
+    // On into the loop from `start` to `end`, round it `loops` more times until the hold is over and then on to the end
+    // of the sample, its release. Released before the loop, the sample plays once.
+    Sustain {
+        frames: usize,
+        start: usize,
+        end: usize,
+        loops: usize,
+    },
+}
+
+impl Playback {
+    fn length(&self) -> usize {
+        match *self {
+            Playback::Repeat { length, .. } => length,
+            Playback::Sustain {
+                frames,
+                start,
+                end,
+                loops,
+            } => frames + loops * (end - start),
+        }
+    }
+
+    fn frame(&self, i: usize) -> usize {
+        match *self {
+            Playback::Repeat { frames, .. } => {
+                if i < frames {
+                    i
+                } else {
+                    i % frames
+                }
+            }
+            Playback::Sustain { start, end, loops, .. } => {
+                let looped = loops * (end - start);
+                if i < end {
+                    i
+                } else if i < end + looped {
+                    start + (i - end) % (end - start)
+                } else {
+                    i - looped
+                }
+            }
+        }
+    }
+}
+
+// The loop of a sample in frames of the mix, None if it is empty or not within the sample.
+fn sustain(sample_loop: &SampleLoop, sample_rate: f64, frames: usize) -> Option<Range<usize>> {
+    let scale = |frame: u64| (frame as f64 * sample_rate / sample_loop.sample_rate as f64).round() as usize;
+    let sustain = scale(sample_loop.start)..scale(sample_loop.end).min(frames);
+    (!sustain.is_empty()).then_some(sustain)
+}
+
+fn add_timing(info: &AudioSampleInfo, settings: &RenderSettings, timing_map: &mut BTreeMap<String, Vec<Timing>>) {
+    let timing = Timing {
+        frame: to_frame(info.time, settings.sample_rate),
+        volume: info.volume,
+        pan: info.pan,
+        pan_mode: info.pan_mode.unwrap_or(settings.pan_mode),
+        hold: info
+            .hold
+            .map(|hold| to_frame(hold, settings.sample_rate).max(0) as usize),
+        cents: cents(info),
+    };
+
+    if let Some(list) = timing_map.get_mut(&info.name) {
+        list.push(timing);
+    } else {
+        timing_map.insert(info.name.clone(), vec![timing]);
+    }
+}
+
+fn calculate_max_length(
+    timing_map: &BTreeMap<String, Vec<Timing>>,
+    length_of: impl Fn(&String, &Timing) -> Option<usize>,
+) -> i64 {
+    let mut max_length = 0_i64;
+
+    for (name, list) in timing_map {
+        for timing in list {
+            if let Some(length) = length_of(name, timing) {
+                max_length = max_length.max(timing.frame + length as i64);
+            }
+        }
+    }
+
+    max_length
+}
+
+// Pitch of an event in cents, including the semitones of --rate.
+fn cents(info: &AudioSampleInfo) -> i32 {
+    (info.pitch.unwrap_or(0.0) * 100.0).round() as i32
+}
+
+// How much faster a sample plays at a pitch.
+fn pitch_ratio(cents: i32) -> f64 {
+    2f64.powf(cents as f64 / 1200.0)
+}
+
+// Pitching plays the sample at another speed: it is resampled as if it had a higher or lower sample rate, which also
+// makes it shorter or longer.
+fn pitch_shift(sample: &SampleData, cents: i32, settings: &RenderSettings) -> Result<SampleData, Box<dyn Error>> {
+    let data: Vec<f32> = (0..sample.len()).map(|index| sample.get(index)).collect();
+    if data.is_empty() {
+        return Ok(SampleData::new(data, settings.compact));
+    }
+    let rate = (settings.sample_rate as f64 * pitch_ratio(cents)).round() as u32;
+    let data = convert(rate.max(1), settings.sample_rate, 2, settings.resampler, &data).with_kind(ErrorKind::Decode)?;
+    Ok(SampleData::new(data, settings.compact))
+}
+
+// Rounds to the nearest frame, so times given in samples land exactly on that sample.
+pub fn to_frame(ms: f64, sample_rate: u32) -> i64 {
+    (ms / 1000.0 * sample_rate as f64).round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli;
+    use crate::commands;
+    use crate::config::FileConfig;
+
+    fn settings(args: &[&str]) -> RenderSettings {
+        let matches = commands::mix::command()
+            .try_get_matches_from(["mix", "-i", "test.csv", "-o", "test.ogg"].iter().chain(args))
+            .unwrap();
+        cli::render_settings(&matches, &FileConfig::default()).unwrap()
+    }
+
+    fn event(time: f64, name: &str, pitch: Option<f32>) -> AudioSampleInfo {
+        AudioSampleInfo {
+            time,
+            volume: 1.0,
+            pan: 0.0,
+            name: name.to_string(),
+            line: 1,
+            lane: None,
+            hold: None,
+            pitch,
+            pan_mode: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn pitched_durations() {
+        let settings = settings(&["--max-duration", "1000"]);
+        assert!(check_duration(&[event(500.0, "sine:440:400ms", None)], 0.0, &settings).is_ok());
+        // An octave down plays the same sample for 800 ms.
+        assert!(check_duration(&[event(500.0, "sine:440:400ms", Some(-12.0))], 0.0, &settings).is_err());
+        assert!(check_duration(&[event(500.0, "sine:440:400ms", Some(12.0))], 0.0, &settings).is_ok());
+        assert!(check_duration(&[event(1500.0, "sine:440:400ms", None)], 1000.0, &settings).is_ok());
+    }
+
+    #[test]
+    fn events_before_from() {
+        let settings = settings(&["--from", "700"]);
+        let infos = vec![
+            event(0.0, "sine:440:500ms", None),
+            event(0.0, "sine:440:500ms", Some(-12.0)),
+            event(0.0, "sine:440:500ms", Some(rate_semitones(0.5))),
+            event(300.0, "sine:440:500ms", None),
+        ];
+        let (kept, skipped) = events_in_range(infos, 700.0, &settings);
+        assert_eq!(
+            kept.iter().map(|info| info.pitch).collect::<Vec<_>>(),
+            [Some(-12.0), Some(-12.0), None]
+        );
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].reason, "ends before --from");
+    }
+
+    #[test]
+    fn clicks_count_as_clipping() {
+        let mut settings = settings(&["--on-clip", "error"]);
+        let loud = || {
+            vec![AudioSampleInfo {
+                volume: 0.9,
+                ..event(0.0, "sine:440:1s", None)
+            }]
+        };
+        assert!(render(loud(), &settings, &mut SampleCache::default()).is_ok());
+        settings.click = Some(Click {
+            tempo_map: crate::metronome::read_tempo_map("120").unwrap(),
+            volume: 1.0,
+            stem: None,
+        });
+        assert!(render(loud(), &settings, &mut SampleCache::default()).is_err());
+        settings.on_clip = ClipPolicy::Clamp;
+        let (_, stats) = render(loud(), &settings, &mut SampleCache::default()).unwrap();
+        assert!(stats.peak > 1.0 && stats.clipped > 0);
+    }
+
+    #[test]
+    fn duplicates() {
+        let infos = vec![
+            event(0.0, "a.wav", None),
+            event(0.0, "a.wav", None),
+            event(0.0, "a.wav", Some(7.0)),
+            AudioSampleInfo {
+                hold: Some(500.0),
+                ..event(0.0, "a.wav", None)
+            },
+            // On the same frame.
+            event(0.01, "a.wav", None),
+            event(1.0, "a.wav", None),
+            event(0.0, "b.wav", None),
+        ];
+        let mut skipped = Vec::new();
+        let kept = drop_duplicates(infos, 48000, &mut skipped);
+        assert_eq!(kept.len(), 5);
+        assert_eq!(skipped.len(), 2);
+        assert!(skipped.iter().all(|event| event.reason == "duplicate event"));
+    }
+
+    #[test]
+    fn humanized_volumes() {
+        // Twice as loud, like with a lane gain of +6 dB.
+        let loud = || {
+            vec![
+                AudioSampleInfo {
+                    volume: 2.0,
+                    ..event(100.0, "a.wav", None)
+                };
+                20
+            ]
+        };
+        let mut infos = loud();
+        humanize(&mut infos, &settings(&["--humanize", "1", "--seed", "1"]));
+        assert!(infos
+            .iter()
+            .all(|info| info.volume == 2.0 && (99.0..=101.0).contains(&info.time)));
+        assert!(infos.iter().any(|info| info.time != 100.0));
+
+        let mut infos = loud();
+        humanize(&mut infos, &settings(&["--humanize-volume", "0.5", "--seed", "1"]));
+        assert!(infos
+            .iter()
+            .all(|info| (1.0..=3.0).contains(&info.volume) && info.time == 100.0));
+        assert!(infos.iter().any(|info| info.volume > 2.0));
+    }
+
+    #[test]
+    fn concatenations() {
+        let names = ["sine:440:200ms".to_string(), "sine:440:200ms".to_string()];
+        // A sine at 0 LUFS peaks over full scale. The files are not clamped on their own, so normalizing turns the
+        // whole concatenation down.
+        let normalized = settings(&["--normalize-samples", "0LUFS", "--on-clip", "normalize"]);
+        let (data, stats) = concat(&names, 50.0, &normalized, &mut SampleCache::default(), &mut Vec::new()).unwrap();
+        assert!(stats.gain < 0.9);
+        assert!(data.iter().all(|value| value.abs() <= 1.0));
+
+        let shifted = settings(&["--offset", "100"]);
+        let mut events = Vec::new();
+        let (data, stats) = concat(&names, 0.0, &shifted, &mut SampleCache::default(), &mut events).unwrap();
+        assert_eq!(data.len(), 2 * 500 * shifted.sample_rate as usize / 1000);
+        assert_eq!(
+            events.iter().map(|event| event.time).collect::<Vec<_>>(),
+            [100.0, 300.0]
+        );
+        assert_eq!(stats.events[0].frame, to_frame(100.0, shifted.sample_rate));
+
+        // Moving it earlier cuts off its start.
+        let shifted = settings(&["--offset", "-250"]);
+        let (data, _) = concat(&names, 0.0, &shifted, &mut SampleCache::default(), &mut Vec::new()).unwrap();
+        assert_eq!(data.len(), 2 * 150 * shifted.sample_rate as usize / 1000);
+    }
+
+    #[test]
+    fn events_after_to() {
+        let settings = settings(&["--to", "500"]);
+        let infos = vec![
+            event(0.0, "a.wav", None),
+            event(499.0, "a.wav", None),
+            event(500.0, "a.wav", None),
+            event(800.0, "b.wav", None),
+        ];
+        let (kept, skipped) = events_in_range(infos, 0.0, &settings);
+        assert_eq!(kept.iter().map(|info| info.time).collect::<Vec<_>>(), [0.0, 499.0]);
+        assert_eq!(skipped.len(), 2);
+        assert!(skipped.iter().all(|event| event.reason == "starts after --to"));
+    }
+
+    #[test]
+    fn retriggers() {
+        let timing = |frame| Timing {
+            frame,
+            volume: 1.0,
+            pan: 0.0,
+            pan_mode: PanMode::Balance,
+            hold: None,
+            cents: 0,
+        };
+        // Out of order, the fades follow the frames.
+        let list = [timing(210), timing(0), timing(50), timing(200)];
+        let fades = retrigger_fades(&list, |_| 100, 10);
+        let fades: Vec<_> = fades
+            .iter()
+            .map(|fade| fade.map(|fade| (fade.fade_in, fade.cut)))
+            .collect();
+        assert_eq!(
+            fades,
+            [
+                Some((10, None)),
+                Some((0, Some(50))),
+                Some((10, None)),
+                Some((0, Some(10)))
+            ]
+        );
+
+        let fade = Fade {
+            fade_in: 10,
+            cut: Some(50),
+            length: 10,
+        };
+        assert_eq!(fade.gain(0), 1.0 / 11.0);
+        assert_eq!(fade.gain(20), 1.0);
+        assert!(fade.gain(55) < 1.0);
+        assert!((fade.gain(59) - 1.0 / 11.0).abs() < 1e-6);
+        assert_eq!(fade.end(), 60);
+
+        // Cut off at once, the second trigger of a sine in phase with the first is not summed up with it.
+        let infos = || {
+            vec![
+                AudioSampleInfo {
+                    volume: 0.4,
+                    ..event(0.0, "sine:440:200ms", None)
+                },
+                AudioSampleInfo {
+                    volume: 0.4,
+                    ..event(100.0, "sine:440:200ms", None)
+                },
+            ]
+        };
+        let (_, summed) = render(infos(), &settings(&[]), &mut SampleCache::default()).unwrap();
+        let (_, cut) = render(
+            infos(),
+            &settings(&["--retrigger-fade", "0"]),
+            &mut SampleCache::default(),
+        )
+        .unwrap();
+        assert!(cut.peak < summed.peak * 0.6);
+    }
+
+    #[test]
+    fn holds() {
+        let held = |hold| Timing {
+            frame: 0,
+            volume: 1.0,
+            pan: 0.0,
+            pan_mode: PanMode::Balance,
+            hold,
+            cents: 0,
+        };
+        // Without a loop the sample starts over until the hold is over.
+        let playback = held(Some(250)).playback(100, None);
+        assert_eq!(playback.length(), 250);
+        assert_eq!(playback.frame(150), 50);
+        assert_eq!(held(Some(30)).playback(100, None).length(), 30);
+        assert_eq!(held(None).playback(100, None).length(), 100);
+
+        // With one it goes round the loop and then plays the release.
+        let playback = held(Some(250)).playback(100, Some(20..60));
+        assert_eq!(playback.length(), 100 + 5 * 40);
+        assert_eq!(playback.frame(59), 59);
+        assert_eq!(playback.frame(60), 20);
+        assert_eq!(playback.frame(260), 60);
+        // Released before the end of the loop, it plays once.
+        assert_eq!(held(Some(30)).playback(100, Some(20..60)).length(), 100);
+
+        let infos = vec![AudioSampleInfo {
+            hold: Some(300.0),
+            ..event(0.0, "sine:440:100ms", None)
+        }];
+        let settings = settings(&[]);
+        let (data, _) = render(infos, &settings, &mut SampleCache::default()).unwrap();
+        let frame = to_frame(250.0, settings.sample_rate) as usize;
+        assert!(data.len() / 2 >= to_frame(300.0, settings.sample_rate) as usize);
+        assert!(data[frame * 2..frame * 2 + 200].iter().any(|value| value.abs() > 0.1));
+    }
+
+    #[test]
+    fn clip_policies() {
+        let loud = || {
+            vec![AudioSampleInfo {
+                volume: 3.0,
+                ..event(0.0, "sine:440:100ms", None)
+            }]
+        };
+        let mix = |policy: &str| render(loud(), &settings(&["--on-clip", policy]), &mut SampleCache::default());
+
+        let (clamped, stats) = mix("clamp").unwrap();
+        assert!(stats.clipped > 0 && !stats.clipping.is_empty());
+        assert!(clamped.iter().all(|value| value.abs() <= 1.0));
+        assert!(clamped.iter().any(|value| value.abs() == 1.0));
+
+        // Rounded off below full scale, where clamping leaves it alone.
+        let (data, _) = mix("soft").unwrap();
+        assert!(data.iter().all(|value| value.abs() <= 1.0));
+        assert!(data
+            .iter()
+            .zip(&clamped)
+            .any(|(soft, clamped)| soft.abs() < clamped.abs() && clamped.abs() < 1.0));
+
+        let (data, stats) = mix("normalize").unwrap();
+        assert!(stats.gain < 0.5 && stats.clipped == 0);
+        assert!(data.iter().all(|value| value.abs() <= 1.0));
+
+        let Err(err) = mix("error") else {
+            panic!("a mix that clips was not refused");
+        };
+        assert_eq!(crate::error::kind_of(err.as_ref()), Some(ErrorKind::Validation));
+        assert!(err.to_string().contains("go over full scale"));
+    }
+}
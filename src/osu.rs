@@ -0,0 +1,588 @@
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+use zip::ZipArchive;
+
+use crate::events::{AudioSampleInfo, CsvOptions};
+
+// Extensions of the files extracted from an .osz archive, the beatmap can only play these.
+const AUDIO_EXTENSIONS: [&str; 4] = ["wav", "ogg", "mp3", "flac"];
+// Hitsounds may be saved in any of these formats, with the same name.
+const HITSOUND_EXTENSIONS: [&str; 3] = ["wav", "ogg", "mp3"];
+
+struct TimingPoint {
+    time: f64,
+    beat_length: f64,
+    sample_set: u32,
+    sample_index: u32,
+    volume: f32,
+    uninherited: bool,
+}
+
+// The sample set, index, volume and file name of a hit object, 0 and empty take them from the timing point.
+#[derive(Default)]
+struct HitSample {
+    normal_set: u32,
+    addition_set: u32,
+    index: u32,
+    volume: f32,
+    file: String,
+}
+
+struct Beatmap<'a> {
+    base: &'a Path,
+    default_set: u32,
+    slider_multiplier: f64,
+    timing_points: Vec<TimingPoint>,
+    events: Vec<AudioSampleInfo>,
+}
+
+// Reads the hitsounds, storyboard samples and music of an osu! beatmap, either an .osu file next to its samples or
+// an .osz archive. Archives with several difficulties need --difficulty to choose one.
+pub fn read_beatmap(path: &Path, options: &CsvOptions) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    let is_archive = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("osz"));
+    if !is_archive {
+        let text = fs::read_to_string(path)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let storyboard = find_storyboard(base);
+        return parse_beatmap(&text, storyboard.as_deref(), base).map_err(|err| err.into());
+    }
+
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut difficulties = Vec::new();
+    let mut storyboard = None;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let name = entry.name().to_lowercase();
+        if name.ends_with(".osu") {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            difficulties.push((version(&text).unwrap_or_else(|| entry.name().to_string()), text));
+        } else if name.ends_with(".osb") {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            storyboard = Some(text);
+        }
+    }
+
+    let text = choose_difficulty(difficulties, options.difficulty.as_deref())?;
+    let base = extract_samples(path, &mut archive)?;
+    parse_beatmap(&text, storyboard.as_deref(), &base).map_err(|err| err.into())
+}
+
+fn choose_difficulty(difficulties: Vec<(String, String)>, wanted: Option<&str>) -> Result<String, String> {
+    let names: Vec<String> = difficulties.iter().map(|(name, _)| name.clone()).collect();
+    match (wanted, difficulties.len()) {
+        (_, 0) => Err("the archive has no .osu files".to_string()),
+        (None, 1) => Ok(difficulties.into_iter().next().unwrap().1),
+        (None, _) => Err(format!(
+            "the archive has {} difficulties, choose one with --difficulty: {}",
+            names.len(),
+            names.join(", ")
+        )),
+        (Some(wanted), _) => difficulties
+            .into_iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .map(|(_, text)| text)
+            .ok_or_else(|| {
+                format!(
+                    "there is no difficulty '{}', the archive has {}",
+                    wanted,
+                    names.join(", ")
+                )
+            }),
+    }
+}
+
+// The samples of an archive are extracted to the temporary directory, so they can be decoded like any other file.
+pub fn extract_samples<R: Read + std::io::Seek>(
+    path: &Path,
+    archive: &mut ZipArchive<R>,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let dir = env::temp_dir().join(format!("audio-sample-mixer-{}", stem));
+
+    let mut extracted = 0;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        // Names that would leave the directory are not extracted.
+        let Some(name) = entry.enclosed_name().map(|name| name.to_path_buf()) else {
+            continue;
+        };
+        let is_audio = name
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+        if entry.is_dir() || !is_audio {
+            continue;
+        }
+
+        let target = dir.join(&name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        fs::write(&target, data)?;
+        extracted += 1;
+    }
+
+    info!(
+        "extracted {} samples of {} to {}",
+        extracted,
+        path.display(),
+        dir.display()
+    );
+    Ok(dir)
+}
+
+// osu! only reads the storyboard file of the directory, its name depends on the song.
+fn find_storyboard(dir: &Path) -> Option<String> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("osb"))
+        })
+        .collect();
+    files.sort();
+    fs::read_to_string(files.first()?).ok()
+}
+
+fn version(text: &str) -> Option<String> {
+    text.lines()
+        .filter_map(|line| line.trim().strip_prefix("Version:"))
+        .map(|version| version.trim().to_string())
+        .next()
+}
+
+fn parse_beatmap(text: &str, storyboard: Option<&str>, base: &Path) -> Result<Vec<AudioSampleInfo>, String> {
+    let mut beatmap = Beatmap {
+        base,
+        default_set: 1,
+        slider_multiplier: 1.4,
+        timing_points: Vec::new(),
+        events: Vec::new(),
+    };
+
+    // The timing points have to be known before the hit objects, which come after them in the file.
+    let mut section = "";
+    let mut music = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            section = line;
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            if section == "[TimingPoints]" && !line.is_empty() {
+                beatmap.timing_points.extend(parse_timing_point(line));
+            }
+            continue;
+        };
+        match (section, key.trim()) {
+            ("[General]", "AudioFilename") => music = Some(value.trim().to_string()),
+            ("[General]", "SampleSet") => beatmap.default_set = sample_set(value.trim()).unwrap_or(1),
+            ("[Difficulty]", "SliderMultiplier") => beatmap.slider_multiplier = value.trim().parse().unwrap_or(1.4),
+            _ => {}
+        }
+    }
+    beatmap.timing_points.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    if let Some(music) = music.filter(|music| !music.is_empty() && music != "virtual") {
+        let name = beatmap.sample_name(&music);
+        beatmap.events.push(AudioSampleInfo {
+            time: 0.0,
+            volume: 1.0,
+            pan: 0.0,
+            name,
+            line: 0,
+            lane: None,
+            hold: None,
+            pitch: None,
+            pan_mode: None,
+            tags: Vec::new(),
+        });
+    }
+
+    let mut section = "";
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            section = line;
+        } else if section == "[HitObjects]" && !line.is_empty() {
+            beatmap
+                .hit_object(line, index as u64 + 1)
+                .map_err(|err| format!("line {}: {}", index + 1, err))?;
+        } else if section == "[Events]" {
+            beatmap.storyboard_sample(line, index as u64 + 1);
+        }
+    }
+
+    if let Some(storyboard) = storyboard {
+        for (index, line) in storyboard.lines().enumerate() {
+            beatmap.storyboard_sample(line.trim(), index as u64 + 1);
+        }
+    }
+
+    debug!("read {} events from the beatmap", beatmap.events.len());
+    Ok(beatmap.events)
+}
+
+fn parse_timing_point(line: &str) -> Option<TimingPoint> {
+    let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+    let time = fields.first()?.parse().ok()?;
+    let beat_length: f64 = fields.get(1)?.parse().ok()?;
+    let number = |index: usize, default: u32| {
+        fields
+            .get(index)
+            .and_then(|field| field.parse().ok())
+            .unwrap_or(default)
+    };
+    Some(TimingPoint {
+        time,
+        beat_length,
+        sample_set: number(3, 0),
+        sample_index: number(4, 1),
+        volume: number(5, 100) as f32,
+        uninherited: fields.get(6).map_or(beat_length > 0.0, |field| *field != "0"),
+    })
+}
+
+fn sample_set(name: &str) -> Option<u32> {
+    match name.to_lowercase().as_str() {
+        "normal" => Some(1),
+        "soft" => Some(2),
+        "drum" => Some(3),
+        _ => None,
+    }
+}
+
+fn parse_hit_sample(field: Option<&str>) -> HitSample {
+    let Some(field) = field.filter(|field| !field.is_empty()) else {
+        return HitSample::default();
+    };
+    let parts: Vec<&str> = field.split(':').collect();
+    let number = |index: usize| parts.get(index).and_then(|part| part.trim().parse().ok()).unwrap_or(0);
+    HitSample {
+        normal_set: number(0),
+        addition_set: number(1),
+        index: number(2),
+        volume: number(3) as f32,
+        file: parts.get(4).map(|file| file.trim().to_string()).unwrap_or_default(),
+    }
+}
+
+impl Beatmap<'_> {
+    // The timing point that is active at `time`, and the one that sets the beat length.
+    fn timing_at(&self, time: f64) -> (Option<&TimingPoint>, Option<&TimingPoint>) {
+        let active = self.timing_points.iter().rev().find(|point| point.time <= time);
+        let uninherited = self
+            .timing_points
+            .iter()
+            .rev()
+            .find(|point| point.uninherited && point.time <= time)
+            .or_else(|| self.timing_points.iter().find(|point| point.uninherited));
+        (active.or(self.timing_points.first()), uninherited)
+    }
+
+    fn hit_object(&mut self, line: &str, line_number: u64) -> Result<(), String> {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() < 5 {
+            return Err(format!(
+                "expected at least 5 fields in a hit object, found {}",
+                fields.len()
+            ));
+        }
+        let time: f64 = fields[2]
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{}' is not a time", fields[2]))?;
+        let kind: u32 = fields[3]
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{}' is not a type", fields[3]))?;
+        let sounds: u32 = fields[4].trim().parse().unwrap_or(0);
+
+        if kind & 2 != 0 {
+            // Sliders play a hitsound on every edge: the head, each repeat and the tail.
+            let slides: u32 = fields.get(6).and_then(|field| field.trim().parse().ok()).unwrap_or(1);
+            let length: f64 = fields.get(7).and_then(|field| field.trim().parse().ok()).unwrap_or(0.0);
+            let edge_sounds: Vec<&str> = fields
+                .get(8)
+                .map(|field| field.split('|').collect())
+                .unwrap_or_default();
+            let edge_sets: Vec<&str> = fields
+                .get(9)
+                .map(|field| field.split('|').collect())
+                .unwrap_or_default();
+            let slide_duration = self.slide_duration(time, length);
+
+            for edge in 0..=slides.max(1) as usize {
+                let edge_time = time + edge as f64 * slide_duration;
+                let sounds = edge_sounds
+                    .get(edge)
+                    .and_then(|sounds| sounds.trim().parse().ok())
+                    .unwrap_or(sounds);
+                let mut hit_sample = parse_hit_sample(edge_sets.get(edge).copied());
+                hit_sample.file.clear();
+                self.hitsounds(edge_time, sounds, &hit_sample, line_number);
+            }
+            return Ok(());
+        }
+
+        // Spinners sound when they end, mania hold notes when they start.
+        let (time, hit_sample) = if kind & 8 != 0 {
+            let end = fields
+                .get(5)
+                .and_then(|field| field.trim().parse().ok())
+                .unwrap_or(time);
+            (end, parse_hit_sample(fields.get(6).copied()))
+        } else if kind & 128 != 0 {
+            let hit_sample = fields
+                .get(5)
+                .and_then(|field| field.split_once(':'))
+                .map(|(_, hit_sample)| hit_sample);
+            (time, parse_hit_sample(hit_sample))
+        } else {
+            (time, parse_hit_sample(fields.get(5).copied()))
+        };
+        self.hitsounds(time, sounds, &hit_sample, line_number);
+        Ok(())
+    }
+
+    fn slide_duration(&self, time: f64, length: f64) -> f64 {
+        let (active, uninherited) = self.timing_at(time);
+        let beat_length = uninherited.map_or(500.0, |point| point.beat_length);
+        let velocity = match active {
+            Some(point) if !point.uninherited && point.beat_length < 0.0 => {
+                (-100.0 / point.beat_length).clamp(0.1, 10.0)
+            }
+            _ => 1.0,
+        };
+        length / (self.slider_multiplier * 100.0 * velocity) * beat_length
+    }
+
+    // The normal sound always plays, whistle, finish and clap are additions on top of it.
+    fn hitsounds(&mut self, time: f64, sounds: u32, hit_sample: &HitSample, line: u64) {
+        let (active, _) = self.timing_at(time);
+        let point_set = active.map_or(0, |point| point.sample_set);
+        let normal_set = match (hit_sample.normal_set, point_set) {
+            (0, 0) => self.default_set,
+            (0, set) => set,
+            (set, _) => set,
+        };
+        let addition_set = if hit_sample.addition_set == 0 {
+            normal_set
+        } else {
+            hit_sample.addition_set
+        };
+        let index = match hit_sample.index {
+            0 => active.map_or(1, |point| point.sample_index),
+            index => index,
+        };
+        let volume = match hit_sample.volume {
+            volume if volume > 0.0 => volume,
+            _ => active.map_or(100.0, |point| point.volume),
+        };
+        let volume = (volume / 100.0).clamp(0.0, 1.0);
+
+        if !hit_sample.file.is_empty() {
+            let name = self.sample_name(&hit_sample.file);
+            self.push(time, volume, name, line);
+            return;
+        }
+
+        let mut names = vec![(normal_set, "normal")];
+        for (bit, sound) in [(2, "whistle"), (4, "finish"), (8, "clap")] {
+            if sounds & bit != 0 {
+                names.push((addition_set, sound));
+            }
+        }
+        for (set, sound) in names {
+            let set = match set {
+                2 => "soft",
+                3 => "drum",
+                _ => "normal",
+            };
+            // Index 1 has no number in the file name.
+            let suffix = if index > 1 { index.to_string() } else { String::new() };
+            let name = self.hitsound_name(&format!("{}-hit{}{}", set, sound, suffix));
+            self.push(time, volume, name, line);
+        }
+    }
+
+    // Sample,time,layer,"file",volume
+    fn storyboard_sample(&mut self, line: &str, line_number: u64) {
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+        if fields.len() < 4 || !(fields[0] == "Sample" || fields[0] == "5") {
+            return;
+        }
+        let Ok(time) = fields[1].parse::<f64>() else {
+            return;
+        };
+        let volume = fields
+            .get(4)
+            .and_then(|field| field.parse::<f32>().ok())
+            .unwrap_or(100.0);
+        let name = self.sample_name(fields[3].trim_matches('"'));
+        self.push(time, (volume / 100.0).clamp(0.0, 1.0), name, line_number);
+    }
+
+    fn push(&mut self, time: f64, volume: f32, name: String, line: u64) {
+        self.events.push(AudioSampleInfo {
+            time,
+            volume,
+            pan: 0.0,
+            name,
+            line,
+            lane: None,
+            hold: None,
+            pitch: None,
+            pan_mode: None,
+            tags: Vec::new(),
+        });
+    }
+
+    // A file of the beatmap is looked up next to it. Files that are not there keep their plain name, so samples
+    // directories can provide them, like the hitsounds of a skin.
+    fn sample_name(&self, file: &str) -> String {
+        let file = file.replace('\\', "/");
+        let path = self.base.join(&file);
+        if path.is_file() {
+            path.to_string_lossy().into_owned()
+        } else {
+            file
+        }
+    }
+
+    fn hitsound_name(&self, stem: &str) -> String {
+        for extension in HITSOUND_EXTENSIONS {
+            let path = self.base.join(format!("{}.{}", stem, extension));
+            if path.is_file() {
+                return path.to_string_lossy().into_owned();
+            }
+        }
+        format!("{}.wav", stem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The time, volume and sample of every event, with the files of the beatmap in a directory that does not exist.
+    fn events(text: &str, storyboard: Option<&str>) -> Vec<(f64, f32, String)> {
+        parse_beatmap(text, storyboard, Path::new("/nonexistent"))
+            .unwrap()
+            .into_iter()
+            .map(|info| (info.time, info.volume, info.name))
+            .collect()
+    }
+
+    fn expected(events: &[(f64, f32, &str)]) -> Vec<(f64, f32, String)> {
+        events
+            .iter()
+            .map(|(time, volume, name)| (*time, *volume, name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn timing_points() {
+        let point = parse_timing_point("1000,500,4,2,3,60,1,0").unwrap();
+        assert_eq!(
+            (point.time, point.beat_length, point.sample_set, point.sample_index),
+            (1000.0, 500.0, 2, 3)
+        );
+        assert_eq!(point.volume, 60.0);
+        assert!(point.uninherited);
+        // Old beatmaps leave out whether a point is inherited, a negative beat length is a slider velocity.
+        assert!(parse_timing_point("0,500").unwrap().uninherited);
+        assert!(!parse_timing_point("0,-50").unwrap().uninherited);
+        assert!(parse_timing_point("0").is_none());
+        assert!(parse_timing_point("x,500").is_none());
+    }
+
+    #[test]
+    fn hit_samples() {
+        let sample = parse_hit_sample(Some("3:2:4:70:kick.wav"));
+        assert_eq!((sample.normal_set, sample.addition_set, sample.index), (3, 2, 4));
+        assert_eq!((sample.volume, sample.file.as_str()), (70.0, "kick.wav"));
+        let sample = parse_hit_sample(Some(""));
+        assert_eq!((sample.normal_set, sample.volume, sample.file.as_str()), (0, 0.0, ""));
+        let sample = parse_hit_sample(Some("1:x"));
+        assert_eq!((sample.normal_set, sample.addition_set, sample.index), (1, 0, 0));
+    }
+
+    #[test]
+    fn difficulties() {
+        let maps = || {
+            vec![
+                ("Easy".to_string(), "e".to_string()),
+                ("Hard".to_string(), "h".to_string()),
+            ]
+        };
+        assert!(choose_difficulty(Vec::new(), None).is_err());
+        assert_eq!(choose_difficulty(maps()[..1].to_vec(), None).unwrap(), "e");
+        assert!(choose_difficulty(maps(), None).unwrap_err().contains("Easy, Hard"));
+        assert_eq!(choose_difficulty(maps(), Some("hard")).unwrap(), "h");
+        assert!(choose_difficulty(maps(), Some("Insane")).is_err());
+    }
+
+    #[test]
+    fn hit_objects() {
+        let text = "[General]\n\
+            AudioFilename: song.mp3\n\
+            SampleSet: Soft\n\
+            [Difficulty]\n\
+            SliderMultiplier:1.4\n\
+            [TimingPoints]\n\
+            0,500,4,0,1,80,1,0\n\
+            [HitObjects]\n\
+            256,192,100,1,2,0:0:0:0:\n\
+            256,192,1000,2,0,L|300:192,2,140\n\
+            256,192,3000,12,0,3500,3:0:2:50:\n\
+            64,192,4000,128,0,4200:0:0:0:0:hold.wav\n";
+        let expected = expected(&[
+            (0.0, 1.0, "song.mp3"),
+            (100.0, 0.8, "soft-hitnormal.wav"),
+            (100.0, 0.8, "soft-hitwhistle.wav"),
+            // A slider plays on its head, its repeat and its tail, 500 ms apart at this length.
+            (1000.0, 0.8, "soft-hitnormal.wav"),
+            (1500.0, 0.8, "soft-hitnormal.wav"),
+            (2000.0, 0.8, "soft-hitnormal.wav"),
+            // Spinners sound when they end.
+            (3500.0, 0.5, "drum-hitnormal2.wav"),
+            (4000.0, 0.8, "hold.wav"),
+        ]);
+        assert_eq!(events(text, None), expected);
+    }
+
+    #[test]
+    fn broken_hit_objects() {
+        let err = parse_beatmap("[HitObjects]\n256,192,100\n", None, Path::new("")).unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        assert!(parse_beatmap("[HitObjects]\n256,192,x,1,0\n", None, Path::new("")).is_err());
+    }
+
+    #[test]
+    fn storyboard_samples() {
+        let storyboard = "[Events]\nSample,250,0,\"sb\\clap.wav\",40\n5,500,0,\"boom.wav\"\nSprite,0,0,\"bg.png\"\n";
+        assert_eq!(
+            events("", Some(storyboard)),
+            expected(&[(250.0, 0.4, "sb/clap.wav"), (500.0, 1.0, "boom.wav")])
+        );
+    }
+}
@@ -0,0 +1,1090 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::os::raw::{c_int, c_long};
+
+// A Vorbis encoder without libvorbis, for builds without the libvorbis feature. It switches to short blocks only
+// for sudden attacks and has one floor for every block size and one residue setup, so its files are larger than
+// those of libvorbis at the same quality, but they play in every Vorbis decoder.
+
+// Samples per block, half of every block overlaps the next one. Long blocks follow each other HALF samples apart, a
+// long block with an attack in it is replaced by eight short ones.
+const SHORT: usize = 256;
+const LONG: usize = 2048;
+const HALF: usize = LONG / 2;
+const SHORT_EXPONENT: u32 = 8;
+const LONG_EXPONENT: u32 = 11;
+// Attacks are looked for in pieces of this many frames, one is a piece with this many times the high frequency
+// energy of the ones before it and at least TRANSIENT_ENERGY.
+const TRANSIENT_PIECE: usize = SHORT / 2;
+const TRANSIENT_RATIO: f64 = 8.0;
+const TRANSIENT_ENERGY: f64 = 1e-4;
+
+// Unsorted floor posts of both block sizes after the ones at 0 and half the block, denser in the low frequencies.
+// Their number is a multiple of POST_DIMENSIONS.
+const SHORT_POSTS: [usize; 12] = [2, 4, 6, 9, 13, 18, 24, 32, 43, 57, 76, 100];
+const LONG_POSTS: [usize; 32] = [
+    4, 6, 8, 11, 14, 18, 23, 28, 34, 41, 49, 58, 68, 80, 93, 108, 125, 144, 166, 190, 217, 248, 283, 322, 366, 416,
+    472, 535, 606, 686, 776, 878,
+];
+const POST_DIMENSIONS: usize = 4;
+// The floor values are indices into the inverse dB table of the decoder by steps of FLOOR_MULTIPLIER, 0 to 127.
+const FLOOR_MULTIPLIER: i32 = 2;
+const FLOOR_RANGE: i32 = 128;
+// ln of the first entry of the inverse dB table, every entry is e^(FLOOR_STEP) times the one before and the last is 1.
+const FLOOR_MIN_LN: f64 = -16.055_21;
+const FLOOR_STEP: f64 = -FLOOR_MIN_LN / 255.0;
+
+// The residue is coded in partitions of this many values, classified by how large their values are.
+const PARTITION: usize = 32;
+const MAX_RESIDUE: i32 = 3968;
+// Largest value of each class after the first and the books they are coded with in every pass: class 0 is all
+// zeroes, the last class takes everything up to MAX_RESIDUE.
+const CLASSES: [(i32, &[usize]); 7] = [
+    (0, &[]),
+    (1, &[TERNARY_BOOK]),
+    (2, &[SMALL_BOOK]),
+    (4, &[MEDIUM_BOOK]),
+    (8, &[FINE_BOOK]),
+    (136, &[COARSE_BOOK, FINE_BOOK]),
+    (MAX_RESIDUE, &[COARSEST_BOOK, COARSE_BOOK, FINE_BOOK]),
+];
+// Partitions that share a class word.
+const CLASS_DIMENSIONS: usize = 2;
+
+// The codebooks in the order of the setup header. Decoders do not accept book 0 for the residue.
+const FLOOR_BOOK: usize = 0;
+const CLASS_BOOK: usize = 1;
+const TERNARY_BOOK: usize = 2;
+const SMALL_BOOK: usize = 3;
+const MEDIUM_BOOK: usize = 4;
+const FINE_BOOK: usize = 5;
+const COARSE_BOOK: usize = 6;
+const COARSEST_BOOK: usize = 7;
+
+// Quantization noise is allowed this far below the level of the spectrum around it, from -0.1 to 1.0 quality, less
+// by up to NOISE_TILT_DB towards the highest frequency. Below ABSOLUTE_LEVEL everything is silence.
+const MIN_NOISE_DB: f64 = 6.0;
+const MAX_NOISE_DB: f64 = 30.0;
+const NOISE_TILT_DB: f64 = 12.0;
+const ABSOLUTE_LEVEL: f64 = 3e-5;
+
+// The average bitrate of encode_managed is searched for in this many encodes.
+const BITRATE_ATTEMPTS: usize = 8;
+// Pages are closed once their body is this large.
+const PAGE_BODY: usize = 4096;
+// The error libvorbis returns for settings it does not implement.
+const NOT_IMPLEMENTED: c_int = -130;
+
+// Encodes interleaved stereo 16 bit samples at a quality from -0.1 to 1.0, like libvorbis. The pages have no serial
+// number and checksum, which set_stream_serial adds.
+pub fn encode(pcm_data: &[i16], sample_rate: u32, quality: f32) -> Vec<u8> {
+    Encoder::new(sample_rate, quality.clamp(-0.1, 1.0) as f64).encode(pcm_data, 0)
+}
+
+// Encodes at the quality that comes closest to the average bitrate `nominal`. Limits are not supported, `min` and `max`
+// have to be -1 like in libvorbis for no limit.
+pub fn encode_managed(
+    pcm_data: &[i16],
+    sample_rate: u32,
+    nominal: c_long,
+    min: c_long,
+    max: c_long,
+) -> Result<Vec<u8>, c_int> {
+    if min != -1 || max != -1 || nominal <= 0 {
+        return Err(NOT_IMPLEMENTED);
+    }
+    let seconds = ((pcm_data.len() / 2) as f64 / sample_rate as f64).max(0.001);
+    let (mut low, mut high) = (-0.1, 1.0);
+    let mut best: Option<(f64, Vec<u8>)> = None;
+    for _ in 0..BITRATE_ATTEMPTS {
+        let quality = (low + high) / 2.0;
+        let output = Encoder::new(sample_rate, quality).encode(pcm_data, nominal as i32);
+        let bitrate = output.len() as f64 * 8.0 / seconds;
+        if bitrate > nominal as f64 {
+            high = quality;
+        } else {
+            low = quality;
+        }
+        let off = (bitrate - nominal as f64).abs();
+        if best.as_ref().is_none_or(|(best_off, _)| off < *best_off) {
+            best = Some((off, output));
+        }
+    }
+    Ok(best.map(|(_, output)| output).unwrap_or_default())
+}
+
+// What the encoder needs for one block size, short blocks first like the modes.
+struct Shape {
+    size: usize,
+    mdct: Mdct,
+    floor: Floor,
+}
+
+struct Encoder {
+    sample_rate: u32,
+    noise_db: f64,
+    shapes: [Shape; 2],
+    books: Vec<Codebook>,
+}
+
+impl Encoder {
+    fn new(sample_rate: u32, quality: f64) -> Encoder {
+        let shape = |size: usize, posts: &[usize]| Shape {
+            size,
+            mdct: Mdct::new(size / 2),
+            floor: Floor::new(posts, size / 2),
+        };
+        Encoder {
+            sample_rate,
+            noise_db: MIN_NOISE_DB + (MAX_NOISE_DB - MIN_NOISE_DB) * (quality + 0.1) / 1.1,
+            shapes: [shape(SHORT, &SHORT_POSTS), shape(LONG, &LONG_POSTS)],
+            books: codebooks(),
+        }
+    }
+
+    fn encode(&self, pcm_data: &[i16], nominal: i32) -> Vec<u8> {
+        let frames = pcm_data.len() / 2;
+        let mut ogg = OggWriter::default();
+        ogg.add(self.identification_header(nominal), 0);
+        ogg.flush(false);
+        ogg.add(comment_header(), 0);
+        ogg.add(self.setup_header(), 0);
+        ogg.flush(false);
+
+        let samples = |channel: usize, start: isize, window: &[f64]| -> Vec<f64> {
+            window
+                .iter()
+                .enumerate()
+                .map(|(i, w)| {
+                    let frame = start + i as isize;
+                    if frame < 0 || frame as usize >= frames {
+                        0.0
+                    } else {
+                        pcm_data[frame as usize * 2 + channel] as f64 / 32768.0 * w
+                    }
+                })
+                .collect()
+        };
+        // The output of a block ends in its middle, the first block only starts the overlap.
+        let long = plan_blocks(pcm_data);
+        let size = |i: usize| self.shapes[long[i] as usize].size;
+        let mut middle = 0;
+        for i in 0..long.len() {
+            if i > 0 {
+                middle += (size(i - 1) + size(i)) / 4;
+            }
+            let previous = i.checked_sub(1).map_or(size(i), size);
+            let next = if i + 1 < long.len() { size(i + 1) } else { size(i) };
+            let window = window(size(i), previous, next);
+            let start = middle as isize - size(i) as isize / 2;
+            let channels = [samples(0, start, &window), samples(1, start, &window)];
+            let packet = self.audio_packet(long[i], (previous == LONG, next == LONG), channels);
+            ogg.add(packet, middle.min(frames) as u64);
+            // The first block alone on a page, decoders take the length of the last page from the one before it.
+            if (i == 0 || ogg.body.len() >= PAGE_BODY) && i + 1 < long.len() {
+                ogg.flush(false);
+            }
+        }
+        ogg.flush(true);
+        ogg.output
+    }
+
+    fn identification_header(&self, nominal: i32) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.write(1, 8);
+        w.write_bytes(b"vorbis");
+        w.write(0, 32);
+        w.write(2, 8);
+        w.write(self.sample_rate, 32);
+        w.write(0, 32);
+        w.write(nominal.max(0) as u32, 32);
+        w.write(0, 32);
+        w.write(SHORT_EXPONENT, 4);
+        w.write(LONG_EXPONENT, 4);
+        w.write(1, 1);
+        w.bytes
+    }
+
+    fn setup_header(&self) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.write(5, 8);
+        w.write_bytes(b"vorbis");
+        w.write(self.books.len() as u32 - 1, 8);
+        for book in &self.books {
+            book.write_header(&mut w);
+        }
+        // One placeholder time domain transform.
+        w.write(0, 6);
+        w.write(0, 16);
+        w.write(self.shapes.len() as u32 - 1, 6);
+        for shape in &self.shapes {
+            shape.floor.write_header(&mut w);
+        }
+
+        // One residue of type 1 for both block sizes, every channel on its own.
+        w.write(0, 6);
+        w.write(1, 16);
+        w.write(0, 24);
+        w.write(HALF as u32, 24);
+        w.write(PARTITION as u32 - 1, 24);
+        w.write(CLASSES.len() as u32 - 1, 6);
+        w.write(CLASS_BOOK as u32, 8);
+        for (_, books) in CLASSES {
+            let cascade = (1 << books.len()) - 1;
+            w.write(cascade & 7, 3);
+            w.write(0, 1);
+        }
+        for (_, books) in CLASSES {
+            for book in books {
+                w.write(*book as u32, 8);
+            }
+        }
+
+        // A mapping for the floor of every block size, with the right channel coupled to the left one.
+        w.write(self.shapes.len() as u32 - 1, 6);
+        for floor in 0..self.shapes.len() {
+            w.write(0, 16);
+            w.write(0, 1);
+            w.write(1, 1);
+            w.write(0, 8);
+            w.write(0, 1);
+            w.write(1, 1);
+            w.write(0, 2);
+            w.write(0, 8);
+            w.write(floor as u32, 8);
+            w.write(0, 8);
+        }
+
+        // A mode for every block size with the mapping of the same number.
+        w.write(self.shapes.len() as u32 - 1, 6);
+        for mode in 0..self.shapes.len() {
+            w.write(mode as u32, 1);
+            w.write(0, 16);
+            w.write(0, 16);
+            w.write(mode as u32, 8);
+        }
+        w.write(1, 1);
+        w.bytes
+    }
+
+    // Codes a windowed block, `neighbours` says if the blocks before and after it are long.
+    fn audio_packet(&self, long: bool, neighbours: (bool, bool), channels: [Vec<f64>; 2]) -> Vec<u8> {
+        let shape = &self.shapes[long as usize];
+        let mut w = BitWriter::default();
+        w.write(0, 1);
+        w.write(long as u32, 1);
+        if long {
+            w.write(neighbours.0 as u32, 1);
+            w.write(neighbours.1 as u32, 1);
+        }
+
+        let mut residues = Vec::with_capacity(2);
+        for windowed in &channels {
+            let spectrum = shape.mdct.forward(windowed);
+            let steps = self.steps(&spectrum);
+            match shape.floor.encode(&spectrum, &steps, &self.books[FLOOR_BOOK], &mut w) {
+                Some(curve) => residues.push(Some(
+                    spectrum
+                        .iter()
+                        .zip(&curve)
+                        .map(|(x, floor)| ((x / floor).round() as i32).clamp(-MAX_RESIDUE, MAX_RESIDUE))
+                        .collect::<Vec<i32>>(),
+                )),
+                None => residues.push(None),
+            }
+        }
+
+        // Coupled channels are both decoded when one of them is used, an unused one is silent anyway.
+        if residues.iter().all(Option::is_none) {
+            return w.bytes;
+        }
+        let zero = vec![0; shape.size / 2];
+        let left = residues[0].as_ref().unwrap_or(&zero);
+        let right = residues[1].as_ref().unwrap_or(&zero);
+        let (magnitude, angle): (Vec<i32>, Vec<i32>) = left.iter().zip(right).map(|(l, r)| couple(*l, *r)).unzip();
+        self.write_residue(&[magnitude, angle], &mut w);
+        w.bytes
+    }
+
+    // The largest quantization step of every frequency that keeps the noise far enough below the spectrum around it.
+    fn steps(&self, spectrum: &[f64]) -> Vec<f64> {
+        let mut energy = vec![0.0; spectrum.len() + 1];
+        for (k, x) in spectrum.iter().enumerate() {
+            energy[k + 1] = energy[k] + x * x;
+        }
+        (0..spectrum.len())
+            .map(|k| {
+                // A sixteenth of the frequency to either side.
+                let width = 1 + k * 64 / spectrum.len();
+                let (low, high) = (k.saturating_sub(width), (k + width + 1).min(spectrum.len()));
+                let level = ((energy[high] - energy[low]) / (high - low) as f64).sqrt();
+                let noise_db = self.noise_db - NOISE_TILT_DB * k as f64 / spectrum.len() as f64;
+                // Uniform quantization leaves noise of a twelfth of the step squared.
+                (level * 12f64.sqrt() * 10f64.powf(-noise_db / 20.0)).max(ABSOLUTE_LEVEL)
+            })
+            .collect()
+    }
+
+    fn write_residue(&self, vectors: &[Vec<i32>; 2], w: &mut BitWriter) {
+        let partitions = vectors[0].len() / PARTITION;
+        let classes: Vec<Vec<usize>> = vectors
+            .iter()
+            .map(|vector| {
+                vector
+                    .chunks(PARTITION)
+                    .map(|values| {
+                        let largest = values.iter().map(|value| value.abs()).max().unwrap_or(0);
+                        CLASSES.iter().position(|(limit, _)| largest <= *limit).unwrap()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // What is left of every value for the next pass.
+        let mut left: Vec<Vec<i32>> = vectors.to_vec();
+        let passes = CLASSES.iter().map(|(_, books)| books.len()).max().unwrap();
+        for pass in 0..passes {
+            for first in (0..partitions).step_by(CLASS_DIMENSIONS) {
+                if pass == 0 {
+                    for class in &classes {
+                        let word = (first..first + CLASS_DIMENSIONS).fold(0, |word, i| word * CLASSES.len() + class[i]);
+                        self.books[CLASS_BOOK].write_entry(word, w);
+                    }
+                }
+                for partition in first..first + CLASS_DIMENSIONS {
+                    for (channel, class) in classes.iter().enumerate() {
+                        let Some(&book) = CLASSES[class[partition]].1.get(pass) else {
+                            continue;
+                        };
+                        let book = &self.books[book];
+                        let values = &mut left[channel][partition * PARTITION..(partition + 1) * PARTITION];
+                        for values in values.chunks_mut(book.dimensions) {
+                            let entry = book.quantize(values);
+                            book.write_entry(entry, w);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Which blocks are long: one for every HALF frames up to the end, but eight short ones in the place of a long one
+// that would put most of an attack in its middle, where the quantization noise would be heard before the attack.
+fn plan_blocks(pcm_data: &[i16]) -> Vec<bool> {
+    let frames = pcm_data.len() / 2;
+    let slots = frames.div_ceil(HALF).max(1);
+    let mut short = vec![false; slots + 1];
+    let energies: Vec<f64> = (0..frames.div_ceil(TRANSIENT_PIECE))
+        .map(|piece| {
+            let (start, end) = (piece * TRANSIENT_PIECE, ((piece + 1) * TRANSIENT_PIECE).min(frames));
+            (start.max(1)..end)
+                .map(|frame| {
+                    (0..2)
+                        .map(|channel| {
+                            let change =
+                                pcm_data[frame * 2 + channel] as f64 - pcm_data[frame * 2 - 2 + channel] as f64;
+                            (change / 32768.0).powi(2)
+                        })
+                        .sum::<f64>()
+                })
+                .sum()
+        })
+        .collect();
+    for (piece, energy) in energies.iter().enumerate().skip(1) {
+        let before = &energies[piece.saturating_sub(4)..piece];
+        let average = before.iter().sum::<f64>() / before.len() as f64;
+        if *energy > TRANSIENT_ENERGY && *energy > average * TRANSIENT_RATIO {
+            // The short blocks of a slot cover the frames from (LONG + SHORT) / 4 after the long block before it.
+            let slot = (piece * TRANSIENT_PIECE + HALF - (LONG + SHORT) / 4) / HALF;
+            // The first block only starts the overlap and the last one has to reach the end.
+            if slot > 0 && slot < slots {
+                short[slot] = true;
+            }
+        }
+    }
+    let mut long = vec![true];
+    for slot in short.iter().skip(1) {
+        if *slot {
+            long.extend([false; LONG / SHORT]);
+        } else {
+            long.push(true);
+        }
+    }
+    long
+}
+
+// The window of a block as decoders apply it, its slopes as long as the overlaps with the blocks around it.
+fn window(size: usize, previous: usize, next: usize) -> Vec<f64> {
+    let slope =
+        |i: usize, length: usize| (PI / 2.0 * ((i as f64 + 0.5) / length as f64 * PI / 2.0).sin().powi(2)).sin();
+    let (left, right) = (previous.min(size) / 2, next.min(size) / 2);
+    let (left_start, right_start) = (size / 4 - left / 2, size * 3 / 4 - right / 2);
+    (0..size)
+        .map(|i| {
+            if i < left_start {
+                0.0
+            } else if i < left_start + left {
+                slope(i - left_start, left)
+            } else if i < right_start {
+                1.0
+            } else if i < right_start + right {
+                slope(right_start + right - 1 - i, right)
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+// Square polar mapping: the inverse of how decoders turn a magnitude and an angle back into both channels.
+fn couple(left: i32, right: i32) -> (i32, i32) {
+    if left > right {
+        if left > 0 {
+            (left, left - right)
+        } else {
+            (right, right - left)
+        }
+    } else if right > 0 {
+        (right, left - right)
+    } else if left < right {
+        (left, right - left)
+    } else {
+        (right, 0)
+    }
+}
+
+fn comment_header() -> Vec<u8> {
+    let vendor = concat!("audio-sample-mixer ", env!("CARGO_PKG_VERSION"));
+    let mut w = BitWriter::default();
+    w.write(3, 8);
+    w.write_bytes(b"vorbis");
+    w.write(vendor.len() as u32, 32);
+    w.write_bytes(vendor.as_bytes());
+    w.write(0, 32);
+    w.write(1, 1);
+    w.bytes
+}
+
+fn codebooks() -> Vec<Codebook> {
+    // Complete prefix codes for the values of one dimension, 0 in the middle.
+    let ternary = [2, 1, 2];
+    let small = [3, 3, 1, 3, 3];
+    let medium = [5, 5, 4, 2, 2, 2, 4, 5, 5];
+    let fine = [6, 6, 6, 6, 5, 5, 3, 3, 2, 3, 3, 5, 5, 6, 6, 6, 6];
+    let coarsest: Vec<u8> = (-15..=15_i32)
+        .map(|value| match value.abs() {
+            0 => 1,
+            1 => 3,
+            2 => 4,
+            3 => 5,
+            4..=7 => 8,
+            _ => 9,
+        })
+        .collect();
+    // The floor values that say how far a post is from its prediction, small ones are the most common.
+    let floor = (0..FLOOR_RANGE)
+        .map(|value| match value {
+            0..=3 => 3,
+            4..=11 => 5,
+            12..=27 => 7,
+            28..=55 => 9,
+            _ => 10,
+        })
+        .collect();
+    let class_lengths = [2, 2, 3, 3, 3, 4, 4];
+    let classes = (0..CLASSES.len() * CLASSES.len())
+        .map(|word| class_lengths[word / CLASSES.len()] + class_lengths[word % CLASSES.len()])
+        .collect();
+    vec![
+        Codebook::scalar(1, floor),
+        Codebook::scalar(CLASS_DIMENSIONS, classes),
+        Codebook::lattice(4, -1, 1, &ternary),
+        Codebook::lattice(2, -2, 1, &small),
+        Codebook::lattice(2, -4, 1, &medium),
+        Codebook::lattice(1, -8, 1, &fine),
+        Codebook::lattice(1, -128, 16, &fine),
+        Codebook::lattice(1, -3840, 256, &coarsest),
+    ]
+}
+
+struct Codebook {
+    dimensions: usize,
+    lengths: Vec<u8>,
+    // Bit reversed, so they are written from the lowest bit.
+    words: Vec<u32>,
+    // Vector books have `values` values in every dimension, `minimum + delta * i`.
+    lattice: Option<(usize, i32, i32)>,
+}
+
+impl Codebook {
+    fn scalar(dimensions: usize, lengths: Vec<u8>) -> Codebook {
+        let words = codewords(&lengths);
+        Codebook {
+            dimensions,
+            lengths,
+            words,
+            lattice: None,
+        }
+    }
+
+    // Every entry is one value per dimension, the first dimension changing fastest, with the lengths of its values
+    // added up.
+    fn lattice(dimensions: usize, minimum: i32, delta: i32, value_lengths: &[u8]) -> Codebook {
+        let values = value_lengths.len();
+        let lengths = (0..values.pow(dimensions as u32))
+            .map(|entry| {
+                (0..dimensions)
+                    .map(|dimension| value_lengths[entry / values.pow(dimension as u32) % values])
+                    .sum()
+            })
+            .collect();
+        Codebook {
+            lattice: Some((values, minimum, delta)),
+            ..Codebook::scalar(dimensions, lengths)
+        }
+    }
+
+    fn write_header(&self, w: &mut BitWriter) {
+        w.write(0x564342, 24);
+        w.write(self.dimensions as u32, 16);
+        w.write(self.lengths.len() as u32, 24);
+        // Neither ordered nor sparse.
+        w.write(0, 1);
+        w.write(0, 1);
+        for length in &self.lengths {
+            w.write(*length as u32 - 1, 5);
+        }
+        match self.lattice {
+            None => w.write(0, 4),
+            Some((values, minimum, delta)) => {
+                w.write(1, 4);
+                w.write(pack_float(minimum), 32);
+                w.write(pack_float(delta), 32);
+                let bits = ilog(values as u32 - 1);
+                w.write(bits - 1, 4);
+                w.write(0, 1);
+                for value in 0..values {
+                    w.write(value as u32, bits);
+                }
+            }
+        }
+    }
+
+    fn write_entry(&self, entry: usize, w: &mut BitWriter) {
+        w.write(self.words[entry], self.lengths[entry] as u32);
+    }
+
+    // The entry closest to the values, which are left with what it does not cover.
+    fn quantize(&self, values: &mut [i32]) -> usize {
+        let (count, minimum, delta) = self.lattice.unwrap();
+        let mut entry = 0;
+        for (dimension, value) in values.iter_mut().enumerate() {
+            let index = ((*value - minimum) as f64 / delta as f64)
+                .round()
+                .clamp(0.0, count as f64 - 1.0) as i32;
+            *value -= minimum + delta * index;
+            entry += index as usize * count.pow(dimension as u32);
+        }
+        entry
+    }
+}
+
+// The codewords decoders assign to the lengths: every entry takes the lowest free word of its length.
+fn codewords(lengths: &[u8]) -> Vec<u32> {
+    let mut marker = [0_u32; 33];
+    let mut words = Vec::with_capacity(lengths.len());
+    for &length in lengths {
+        let length = length as usize;
+        let mut entry = marker[length];
+        words.push(entry);
+        for j in (1..=length).rev() {
+            if marker[j] & 1 == 1 {
+                if j == 1 {
+                    marker[1] += 1;
+                } else {
+                    marker[j] = marker[j - 1] << 1;
+                }
+                break;
+            }
+            marker[j] += 1;
+        }
+        for j in length + 1..33 {
+            if marker[j] >> 1 != entry {
+                break;
+            }
+            entry = marker[j];
+            marker[j] = marker[j - 1] << 1;
+        }
+    }
+    words
+        .iter()
+        .zip(lengths)
+        .map(|(word, &length)| (0..length).fold(0, |reversed, bit| reversed << 1 | (word >> bit & 1)))
+        .collect()
+}
+
+// Integers of the float format of Vorbis: a 21 bit mantissa times 2 ^ (exponent - 788).
+fn pack_float(value: i32) -> u32 {
+    let sign = if value < 0 { 1 << 31 } else { 0 };
+    sign | 788 << 21 | value.unsigned_abs()
+}
+
+fn ilog(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+// Floor type 1: a line through posts at fixed frequencies, in steps of the inverse dB table. It is the quantization
+// step of the residue.
+struct Floor {
+    // In the order they are coded, starting with the ones at 0 and `half`.
+    x: Vec<usize>,
+    half: usize,
+    posts: usize,
+    // Indices of `x` from the lowest to the highest frequency.
+    sorted: Vec<usize>,
+    // The closest posts coded before every post below and above it, from which it is predicted.
+    neighbours: Vec<(usize, usize)>,
+}
+
+impl Floor {
+    fn new(posts: &[usize], half: usize) -> Floor {
+        let mut sorted_posts = posts.to_vec();
+        sorted_posts.sort_unstable();
+        let all: Vec<usize> = [0].into_iter().chain(sorted_posts).chain([half]).collect();
+
+        // Posts in the middle of the ones before them come first, so later posts are predicted from close neighbours.
+        let mut x = vec![0, half];
+        let mut ranges = VecDeque::from([(0, all.len() - 1)]);
+        while let Some((low, high)) = ranges.pop_front() {
+            if high - low > 1 {
+                let middle = (low + high) / 2;
+                x.push(all[middle]);
+                ranges.push_back((low, middle));
+                ranges.push_back((middle, high));
+            }
+        }
+
+        let mut sorted: Vec<usize> = (0..x.len()).collect();
+        sorted.sort_by_key(|i| x[*i]);
+        let neighbours = (0..x.len())
+            .map(|i| {
+                let low = (0..i).filter(|j| x[*j] < x[i]).max_by_key(|j| x[*j]).unwrap_or(0);
+                let high = (0..i).filter(|j| x[*j] > x[i]).min_by_key(|j| x[*j]).unwrap_or(0);
+                (low, high)
+            })
+            .collect();
+        Floor {
+            x,
+            half,
+            posts: posts.len(),
+            sorted,
+            neighbours,
+        }
+    }
+
+    fn write_header(&self, w: &mut BitWriter) {
+        let partitions = self.posts / POST_DIMENSIONS;
+        w.write(1, 16);
+        w.write(partitions as u32, 5);
+        for _ in 0..partitions {
+            w.write(0, 4);
+        }
+        // One class without subclasses.
+        w.write(POST_DIMENSIONS as u32 - 1, 3);
+        w.write(0, 2);
+        w.write(FLOOR_BOOK as u32 + 1, 8);
+        w.write(FLOOR_MULTIPLIER as u32 - 1, 2);
+        w.write(ilog(self.half as u32 - 1), 4);
+        for x in &self.x[2..] {
+            w.write(*x as u32, ilog(self.half as u32 - 1));
+        }
+    }
+
+    // Writes the floor that is at most `steps` around every post and returns it as the decoder renders it, None for a
+    // channel that is silent. It stays high enough that no residue of `spectrum` goes past MAX_RESIDUE.
+    fn encode(&self, spectrum: &[f64], steps: &[f64], book: &Codebook, w: &mut BitWriter) -> Option<Vec<f64>> {
+        let index = |step: f64| ((step.ln() - FLOOR_MIN_LN) / FLOOR_STEP) as i32 / FLOOR_MULTIPLIER;
+        // Rounding the index down lowers the floor by up to one multiple of the step.
+        let rounding = (FLOOR_STEP * FLOOR_MULTIPLIER as f64).exp();
+        let mut y = vec![0; self.x.len()];
+        for (position, &i) in self.sorted.iter().enumerate() {
+            let before = position.checked_sub(1).map_or(0, |before| self.x[self.sorted[before]]);
+            let after = self.sorted.get(position + 1).map_or(self.half, |&after| self.x[after]);
+            let low = (before + self.x[i]) / 2;
+            let high = (self.x[i] + after).div_ceil(2);
+            let step = steps[low.min(self.half - 1)..high.clamp(low + 1, self.half)]
+                .iter()
+                .copied()
+                .fold(f64::INFINITY, f64::min);
+            // Every frequency up to the posts next to this one is rendered partly from it.
+            let peak = spectrum[before..after.max(before + 1).min(self.half)]
+                .iter()
+                .fold(0.0, |peak: f64, x| peak.max(x.abs()));
+            y[i] = index(step.max(peak * rounding / MAX_RESIDUE as f64)).clamp(0, FLOOR_RANGE - 1);
+        }
+        if steps.iter().all(|step| *step <= ABSOLUTE_LEVEL) {
+            w.write(0, 1);
+            return None;
+        }
+
+        w.write(1, 1);
+        let bits = ilog(FLOOR_RANGE as u32 - 1);
+        w.write(y[0] as u32, bits);
+        w.write(y[1] as u32, bits);
+        let mut used = vec![false; self.x.len()];
+        used[0] = true;
+        used[1] = true;
+        for i in 2..self.x.len() {
+            let (low, high) = self.neighbours[i];
+            let predicted = render_point(self.x[low], y[low], self.x[high], y[high], self.x[i]);
+            let value = fold(y[i], predicted);
+            if value == 0 {
+                y[i] = predicted;
+            } else {
+                used[low] = true;
+                used[high] = true;
+                used[i] = true;
+            }
+            book.write_entry(value as usize, w);
+        }
+
+        let mut curve = vec![0.0; self.half];
+        let (mut lx, mut ly) = (0, y[0] * FLOOR_MULTIPLIER);
+        for &i in &self.sorted[1..] {
+            if used[i] {
+                let (hx, hy) = (self.x[i], y[i] * FLOOR_MULTIPLIER);
+                render_line(lx, ly, hx, hy, &mut curve);
+                (lx, ly) = (hx, hy);
+            }
+        }
+        Some(curve)
+    }
+}
+
+// The value decoders turn back into `y` from the prediction, 0 if it is the prediction.
+fn fold(y: i32, predicted: i32) -> i32 {
+    let high_room = FLOOR_RANGE - predicted;
+    let low_room = predicted;
+    let room = 2 * high_room.min(low_room);
+    let offset = y - predicted;
+    if offset > 0 {
+        if 2 * offset < room {
+            2 * offset
+        } else {
+            offset + low_room
+        }
+    } else if offset < 0 {
+        if -2 * offset - 1 < room {
+            -2 * offset - 1
+        } else {
+            high_room - 1 - offset
+        }
+    } else {
+        0
+    }
+}
+
+fn render_point(x0: usize, y0: i32, x1: usize, y1: i32, x: usize) -> i32 {
+    let dy = y1 - y0;
+    let offset = dy.abs() * (x - x0) as i32 / (x1 - x0) as i32;
+    if dy < 0 {
+        y0 - offset
+    } else {
+        y0 + offset
+    }
+}
+
+// Like the decoders draw the floor, from x0 up to but not including x1.
+fn render_line(x0: usize, y0: i32, x1: usize, y1: i32, curve: &mut [f64]) {
+    let dy = y1 - y0;
+    let adx = (x1 - x0) as i32;
+    let base = dy / adx;
+    let sy = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * adx;
+    let mut y = y0;
+    let mut err = 0;
+    curve[x0] = inverse_db(y);
+    for value in curve.iter_mut().take(x1).skip(x0 + 1) {
+        err += ady;
+        if err >= adx {
+            err -= adx;
+            y += sy;
+        } else {
+            y += base;
+        }
+        *value = inverse_db(y);
+    }
+}
+
+fn inverse_db(index: i32) -> f64 {
+    (FLOOR_MIN_LN + FLOOR_STEP * index as f64).exp()
+}
+
+// The MDCT of a block as a DCT-IV of half its length, which is computed with a complex FFT of a quarter of it.
+struct Mdct {
+    half: usize,
+    // e^(-i pi n / half) before the FFT and e^(-i pi (4k + 1) / (4 half)) after it.
+    before: Vec<(f64, f64)>,
+    after: Vec<(f64, f64)>,
+    fft: Vec<(f64, f64)>,
+}
+
+impl Mdct {
+    fn new(half: usize) -> Mdct {
+        let quarter = half / 2;
+        let turn = |angle: f64| (angle.cos(), -angle.sin());
+        Mdct {
+            half,
+            before: (0..quarter).map(|n| turn(PI * n as f64 / half as f64)).collect(),
+            after: (0..quarter)
+                .map(|k| turn(PI * (4 * k + 1) as f64 / (4 * half) as f64))
+                .collect(),
+            fft: (0..quarter / 2)
+                .map(|k| turn(2.0 * PI * k as f64 / quarter as f64))
+                .collect(),
+        }
+    }
+
+    // The coefficients of a windowed block, scaled so the decoders put the samples back together.
+    fn forward(&self, block: &[f64]) -> Vec<f64> {
+        let (q, h) = (self.half / 2, self.half);
+        // Folded into the input of the DCT-IV: -c' - d and a - b' of the quarters a, b, c, d, with ' reversed.
+        let folded: Vec<f64> = (0..h)
+            .map(|n| {
+                if n < q {
+                    -block[h + q - 1 - n] - block[h + q + n]
+                } else {
+                    block[n - q] - block[h + q - 1 - n]
+                }
+            })
+            .collect();
+
+        let mut z: Vec<(f64, f64)> = (0..q)
+            .map(|n| multiply((folded[2 * n], folded[h - 1 - 2 * n]), self.before[n]))
+            .collect();
+        self.transform(&mut z);
+
+        let scale = 2.0 / h as f64;
+        let mut output = vec![0.0; h];
+        for (k, value) in z.iter().enumerate() {
+            let (re, im) = multiply(*value, self.after[k]);
+            output[2 * k] = re * scale;
+            output[h - 1 - 2 * k] = -im * scale;
+        }
+        output
+    }
+
+    // In place radix 2 FFT.
+    fn transform(&self, data: &mut [(f64, f64)]) {
+        let n = data.len();
+        let bits = n.trailing_zeros();
+        for i in 0..n {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+        let mut size = 2;
+        while size <= n {
+            let stride = n / size;
+            for start in (0..n).step_by(size) {
+                for k in 0..size / 2 {
+                    let twiddle = multiply(data[start + k + size / 2], self.fft[k * stride]);
+                    let even = data[start + k];
+                    data[start + k] = (even.0 + twiddle.0, even.1 + twiddle.1);
+                    data[start + k + size / 2] = (even.0 - twiddle.0, even.1 - twiddle.1);
+                }
+            }
+            size *= 2;
+        }
+    }
+}
+
+fn multiply(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+// Packs values from their lowest bit on, like every Vorbis field.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        for bit in 0..count {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if value >> bit & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 1 << (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write(*byte as u32, 8);
+        }
+    }
+}
+
+// Collects packets into Ogg pages. The serial number and checksum are left at 0.
+#[derive(Default)]
+struct OggWriter {
+    output: Vec<u8>,
+    sequence: u32,
+    lacing: Vec<u8>,
+    body: Vec<u8>,
+    // Of the last packet that ended on the page, None if none did.
+    granule: Option<u64>,
+    // The page starts in the middle of a packet.
+    continued: bool,
+}
+
+impl OggWriter {
+    fn add(&mut self, packet: Vec<u8>, granule: u64) {
+        let mut rest = packet.as_slice();
+        loop {
+            if self.lacing.len() == 255 {
+                self.write_page(false);
+                self.continued = true;
+            }
+            let length = rest.len().min(255);
+            self.lacing.push(length as u8);
+            self.body.extend_from_slice(&rest[..length]);
+            rest = &rest[length..];
+            if length < 255 {
+                break;
+            }
+        }
+        self.granule = Some(granule);
+    }
+
+    fn flush(&mut self, last: bool) {
+        if !self.lacing.is_empty() || last {
+            self.write_page(last);
+        }
+    }
+
+    fn write_page(&mut self, last: bool) {
+        let mut flags = 0;
+        if self.continued {
+            flags |= 1;
+        }
+        if self.sequence == 0 {
+            flags |= 2;
+        }
+        if last {
+            flags |= 4;
+        }
+        self.output.extend_from_slice(b"OggS");
+        self.output.push(0);
+        self.output.push(flags);
+        self.output
+            .extend_from_slice(&self.granule.take().unwrap_or(u64::MAX).to_le_bytes());
+        self.output.extend_from_slice(&[0; 4]);
+        self.output.extend_from_slice(&self.sequence.to_le_bytes());
+        self.output.extend_from_slice(&[0; 4]);
+        self.output.push(self.lacing.len() as u8);
+        self.output.append(&mut self.lacing);
+        self.output.append(&mut self.body);
+        self.sequence += 1;
+        self.continued = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::io::Cursor;
+
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    use crate::export::{encode, Encoding};
+
+    const RATE: u32 = 44100;
+
+    // A 440 Hz sine on the left and a quieter 1 kHz sine on the right.
+    fn sines(frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|i| {
+                let t = i as f64 / RATE as f64;
+                [
+                    (0.5 * (2.0 * PI * 440.0 * t).sin()) as f32,
+                    (0.25 * (2.0 * PI * 1000.0 * t).sin()) as f32,
+                ]
+            })
+            .collect()
+    }
+
+    // Through export, which adds the serial numbers and checksums of the pages, and back with symphonia.
+    fn round_trip(data: &[f32], quality: f32) -> Vec<f32> {
+        let ogg = encode(data, Encoding::Quality(quality), RATE, Some(1)).unwrap();
+        let mss = MediaSourceStream::new(Box::new(Cursor::new(ogg)), Default::default());
+        let options = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let mut format = symphonia::default::get_probe()
+            .format(&Hint::new(), mss, &options, &MetadataOptions::default())
+            .unwrap()
+            .format;
+        let track = format.default_track().unwrap();
+        assert_eq!(track.codec_params.sample_rate, Some(RATE));
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &Default::default())
+            .unwrap();
+        let mut decoded = Vec::new();
+        while let Ok(packet) = format.next_packet() {
+            let audio = decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<f32>::new(audio.capacity() as u64, *audio.spec());
+            buffer.copy_interleaved_ref(audio);
+            decoded.extend_from_slice(buffer.samples());
+        }
+        decoded
+    }
+
+    fn rms(values: impl Iterator<Item = f32>) -> f64 {
+        let (sum, count) = values.fold((0.0, 0), |(sum, count), value| {
+            (sum + (value as f64).powi(2), count + 1)
+        });
+        (sum / count.max(1) as f64).sqrt()
+    }
+
+    #[test]
+    fn sine_round_trip() {
+        let data = sines(RATE as usize * 2);
+        for (quality, max_error_db) in [(0.7, -45.0), (0.0, -30.0)] {
+            let decoded = round_trip(&data, quality);
+            assert_eq!(decoded.len(), data.len(), "quality {}", quality);
+            let error = rms(data.iter().zip(&decoded).map(|(a, b)| a - b)) / rms(data.iter().copied());
+            let error_db = 20.0 * error.log10();
+            assert!(
+                error_db < max_error_db,
+                "{:.1} dB of error at quality {}",
+                error_db,
+                quality
+            );
+        }
+    }
+
+    #[test]
+    fn short_and_silent() {
+        // Shorter than a block, the last granule position cuts the padding off.
+        let data = sines(100);
+        assert_eq!(round_trip(&data, 0.5).len(), data.len());
+
+        let silence = vec![0.0; RATE as usize * 2];
+        let decoded = round_trip(&silence, 0.5);
+        assert_eq!(decoded.len(), silence.len());
+        assert!(decoded.iter().all(|value| value.abs() < 1e-4));
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+
+use crate::decode::probe_audio;
+use crate::events::AudioSampleInfo;
+use crate::mix::RenderSettings;
+use crate::resolve::split_track;
+use crate::synth::parse_synth;
+
+// Writes a Reaper project with one track per sample and one media item per event, at its time and with its volume
+// and pan, instead of mixing. Returns the number of items.
+pub fn write_project(
+    path: &Path,
+    infos: &[AudioSampleInfo],
+    settings: &RenderSettings,
+) -> Result<usize, Box<dyn Error>> {
+    let mut tracks: BTreeMap<&str, Vec<&AudioSampleInfo>> = BTreeMap::new();
+    for info in infos {
+        tracks.entry(&info.name).or_default().push(info);
+    }
+
+    let mut text = String::new();
+    let _ = writeln!(text, "<REAPER_PROJECT 0.1 \"6.0\" 0");
+    let _ = writeln!(text, "  SAMPLERATE {} 0 0", settings.sample_rate);
+
+    let mut items = 0;
+    let mut missing = Vec::new();
+    for (name, events) in tracks {
+        if parse_synth(name).is_some() {
+            warn!("leaving out {}: generated samples have no file for a media item", name);
+            missing.push(name);
+            continue;
+        }
+        let file = settings.resolver.resolve(name);
+        let duration = match probe_audio(&file, split_track(name).1.or(settings.track)) {
+            Ok(audio) => audio.duration() as f64,
+            Err(err) => {
+                warn!("leaving out {}: {}", name, err);
+                missing.push(name);
+                continue;
+            }
+        };
+        // Reaper resolves relative paths against the project file, absolute ones work wherever it is saved.
+        let file = fs::canonicalize(&file).unwrap_or(file);
+
+        let _ = writeln!(text, "  <TRACK");
+        let _ = writeln!(text, "    NAME {}", quote(name));
+        for info in events {
+            // Items cannot start before 0, the part before is cut off like in the mix.
+            let start = info.time / 1000.0;
+            let offset = if start < 0.0 { -start } else { 0.0 };
+            if offset >= duration {
+                continue;
+            }
+            let _ = writeln!(text, "    <ITEM");
+            let _ = writeln!(text, "      POSITION {:.6}", start.max(0.0));
+            let _ = writeln!(text, "      LENGTH {:.6}", duration - offset);
+            let _ = writeln!(text, "      SOFFS {:.6}", offset);
+            let _ = writeln!(text, "      VOLPAN {} {} 1 -1", info.volume, info.pan);
+            let _ = writeln!(text, "      NAME {}", quote(name));
+            let _ = writeln!(text, "      <SOURCE {}", source_type(&file));
+            let _ = writeln!(text, "        FILE {}", quote(&file.to_string_lossy()));
+            let _ = writeln!(text, "      >");
+            let _ = writeln!(text, "    >");
+            items += 1;
+        }
+        let _ = writeln!(text, "  >");
+    }
+    let _ = writeln!(text, ">");
+
+    fs::write(path, text)?;
+    if !missing.is_empty() {
+        warn!(
+            "{} samples are not in the project: {}",
+            missing.len(),
+            missing.join(", ")
+        );
+    }
+    info!("wrote {} items to {}", items, path.display());
+    Ok(items)
+}
+
+fn source_type(file: &Path) -> &'static str {
+    let extension = file.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    match extension.to_ascii_lowercase().as_str() {
+        "ogg" => "VORBIS",
+        "opus" => "OPUS",
+        "mp3" => "MP3",
+        "flac" => "FLAC",
+        _ => "WAVE",
+    }
+}
+
+// Reaper has no escapes in strings, a string with double quotes is put in single quotes or backticks instead.
+fn quote(value: &str) -> String {
+    if !value.contains('"') {
+        format!("\"{}\"", value)
+    } else if !value.contains('\'') {
+        format!("'{}'", value)
+    } else {
+        format!("`{}`", value.replace('`', "'"))
+    }
+}
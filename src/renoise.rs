@@ -0,0 +1,372 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+use zip::ZipArchive;
+
+use crate::decode::{probe_audio, read_audio, UpmixLaw};
+use crate::events::AudioSampleInfo;
+use crate::export::write_wav;
+use crate::osu::extract_samples;
+use crate::resample::ConverterType;
+use crate::xml::{parse_xml, Element};
+
+// Renoise counts notes from C-0, C-4 is 48.
+const NOTE_NAMES: [&str; 12] = ["C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-"];
+const DEFAULT_BASE_NOTE: i32 = 48;
+// Volume and panning columns go from 00 to 80, higher values are effects.
+const FULL_COLUMN: u32 = 0x80;
+
+struct Sample {
+    path: Option<PathBuf>,
+    volume: f32,
+    pan: f32,
+    // Semitones the sample is played higher than the note, including the base note.
+    transpose: f64,
+    note_start: i32,
+    note_end: i32,
+}
+
+// A note as the pattern plays it, until the next note or note off in its column.
+struct Note {
+    instrument: usize,
+    sample: usize,
+    note: i32,
+    time: f64,
+    end: Option<f64>,
+    volume: Option<f32>,
+    pan: Option<f32>,
+    line: u64,
+}
+
+// The sample of a note decoded at the sample rate of its file.
+struct Decoded {
+    data: Vec<f32>,
+    sample_rate: u32,
+}
+
+// Reads a Renoise song: the notes of the pattern sequence with the samples of their instruments. Like the samples of
+// tracker modules, every note gets its sample extracted to the temporary directory with a sample rate that plays it
+// at its pitch and as long as the note lasts. Volume, panning and delay columns are followed, effect columns,
+// envelopes, loops, track devices and automation are not.
+pub fn read_song(path: &Path) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut text = String::new();
+    archive
+        .by_name("Song.xml")
+        .map_err(|_| "the archive has no Song.xml")?
+        .read_to_string(&mut text)?;
+    let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    let dir = extract_samples(path, &mut archive)?;
+
+    let song = parse_xml(&text)?;
+    if song.name != "RenoiseSong" {
+        return Err(format!("<{}> is not a Renoise song", song.name).into());
+    }
+    let global = song.child("GlobalSongData").ok_or("the song has no GlobalSongData")?;
+    let bpm = number(global, "BeatsPerMin", 120.0).max(1.0);
+    let lines_per_beat = number(global, "LinesPerBeat", 4.0).max(1.0);
+    let line_length = 60000.0 / bpm / lines_per_beat;
+
+    let instruments = read_instruments(&song, &names, &dir);
+    let notes = sequence(&song, &instruments, line_length)?;
+    write_notes(path, &notes, &instruments)
+}
+
+fn read_instruments(song: &Element, names: &[String], dir: &Path) -> Vec<Vec<Sample>> {
+    let Some(instruments) = song.child("Instruments") else {
+        return Vec::new();
+    };
+    instruments
+        .children("Instrument")
+        .enumerate()
+        .map(|(instrument, element)| {
+            // Renoise 3 keeps the samples in the sample generator, older versions directly in the instrument.
+            let samples = element
+                .child("SampleGenerator")
+                .and_then(|generator| generator.child("Samples"))
+                .or_else(|| element.child("Samples"));
+            let Some(samples) = samples else {
+                return Vec::new();
+            };
+            samples
+                .children("Sample")
+                .enumerate()
+                .map(|(sample, element)| {
+                    let mapping = element.child("Mapping").unwrap_or(element);
+                    let base_note = number(mapping, "BaseNote", DEFAULT_BASE_NOTE as f64);
+                    Sample {
+                        path: sample_file(names, instrument, sample).map(|name| dir.join(name)),
+                        volume: number(element, "Volume", 1.0) as f32,
+                        pan: number(element, "Panning", 0.5) as f32 * 2.0 - 1.0,
+                        transpose: number(element, "Transpose", 0.0) + number(element, "Finetune", 0.0) / 128.0
+                            - base_note,
+                        note_start: number(mapping, "NoteStart", 0.0) as i32,
+                        note_end: number(mapping, "NoteEnd", 119.0) as i32,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Samples are stored as SampleData/Instrument00 (name)/Sample00 (name).flac.
+fn sample_file(names: &[String], instrument: usize, sample: usize) -> Option<&String> {
+    let instrument = format!("SampleData/Instrument{:02}", instrument);
+    let sample = format!("Sample{:02}", sample);
+    names.iter().find(|name| {
+        let mut parts = name.split('/');
+        let directory = format!("{}/{}", parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        directory.starts_with(&instrument) && parts.next().is_some_and(|file| file.starts_with(&sample))
+    })
+}
+
+fn sequence(song: &Element, instruments: &[Vec<Sample>], line_length: f64) -> Result<Vec<Note>, Box<dyn Error>> {
+    let patterns: Vec<&Element> = song
+        .child("PatternPool")
+        .and_then(|pool| pool.child("Patterns"))
+        .map(|patterns| patterns.children("Pattern").collect())
+        .unwrap_or_default();
+    let entries: Vec<usize> = song
+        .child("PatternSequence")
+        .and_then(|sequence| sequence.child("SequenceEntries"))
+        .map(|entries| {
+            entries
+                .children("SequenceEntry")
+                .filter_map(|entry| entry.value("Pattern")?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    // Only sequencer tracks have notes, the group, send and master tracks after them do not.
+    let active: Vec<bool> = song
+        .child("Tracks")
+        .map(|tracks| {
+            tracks
+                .children
+                .iter()
+                .map(|track| track.value("State").is_none_or(|state| state == "Active"))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut notes: Vec<Note> = Vec::new();
+    // The note that is playing and the last instrument of every (track, column).
+    let mut playing: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut last_instrument: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut unmapped = 0;
+    let mut start = 0.0;
+    let mut lines_played = 0;
+
+    for pattern in entries {
+        let pattern = patterns
+            .get(pattern)
+            .ok_or_else(|| format!("the sequence plays pattern {}, which does not exist", pattern))?;
+        let lines = number(pattern, "NumberOfLines", 64.0) as usize;
+        let tracks = pattern
+            .child("Tracks")
+            .map_or(Vec::new(), |tracks| tracks.children.iter().collect());
+
+        for (track_index, track) in tracks.iter().enumerate() {
+            if !active.get(track_index).copied().unwrap_or(true) {
+                continue;
+            }
+            let Some(track_lines) = track.child("Lines") else {
+                continue;
+            };
+            for line in track_lines.children("Line") {
+                let Some(index) = line.attribute("index").and_then(|index| index.parse::<usize>().ok()) else {
+                    continue;
+                };
+                if index >= lines {
+                    continue;
+                }
+                let Some(columns) = line.child("NoteColumns") else {
+                    continue;
+                };
+                for (column_index, column) in columns.children("NoteColumn").enumerate() {
+                    let key = (track_index, column_index);
+                    let Some(note) = column.value("Note").filter(|note| *note != "---") else {
+                        continue;
+                    };
+                    let delay = hex(column, "Delay").unwrap_or(0) as f64 / 256.0;
+                    let time = start + (index as f64 + delay) * line_length;
+
+                    if let Some(previous) = playing.remove(&key) {
+                        notes[previous].end.get_or_insert(time);
+                    }
+                    let Some(note) = parse_note(note) else {
+                        continue;
+                    };
+                    let Some(instrument) = hex(column, "Instrument")
+                        .map(|instrument| instrument as usize)
+                        .or_else(|| last_instrument.get(&key).copied())
+                    else {
+                        continue;
+                    };
+                    last_instrument.insert(key, instrument);
+
+                    let samples = instruments.get(instrument).map_or(&[][..], Vec::as_slice);
+                    let Some(sample) = samples
+                        .iter()
+                        .position(|sample| (sample.note_start..=sample.note_end).contains(&note))
+                    else {
+                        unmapped += 1;
+                        continue;
+                    };
+
+                    playing.insert(key, notes.len());
+                    notes.push(Note {
+                        instrument,
+                        sample,
+                        note,
+                        time,
+                        end: None,
+                        volume: hex(column, "Volume")
+                            .filter(|volume| *volume <= FULL_COLUMN)
+                            .map(|volume| volume as f32 / FULL_COLUMN as f32),
+                        pan: hex(column, "Panning")
+                            .filter(|pan| *pan <= FULL_COLUMN)
+                            .map(|pan| pan as f32 / (FULL_COLUMN / 2) as f32 - 1.0),
+                        line: lines_played + index as u64 + 1,
+                    });
+                }
+            }
+        }
+
+        start += lines as f64 * line_length;
+        lines_played += lines as u64;
+    }
+
+    if unmapped > 0 {
+        warn!("left out {} notes without a sample in their instrument", unmapped);
+    }
+    debug!("the song is {} lines and {:.3} s long", lines_played, start / 1000.0);
+    Ok(notes)
+}
+
+// Writes the sample of every note at the sample rate of its pitch, as long as the note lasts. Notes with the same
+// sample, pitch and length share their file.
+fn write_notes(
+    path: &Path,
+    notes: &[Note],
+    instruments: &[Vec<Sample>],
+) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let dir = env::temp_dir().join(format!("audio-sample-mixer-{}-notes", stem));
+    fs::create_dir_all(&dir)?;
+
+    let mut decoded: HashMap<(usize, usize), Option<Decoded>> = HashMap::new();
+    let mut written = HashSet::new();
+    let mut infos = Vec::with_capacity(notes.len());
+    for note in notes {
+        let sample = &instruments[note.instrument][note.sample];
+        let audio = decoded
+            .entry((note.instrument, note.sample))
+            .or_insert_with(|| decode(sample.path.as_deref(), note.instrument, note.sample));
+        let Some(audio) = audio else {
+            continue;
+        };
+
+        let semitones = note.note as f64 + sample.transpose;
+        let rate = (audio.sample_rate as f64 * 2f64.powf(semitones / 12.0))
+            .round()
+            .max(1.0) as u32;
+        let frames = match note.end {
+            Some(end) => (((end - note.time) * rate as f64 / 1000.0).ceil() as usize).min(audio.data.len() / 2),
+            None => audio.data.len() / 2,
+        };
+        if frames == 0 {
+            continue;
+        }
+
+        let file = dir.join(format!(
+            "{:02}_{:02}_{}_{}.wav",
+            note.instrument, note.sample, rate, frames
+        ));
+        if written.insert(file.clone()) {
+            write_wav(&audio.data[..frames * 2], 2, &file, rate)?;
+        }
+        let volume = note.volume.unwrap_or(1.0) * sample.volume;
+        if volume <= 0.0 {
+            continue;
+        }
+        infos.push(AudioSampleInfo {
+            time: note.time,
+            volume: volume.min(1.0),
+            pan: note.pan.unwrap_or(sample.pan).clamp(-1.0, 1.0),
+            name: file.to_string_lossy().into_owned(),
+            line: note.line,
+            lane: None,
+            hold: None,
+            pitch: None,
+            pan_mode: None,
+            tags: Vec::new(),
+        });
+    }
+
+    infos.sort_by(|a, b| a.time.total_cmp(&b.time));
+    info!(
+        "read {} notes of {}, their samples are extracted to {}",
+        infos.len(),
+        path.display(),
+        dir.display()
+    );
+    Ok(infos)
+}
+
+fn decode(path: Option<&Path>, instrument: usize, sample: usize) -> Option<Decoded> {
+    let Some(path) = path else {
+        warn!(
+            "sample {:02} of instrument {:02} is not in the archive",
+            sample, instrument
+        );
+        return None;
+    };
+    let decoded = probe_audio(path, None).and_then(|audio| {
+        let data = read_audio(
+            path,
+            audio.sample_rate,
+            ConverterType::SincBestQuality,
+            UpmixLaw::Duplicate,
+            None,
+            false,
+            false,
+        )?;
+        Ok(Decoded {
+            data,
+            sample_rate: audio.sample_rate,
+        })
+    });
+    match decoded {
+        Ok(decoded) => Some(decoded),
+        Err(err) => {
+            warn!("could not decode {}: {}, its notes are left out", path.display(), err);
+            None
+        }
+    }
+}
+
+// C-4, C#4, and OFF as None.
+fn parse_note(note: &str) -> Option<i32> {
+    if note.len() != 3 {
+        return None;
+    }
+    let name = NOTE_NAMES.iter().position(|name| note.starts_with(name))? as i32;
+    let octave = note[2..].parse::<i32>().ok()?;
+    Some(octave * 12 + name)
+}
+
+fn hex(element: &Element, name: &str) -> Option<u32> {
+    u32::from_str_radix(element.value(name)?, 16).ok()
+}
+
+fn number(element: &Element, name: &str, default: f64) -> f64 {
+    element
+        .value(name)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,145 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::events::AudioSampleInfo;
+use crate::summary::Summary;
+
+// Writes the summary of a render as plain text for reading, see write_summary for the JSON version.
+pub fn write_report(path: &Path, summary: &Summary) -> Result<(), Box<dyn Error>> {
+    fs::write(path, format_report(summary))?;
+    Ok(())
+}
+
+// Writes an Audacity label track with a point label per event, `start` is the time of the first sample of the mix
+// in ms and `duration` its length in s. Events outside of the mix are left out.
+pub fn write_labels(path: &Path, events: &[AudioSampleInfo], start: f64, duration: f64) -> Result<(), Box<dyn Error>> {
+    let mut text = String::new();
+    for event in events {
+        let time = (event.time - start) / 1000.0;
+        if (0.0..=duration).contains(&time) {
+            let _ = writeln!(text, "{:.6}\t{:.6}\t{}", time, time, event.name);
+        }
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+// Writes a cue sheet of the tracks of a split mix, one file with one track each, titled if the track has a title.
+pub fn write_cue(path: &Path, title: &str, tracks: &[(String, Option<String>)]) -> Result<(), Box<dyn Error>> {
+    // Cue sheets cannot escape quotes.
+    let quote = |text: &str| format!("\"{}\"", text.replace('"', "'"));
+    let mut text = String::new();
+    let _ = writeln!(text, "TITLE {}", quote(title));
+    for (i, (file, track_title)) in tracks.iter().enumerate() {
+        let _ = writeln!(text, "FILE {} WAVE", quote(file));
+        let _ = writeln!(text, "  TRACK {:02} AUDIO", i + 1);
+        if let Some(track_title) = track_title {
+            let _ = writeln!(text, "    TITLE {}", quote(track_title));
+        }
+        let _ = writeln!(text, "    INDEX 01 00:00:00");
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+fn format_report(summary: &Summary) -> String {
+    let mut text = String::new();
+
+    // Writing to a String cannot fail.
+    let _ = writeln!(text, "Input: {}", summary.input);
+    let _ = writeln!(text, "Output: {}", summary.output);
+    let _ = writeln!(
+        text,
+        "Duration: {} ({:.3} s at {} Hz)",
+        format_time(summary.duration as f64 * 1000.0),
+        summary.duration,
+        summary.sample_rate
+    );
+    let _ = writeln!(
+        text,
+        "Events: {} in the CSV file, {} mixed, {} skipped",
+        summary.events,
+        summary.mixed_events,
+        summary.skipped_events.len()
+    );
+    let _ = writeln!(
+        text,
+        "Peak before clamping: {:.3} ({:+.1} dBFS), {} clipped samples",
+        summary.peak, summary.peak_dbfs, summary.clipped_samples
+    );
+    let _ = writeln!(text, "Fingerprint: {}", summary.fingerprint);
+    if summary.gain_db != 0.0 {
+        let _ = writeln!(text, "Auto gain: {:+.1} dB", summary.gain_db);
+    }
+    if !summary.missing_samples.is_empty() {
+        let _ = writeln!(text, "Missing samples: {}", summary.missing_samples.join(", "));
+    }
+    if !summary.undecodable_samples.is_empty() {
+        let _ = writeln!(
+            text,
+            "Samples that could not be decoded: {}",
+            summary.undecodable_samples.join(", ")
+        );
+    }
+
+    if !summary.skipped_events.is_empty() {
+        let _ = writeln!(text, "\nSkipped events:");
+        for event in &summary.skipped_events {
+            let _ = writeln!(
+                text,
+                "  {:>10}  {}  ({})",
+                format_time(event.time),
+                event.name,
+                event.reason
+            );
+        }
+    }
+
+    if !summary.clipping.is_empty() {
+        let worst = summary
+            .clipping
+            .iter()
+            .map(|range| range.overshoot_db)
+            .fold(0.0, f32::max);
+        let _ = writeln!(
+            text,
+            "\nClipping: {} places, up to {:+.1} dB over full scale:",
+            summary.clipping.len(),
+            worst
+        );
+        for range in &summary.clipping {
+            let _ = writeln!(
+                text,
+                "  {:>10} - {:<10}  {} clipped samples, {:+.1} dB",
+                format_time(range.start),
+                format_time(range.end),
+                range.clipped_samples,
+                range.overshoot_db
+            );
+        }
+    }
+
+    if !summary.loudest.is_empty() {
+        let _ = writeln!(text, "\nLoudest moments:");
+        for moment in &summary.loudest {
+            let _ = writeln!(
+                text,
+                "  {:>10}  {:.3} ({:+.1} dBFS)",
+                format_time(moment.time),
+                moment.peak,
+                moment.peak_dbfs
+            );
+        }
+    }
+
+    text
+}
+
+// 83456.7 ms becomes 1:23.457, -250 ms -0:00.250
+pub fn format_time(ms: f64) -> String {
+    let sign = if ms.round() < 0.0 { "-" } else { "" };
+    let ms = ms.abs().round() as u64;
+    format!("{}{}:{:02}.{:03}", sign, ms / 60000, ms / 1000 % 60, ms % 1000)
+}
@@ -0,0 +1,165 @@
+// Resampling with libsamplerate or, in builds without the libsamplerate feature, with converters of the same names
+// written in Rust.
+#[cfg(feature = "libsamplerate")]
+pub use samplerate::{convert, ConverterType};
+
+#[cfg(not(feature = "libsamplerate"))]
+pub use self::sinc::{convert, ConverterType};
+
+#[cfg(not(feature = "libsamplerate"))]
+mod sinc {
+    use std::error::Error;
+    use std::f64::consts::PI;
+    use std::fmt;
+
+    // Values of the windowed sinc between two of its zero crossings, read with linear interpolation.
+    const RESOLUTION: usize = 256;
+    // Of the Kaiser window, which leaves the aliasing below about -90 dB.
+    const KAISER_BETA: f64 = 9.0;
+
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+    pub enum ConverterType {
+        SincBestQuality,
+        SincMediumQuality,
+        SincFastest,
+        ZeroOrderHold,
+        Linear,
+    }
+
+    #[derive(Debug)]
+    pub struct ResampleError(&'static str);
+
+    impl fmt::Display for ResampleError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl Error for ResampleError {}
+
+    // Like convert of libsamplerate: interleaved frames of `channels` samples, as many frames out as the rates ask
+    // for, rounded up.
+    pub fn convert(
+        from_rate: u32,
+        to_rate: u32,
+        channels: usize,
+        converter_type: ConverterType,
+        input: &[f32],
+    ) -> Result<Vec<f32>, ResampleError> {
+        if from_rate == 0 || to_rate == 0 {
+            return Err(ResampleError("sample rates have to be above 0"));
+        }
+        if channels == 0 || !input.len().is_multiple_of(channels) {
+            return Err(ResampleError("the data is not made of whole frames"));
+        }
+        let frames = input.len() / channels;
+        let output_frames = (frames as u64 * to_rate as u64).div_ceil(from_rate as u64) as usize;
+        let sample = |frame: isize, channel: usize| {
+            if frame < 0 || frame as usize >= frames {
+                0.0
+            } else {
+                input[frame as usize * channels + channel] as f64
+            }
+        };
+
+        let zero_crossings = match converter_type {
+            ConverterType::SincBestQuality => 32,
+            ConverterType::SincMediumQuality => 16,
+            ConverterType::SincFastest => 8,
+            ConverterType::ZeroOrderHold | ConverterType::Linear => 0,
+        };
+        let sinc = Sinc::new(zero_crossings, from_rate, to_rate);
+        let mut weights = Vec::new();
+        let mut output = Vec::with_capacity(output_frames * channels);
+        for frame in 0..output_frames {
+            // The position in the input, exact for every rate.
+            let position = frame as u64 * from_rate as u64;
+            let index = (position / to_rate as u64) as isize;
+            let fraction = (position % to_rate as u64) as f64 / to_rate as f64;
+            match converter_type {
+                ConverterType::ZeroOrderHold => {
+                    output.extend((0..channels).map(|channel| sample(index, channel) as f32))
+                }
+                ConverterType::Linear => output.extend((0..channels).map(|channel| {
+                    (sample(index, channel) * (1.0 - fraction) + sample(index + 1, channel) * fraction) as f32
+                })),
+                _ => {
+                    let first = sinc.weights(fraction, &mut weights);
+                    output.extend((0..channels).map(|channel| {
+                        let sum: f64 = weights
+                            .iter()
+                            .enumerate()
+                            .map(|(i, weight)| sample(index + first + i as isize, channel) * weight)
+                            .sum();
+                        sum as f32
+                    }));
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    // A sinc low pass at the lower of both Nyquist frequencies, cut off after a number of zero crossings on each side.
+    struct Sinc {
+        zero_crossings: usize,
+        // Of the input Nyquist frequency.
+        cutoff: f64,
+        table: Vec<f64>,
+    }
+
+    impl Sinc {
+        fn new(zero_crossings: usize, from_rate: u32, to_rate: u32) -> Sinc {
+            let table = (0..=zero_crossings * RESOLUTION + 1)
+                .map(|i| {
+                    let x = i as f64 / RESOLUTION as f64;
+                    let sinc = if i == 0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                    sinc * kaiser(x / zero_crossings.max(1) as f64)
+                })
+                .collect();
+            Sinc {
+                zero_crossings,
+                cutoff: (to_rate as f64 / from_rate as f64).min(1.0),
+                table,
+            }
+        }
+
+        // Fills `weights` for the input frames around a position `fraction` after a frame and returns the first of
+        // them relative to that frame.
+        fn weights(&self, fraction: f64, weights: &mut Vec<f64>) -> isize {
+            let reach = self.zero_crossings as f64 / self.cutoff;
+            let first = (fraction - reach).floor() as isize + 1;
+            let last = (fraction + reach).ceil() as isize - 1;
+            weights.clear();
+            weights.extend((first..=last).map(|i| {
+                let x = (fraction - i as f64).abs() * self.cutoff * RESOLUTION as f64;
+                let j = x as usize;
+                if j >= self.zero_crossings * RESOLUTION {
+                    0.0
+                } else {
+                    let between = x - j as f64;
+                    (self.table[j] * (1.0 - between) + self.table[j + 1] * between) * self.cutoff
+                }
+            }));
+            first
+        }
+    }
+
+    // From 1 in the middle to the edge at 1.
+    fn kaiser(x: f64) -> f64 {
+        if x >= 1.0 {
+            0.0
+        } else {
+            bessel_i0(KAISER_BETA * (1.0 - x * x).sqrt()) / bessel_i0(KAISER_BETA)
+        }
+    }
+
+    fn bessel_i0(x: f64) -> f64 {
+        let mut sum = 1.0;
+        let mut term = 1.0;
+        for k in 1..32 {
+            term *= (x / 2.0 / k as f64).powi(2);
+            sum += term;
+        }
+        sum
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use log::{debug, warn};
+
+use crate::download::{default_cache_dir, fetch, is_url};
+
+// Extensions tried in this order when --any-extension is set.
+const EXTENSIONS: [&str; 4] = ["wav", "ogg", "mp3", "flac"];
+
+// The path of a sample name in a CSV file. Charts made on Windows separate directories with '\\', which is an ordinary
+// character in file names elsewhere.
+pub fn sample_path(name: &str) -> PathBuf {
+    if cfg!(windows) {
+        long_path(PathBuf::from(name))
+    } else {
+        PathBuf::from(name.replace('\\', "/"))
+    }
+}
+
+// Windows only opens absolute paths longer than MAX_PATH with the \\?\ prefix, which also turns off the handling of /
+// and .., so the path is made absolute by Windows first. Deeply nested keysound folders with Japanese names get there.
+#[cfg(windows)]
+fn long_path(path: PathBuf) -> PathBuf {
+    const MAX_PATH: usize = 260;
+    let verbatim =
+        matches!(path.components().next(), Some(std::path::Component::Prefix(prefix)) if prefix.kind().is_verbatim());
+    if path.as_os_str().len() < MAX_PATH || !path.is_absolute() || verbatim {
+        return path;
+    }
+    let Some(absolute) = std::path::absolute(&path)
+        .ok()
+        .and_then(|path| path.to_str().map(str::to_string))
+    else {
+        return path;
+    };
+    match absolute.strip_prefix(r"\\") {
+        Some(share) => PathBuf::from(format!(r"\\?\UNC\{}", share)),
+        None => PathBuf::from(format!(r"\\?\{}", absolute)),
+    }
+}
+
+#[cfg(not(windows))]
+fn long_path(path: PathBuf) -> PathBuf {
+    path
+}
+
+// Sample names can choose the audio track of a file with several of them, song.mkv#2 is the second audio track of
+// song.mkv. Without a number after the '#' it belongs to the file name.
+pub fn split_track(name: &str) -> (&str, Option<usize>) {
+    if let Some((file, number)) = name.rsplit_once('#') {
+        if !file.is_empty() && !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit()) {
+            if let Ok(number) = number.parse() {
+                return (file, Some(number));
+            }
+        }
+    }
+    (name, None)
+}
+
+#[derive(Clone)]
+pub struct SampleResolver {
+    pub samples_dirs: Vec<PathBuf>,
+    pub ignore_case: bool,
+    pub any_extension: bool,
+    pub recursive: bool,
+    // Whether names are also looked up relative to the current directory, serve only uses the samples directories.
+    pub in_current_dir: bool,
+    // Whether URLs are downloaded, serve only does it with --allow-urls.
+    pub downloads: bool,
+    // Where downloaded samples are kept, the default cache directory without one.
+    pub cache_dir: Option<PathBuf>,
+    // Every file below the samples directories, only listed once the first recursive lookup needs it.
+    index: OnceLock<Vec<PathBuf>>,
+}
+
+// Where samples are found, without the cache of downloads, which is named by checksums, and the index.
+impl fmt::Debug for SampleResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SampleResolver")
+            .field("samples_dirs", &self.samples_dirs)
+            .field("ignore_case", &self.ignore_case)
+            .field("any_extension", &self.any_extension)
+            .field("recursive", &self.recursive)
+            .field("in_current_dir", &self.in_current_dir)
+            .field("downloads", &self.downloads)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SampleResolver {
+    pub fn new(samples_dirs: Vec<PathBuf>) -> SampleResolver {
+        SampleResolver {
+            samples_dirs,
+            ignore_case: false,
+            any_extension: false,
+            recursive: false,
+            in_current_dir: true,
+            downloads: true,
+            cache_dir: None,
+            index: OnceLock::new(),
+        }
+    }
+
+    // A copy that searches other directories with the same fallbacks.
+    pub fn with_samples_dirs(&self, samples_dirs: Vec<PathBuf>) -> SampleResolver {
+        SampleResolver {
+            samples_dirs,
+            ignore_case: self.ignore_case,
+            any_extension: self.any_extension,
+            recursive: self.recursive,
+            in_current_dir: self.in_current_dir,
+            downloads: self.downloads,
+            cache_dir: self.cache_dir.clone(),
+            index: OnceLock::new(),
+        }
+    }
+
+    // Sample names are looked up relative to the current directory first, then in each samples directory in order.
+    // Only if none of them has an exact match, the fallbacks are tried in the same order and after that the
+    // subdirectories are searched with --recursive. URLs are downloaded into the cache instead.
+    pub fn resolve(&self, name: &str) -> PathBuf {
+        let name = split_track(name).0;
+        if is_url(name) {
+            if !self.downloads {
+                return PathBuf::from(name);
+            }
+            return self.download(name);
+        }
+        let path = &sample_path(name);
+
+        if path.is_absolute() && path.is_file() {
+            return path.to_path_buf();
+        }
+
+        let candidates: Vec<PathBuf> = if path.is_absolute() {
+            vec![path.to_path_buf()]
+        } else {
+            let mut candidates = Vec::new();
+            if self.in_current_dir {
+                candidates.push(path.to_path_buf());
+            }
+            candidates.extend(self.samples_dirs.iter().map(|dir| long_path(dir.join(path))));
+            candidates
+        };
+
+        if let Some(found) = candidates.iter().find(|candidate| candidate.is_file()) {
+            return found.clone();
+        }
+
+        if self.ignore_case || self.any_extension {
+            for candidate in &candidates {
+                if let Some(found) = self.find_similar(candidate) {
+                    debug!("{} resolved to {}", name, found.display());
+                    return found;
+                }
+            }
+        }
+
+        if self.recursive && !path.is_absolute() {
+            if let Some(found) = self.find_recursive(name) {
+                debug!("{} resolved to {}", name, found.display());
+                return found;
+            }
+        }
+
+        // A sample that is not found must not be opened relative to the current directory either.
+        if self.in_current_dir {
+            path.to_path_buf()
+        } else {
+            candidates.into_iter().next().unwrap_or_default()
+        }
+    }
+
+    // A failed download is logged and leaves the sample missing.
+    fn download(&self, url: &str) -> PathBuf {
+        let Some(cache_dir) = self.cache_dir.clone().or_else(default_cache_dir) else {
+            warn!(
+                "could not download {}: there is no cache directory, set one with --cache-dir",
+                url
+            );
+            return PathBuf::from(url);
+        };
+        match fetch(url, &cache_dir) {
+            Ok(path) => path,
+            Err(err) => {
+                warn!("could not download {}: {}", url, err);
+                PathBuf::from(url)
+            }
+        }
+    }
+
+    // Looks for a file next to `path` that only differs in case or extension, depending on the enabled fallbacks.
+    fn find_similar(&self, path: &Path) -> Option<PathBuf> {
+        let file_name = path.file_name()?.to_str()?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let mut entries: Vec<String> = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        entries.sort();
+
+        let (_, entry) = entries
+            .iter()
+            .filter_map(|entry| self.rank(entry, file_name).map(|rank| (rank, entry)))
+            .min_by_key(|(rank, _)| *rank)?;
+
+        Some(path.with_file_name(entry))
+    }
+
+    // Searches the subdirectories of the samples directories (or the current directory without any) for the file
+    // name of `name`. The first match wins, further equally good matches are reported.
+    fn find_recursive(&self, name: &str) -> Option<PathBuf> {
+        let path = sample_path(name);
+        let file_name = path.file_name()?.to_str()?;
+
+        let index = self.index.get_or_init(|| {
+            if self.samples_dirs.is_empty() && self.in_current_dir {
+                list_files(&[PathBuf::from(".")])
+            } else {
+                list_files(&self.samples_dirs)
+            }
+        });
+
+        let matches: Vec<(usize, &PathBuf)> = index
+            .iter()
+            .filter_map(|file| {
+                let entry = file.file_name()?.to_str()?;
+                self.rank(entry, file_name).map(|rank| (rank, file))
+            })
+            .collect();
+
+        let best = matches.iter().map(|(rank, _)| *rank).min()?;
+        let mut best_matches = matches.iter().filter(|(rank, _)| *rank == best).map(|(_, file)| *file);
+
+        let found = best_matches.next()?.clone();
+        let others: Vec<String> = best_matches.map(|file| file.display().to_string()).collect();
+        if !others.is_empty() {
+            warn!(
+                "{} is ambiguous, using {} instead of {}",
+                name,
+                found.display(),
+                others.join(", ")
+            );
+        }
+
+        Some(found)
+    }
+
+    // How well a file name matches the wanted one with the enabled fallbacks, lower is better.
+    fn rank(&self, entry: &str, file_name: &str) -> Option<usize> {
+        let same = |a: &str, b: &str| {
+            if self.ignore_case {
+                a.to_lowercase() == b.to_lowercase()
+            } else {
+                a == b
+            }
+        };
+
+        if entry == file_name {
+            return Some(0);
+        }
+        if self.ignore_case && same(entry, file_name) {
+            return Some(1);
+        }
+
+        if self.any_extension {
+            let stem = Path::new(file_name).file_stem()?.to_str()?;
+            for (i, extension) in EXTENSIONS.iter().enumerate() {
+                if same(entry, &format!("{}.{}", stem, extension)) {
+                    return Some(2 + i);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+// Lists all files below `dirs` breadth first in sorted order, so shallower files come first.
+pub fn list_files(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut queue: VecDeque<PathBuf> = dirs.iter().cloned().collect();
+
+    while let Some(dir) = queue.pop_front() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        let mut entries: Vec<_> = entries.filter_map(|entry| entry.ok()).collect();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            // Symlinked directories are not followed to avoid loops.
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => queue.push_back(entry.path()),
+                Ok(_) if entry.path().is_file() => files.push(entry.path()),
+                _ => {}
+            }
+        }
+    }
+
+    files
+}
@@ -0,0 +1,173 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::events::AudioSampleInfo;
+
+// Hands the events of a file to the `transform` function of a Rhai script and reads back the array it returns.
+// Every event is a map with time (ms), volume, pan, file, line and lane, hold (ms), pitch (semitones), pan_mode
+// (balance or true) and tags (an array of strings) if it has them, new events can
+// be made with `event(time, volume, pan, file)` and `db(-12.0)` converts a level to a volume factor:
+//
+//     fn transform(events) {
+//         let ghosts = [];
+//         for e in events {
+//             if e.file == "snare.wav" { ghosts.push(event(e.time + 15.0, e.volume * db(-12.0), e.pan, e.file)); }
+//         }
+//         events + ghosts
+//     }
+#[cfg(feature = "scripting")]
+pub fn transform(script: &Path, infos: Vec<AudioSampleInfo>) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    use log::info;
+    use rhai::{Array, Dynamic, Engine, Map, Scope, FLOAT};
+
+    use crate::config::parse_pan_mode;
+    use crate::events::{check_pan, check_pitch, check_volume};
+    use crate::mix::PanMode;
+
+    fn to_map(info: &AudioSampleInfo) -> Map {
+        let mut map = Map::new();
+        map.insert("time".into(), Dynamic::from_float(info.time as FLOAT));
+        map.insert("volume".into(), Dynamic::from_float(info.volume as FLOAT));
+        map.insert("pan".into(), Dynamic::from_float(info.pan as FLOAT));
+        map.insert("file".into(), Dynamic::from(info.name.clone()));
+        map.insert("line".into(), Dynamic::from_int(info.line as i64));
+        if let Some(lane) = &info.lane {
+            map.insert("lane".into(), Dynamic::from(lane.clone()));
+        }
+        if let Some(hold) = info.hold {
+            map.insert("hold".into(), Dynamic::from_float(hold as FLOAT));
+        }
+        if let Some(pitch) = info.pitch {
+            map.insert("pitch".into(), Dynamic::from_float(pitch as FLOAT));
+        }
+        if let Some(pan_mode) = info.pan_mode {
+            let name = match pan_mode {
+                PanMode::Balance => "balance",
+                PanMode::TruePan => "true",
+            };
+            map.insert("pan_mode".into(), Dynamic::from(name.to_string()));
+        }
+        if !info.tags.is_empty() {
+            let tags: Array = info.tags.iter().cloned().map(Dynamic::from).collect();
+            map.insert("tags".into(), Dynamic::from_array(tags));
+        }
+        map
+    }
+
+    let mut engine = Engine::new();
+    engine.register_fn("db", |db: FLOAT| (10.0 as FLOAT).powf(db / 20.0));
+    engine.register_fn("event", |time: FLOAT, volume: FLOAT, pan: FLOAT, file: &str| {
+        to_map(&AudioSampleInfo {
+            time,
+            volume: volume as f32,
+            pan: pan as f32,
+            name: file.to_string(),
+            line: 0,
+            lane: None,
+            hold: None,
+            pitch: None,
+            pan_mode: None,
+            tags: Vec::new(),
+        })
+    });
+    let ast = engine
+        .compile_file(script.to_path_buf())
+        .map_err(|err| format!("{}: {}", script.display(), err))?;
+
+    let count = infos.len();
+    let events: Array = infos.into_iter().map(|info| Dynamic::from_map(to_map(&info))).collect();
+    let returned = engine
+        .call_fn::<Array>(&mut Scope::new(), &ast, "transform", (events,))
+        .map_err(|err| format!("{}: {}", script.display(), err))?;
+
+    let mut transformed = Vec::with_capacity(returned.len());
+    for (i, event) in returned.into_iter().enumerate() {
+        let problem = |message: String| format!("{}: event {} of transform: {}", script.display(), i + 1, message);
+        let map = event
+            .try_cast::<Map>()
+            .ok_or_else(|| problem("not a map".to_string()))?;
+        // Whole numbers are accepted for all numeric fields, `e.time = 500` is a common way to write them.
+        let number = |key: &str| -> Result<f64, String> {
+            let value = map.get(key).ok_or_else(|| problem(format!("{} is missing", key)))?;
+            value
+                .as_float()
+                .or_else(|_| value.as_int().map(|value| value as FLOAT))
+                .map_err(|_| problem(format!("{} is a {} instead of a number", key, value.type_name())))
+        };
+
+        let time = number("time")?;
+        if !time.is_finite() {
+            return Err(problem(format!("time {} is not a number", time)).into());
+        }
+        let volume = check_volume(number("volume")? as f32).map_err(problem)?;
+        let pan = check_pan(number("pan")? as f32).map_err(problem)?;
+        let name = map
+            .get("file")
+            .and_then(|file| file.clone().into_string().ok())
+            .filter(|file| !file.is_empty())
+            .ok_or_else(|| problem("file is missing or not a string".to_string()))?;
+        let line = map.get("line").and_then(|line| line.as_int().ok()).unwrap_or(0).max(0) as u64;
+        let lane = map.get("lane").and_then(|lane| lane.clone().into_string().ok());
+        let hold = match map.get("hold") {
+            Some(hold) if !hold.is_unit() => match number("hold")? {
+                hold if hold > 0.0 && hold.is_finite() => Some(hold),
+                hold => return Err(problem(format!("hold {} is not a positive number", hold)).into()),
+            },
+            _ => None,
+        };
+        let pitch = match map.get("pitch") {
+            Some(pitch) if !pitch.is_unit() => Some(check_pitch(number("pitch")? as f32).map_err(problem)?),
+            _ => None,
+        };
+        let pan_mode = match map.get("pan_mode") {
+            Some(pan_mode) if !pan_mode.is_unit() => {
+                let name = pan_mode
+                    .clone()
+                    .into_string()
+                    .map_err(|_| problem("pan_mode is not a string".to_string()))?;
+                Some(parse_pan_mode(&name).map_err(problem)?)
+            }
+            _ => None,
+        };
+
+        let tags = match map.get("tags") {
+            Some(tags) if !tags.is_unit() => tags
+                .clone()
+                .into_array()
+                .ok()
+                .and_then(|tags| tags.into_iter().map(|tag| tag.into_string().ok()).collect())
+                .ok_or_else(|| problem("tags is not an array of strings".to_string()))?,
+            _ => Vec::new(),
+        };
+
+        transformed.push(AudioSampleInfo {
+            time,
+            volume,
+            pan,
+            name,
+            line,
+            lane,
+            hold,
+            pitch,
+            pan_mode,
+            tags,
+        });
+    }
+
+    info!(
+        "{} turned {} events into {}",
+        script.display(),
+        count,
+        transformed.len()
+    );
+    Ok(transformed)
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn transform(script: &Path, _infos: Vec<AudioSampleInfo>) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    Err(format!(
+        "{} cannot be run, this build does not include the scripting feature",
+        script.display()
+    )
+    .into())
+}
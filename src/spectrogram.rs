@@ -0,0 +1,185 @@
+use std::error::Error;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use log::info;
+
+const WIDTH: usize = 1200;
+const HEIGHT: usize = 512;
+const FFT_SIZE: usize = 4096;
+// Lowest frequency at the bottom of the image, the top is half the sample rate.
+const MIN_FREQUENCY: f32 = 20.0;
+// Levels in dBFS from black to white.
+const FLOOR_DB: f32 = -120.0;
+
+// Writes a spectrogram of the mix as a PNG image: time from left to right, frequency on a log scale from bottom to
+// top and the level as colour. Aliasing of resampled samples shows as mirrored lines near the top, clipping as
+// harmonics across the whole height.
+pub fn write_spectrogram(path: &Path, data: &[f32], sample_rate: u32) -> Result<(), Box<dyn Error>> {
+    let frames = data.len() / 2;
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+        .collect();
+    // A full scale sine has a peak of half the sum of the window in its bin.
+    let full_scale = window.iter().sum::<f32>() / 2.0;
+
+    let max_frequency = sample_rate as f32 / 2.0;
+    let bin_of = |row: usize| {
+        let frequency = MIN_FREQUENCY * (max_frequency / MIN_FREQUENCY).powf(row as f32 / HEIGHT as f32);
+        ((frequency / sample_rate as f32 * FFT_SIZE as f32) as usize).min(FFT_SIZE / 2)
+    };
+
+    let mut pixels = vec![0_u8; WIDTH * HEIGHT * 3];
+    for column in 0..WIDTH {
+        let center = column * frames / WIDTH;
+        let mut real: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| {
+                let frame = (center + i).checked_sub(FFT_SIZE / 2);
+                let value = frame
+                    .and_then(|frame| data.get(frame * 2..frame * 2 + 2))
+                    .map_or(0.0, |frame| (frame[0] + frame[1]) / 2.0);
+                value * window[i]
+            })
+            .collect();
+        let mut imaginary = vec![0.0; FFT_SIZE];
+        fft(&mut real, &mut imaginary);
+
+        for row in 0..HEIGHT {
+            // Every row shows the loudest bin between its frequency and the next, at least one bin.
+            let (first, last) = (bin_of(row), bin_of(row + 1).max(bin_of(row) + 1));
+            let magnitude = (first..last.min(FFT_SIZE / 2 + 1))
+                .map(|bin| (real[bin].powi(2) + imaginary[bin].powi(2)).sqrt())
+                .fold(0.0, f32::max);
+            let level = (20.0 * (magnitude / full_scale).max(1e-12).log10() - FLOOR_DB) / -FLOOR_DB;
+
+            let pixel = ((HEIGHT - 1 - row) * WIDTH + column) * 3;
+            pixels[pixel..pixel + 3].copy_from_slice(&colour(level.clamp(0.0, 1.0)));
+        }
+    }
+
+    fs::write(path, encode_png(WIDTH, HEIGHT, &pixels))?;
+    info!("wrote the spectrogram to {}", path.display());
+    Ok(())
+}
+
+// Black, blue, magenta, orange, yellow and white from quiet to loud.
+fn colour(level: f32) -> [u8; 3] {
+    const STOPS: [[f32; 3]; 6] = [
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 0.6],
+        [0.6, 0.0, 0.6],
+        [1.0, 0.4, 0.0],
+        [1.0, 0.9, 0.0],
+        [1.0, 1.0, 1.0],
+    ];
+    let position = level * (STOPS.len() - 1) as f32;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let fraction = position - index as f32;
+    let mut rgb = [0; 3];
+    for (channel, value) in rgb.iter_mut().enumerate() {
+        let (from, to) = (STOPS[index][channel], STOPS[index + 1][channel]);
+        *value = ((from + (to - from) * fraction) * 255.0).round() as u8;
+    }
+    rgb
+}
+
+// In-place radix-2 FFT, the length has to be a power of two.
+fn fft(real: &mut [f32], imaginary: &mut [f32]) {
+    let n = real.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= n {
+        let angle = -2.0 * PI / length as f32;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + length / 2);
+                let (br, bi) = (real[b] * cos - imaginary[b] * sin, real[b] * sin + imaginary[b] * cos);
+                real[b] = real[a] - br;
+                imaginary[b] = imaginary[a] - bi;
+                real[a] += br;
+                imaginary[a] += bi;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+// An 8 bit RGB PNG with the image data in uncompressed deflate blocks, which every decoder reads.
+fn encode_png(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for line in pixels.chunks(width * 3) {
+        // Filter type none.
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = raw.chunks(0xffff).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        zlib.push(u8::from(i + 1 == blocks.len()));
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filtering and no interlacing.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,135 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::events::Marker;
+
+#[derive(Serialize)]
+pub struct Summary {
+    pub input: String,
+    pub output: String,
+    pub sample_rate: u32,
+    pub duration: f32,
+    pub events: usize,
+    pub peak: f32,
+    pub peak_dbfs: f32,
+    pub clipped_samples: usize,
+    // Gain in dB applied by --auto-gain, 0 or negative.
+    pub gain_db: f32,
+    // SHA-256 of the mix as 16 bit samples, see export::fingerprint.
+    pub fingerprint: String,
+    pub triggers: BTreeMap<String, usize>,
+    pub missing_samples: Vec<String>,
+    pub undecodable_samples: Vec<String>,
+    pub mixed_events: usize,
+    pub skipped_events: Vec<SkippedEvent>,
+    pub loudest: Vec<LoudMoment>,
+    pub clipping: Vec<ClipRange>,
+    // Marker rows of the CSV file that are within the mix, times in ms like in the CSV file.
+    pub markers: Vec<Marker>,
+    // Files of --split, one for every track.
+    pub tracks: Vec<String>,
+    pub preview: Option<PreviewClip>,
+    pub timings: Timings,
+}
+
+// The clip of --preview, from `start` in ms like the times of the CSV file, `duration` in s.
+#[derive(Serialize)]
+pub struct PreviewClip {
+    pub file: String,
+    pub start: f64,
+    pub duration: f32,
+}
+
+// An event of the CSV file that was not mixed. Times are in ms, like in the CSV file.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SkippedEvent {
+    pub time: f64,
+    pub name: String,
+    pub reason: Cow<'static, str>,
+}
+
+// The peak of one of the loudest parts of the mix, before clamping.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LoudMoment {
+    pub time: f64,
+    pub peak: f32,
+    pub peak_dbfs: f32,
+}
+
+// A part of the mix that went over full scale and was clamped, from `start` to `end` in ms. The overshoot is the
+// peak in dB above full scale.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ClipRange {
+    pub start: f64,
+    pub end: f64,
+    pub clipped_samples: usize,
+    pub peak: f32,
+    pub overshoot_db: f32,
+}
+
+// An event as it was mixed, after humanizing, --from and --to, deduplication and the policies for missing samples.
+// The frame counts from the first frame of the mix and is negative for events that started before it. The gains are
+// the factors of the left and right channel after the pan law and --auto-gain, a true pan also plays one channel on the
+// other side.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct MixedEvent {
+    pub frame: i64,
+    pub time: f64,
+    pub name: String,
+    pub file: String,
+    pub volume: f32,
+    pub pan: f32,
+    pub gain_left: f32,
+    pub gain_right: f32,
+    pub gain_left_to_right: f32,
+    pub gain_right_to_left: f32,
+    pub frames: usize,
+    pub duration: f64,
+}
+
+// The document of --export-events, times and durations in seconds.
+#[derive(Serialize)]
+pub struct EventExport<'a> {
+    pub input: &'a str,
+    pub output: &'a str,
+    pub sample_rate: u32,
+    pub start: f64,
+    pub duration: f32,
+    pub events: &'a [MixedEvent],
+}
+
+// Wall clock seconds spent in each stage of the run.
+#[derive(Serialize)]
+pub struct Timings {
+    pub decode: f32,
+    pub mix: f32,
+    pub encode: f32,
+    pub total: f32,
+}
+
+#[derive(Serialize)]
+pub struct BatchSummary {
+    pub jobs: Vec<Summary>,
+    pub failures: Vec<JobFailure>,
+    pub decoded_samples: usize,
+    pub reused_samples: usize,
+    pub total: f32,
+}
+
+#[derive(Serialize)]
+pub struct JobFailure {
+    pub input: String,
+    pub code: i32,
+    pub message: String,
+}
+
+pub fn write_summary<T: Serialize>(path: &Path, summary: &T) -> Result<(), Box<dyn Error>> {
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(file, summary)?;
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::f64::consts::TAU;
+
+use crate::cli::parse_time;
+use crate::random::Rng;
+
+// Tones and noise start and end with a fade this long, so they do not click.
+const FADE_MS: f64 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Waveform {
+    Sine,
+    Square,
+    Saw,
+    Triangle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Sound {
+    Tone(Waveform, f64),
+    WhiteNoise,
+    PinkNoise,
+    Silence,
+}
+
+// A sample that is generated instead of decoded, from a name like sine:440:200ms.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Synth {
+    sound: Sound,
+    // In ms.
+    length: f64,
+}
+
+// Recognizes the names of generated samples: `sine|square|saw|triangle:<hz>:<time>`, `noise:white|pink:<time>` and
+// `silence:<time>`, times as in --from. Returns None for any other name, which is a file.
+pub fn parse_synth(name: &str) -> Option<Result<Synth, String>> {
+    let fields: Vec<&str> = name.split(':').collect();
+    let waveform = match fields[0] {
+        "sine" => Some(Waveform::Sine),
+        "square" => Some(Waveform::Square),
+        "saw" => Some(Waveform::Saw),
+        "triangle" => Some(Waveform::Triangle),
+        "noise" | "silence" => None,
+        _ => return None,
+    };
+
+    let invalid = |expected: &str| format!("invalid generated sample '{}', expected {}", name, expected);
+    let synth = match (waveform, &fields[..]) {
+        (Some(waveform), [_, frequency, length @ ..]) if !length.is_empty() => frequency
+            .parse()
+            .ok()
+            .filter(|frequency: &f64| frequency.is_finite() && *frequency > 0.0)
+            .ok_or_else(|| invalid(&format!("{}:<frequency in Hz>:<length>", fields[0])))
+            .and_then(|frequency| synth(Sound::Tone(waveform, frequency), &length.join(":"))),
+        (Some(_), _) => Err(invalid(&format!("{}:<frequency in Hz>:<length>", fields[0]))),
+        (None, ["noise", "white", length @ ..]) if !length.is_empty() => synth(Sound::WhiteNoise, &length.join(":")),
+        (None, ["noise", "pink", length @ ..]) if !length.is_empty() => synth(Sound::PinkNoise, &length.join(":")),
+        (None, ["noise", ..]) => Err(invalid("noise:white:<length> or noise:pink:<length>")),
+        (None, ["silence", length @ ..]) if !length.is_empty() => synth(Sound::Silence, &length.join(":")),
+        (None, _) => Err(invalid("silence:<length>")),
+    };
+    Some(synth)
+}
+
+// The length can be mm:ss and have a colon of its own.
+fn synth(sound: Sound, length: &str) -> Result<Synth, String> {
+    let length = parse_time(length)?;
+    if length <= 0.0 {
+        return Err("a generated sample has to be longer than 0 ms".to_string());
+    }
+    Ok(Synth { sound, length })
+}
+
+impl Synth {
+    // In seconds, like the durations of decoded samples.
+    pub fn duration(&self) -> f32 {
+        (self.length / 1000.0) as f32
+    }
+
+    // Stereo at full scale, the same on both channels. Noise is always the same for the same sample rate, so mixes
+    // with it stay reproducible.
+    pub fn render(&self, sample_rate: u32) -> Vec<f32> {
+        let rate = sample_rate as f64;
+        let frames = (self.length * rate / 1000.0).round() as usize;
+        let mut rng = Rng::new(rate.to_bits());
+        let unit = |rng: &mut Rng| (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0;
+        // Paul Kellet's economy filter, white noise turned into noise that falls by 3 dB per octave.
+        let mut pink = [0.0; 3];
+
+        let fade = ((FADE_MS * rate / 1000.0) as usize).min(frames / 2).max(1);
+        let mut data = Vec::with_capacity(frames * 2);
+        for frame in 0..frames {
+            let phase = (frame as f64 * self.frequency() / rate).fract();
+            let value = match self.sound {
+                Sound::Tone(Waveform::Sine, _) => (TAU * phase).sin(),
+                Sound::Tone(Waveform::Square, _) => {
+                    if phase < 0.5 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                }
+                Sound::Tone(Waveform::Saw, _) => 2.0 * phase - 1.0,
+                Sound::Tone(Waveform::Triangle, _) => 1.0 - 4.0 * (phase - 0.5).abs(),
+                Sound::WhiteNoise => unit(&mut rng),
+                Sound::PinkNoise => {
+                    let white = unit(&mut rng);
+                    pink[0] = 0.99765 * pink[0] + white * 0.0990460;
+                    pink[1] = 0.96300 * pink[1] + white * 0.2965164;
+                    pink[2] = 0.57000 * pink[2] + white * 1.0526913;
+                    (pink[0] + pink[1] + pink[2] + white * 0.1848) / 4.0
+                }
+                Sound::Silence => 0.0,
+            };
+            let ramp = (frame.min(frames - 1 - frame) as f64 / fade as f64).min(1.0);
+            let value = (value * ramp) as f32;
+            data.push(value);
+            data.push(value);
+        }
+        data
+    }
+
+    fn frequency(&self) -> f64 {
+        match self.sound {
+            Sound::Tone(_, frequency) => frequency,
+            _ => 0.0,
+        }
+    }
+}
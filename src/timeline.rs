@@ -0,0 +1,23 @@
+// Bars from empty to full, for drawing levels and event densities in the terminal.
+pub const BARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// Number of events in each of `width` columns between `start` and `end` in ms.
+pub fn density(times: &[f64], start: f64, end: f64, width: usize) -> Vec<usize> {
+    let mut counts = vec![0; width];
+    for &time in times {
+        if (start..=end).contains(&time) {
+            let column = ((time - start) / (end - start) * width as f64) as usize;
+            counts[column.min(width - 1)] += 1;
+        }
+    }
+    counts
+}
+
+// Every count as a bar, `max` and more are full bars and anything above zero is at least the smallest one.
+pub fn bars(counts: &[usize], max: usize) -> String {
+    let max = max.max(1);
+    counts
+        .iter()
+        .map(|&count| BARS[(count.min(max) * (BARS.len() - 1)).div_ceil(max)])
+        .collect()
+}
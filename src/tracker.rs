@@ -0,0 +1,1207 @@
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use log::{debug, info};
+
+use crate::events::AudioSampleInfo;
+use crate::export::write_wav;
+
+// Clock of a PAL Amiga, a note with period p plays its sample at PAL_CLOCK / (2 * p) Hz.
+const PAL_CLOCK: f64 = 7_093_789.2;
+// The period of C-5 in the keys of the other formats, 60 semitones above C-0.
+const C5_PERIOD: f64 = 428.0;
+const C5: u8 = 60;
+const SAMPLES: usize = 31;
+const ROWS: usize = 64;
+const HEADER_SIZE: usize = 1084;
+// Channels are panned left, right, right, left like on the Amiga, but not all the way.
+const STEREO_SEPARATION: f32 = 0.5;
+// Channels of Impulse Tracker and Scream Tracker 3 modules.
+const IT_CHANNELS: usize = 64;
+const S3M_CHANNELS: usize = 32;
+
+struct Sample {
+    data: Vec<f32>,
+    // Rate that plays the key C-5, periods play it at c5_speed * C5_PERIOD / period.
+    c5_speed: f64,
+    volume: f32,
+    // The global volume of Impulse Tracker samples, 1 in the other formats.
+    gain: f32,
+    // Samples of XM and IT modules can set the panning of their channel.
+    pan: Option<f32>,
+    looping: Option<Loop>,
+}
+
+#[derive(Clone, Copy)]
+struct Loop {
+    start: usize,
+    end: usize,
+    ping_pong: bool,
+}
+
+// XM and IT instruments map each of the 120 keys to a key and a sample, counted from 1 like in the patterns.
+struct Instrument {
+    keymap: Vec<(u8, usize)>,
+    gain: f32,
+    pan: Option<f32>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pitch {
+    Period(u16),
+    Key(u8),
+    // Key off, note cut and note fade end the note of the channel.
+    Off,
+}
+
+// The effects that are followed, from the effect column and the volume column of XM and IT modules.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Effect {
+    #[default]
+    None,
+    Speed(u8),
+    Tempo(u8),
+    Volume(f32),
+    Pan(f32),
+    Offset(usize),
+    Jump(usize),
+    Break(usize),
+    Delay(u8),
+    Cut(u8),
+    Portamento,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Cell {
+    pitch: Option<Pitch>,
+    instrument: usize,
+    effects: [Effect; 2],
+}
+
+struct Pattern {
+    rows: usize,
+    cells: Vec<Cell>,
+}
+
+struct Module {
+    samples: Vec<Sample>,
+    // Empty when the instruments of the patterns are samples, like in MOD and S3M modules.
+    instruments: Vec<Instrument>,
+    // Pattern numbers, None for the markers that Impulse Tracker and Scream Tracker 3 skip.
+    orders: Vec<Option<usize>>,
+    patterns: Vec<Pattern>,
+    channels: usize,
+    pans: Vec<f32>,
+    speed: u8,
+    tempo: u8,
+}
+
+impl Module {
+    // The sample and pitch that a pitch of an instrument plays.
+    fn resolve(&self, instrument: usize, pitch: Pitch) -> Option<(usize, Pitch)> {
+        let (sample, pitch) = match (self.instruments.is_empty(), pitch) {
+            (_, Pitch::Off) => return None,
+            (true, pitch) => (instrument, pitch),
+            (false, Pitch::Key(key)) => {
+                let (key, sample) = *self
+                    .instruments
+                    .get(instrument.checked_sub(1)?)?
+                    .keymap
+                    .get(key as usize)?;
+                (sample, Pitch::Key(key))
+            }
+            (false, Pitch::Period(_)) => return None,
+        };
+        let sample = sample.checked_sub(1)?;
+        (sample < self.samples.len()).then_some((sample, pitch))
+    }
+}
+
+impl Sample {
+    fn rate(&self, pitch: Pitch) -> f64 {
+        match pitch {
+            Pitch::Period(period) => self.c5_speed * C5_PERIOD / period.max(1) as f64,
+            Pitch::Key(key) => self.c5_speed * 2f64.powf((key as f64 - C5 as f64) / 12.0),
+            Pitch::Off => 0.0,
+        }
+    }
+}
+
+// A note as it is played: from its start until the next note in its channel cuts it off.
+struct Note {
+    sample: usize,
+    rate: f64,
+    offset: usize,
+    time: f64,
+    end: Option<f64>,
+    volume: f32,
+    pan: f32,
+    row: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Channel {
+    instrument: usize,
+    pitch: Option<Pitch>,
+    volume: f32,
+    pan: f32,
+    playing: Option<usize>,
+}
+
+// Reads a ProTracker, FastTracker 2 (.xm), Impulse Tracker (.it) or Scream Tracker 3 (.s3m) module and turns every
+// note into an event. The mixer plays samples at the rate in their file, so the sample of every note is extracted to
+// the temporary directory with the sample rate of its pitch, and as long as the note lasts: looped samples are
+// unrolled until the next note in the channel and the others are cut off by it. Speed, tempo, volume, panning,
+// sample offset, position jumps, pattern breaks, note delays, note cuts and key offs are followed, slides, vibrato,
+// envelopes and the other effects that change a note while it plays are not.
+pub fn read_module(path: &Path) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    let extension = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+    let data = fs::read(path)?;
+    let module = match extension.as_str() {
+        "xm" => parse_xm(&data)?,
+        "it" => parse_it(&data)?,
+        "s3m" => parse_s3m(&data)?,
+        _ => parse_mod(&data)?,
+    };
+    let notes = sequence(&module);
+    write_notes(path, &extension, &notes, &module.samples)
+}
+
+fn parse_mod(module: &[u8]) -> Result<Module, Box<dyn Error>> {
+    if module.len() < HEADER_SIZE {
+        return Err("the file is too short for a ProTracker module".into());
+    }
+    let channels = channel_count(&module[1080..1084]).ok_or_else(|| {
+        format!(
+            "{:?} is not the signature of a ProTracker module with 31 samples",
+            String::from_utf8_lossy(&module[1080..1084])
+        )
+    })?;
+
+    let song_length = (module[950] as usize).clamp(1, 128);
+    let orders = &module[952..1080];
+    let patterns = orders.iter().copied().max().unwrap_or(0) as usize + 1;
+    let pattern_size = ROWS * channels * 4;
+    let pattern_data = module
+        .get(HEADER_SIZE..HEADER_SIZE + patterns * pattern_size)
+        .ok_or("the patterns are cut off")?;
+
+    // The sample data follows the patterns in the order of the sample headers.
+    let mut samples = Vec::with_capacity(SAMPLES);
+    let mut position = HEADER_SIZE + patterns * pattern_size;
+    for header in module[20..20 + SAMPLES * 30].chunks_exact(30) {
+        let word = |index: usize| u16::from_be_bytes([header[index], header[index + 1]]) as usize * 2;
+        let length = word(22);
+        let data = pcm(module.get(position..).unwrap_or(&[]), length, false, true, false);
+        position += length;
+        // In eighths of a semitone, from -8 to 7.
+        let finetune = ((header[24] & 0x0f) << 4) as i8 >> 4;
+        let (loop_start, loop_length) = (word(26), word(28));
+        samples.push(Sample {
+            c5_speed: PAL_CLOCK / (2.0 * C5_PERIOD) * 2f64.powf(finetune as f64 / 96.0),
+            volume: header[25].min(64) as f32 / 64.0,
+            gain: 1.0,
+            pan: None,
+            looping: (loop_length > 2 && loop_start + loop_length <= data.len()).then_some(Loop {
+                start: loop_start,
+                end: loop_start + loop_length,
+                ping_pong: false,
+            }),
+            data,
+        });
+    }
+
+    let patterns = pattern_data
+        .chunks_exact(pattern_size)
+        .map(|pattern| Pattern {
+            rows: ROWS,
+            cells: pattern
+                .chunks_exact(4)
+                .map(|cell| {
+                    let period = u16::from_be_bytes([cell[0] & 0x0f, cell[1]]);
+                    Cell {
+                        pitch: (period > 0).then_some(Pitch::Period(period)),
+                        instrument: ((cell[0] & 0xf0) | (cell[2] >> 4)) as usize,
+                        effects: [protracker_effect(cell[2] & 0x0f, cell[3]), Effect::None],
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+
+    Ok(Module {
+        samples,
+        instruments: Vec::new(),
+        orders: orders[..song_length]
+            .iter()
+            .map(|pattern| Some(*pattern as usize))
+            .collect(),
+        patterns,
+        channels,
+        pans: (0..channels)
+            .map(|channel| {
+                if channel % 4 == 0 || channel % 4 == 3 {
+                    -STEREO_SEPARATION
+                } else {
+                    STEREO_SEPARATION
+                }
+            })
+            .collect(),
+        speed: 6,
+        tempo: 125,
+    })
+}
+
+// Channels of the signature at offset 1080: M.K. and its variants have four, xCHN and xxCH the given number.
+fn channel_count(signature: &[u8]) -> Option<usize> {
+    match signature {
+        b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => Some(4),
+        b"FLT8" | b"OCTA" | b"CD81" => Some(8),
+        [digit, b'C', b'H', b'N'] if digit.is_ascii_digit() => Some((digit - b'0') as usize),
+        [tens, ones, b'C', b'H'] if tens.is_ascii_digit() && ones.is_ascii_digit() => {
+            Some(((tens - b'0') * 10 + (ones - b'0')) as usize)
+        }
+        _ => None,
+    }
+    .filter(|channels| *channels > 0)
+}
+
+// Effects of ProTracker, which XM modules share.
+fn protracker_effect(effect: u8, parameter: u8) -> Effect {
+    let (x, y) = (parameter >> 4, parameter & 0x0f);
+    match (effect, x) {
+        (0x3, _) | (0x5, _) => Effect::Portamento,
+        (0x8, _) => Effect::Pan(parameter as f32 / 127.5 - 1.0),
+        (0x9, _) => Effect::Offset(parameter as usize * 256),
+        (0xb, _) => Effect::Jump(parameter as usize),
+        (0xc, _) => Effect::Volume(parameter.min(64) as f32 / 64.0),
+        (0xd, _) => Effect::Break((x * 10 + y) as usize),
+        (0xe, 0x8) => Effect::Pan(y as f32 / 7.5 - 1.0),
+        (0xe, 0xc) => Effect::Cut(y),
+        (0xe, 0xd) => Effect::Delay(y),
+        (0xf, _) if parameter == 0 => Effect::None,
+        (0xf, _) if parameter < 32 => Effect::Speed(parameter),
+        (0xf, _) => Effect::Tempo(parameter),
+        _ => Effect::None,
+    }
+}
+
+// Effects of Impulse Tracker and Scream Tracker 3, the commands A to Z are 1 to 26. Scream Tracker 3 writes the row
+// of pattern breaks in decimal digits and pans from 0 to 0x80.
+fn scream_tracker_effect(command: u8, parameter: u8, impulse: bool) -> Effect {
+    let (x, y) = (parameter >> 4, parameter & 0x0f);
+    match (command, x) {
+        (1, _) if parameter > 0 => Effect::Speed(parameter),
+        (2, _) => Effect::Jump(parameter as usize),
+        (3, _) if impulse => Effect::Break(parameter as usize),
+        (3, _) => Effect::Break((x * 10 + y) as usize),
+        (7, _) | (12, _) => Effect::Portamento,
+        (15, _) => Effect::Offset(parameter as usize * 256),
+        (19, 0x8) => Effect::Pan(y as f32 / 7.5 - 1.0),
+        (19, 0xc) => Effect::Cut(y),
+        (19, 0xd) => Effect::Delay(y),
+        (20, _) if parameter >= 0x20 => Effect::Tempo(parameter),
+        (24, _) if impulse => Effect::Pan(parameter as f32 / 127.5 - 1.0),
+        (24, _) if parameter <= 0x80 => Effect::Pan(parameter as f32 / 64.0 - 1.0),
+        _ => Effect::None,
+    }
+}
+
+fn word(data: &[u8], at: usize) -> Result<usize, Box<dyn Error>> {
+    let bytes = data.get(at..at + 2).ok_or("the module is cut off")?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+}
+
+fn dword(data: &[u8], at: usize) -> Result<usize, Box<dyn Error>> {
+    let bytes = data.get(at..at + 4).ok_or("the module is cut off")?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+// Reads 8 or 16 bit little endian sample data of up to the given number of frames, as much of it as the file has.
+// XM modules store the differences between the samples instead of the samples.
+fn pcm(data: &[u8], frames: usize, sixteen: bool, signed: bool, delta: bool) -> Vec<f32> {
+    let (mut previous, flip) = (0i32, if signed { 0 } else { 1 });
+    let values = if sixteen {
+        data.chunks_exact(2)
+            .take(frames)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1] ^ (flip << 7)]) as i32)
+            .collect::<Vec<_>>()
+    } else {
+        data.iter()
+            .take(frames)
+            .map(|byte| (byte ^ (flip << 7)) as i8 as i32)
+            .collect()
+    };
+    values
+        .into_iter()
+        .map(|value| {
+            if delta {
+                previous = if sixteen {
+                    (previous + value) as i16 as i32
+                } else {
+                    (previous + value) as i8 as i32
+                };
+            } else {
+                previous = value;
+            }
+            previous as f32 / if sixteen { 32768.0 } else { 128.0 }
+        })
+        .collect()
+}
+
+fn parse_xm(module: &[u8]) -> Result<Module, Box<dyn Error>> {
+    if !module.starts_with(b"Extended Module: ") {
+        return Err("the file is not a FastTracker 2 module".into());
+    }
+    let song_length = word(module, 64)?.min(256);
+    let channels = word(module, 68)?.clamp(1, 64);
+    let (pattern_count, instrument_count) = (word(module, 70)?, word(module, 72)?);
+    let orders = module.get(80..80 + song_length).ok_or("the module is cut off")?;
+
+    let mut position = 60 + dword(module, 60)?;
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for _ in 0..pattern_count {
+        let rows = word(module, position + 5)?.max(1);
+        let size = word(module, position + 7)?;
+        position += dword(module, position)?;
+        let data = module
+            .get(position..position + size)
+            .ok_or("the patterns are cut off")?;
+        position += size;
+
+        let mut cells = vec![Cell::default(); rows * channels];
+        let mut bytes = data.iter().copied();
+        for cell in cells.iter_mut() {
+            let Some(first) = bytes.next() else {
+                break;
+            };
+            // A set high bit says which of the five columns follow, otherwise all of them do.
+            let (flags, note) = if first & 0x80 != 0 {
+                (first, None)
+            } else {
+                (0x1f, Some(first))
+            };
+            let mut column = |bit: u8| if flags & bit != 0 { bytes.next().unwrap_or(0) } else { 0 };
+            let note = note.unwrap_or_else(|| column(0x01));
+            let (instrument, volume, effect, parameter) = (column(0x02), column(0x04), column(0x08), column(0x10));
+            cell.pitch = match note {
+                1..=96 => Some(Pitch::Key(note + 11)),
+                97 => Some(Pitch::Off),
+                _ => None,
+            };
+            cell.instrument = instrument as usize;
+            cell.effects = [
+                match volume {
+                    0x10..=0x50 => Effect::Volume((volume - 0x10) as f32 / 64.0),
+                    0xc0..=0xcf => Effect::Pan((volume & 0x0f) as f32 / 7.5 - 1.0),
+                    0xf0..=0xff => Effect::Portamento,
+                    _ => Effect::None,
+                },
+                protracker_effect(effect, parameter),
+            ];
+        }
+        patterns.push(Pattern { rows, cells });
+    }
+
+    // Every instrument is followed by the headers of its samples and then their data.
+    let mut samples = Vec::new();
+    let mut instruments = Vec::with_capacity(instrument_count);
+    for _ in 0..instrument_count {
+        let header = position;
+        let count = word(module, header + 27)?;
+        position += dword(module, header)?;
+        let first = samples.len();
+        let mut headers = Vec::with_capacity(count);
+        for _ in 0..count {
+            headers.push(position);
+            position += dword(module, header + 29)?;
+        }
+        for header in headers {
+            let length = dword(module, header)?;
+            let kind = *module.get(header + 14).ok_or("the module is cut off")?;
+            let sixteen = kind & 0x10 != 0;
+            let width = if sixteen { 2 } else { 1 };
+            let data = pcm(
+                module.get(position..).unwrap_or(&[]),
+                length / width,
+                sixteen,
+                true,
+                true,
+            );
+            position += length;
+            let (loop_start, loop_length) = (dword(module, header + 4)? / width, dword(module, header + 8)? / width);
+            let finetune = module[header + 13] as i8 as f64 / 128.0;
+            let relative_note = module[header + 16] as i8 as f64;
+            samples.push(Sample {
+                // Without a relative note and finetune C-4 plays at 8363 Hz, which is C-5 here.
+                c5_speed: 8363.0 * 2f64.powf((relative_note + finetune) / 12.0),
+                volume: module[header + 12].min(64) as f32 / 64.0,
+                gain: 1.0,
+                pan: Some(module[header + 15] as f32 / 127.5 - 1.0),
+                looping: (kind & 0x03 != 0 && loop_length > 0 && loop_start + loop_length <= data.len()).then_some(
+                    Loop {
+                        start: loop_start,
+                        end: loop_start + loop_length,
+                        ping_pong: kind & 0x03 == 2,
+                    },
+                ),
+                data,
+            });
+        }
+
+        // The keymap has the samples of the 96 notes from C-0, which is the key 12.
+        let keys = if count > 0 {
+            module.get(header + 33..header + 129).ok_or("the module is cut off")?
+        } else {
+            &[]
+        };
+        instruments.push(Instrument {
+            keymap: (0..120u8)
+                .map(|key| {
+                    let sample = (key as usize)
+                        .checked_sub(12)
+                        .and_then(|note| keys.get(note))
+                        .filter(|sample| (**sample as usize) < count)
+                        .map_or(0, |sample| first + *sample as usize + 1);
+                    (key, sample)
+                })
+                .collect(),
+            gain: 1.0,
+            pan: None,
+        });
+    }
+
+    Ok(Module {
+        samples,
+        instruments,
+        orders: orders.iter().map(|pattern| Some(*pattern as usize)).collect(),
+        patterns,
+        channels,
+        pans: vec![0.0; channels],
+        speed: word(module, 76)?.clamp(1, 31) as u8,
+        tempo: word(module, 78)?.clamp(32, 255) as u8,
+    })
+}
+
+fn parse_it(module: &[u8]) -> Result<Module, Box<dyn Error>> {
+    if !module.starts_with(b"IMPM") {
+        return Err("the file is not an Impulse Tracker module".into());
+    }
+    let (order_count, instrument_count) = (word(module, 32)?, word(module, 34)?);
+    let (sample_count, pattern_count) = (word(module, 36)?, word(module, 38)?);
+    let (compatible, flags) = (word(module, 42)?, word(module, 44)?);
+    let settings = module.get(64..192).ok_or("the module is cut off")?;
+    let orders = module.get(192..192 + order_count).ok_or("the module is cut off")?;
+    let offsets = 192 + order_count;
+    let offset = |index: usize| dword(module, offsets + index * 4);
+
+    // Channels from 0 to 64, 100 is surround and the high bit mutes the channel.
+    let stereo = flags & 0x01 != 0;
+    let muted: Vec<bool> = settings[..IT_CHANNELS].iter().map(|pan| pan & 0x80 != 0).collect();
+    let pans = settings[..IT_CHANNELS]
+        .iter()
+        .map(|pan| match pan & 0x7f {
+            pan @ 0..=64 if stereo => pan as f32 / 32.0 - 1.0,
+            _ => 0.0,
+        })
+        .collect();
+
+    let mut instruments = Vec::new();
+    if flags & 0x04 != 0 {
+        for index in 0..instrument_count {
+            let header = offset(index)?;
+            let keymap = module
+                .get(header + 64..header + 304)
+                .ok_or("the instruments are cut off")?;
+            // Instruments of Impulse Tracker 1 have neither a global volume nor a panning.
+            let (gain, pan) = if compatible >= 0x200 {
+                let default_pan = module[header + 25];
+                (
+                    module[header + 24].min(128) as f32 / 128.0,
+                    (default_pan & 0x80 == 0 && stereo).then_some(default_pan.min(64) as f32 / 32.0 - 1.0),
+                )
+            } else {
+                (1.0, None)
+            };
+            instruments.push(Instrument {
+                keymap: keymap
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0].min(119), pair[1] as usize))
+                    .collect(),
+                gain,
+                pan,
+            });
+        }
+    }
+
+    let mut samples = Vec::with_capacity(sample_count);
+    for index in 0..sample_count {
+        let header = offset(instrument_count + index)?;
+        let bytes = module.get(header..header + 80).ok_or("the samples are cut off")?;
+        let (sample_flags, conversion) = (bytes[18], bytes[46]);
+        let sixteen = sample_flags & 0x02 != 0;
+        let length = if sample_flags & 0x01 != 0 { dword(bytes, 48)? } else { 0 };
+        let start = dword(bytes, 72)?;
+        let data = module.get(start..).unwrap_or(&[]);
+        // Stereo samples keep their left channel.
+        let data = if sample_flags & 0x08 != 0 {
+            decompress(data, length, sixteen, conversion & 0x04 != 0)
+        } else {
+            pcm(data, length, sixteen, conversion & 0x01 != 0, false)
+        };
+        let (loop_start, loop_end) = (dword(bytes, 52)?, dword(bytes, 56)?);
+        let default_pan = bytes[47];
+        samples.push(Sample {
+            c5_speed: dword(bytes, 60)?.max(1) as f64,
+            volume: bytes[19].min(64) as f32 / 64.0,
+            gain: bytes[17].min(64) as f32 / 64.0,
+            pan: (default_pan & 0x80 != 0 && stereo).then_some((default_pan & 0x7f).min(64) as f32 / 32.0 - 1.0),
+            looping: (sample_flags & 0x10 != 0 && loop_start < loop_end && loop_end <= data.len()).then_some(Loop {
+                start: loop_start,
+                end: loop_end,
+                ping_pong: sample_flags & 0x40 != 0,
+            }),
+            data,
+        });
+    }
+
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for index in 0..pattern_count {
+        let header = offset(instrument_count + sample_count + index)?;
+        // Patterns without an offset are 64 empty rows.
+        if header == 0 {
+            patterns.push(Pattern {
+                rows: ROWS,
+                cells: vec![Cell::default(); ROWS * IT_CHANNELS],
+            });
+            continue;
+        }
+        let size = word(module, header)?;
+        let rows = word(module, header + 2)?.max(1);
+        let data = module
+            .get(header + 8..header + 8 + size)
+            .ok_or("the patterns are cut off")?;
+        patterns.push(Pattern {
+            rows,
+            cells: unpack_it_pattern(data, rows, &muted),
+        });
+    }
+
+    Ok(Module {
+        samples,
+        instruments,
+        orders: orders_of(orders),
+        patterns,
+        channels: IT_CHANNELS,
+        pans,
+        speed: module[50].max(1),
+        tempo: module[51].max(32),
+    })
+}
+
+// Every cell starts with its channel and can repeat the note, instrument, volume and effect of the last cell with
+// a mask of that channel.
+fn unpack_it_pattern(data: &[u8], rows: usize, muted: &[bool]) -> Vec<Cell> {
+    let mut cells = vec![Cell::default(); rows * IT_CHANNELS];
+    let mut masks = [0u8; IT_CHANNELS];
+    let mut last = [(0u8, 0u8, 0u8, 0u8, 0u8); IT_CHANNELS];
+    let mut bytes = data.iter().copied();
+    let mut row = 0;
+    while row < rows {
+        let Some(variable) = bytes.next() else {
+            break;
+        };
+        if variable == 0 {
+            row += 1;
+            continue;
+        }
+        let channel = (variable as usize - 1) & 63;
+        if variable & 0x80 != 0 {
+            masks[channel] = bytes.next().unwrap_or(0);
+        }
+        let mask = masks[channel];
+        let (mut note, mut instrument, mut volume, mut command, mut parameter) = last[channel];
+        if mask & 0x01 != 0 {
+            note = bytes.next().unwrap_or(0);
+        }
+        if mask & 0x02 != 0 {
+            instrument = bytes.next().unwrap_or(0);
+        }
+        if mask & 0x04 != 0 {
+            volume = bytes.next().unwrap_or(0);
+        }
+        if mask & 0x08 != 0 {
+            command = bytes.next().unwrap_or(0);
+            parameter = bytes.next().unwrap_or(0);
+        }
+        last[channel] = (note, instrument, volume, command, parameter);
+        if muted[channel] {
+            continue;
+        }
+
+        let cell = &mut cells[row * IT_CHANNELS + channel];
+        if mask & 0x11 != 0 {
+            cell.pitch = match note {
+                0..=119 => Some(Pitch::Key(note)),
+                253..=255 => Some(Pitch::Off),
+                _ => None,
+            };
+        }
+        if mask & 0x22 != 0 {
+            cell.instrument = instrument as usize;
+        }
+        if mask & 0x44 != 0 {
+            cell.effects[0] = match volume {
+                0..=64 => Effect::Volume(volume as f32 / 64.0),
+                128..=192 => Effect::Pan((volume - 128) as f32 / 32.0 - 1.0),
+                193..=202 => Effect::Portamento,
+                _ => Effect::None,
+            };
+        }
+        if mask & 0x88 != 0 {
+            cell.effects[1] = scream_tracker_effect(command, parameter, true);
+        }
+    }
+    cells
+}
+
+// Orders of Impulse Tracker and Scream Tracker 3 modules end at 255 and skip 254.
+fn orders_of(orders: &[u8]) -> Vec<Option<usize>> {
+    orders
+        .iter()
+        .take_while(|pattern| **pattern != 255)
+        .map(|pattern| (*pattern != 254).then_some(*pattern as usize))
+        .collect()
+}
+
+// Decompresses the IT214 and IT215 samples that Impulse Tracker writes: blocks of up to 0x8000 8 bit or 0x4000 16
+// bit samples, each a stream of differences whose width in bits changes on the way. IT215 stores differences of
+// the differences.
+fn decompress(data: &[u8], frames: usize, sixteen: bool, it215: bool) -> Vec<f32> {
+    let (top, block_frames, change_bits, scale) = if sixteen {
+        (17, 0x4000, 4, 32768.0)
+    } else {
+        (9, 0x8000, 3, 128.0)
+    };
+    let mut samples = Vec::with_capacity(frames);
+    let mut position = 0;
+    while samples.len() < frames && position + 2 <= data.len() {
+        let size = u16::from_le_bytes([data[position], data[position + 1]]) as usize;
+        let block = &data[position + 2..(position + 2 + size).min(data.len())];
+        position += 2 + size;
+
+        let mut bit = 0;
+        let (mut width, mut first, mut second) = (top, 0i32, 0i32);
+        let end = (samples.len() + block_frames).min(frames);
+        while samples.len() < end && bit < block.len() * 8 {
+            let value = read_bits(block, &mut bit, width);
+            if width < 7 {
+                if value == 1 << (width - 1) {
+                    let changed = read_bits(block, &mut bit, change_bits) + 1;
+                    width = if changed < width { changed } else { changed + 1 };
+                    continue;
+                }
+            } else if width < top {
+                let border = ((1u32 << (top - 1)) - 1) >> (top - width);
+                let border = border - if sixteen { 8 } else { 4 };
+                if value > border && value <= border + if sixteen { 16 } else { 8 } {
+                    let changed = value - border;
+                    width = if changed < width { changed } else { changed + 1 };
+                    continue;
+                }
+            } else if width == top {
+                if value & (1 << (top - 1)) != 0 {
+                    width = (value + 1) & 0xff;
+                    if width == 0 || width > top {
+                        break;
+                    }
+                    continue;
+                }
+            } else {
+                break;
+            }
+            let shift = 32 - width.min(top - 1);
+            first = first.wrapping_add(((value << shift) as i32) >> shift);
+            second = second.wrapping_add(first);
+            let value = if it215 { second } else { first };
+            let value = if sixteen {
+                value as i16 as f32
+            } else {
+                value as i8 as f32
+            };
+            samples.push(value / scale);
+        }
+    }
+    samples
+}
+
+// Reads bits from the lowest of each byte on.
+fn read_bits(data: &[u8], bit: &mut usize, count: u32) -> u32 {
+    let mut value = 0;
+    for index in 0..count {
+        let byte = data.get(*bit / 8).copied().unwrap_or(0);
+        value |= ((byte >> (*bit % 8)) as u32 & 1) << index;
+        *bit += 1;
+    }
+    value
+}
+
+fn parse_s3m(module: &[u8]) -> Result<Module, Box<dyn Error>> {
+    if module.get(0x2c..0x30) != Some(b"SCRM") {
+        return Err("the file is not a Scream Tracker 3 module".into());
+    }
+    let (order_count, instrument_count) = (word(module, 0x20)?, word(module, 0x22)?);
+    let pattern_count = word(module, 0x24)?;
+    let signed = word(module, 0x2a)? == 1;
+    let stereo = module[0x33] & 0x80 != 0;
+    let settings = module.get(0x40..0x60).ok_or("the module is cut off")?;
+    let orders = module.get(0x60..0x60 + order_count).ok_or("the module is cut off")?;
+    let pointers = 0x60 + order_count;
+    let pointer = |index: usize| word(module, pointers + index * 2).map(|pointer| pointer * 16);
+
+    // Channels 0 to 7 are on the left and 8 to 15 on the right, the others are AdLib channels or unused. A pan table
+    // after the pointers can place them anywhere.
+    let table = module
+        .get(pointers + (instrument_count + pattern_count) * 2..)
+        .filter(|_| module[0x35] == 252)
+        .and_then(|table| table.get(..S3M_CHANNELS));
+    let muted: Vec<bool> = settings
+        .iter()
+        .map(|setting| setting & 0x80 != 0 || setting & 0x7f >= 16)
+        .collect();
+    let pans = (0..S3M_CHANNELS)
+        .map(|channel| match table.map(|table| table[channel]) {
+            _ if !stereo => 0.0,
+            Some(pan) if pan & 0x20 != 0 => (pan & 0x0f) as f32 / 7.5 - 1.0,
+            _ if settings[channel] & 0x7f < 8 => 3.0 / 7.5 - 1.0,
+            _ => 12.0 / 7.5 - 1.0,
+        })
+        .collect();
+
+    let mut samples = Vec::with_capacity(instrument_count);
+    for index in 0..instrument_count {
+        let header = pointer(index)?;
+        let bytes = module.get(header..header + 0x50).ok_or("the samples are cut off")?;
+        let sample_flags = bytes[0x1f];
+        let sixteen = sample_flags & 0x04 != 0;
+        // Only type 1 is sampled, the others are AdLib instruments.
+        let length = if bytes[0] == 1 { dword(bytes, 0x10)? } else { 0 };
+        let start = ((bytes[0x0d] as usize) << 16 | word(bytes, 0x0e)?) * 16;
+        let data = pcm(module.get(start..).unwrap_or(&[]), length, sixteen, signed, false);
+        let (loop_start, loop_end) = (dword(bytes, 0x14)?, dword(bytes, 0x18)?);
+        samples.push(Sample {
+            // The C2 speed is the rate of C-4.
+            c5_speed: dword(bytes, 0x20)?.max(1) as f64 * 2.0,
+            volume: bytes[0x1c].min(64) as f32 / 64.0,
+            gain: 1.0,
+            pan: None,
+            looping: (sample_flags & 0x01 != 0 && loop_start < loop_end && loop_end <= data.len()).then_some(Loop {
+                start: loop_start,
+                end: loop_end,
+                ping_pong: false,
+            }),
+            data,
+        });
+    }
+
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for index in 0..pattern_count {
+        let header = pointer(instrument_count + index)?;
+        let mut cells = vec![Cell::default(); ROWS * S3M_CHANNELS];
+        let data = if header == 0 {
+            &[]
+        } else {
+            module.get(header + 2..).ok_or("the patterns are cut off")?
+        };
+        let mut bytes = data.iter().copied();
+        let mut row = 0;
+        while row < ROWS {
+            let Some(what) = bytes.next() else {
+                break;
+            };
+            if what == 0 {
+                row += 1;
+                continue;
+            }
+            let channel = (what & 31) as usize;
+            let mut cell = Cell::default();
+            if what & 0x20 != 0 {
+                let (note, instrument) = (bytes.next().unwrap_or(255), bytes.next().unwrap_or(0));
+                cell.pitch = match note {
+                    254 => Some(Pitch::Off),
+                    255 => None,
+                    note => Some(Pitch::Key((note >> 4) * 12 + (note & 0x0f).min(11))),
+                };
+                cell.instrument = instrument as usize;
+            }
+            if what & 0x40 != 0 {
+                let volume = bytes.next().unwrap_or(255);
+                if volume <= 64 {
+                    cell.effects[0] = Effect::Volume(volume as f32 / 64.0);
+                }
+            }
+            if what & 0x80 != 0 {
+                let (command, parameter) = (bytes.next().unwrap_or(0), bytes.next().unwrap_or(0));
+                cell.effects[1] = scream_tracker_effect(command, parameter, false);
+            }
+            if !muted[channel] {
+                cells[row * S3M_CHANNELS + channel] = cell;
+            }
+        }
+        patterns.push(Pattern { rows: ROWS, cells });
+    }
+
+    Ok(Module {
+        samples,
+        instruments: Vec::new(),
+        orders: orders_of(orders),
+        patterns,
+        channels: S3M_CHANNELS,
+        pans,
+        speed: module[0x31].max(1),
+        tempo: module[0x32].max(32),
+    })
+}
+
+// Plays the order list row by row. Every row is played once, a jump back to a row that was already played ends the
+// song, as it would loop forever.
+fn sequence(module: &Module) -> Vec<Note> {
+    let channels = module.channels;
+    let mut notes: Vec<Note> = Vec::new();
+    let mut state: Vec<Channel> = module
+        .pans
+        .iter()
+        .map(|pan| Channel {
+            instrument: 0,
+            pitch: None,
+            volume: 1.0,
+            pan: *pan,
+            playing: None,
+        })
+        .collect();
+    let empty = Pattern {
+        rows: ROWS,
+        cells: vec![Cell::default(); ROWS * channels],
+    };
+    let (mut speed, mut tempo) = (module.speed as f64, module.tempo as f64);
+    let mut time = 0.0;
+    let mut played = HashSet::new();
+    let (mut order, mut row) = (0, 0);
+    let mut rows_played = 0;
+
+    loop {
+        while let Some(None) = module.orders.get(order) {
+            order += 1;
+        }
+        let Some(Some(pattern)) = module.orders.get(order) else {
+            break;
+        };
+        let pattern = module.patterns.get(*pattern).unwrap_or(&empty);
+        if row >= pattern.rows {
+            row = 0;
+        }
+        if !played.insert((order, row)) {
+            break;
+        }
+        let cells = &pattern.cells[row * channels..][..channels];
+        rows_played += 1;
+
+        // Speed and tempo already apply to the row that sets them.
+        for effect in cells.iter().flat_map(|cell| cell.effects) {
+            match effect {
+                Effect::Speed(value) => speed = value as f64,
+                Effect::Tempo(value) => tempo = value as f64,
+                _ => {}
+            }
+        }
+        let tick = 2500.0 / tempo;
+
+        let (mut jump, mut row_break) = (None, None);
+        for (channel, cell) in cells.iter().enumerate() {
+            let state = &mut state[channel];
+            let pitch = cell.pitch.filter(|pitch| *pitch != Pitch::Off);
+
+            if cell.instrument > 0 {
+                state.instrument = cell.instrument;
+                let current = pitch.or(state.pitch).unwrap_or(Pitch::Key(C5));
+                if let Some((sample, _)) = module.resolve(cell.instrument, current) {
+                    let sample = &module.samples[sample];
+                    let instrument = module.instruments.get(cell.instrument - 1);
+                    state.volume = sample.volume;
+                    if let Some(pan) = sample.pan.or(instrument.and_then(|instrument| instrument.pan)) {
+                        state.pan = pan;
+                    }
+                }
+            }
+            let (mut delay, mut offset, mut portamento) = (0.0, 0, false);
+            for effect in cell.effects {
+                match effect {
+                    Effect::Volume(volume) => state.volume = volume,
+                    Effect::Pan(pan) => state.pan = pan,
+                    Effect::Offset(value) => offset = value,
+                    Effect::Jump(value) => jump = Some(value),
+                    Effect::Break(value) => row_break = Some(value),
+                    Effect::Delay(ticks) => delay = ticks as f64 * tick,
+                    // Tone portamento slides the playing note to the new pitch instead of starting another one.
+                    Effect::Portamento => portamento = true,
+                    _ => {}
+                }
+            }
+
+            let resolved = pitch.and_then(|pitch| module.resolve(state.instrument, pitch));
+            if let (Some((sample, key)), false) = (resolved, portamento) {
+                let start = time + delay;
+                if let Some(playing) = state.playing {
+                    notes[playing].end.get_or_insert(start);
+                }
+                let instrument = module.instruments.get(state.instrument - 1);
+                let gain = module.samples[sample].gain * instrument.map_or(1.0, |instrument| instrument.gain);
+                state.pitch = pitch;
+                state.playing = Some(notes.len());
+                notes.push(Note {
+                    sample,
+                    rate: module.samples[sample].rate(key),
+                    offset,
+                    time: start,
+                    end: None,
+                    volume: state.volume * gain,
+                    pan: state.pan.clamp(-1.0, 1.0),
+                    row: rows_played,
+                });
+            }
+
+            let cut = cell
+                .effects
+                .iter()
+                .find_map(|effect| match effect {
+                    Effect::Cut(ticks) => Some(time + *ticks as f64 * tick),
+                    Effect::Volume(volume) if *volume == 0.0 && cell.pitch.is_none() => Some(time),
+                    _ => None,
+                })
+                .or((cell.pitch == Some(Pitch::Off)).then_some(time + delay));
+            if let (Some(cut), Some(playing)) = (cut, state.playing) {
+                notes[playing].end.get_or_insert(cut);
+                state.playing = None;
+            }
+        }
+
+        time += speed * tick;
+        if jump.is_some() || row_break.is_some() {
+            order = jump.unwrap_or(order + 1);
+            row = row_break.unwrap_or(0);
+        } else if row + 1 == pattern.rows {
+            (order, row) = (order + 1, 0);
+        } else {
+            row += 1;
+        }
+    }
+
+    // Looped samples ring until the end of the song.
+    for note in notes.iter_mut() {
+        note.end.get_or_insert(time);
+    }
+    debug!("the song is {} rows and {:.3} s long", rows_played, time / 1000.0);
+    notes
+}
+
+// Writes the audio of every note that is not silent as a mono file at the rate of its pitch. Notes with the same
+// sample, pitch, offset and length share their file.
+fn write_notes(
+    path: &Path,
+    extension: &str,
+    notes: &[Note],
+    samples: &[Sample],
+) -> Result<Vec<AudioSampleInfo>, Box<dyn Error>> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let dir = env::temp_dir().join(format!("audio-sample-mixer-{}-{}", stem, extension));
+    fs::create_dir_all(&dir)?;
+
+    let mut written = HashSet::new();
+    let mut infos = Vec::with_capacity(notes.len());
+    for note in notes {
+        let sample = &samples[note.sample];
+        if note.volume == 0.0 || note.offset >= sample.data.len() {
+            continue;
+        }
+        let rate = note.rate.round().max(1.0) as u32;
+        let duration = note.end.unwrap_or(note.time) - note.time;
+        let frames = (duration * rate as f64 / 1000.0).ceil() as usize;
+        if frames == 0 {
+            continue;
+        }
+
+        let data: Vec<f32> = match sample.looping {
+            Some(Loop { start, end, ping_pong }) => {
+                // Ping-pong loops play backwards from their end, without repeating the ends.
+                let mut looping = sample.data[start..end].to_vec();
+                if ping_pong && end - start > 2 {
+                    looping.extend(sample.data[start + 1..end - 1].iter().rev());
+                }
+                sample.data[note.offset.min(end)..end]
+                    .iter()
+                    .chain(looping.iter().cycle())
+                    .take(frames)
+                    .copied()
+                    .collect()
+            }
+            None => sample.data[note.offset..].iter().take(frames).copied().collect(),
+        };
+
+        let file = dir.join(format!(
+            "{:02}_{}_{}_{}.wav",
+            note.sample + 1,
+            rate,
+            note.offset,
+            data.len()
+        ));
+        if written.insert(file.clone()) {
+            write_wav(&data, 1, &file, rate)?;
+        }
+        infos.push(AudioSampleInfo {
+            time: note.time,
+            volume: note.volume,
+            pan: note.pan,
+            name: file.to_string_lossy().into_owned(),
+            line: note.row,
+            lane: None,
+            hold: None,
+            pitch: None,
+            pan_mode: None,
+            tags: Vec::new(),
+        });
+    }
+
+    info!(
+        "read {} notes of {}, their samples are extracted to {}",
+        infos.len(),
+        path.display(),
+        dir.display()
+    );
+    Ok(infos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Packs values of the given widths the way Impulse Tracker compresses samples, in a block with its size.
+    fn block(values: &[(u32, u32)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut bit = 0;
+        for (value, width) in values {
+            for index in 0..*width {
+                if bit / 8 == bytes.len() {
+                    bytes.push(0);
+                }
+                bytes[bit / 8] |= (((value >> index) & 1) as u8) << (bit % 8);
+                bit += 1;
+            }
+        }
+        let mut block = (bytes.len() as u16).to_le_bytes().to_vec();
+        block.extend(bytes);
+        block
+    }
+
+    #[test]
+    fn compressed_samples() {
+        // 5 in 9 bits, a change to 3 bits, then -1 and 1 as differences.
+        let data = block(&[(5, 9), (0x102, 9), (0b111, 3), (0b001, 3)]);
+        let samples: Vec<f32> = decompress(&data, 3, false, false)
+            .iter()
+            .map(|value| value * 128.0)
+            .collect();
+        assert_eq!(samples, [5.0, 4.0, 5.0]);
+        // IT215 adds up the differences twice.
+        let samples: Vec<f32> = decompress(&data, 3, false, true)
+            .iter()
+            .map(|value| value * 128.0)
+            .collect();
+        assert_eq!(samples, [5.0, 9.0, 14.0]);
+        // A change from 3 bits to 5 is the value 4 followed by 3 in three bits, then 5 bits hold -15. Their -16 would
+        // mark another change.
+        let data = block(&[(0x102, 9), (0b100, 3), (0b011, 3), (0b10001, 5)]);
+        assert_eq!(decompress(&data, 1, false, false), [-15.0 / 128.0]);
+    }
+
+    // A module of two channels and one pattern, with an instrument whose sample is an octave up.
+    fn extended_module(pattern: &[u8], rows: u16) -> Vec<u8> {
+        let mut module = b"Extended Module: ".to_vec();
+        module.resize(60, 0);
+        module.extend(276u32.to_le_bytes());
+        for value in [1u16, 0, 2, 1, 1, 1, 6, 125] {
+            module.extend(value.to_le_bytes());
+        }
+        module.resize(80 + 256, 0);
+        module.extend(9u32.to_le_bytes());
+        module.push(0);
+        module.extend(rows.to_le_bytes());
+        module.extend((pattern.len() as u16).to_le_bytes());
+        module.extend(pattern);
+
+        let mut instrument = vec![0; 263];
+        instrument[..4].copy_from_slice(&263u32.to_le_bytes());
+        instrument[27] = 1;
+        instrument[29..33].copy_from_slice(&40u32.to_le_bytes());
+        module.extend(instrument);
+        let mut sample = vec![0; 40];
+        sample[..4].copy_from_slice(&4u32.to_le_bytes());
+        (sample[12], sample[15], sample[16]) = (32, 0xff, 12);
+        module.extend(sample);
+        module.extend([10, 10, 0xec, 0xec]);
+        module
+    }
+
+    #[test]
+    fn extended_modules() {
+        let pattern = [
+            // C-4 of instrument 1 on the first channel, C-5 at volume 16 on the second.
+            49, 1, 0, 0, 0, 0x87, 61, 1, 0x20, //
+            // A key off on the first channel, nothing on the second.
+            0x81, 97, 0x80,
+        ];
+        let module = parse_xm(&extended_module(&pattern, 2)).unwrap();
+        let samples = &module.samples[0].data;
+        assert_eq!(samples.len(), 4);
+        assert_eq!(
+            samples.iter().map(|value| value * 128.0).collect::<Vec<_>>(),
+            [10.0, 20.0, 0.0, -20.0]
+        );
+
+        let notes = sequence(&module);
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].rate.round(), 16726.0);
+        assert_eq!((notes[0].time, notes[0].end), (0.0, Some(120.0)));
+        assert_eq!(notes[0].volume, 0.5);
+        assert_eq!(notes[0].pan, 1.0);
+        assert_eq!(notes[1].rate.round(), 33452.0);
+        assert_eq!(notes[1].end, Some(240.0));
+        assert_eq!(notes[1].volume, 0.25);
+
+        assert!(parse_xm(&extended_module(&pattern, 2)[..400]).is_err());
+    }
+
+    #[test]
+    fn impulse_patterns() {
+        let data = [
+            // Everything on the first channel: C-5 of sample 1, volume 32 and a note delay of 3 ticks.
+            0x81, 0x0f, 60, 1, 32, 19, 0xd3, 0, //
+            // The same again by repeating the last values.
+            0x81, 0xf0, 0, //
+            // A note off on the second channel and a muted third channel.
+            0x82, 0x01, 255, 0x83, 0x01, 72, 0,
+        ];
+        let mut muted = [false; IT_CHANNELS];
+        muted[2] = true;
+        let cells = unpack_it_pattern(&data, 3, &muted);
+        for cell in [cells[0], cells[IT_CHANNELS]] {
+            assert!(cell.pitch == Some(Pitch::Key(60)));
+            assert_eq!(cell.instrument, 1);
+            assert!(cell.effects == [Effect::Volume(0.5), Effect::Delay(3)]);
+        }
+        assert!(cells[2 * IT_CHANNELS + 1].pitch == Some(Pitch::Off));
+        assert!(cells[2 * IT_CHANNELS + 2].pitch.is_none());
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{stdin, stdout, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::cli::parse_time;
+use crate::commands::preview::Player;
+use crate::events::{read_events, trigger_counts};
+use crate::mix::{render, to_frame, Progress, RenderSettings, RenderStats, SampleCache};
+use crate::report::format_time;
+use crate::timeline::{bars, density, BARS};
+
+const TIMELINE_WIDTH: usize = 64;
+const REFRESH: Duration = Duration::from_millis(200);
+// Levels below this many dBFS are drawn empty.
+const METER_FLOOR: f32 = -48.0;
+
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+// Saves the cursor, rewrites the first line and puts the cursor back where the user is typing.
+const STATUS_LINE: &str = "\x1b7\x1b[1;1H\x1b[2K";
+const RESTORE_CURSOR: &str = "\x1b8";
+
+enum Event {
+    Command(String),
+    Rendered(Box<Result<(Vec<f32>, RenderStats), String>>, SampleCache),
+    InputClosed,
+}
+
+struct Mix {
+    data: Vec<f32>,
+    stats: RenderStats,
+}
+
+struct Tui {
+    input: PathBuf,
+    settings: RenderSettings,
+    host: Option<String>,
+    progress: Arc<Progress>,
+    events: Sender<Event>,
+    // Taken by the render thread while it runs, so samples are only decoded once per session.
+    cache: Option<SampleCache>,
+    triggers: Vec<(String, usize)>,
+    times: Vec<f64>,
+    muted: HashSet<String>,
+    mix: Option<Mix>,
+    player: Option<Player>,
+    message: String,
+}
+
+// Line based like preview, so it works in every terminal: commands are typed and confirmed with enter while the
+// screen is redrawn around them.
+pub fn run(input: &Path, settings: RenderSettings, host: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let (sender, receiver) = channel();
+
+    let input_sender = sender.clone();
+    thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if input_sender.send(Event::Command(line)).is_err() {
+                return;
+            }
+        }
+        let _ = input_sender.send(Event::InputClosed);
+    });
+
+    let progress = Arc::new(Progress::default());
+    let mut tui = Tui {
+        input: input.to_path_buf(),
+        settings: RenderSettings {
+            progress: Some(progress.clone()),
+            ..settings
+        },
+        host: host.map(str::to_string),
+        progress,
+        events: sender,
+        cache: Some(SampleCache::default()),
+        triggers: Vec::new(),
+        times: Vec::new(),
+        muted: HashSet::new(),
+        mix: None,
+        player: None,
+        message: String::new(),
+    };
+
+    tui.start_render();
+    tui.draw();
+
+    loop {
+        match receiver.recv_timeout(REFRESH) {
+            Ok(Event::Command(line)) => {
+                if !tui.command(&line) {
+                    break;
+                }
+                tui.draw();
+            }
+            Ok(Event::Rendered(result, cache)) => {
+                tui.finish_render(*result, cache);
+                tui.draw();
+            }
+            Ok(Event::InputClosed) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => tui.draw_status(),
+        }
+    }
+
+    println!();
+    Ok(())
+}
+
+impl Tui {
+    fn rendering(&self) -> bool {
+        self.cache.is_none()
+    }
+
+    // Reads the CSV file again and mixes it on another thread, muted samples are mixed silently.
+    fn start_render(&mut self) {
+        let mut infos = match read_events(&self.input, &self.settings.csv) {
+            Ok(infos) => infos,
+            Err(err) => {
+                self.message = err.to_string();
+                return;
+            }
+        };
+
+        self.triggers = trigger_counts(&infos).into_iter().collect();
+        self.times = infos.iter().map(|info| info.time).collect();
+        for info in infos.iter_mut() {
+            if self.muted.contains(&info.name) {
+                info.volume = 0.0;
+            }
+        }
+
+        let Some(mut cache) = self.cache.take() else {
+            return;
+        };
+        let settings = self.settings.clone();
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let result = render(infos, &settings, &mut cache).map_err(|err| err.to_string());
+            let _ = events.send(Event::Rendered(Box::new(result), cache));
+        });
+    }
+
+    fn finish_render(&mut self, result: Result<(Vec<f32>, RenderStats), String>, cache: SampleCache) {
+        self.cache = Some(cache);
+        match result {
+            Ok((data, stats)) => {
+                if let Some(player) = &self.player {
+                    player.set_data(data.clone());
+                }
+                self.mix = Some(Mix { data, stats });
+            }
+            Err(err) => self.message = err,
+        }
+    }
+
+    // Returns false to quit.
+    fn command(&mut self, line: &str) -> bool {
+        self.message.clear();
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let arguments: Vec<&str> = words.collect();
+
+        match command {
+            "q" => return false,
+            "r" if self.rendering() => self.message = "still mixing".to_string(),
+            "r" => self.start_render(),
+            "m" => self.toggle_mutes(&arguments),
+            "" | "p" => self.play(arguments.first().copied()),
+            _ => self.message = format!("unknown command '{}'", line.trim()),
+        }
+        true
+    }
+
+    fn toggle_mutes(&mut self, arguments: &[&str]) {
+        if self.rendering() {
+            self.message = "wait until the mix is finished".to_string();
+            return;
+        }
+
+        for argument in arguments {
+            let name = match argument.parse::<usize>() {
+                Ok(number) if (1..=self.triggers.len()).contains(&number) => self.triggers[number - 1].0.clone(),
+                _ => {
+                    self.message = format!("there is no sample number {}", argument);
+                    return;
+                }
+            };
+            if !self.muted.remove(&name) {
+                self.muted.insert(name);
+            }
+        }
+        self.start_render();
+    }
+
+    // Without a time playback is paused or resumed, with one it continues from there.
+    fn play(&mut self, time: Option<&str>) {
+        let Some(mix) = &self.mix else {
+            self.message = "nothing is mixed yet".to_string();
+            return;
+        };
+
+        if self.player.is_none() {
+            match Player::new(mix.data.clone(), self.settings.sample_rate, true, self.host.as_deref()) {
+                Ok(player) => self.player = Some(player),
+                Err(err) => {
+                    self.message = format!("could not play: {}", err);
+                    return;
+                }
+            }
+        }
+        let player = self.player.as_ref().unwrap();
+
+        match time.map(parse_time) {
+            Some(Ok(ms)) => {
+                let position = to_frame(ms - self.start(), self.settings.sample_rate).max(0) as usize * 2;
+                player.seek(position.min(player.len()));
+                player.set_paused(false);
+            }
+            Some(Err(err)) => self.message = err,
+            None => {
+                if player.position() >= player.len() {
+                    player.seek(0);
+                }
+                player.set_paused(!player.is_paused());
+            }
+        }
+    }
+
+    // Time of the first sample of the mix in ms, before 0 with --extend-before-zero.
+    fn start(&self) -> f64 {
+        match &self.mix {
+            Some(mix) => mix.stats.start,
+            None => self.settings.from,
+        }
+    }
+
+    // Start and end of the timeline in ms.
+    fn span(&self) -> (f64, f64) {
+        let start = self.start();
+        let end = match &self.mix {
+            Some(mix) => start + self.to_ms(mix.data.len()),
+            None => self.times.iter().copied().fold(start, f64::max),
+        };
+        (start, end.max(start + 1.0))
+    }
+
+    fn to_ms(&self, position: usize) -> f64 {
+        (position / 2) as f64 / self.settings.sample_rate as f64 * 1000.0
+    }
+
+    fn status(&self) -> String {
+        if self.rendering() {
+            let progress = &self.progress;
+            return format!(
+                "mixing: {} of {} samples decoded, {} of {} events mixed",
+                progress.decoded.load(Ordering::Relaxed),
+                progress.to_decode.load(Ordering::Relaxed),
+                progress.mixed.load(Ordering::Relaxed),
+                progress.to_mix.load(Ordering::Relaxed)
+            );
+        }
+
+        let (start, end) = self.span();
+        match &self.player {
+            Some(player) => {
+                let position = player.position().min(player.len());
+                let state = if player.is_paused() || position >= player.len() {
+                    "paused"
+                } else {
+                    "playing"
+                };
+                format!(
+                    "{} {} / {}",
+                    state,
+                    format_time(start + self.to_ms(position)),
+                    format_time(end)
+                )
+            }
+            None if self.mix.is_some() => format!("mixed {}", format_time(end - start)),
+            None => "not mixed".to_string(),
+        }
+    }
+
+    fn draw_status(&self) {
+        print!("{}{}{}", STATUS_LINE, self.status(), RESTORE_CURSOR);
+        let _ = stdout().flush();
+    }
+
+    fn draw(&self) {
+        let mut screen = String::from(CLEAR_SCREEN);
+        let (start, end) = self.span();
+
+        screen += &format!("{}\n", self.status());
+        screen += &format!(
+            "{}: {} events, {} samples\n\n",
+            self.input.display(),
+            self.times.len(),
+            self.triggers.len()
+        );
+
+        screen += &format!(
+            "        {:<width$}{:>10}\n",
+            format_time(start),
+            format_time(end),
+            width = TIMELINE_WIDTH + 2 - 10
+        );
+        screen += &format!("events  |{}|\n", self.event_bars(start, end));
+        let levels = match &self.mix {
+            Some(mix) => level_bars(&mix.data),
+            None => " ".repeat(TIMELINE_WIDTH),
+        };
+        screen += &format!("level   |{}|\n", levels);
+
+        if let Some(mix) = &self.mix {
+            let stats = &mix.stats;
+            screen += &format!(
+                "peak {:.3} ({:+.1} dBFS), {} clipped samples, decoded in {:.2} s, mixed in {:.2} s\n",
+                stats.peak,
+                20.0 * stats.peak.log10(),
+                stats.clipped,
+                stats.decode_time.as_secs_f32(),
+                stats.mix_time.as_secs_f32()
+            );
+        }
+
+        screen += "\n   #  mute  triggers  sample\n";
+        for (number, (name, count)) in self.triggers.iter().enumerate() {
+            let mute = if self.muted.contains(name) { "M" } else { "" };
+            screen += &format!("{:>4}  {:^4}  {:>8}  {}\n", number + 1, mute, count, name);
+        }
+
+        if !self.message.is_empty() {
+            screen += &format!("\n{}\n", self.message);
+        }
+        screen += "\nr: mix again  m <numbers>: mute/unmute  enter or p [time]: play/pause  q: quit\n> ";
+
+        print!("{}", screen);
+        let _ = stdout().flush();
+    }
+
+    // Number of events per column, scaled to the busiest column.
+    fn event_bars(&self, start: f64, end: f64) -> String {
+        let counts = density(&self.times, start, end, TIMELINE_WIDTH);
+        bars(&counts, counts.iter().copied().max().unwrap_or(0))
+    }
+}
+
+// Peak level per column in dBFS.
+fn level_bars(data: &[f32]) -> String {
+    let column = data.len().div_ceil(TIMELINE_WIDTH).max(1);
+    let mut bars: String = data
+        .chunks(column)
+        .map(|values| {
+            let peak = values.iter().fold(0.0_f32, |peak, value| peak.max(value.abs()));
+            let level = (20.0 * peak.log10() - METER_FLOOR) / -METER_FLOOR;
+            BARS[(level.clamp(0.0, 1.0) * (BARS.len() - 1) as f32).ceil() as usize]
+        })
+        .collect();
+
+    while bars.chars().count() < TIMELINE_WIDTH {
+        bars.push(' ');
+    }
+    bars
+}
@@ -0,0 +1,131 @@
+use std::mem;
+use std::ptr;
+use std::slice;
+
+use libc::{c_int, c_long};
+use ogg_sys::{
+    ogg_packet, ogg_page, ogg_stream_clear, ogg_stream_flush, ogg_stream_init, ogg_stream_packetin, ogg_stream_pageout,
+    ogg_stream_state,
+};
+use vorbis_sys::{
+    vorbis_analysis, vorbis_analysis_blockout, vorbis_analysis_buffer, vorbis_analysis_headerout, vorbis_analysis_init,
+    vorbis_analysis_wrote, vorbis_bitrate_addblock, vorbis_bitrate_flushpacket, vorbis_block, vorbis_block_clear,
+    vorbis_block_init, vorbis_comment, vorbis_comment_clear, vorbis_comment_init, vorbis_dsp_clear, vorbis_dsp_state,
+    vorbis_info, vorbis_info_clear, vorbis_info_init,
+};
+
+// Frames handed to the encoder at once, like vorbis-encoder does.
+const BLOCK_FRAMES: usize = 2048;
+
+// Part of libvorbisenc, which vorbis-encoder builds and links but does not expose.
+extern "C" {
+    fn vorbis_encode_init(
+        vi: *mut vorbis_info,
+        channels: c_long,
+        rate: c_long,
+        max_bitrate: c_long,
+        nominal_bitrate: c_long,
+        min_bitrate: c_long,
+    ) -> c_int;
+}
+
+// The state of libvorbis and libogg, which point at each other and must not move while they are used.
+struct State {
+    stream: ogg_stream_state,
+    info: vorbis_info,
+    comment: vorbis_comment,
+    dsp: vorbis_dsp_state,
+    block: vorbis_block,
+}
+
+// Encodes interleaved stereo 16 bit samples with bitrate management: libvorbis keeps the average near `nominal`
+// and every second between `min` and `max`, all in bits per second with -1 for no limit. Errors are the codes of
+// libvorbis, like those of vorbis-encoder.
+pub fn encode_managed(
+    pcm_data: &[i16],
+    sample_rate: u32,
+    nominal: c_long,
+    min: c_long,
+    max: c_long,
+) -> Result<Vec<u8>, c_int> {
+    let mut output = Vec::new();
+    // Safety: every struct is zeroed and then initialized by libvorbis or libogg before it is used, the boxed state
+    // stays in place until it is cleared at the end.
+    unsafe {
+        let mut state: Box<State> = Box::new(mem::zeroed());
+        let state = &mut *state;
+        vorbis_info_init(&mut state.info);
+        let result = vorbis_encode_init(&mut state.info, 2, sample_rate as c_long, max, nominal, min);
+        if result != 0 {
+            return Err(result);
+        }
+        vorbis_comment_init(&mut state.comment);
+        vorbis_analysis_init(&mut state.dsp, &mut state.info);
+        vorbis_block_init(&mut state.dsp, &mut state.block);
+        // The serial number is replaced by set_stream_serial afterwards.
+        ogg_stream_init(&mut state.stream, 0);
+
+        let mut header: ogg_packet = mem::zeroed();
+        let mut header_comment: ogg_packet = mem::zeroed();
+        let mut header_code: ogg_packet = mem::zeroed();
+        vorbis_analysis_headerout(
+            &mut state.dsp,
+            &mut state.comment,
+            &mut header,
+            &mut header_comment,
+            &mut header_code,
+        );
+        ogg_stream_packetin(&mut state.stream, &mut header);
+        ogg_stream_packetin(&mut state.stream, &mut header_comment);
+        ogg_stream_packetin(&mut state.stream, &mut header_code);
+        // Audio starts on a page of its own.
+        let mut page: ogg_page = mem::zeroed();
+        while ogg_stream_flush(&mut state.stream, &mut page) != 0 {
+            write_page(&page, &mut output);
+        }
+
+        for block in pcm_data.chunks(BLOCK_FRAMES * 2) {
+            let frames = block.len() / 2;
+            let buffer = vorbis_analysis_buffer(&mut state.dsp, frames as c_int);
+            let left = slice::from_raw_parts_mut(*buffer, frames);
+            let right = slice::from_raw_parts_mut(*buffer.add(1), frames);
+            for (i, frame) in block.chunks_exact(2).enumerate() {
+                left[i] = frame[0] as f32 / 32768.0;
+                right[i] = frame[1] as f32 / 32768.0;
+            }
+            vorbis_analysis_wrote(&mut state.dsp, frames as c_int);
+            write_blocks(state, &mut output);
+        }
+        vorbis_analysis_wrote(&mut state.dsp, 0);
+        write_blocks(state, &mut output);
+
+        ogg_stream_clear(&mut state.stream);
+        vorbis_block_clear(&mut state.block);
+        vorbis_dsp_clear(&mut state.dsp);
+        vorbis_comment_clear(&mut state.comment);
+        vorbis_info_clear(&mut state.info);
+    }
+    Ok(output)
+}
+
+// Safety: the state has to be initialized by encode_managed.
+unsafe fn write_blocks(state: &mut State, output: &mut Vec<u8>) {
+    let mut packet: ogg_packet = mem::zeroed();
+    let mut page: ogg_page = mem::zeroed();
+    while vorbis_analysis_blockout(&mut state.dsp, &mut state.block) == 1 {
+        vorbis_analysis(&mut state.block, ptr::null_mut());
+        vorbis_bitrate_addblock(&mut state.block);
+        while vorbis_bitrate_flushpacket(&mut state.dsp, &mut packet) != 0 {
+            ogg_stream_packetin(&mut state.stream, &mut packet);
+            while ogg_stream_pageout(&mut state.stream, &mut page) != 0 {
+                write_page(&page, output);
+            }
+        }
+    }
+}
+
+// Safety: the page has to come from libogg.
+unsafe fn write_page(page: &ogg_page, output: &mut Vec<u8>) {
+    output.extend_from_slice(slice::from_raw_parts(page.header, page.header_len as usize));
+    output.extend_from_slice(slice::from_raw_parts(page.body, page.body_len as usize));
+}
@@ -0,0 +1,44 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+use crate::events::{read_events, CsvOptions};
+use crate::resolve::SampleResolver;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Editors often save in several steps, so wait a little for the last write before rendering.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+// The input file and every sample it currently references.
+pub fn watched_files(input: &Path, resolver: &SampleResolver, options: &CsvOptions) -> Vec<PathBuf> {
+    let mut files = vec![input.to_path_buf()];
+
+    if let Ok(infos) = read_events(input, options) {
+        let names: HashSet<&str> = infos.iter().map(|info| info.name.as_str()).collect();
+        files.extend(names.into_iter().map(|name| resolver.resolve(name)));
+    }
+
+    files
+}
+
+// Blocks until one of the files is modified, created or deleted.
+pub fn wait_for_change(files: &[PathBuf]) {
+    let snapshot = modification_times(files);
+
+    loop {
+        sleep(POLL_INTERVAL);
+        if modification_times(files) != snapshot {
+            sleep(SETTLE_TIME);
+            return;
+        }
+    }
+}
+
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
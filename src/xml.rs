@@ -0,0 +1,171 @@
+// An element with its attributes, children and the text directly inside it.
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn number(&self, name: &str, default: f64) -> f64 {
+        self.attribute(name)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    // The text of the first child with this name, as Renoise stores its values.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.trim())
+    }
+}
+
+// Just enough XML for project files: elements, attributes and text. Comments, CDATA sections and declarations are
+// skipped.
+pub fn parse_xml(text: &str) -> Result<Element, String> {
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        if let Some(element) = stack.last_mut() {
+            element.text.push_str(&unescape(&rest[..start]));
+        }
+        rest = &rest[start..];
+        let skip = [("<!--", "-->"), ("<![CDATA[", "]]>"), ("<?", "?>"), ("<!", ">")]
+            .iter()
+            .find(|(open, _)| rest.starts_with(open));
+        if let Some((_, close)) = skip {
+            let end = rest.find(close).ok_or("a comment or declaration is not closed")?;
+            rest = &rest[end + close.len()..];
+            continue;
+        }
+
+        let end = tag_end(rest).ok_or("a tag is not closed")?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let element = stack
+                .pop()
+                .ok_or_else(|| format!("</{}> closes nothing", name.trim()))?;
+            if element.name != name.trim() {
+                return Err(format!("</{}> closes <{}>", name.trim(), element.name));
+            }
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(element),
+                None => root = Some(element),
+            }
+            continue;
+        }
+
+        let closed = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let element = Element {
+            name: tag[..name_end].to_string(),
+            attributes: parse_attributes(&tag[name_end..])?,
+            children: Vec::new(),
+            text: String::new(),
+        };
+        if !closed {
+            stack.push(element);
+            continue;
+        }
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(element),
+            None => root = Some(element),
+        }
+    }
+
+    if let Some(element) = stack.last() {
+        return Err(format!("<{}> is not closed", element.name));
+    }
+    root.ok_or_else(|| "the file has no XML elements".to_string())
+}
+
+// The end of a tag, skipping '>' in quoted attribute values.
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, character) in tag.char_indices() {
+        match (quote, character) {
+            (None, '"' | '\'') => quote = Some(character),
+            (Some(open), _) if open == character => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_attributes(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut attributes = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let equals = rest
+            .find('=')
+            .ok_or_else(|| format!("attribute {} has no value", rest))?;
+        let name = rest[..equals].trim().to_string();
+        let value = rest[equals + 1..].trim_start();
+        let quote = value.chars().next().filter(|quote| *quote == '"' || *quote == '\'');
+        let quote = quote.ok_or_else(|| format!("the value of {} is not quoted", name))?;
+        let end = value[1..]
+            .find(quote)
+            .ok_or_else(|| format!("the value of {} is not closed", name))?;
+        attributes.push((name, unescape(&value[1..end + 1])));
+        rest = value[end + 2..].trim_start();
+    }
+    Ok(attributes)
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|decimal| decimal.parse()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match character {
+            Some(character) => {
+                unescaped.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}